@@ -3,9 +3,14 @@ const API_URL = '/api';
 let warehouses = [];
 let suppliers = [];
 let stockBatches = [];
-let allStockBatches = [];
-
-document.addEventListener('DOMContentLoaded', () => {
+let config = {
+    currency: 'VND',
+    default_warehouse_id: null,
+    alerts: { expiry_days: 90, critical_expiry_days: 30, low_stock_quantity: 10 }
+};
+
+document.addEventListener('DOMContentLoaded', async () => {
+    await loadConfig();
     loadWarehouses();
     loadSuppliers();
     loadStockBatches();
@@ -17,6 +22,17 @@ document.addEventListener('DOMContentLoaded', () => {
     setupTabs();
 });
 
+async function loadConfig() {
+    try {
+        const res = await fetch(`${API_URL}/config`);
+        if (res.ok) config = await res.json();
+    } catch (err) {
+        console.error('Failed to load config, using defaults', err);
+    }
+    const days = document.getElementById('expiryDays');
+    if (days) days.textContent = config.alerts.expiry_days;
+}
+
 function setupTabs() {
     // Main tabs
     const mainTabBtns = document.querySelectorAll('.main-tabs .tab-btn');
@@ -81,7 +97,8 @@ function setupEventListeners() {
 
 // Warehouse Management
 async function loadWarehouses() {
-    const res = await fetch(`${API_URL}/warehouses`);
+    // Archived warehouses stay listed so history can name them; pickers skip them
+    const res = await fetch(`${API_URL}/warehouses?include_archived=true`);
     warehouses = await res.json();
     renderWarehouses();
     populateWarehouseSelects();
@@ -97,14 +114,19 @@ function renderWarehouses() {
             ? '<span class="badge badge-main">Main</span>'
             : '<span class="badge badge-store">Store</span>';
 
+        const archived = !!wh.archived_at;
         const tr = document.createElement('tr');
+        if (archived) tr.classList.add('archived');
         tr.innerHTML = `
             <td>${wh.id}</td>
             <td>${wh.name}</td>
-            <td>${typeBadge}</td>
+            <td>${typeBadge}${archived ? ' <span class="badge badge-archived">Archived</span>' : ''}</td>
             <td>${batchCount}</td>
             <td>
                 <button class="btn-sm btn-secondary" onclick="openEditWarehouseModal(${wh.id})">Edit</button>
+                ${archived
+                    ? `<button class="btn-sm btn-secondary" onclick="setArchived('warehouses', ${wh.id}, ${wh.version}, false)">Restore</button>`
+                    : `<button class="btn-sm btn-secondary" onclick="setArchived('warehouses', ${wh.id}, ${wh.version}, true)">Archive</button>`}
             </td>
         `;
         tbody.appendChild(tr);
@@ -125,13 +147,18 @@ function populateWarehouseSelects() {
         select.innerHTML = select.id === 'warehouseFilter' ? '<option value="">All Warehouses</option>' : '';
 
         warehouses.forEach(wh => {
+            if (wh.archived_at && select.id !== 'warehouseFilter') return;
             const option = document.createElement('option');
             option.value = wh.id;
             option.textContent = `${wh.name} (${wh.warehouse_type})`;
             select.appendChild(option);
         });
 
-        if (currentValue) select.value = currentValue;
+        if (currentValue) {
+            select.value = currentValue;
+        } else if (select.id !== 'warehouseFilter' && config.default_warehouse_id) {
+            select.value = config.default_warehouse_id;
+        }
     });
 }
 
@@ -139,11 +166,12 @@ async function createWarehouse() {
     const name = document.getElementById('warehouseName').value;
     const warehouse_type = document.getElementById('warehouseType').value;
 
-    await fetch(`${API_URL}/warehouses`, {
+    const res = await fetch(`${API_URL}/warehouses`, {
         method: 'POST',
-        headers: { 'Content-Type': 'application/json' },
+        headers: { 'Content-Type': 'application/json', 'Idempotency-Key': crypto.randomUUID() },
         body: JSON.stringify({ name, warehouse_type })
     });
+    if (!res.ok) return showApiError(res);
 
     closeModal('createWarehouseModal');
     document.getElementById('createWarehouseForm').reset();
@@ -156,9 +184,25 @@ function openCreateWarehouseModal() {
 
 // Supplier Management
 async function loadSuppliers() {
-    const res = await fetch(`${API_URL}/suppliers`);
+    const res = await fetch(`${API_URL}/suppliers?include_archived=true`);
     suppliers = await res.json();
     renderSuppliers();
+    populateSupplierSelects();
+}
+
+function populateSupplierSelects() {
+    const select = document.getElementById('importSupplier');
+    const currentValue = select.value;
+    select.innerHTML = '<option value="">No Supplier</option>';
+
+    suppliers.filter(s => !s.archived_at).forEach(s => {
+        const option = document.createElement('option');
+        option.value = s.id;
+        option.textContent = s.name;
+        select.appendChild(option);
+    });
+
+    if (currentValue) select.value = currentValue;
 }
 
 function renderSuppliers() {
@@ -166,15 +210,20 @@ function renderSuppliers() {
     tbody.innerHTML = '';
 
     suppliers.forEach(s => {
+        const archived = !!s.archived_at;
         const tr = document.createElement('tr');
+        if (archived) tr.classList.add('archived');
         tr.innerHTML = `
             <td>${s.id}</td>
-            <td>${s.name}</td>
+            <td>${s.name}${archived ? ' <span class="badge badge-archived">Archived</span>' : ''}</td>
             <td>${s.contact}</td>
             <td>${s.phone}</td>
             <td>${s.address}</td>
             <td>
                 <button class="btn-sm btn-secondary" onclick="openEditSupplierModal(${s.id})">Edit</button>
+                ${archived
+                    ? `<button class="btn-sm btn-secondary" onclick="setArchived('suppliers', ${s.id}, ${s.version}, false)">Restore</button>`
+                    : `<button class="btn-sm btn-secondary" onclick="setArchived('suppliers', ${s.id}, ${s.version}, true)">Archive</button>`}
             </td>
         `;
         tbody.appendChild(tr);
@@ -187,11 +236,12 @@ async function createSupplier() {
     const phone = document.getElementById('supplierPhone').value;
     const address = document.getElementById('supplierAddress').value;
 
-    await fetch(`${API_URL}/suppliers`, {
+    const res = await fetch(`${API_URL}/suppliers`, {
         method: 'POST',
-        headers: { 'Content-Type': 'application/json' },
+        headers: { 'Content-Type': 'application/json', 'Idempotency-Key': crypto.randomUUID() },
         body: JSON.stringify({ name, contact, phone, address })
     });
+    if (!res.ok) return showApiError(res);
 
     closeModal('createSupplierModal');
     document.getElementById('createSupplierForm').reset();
@@ -203,6 +253,7 @@ function openEditSupplierModal(id) {
     if (!supplier) return;
 
     document.getElementById('editSupplierId').value = supplier.id;
+    document.getElementById('editSupplierVersion').value = supplier.version;
     document.getElementById('editSupplierName').value = supplier.name;
     document.getElementById('editSupplierContact').value = supplier.contact;
     document.getElementById('editSupplierPhone').value = supplier.phone;
@@ -213,21 +264,41 @@ function openEditSupplierModal(id) {
 
 async function updateSupplier() {
     const id = document.getElementById('editSupplierId').value;
+    const version = document.getElementById('editSupplierVersion').value;
     const name = document.getElementById('editSupplierName').value;
     const contact = document.getElementById('editSupplierContact').value;
     const phone = document.getElementById('editSupplierPhone').value;
     const address = document.getElementById('editSupplierAddress').value;
 
-    await fetch(`${API_URL}/suppliers/${id}`, {
+    const res = await fetch(`${API_URL}/suppliers/${id}`, {
         method: 'PUT',
-        headers: { 'Content-Type': 'application/json' },
+        headers: { 'Content-Type': 'application/json', 'If-Match': `"${version}"` },
         body: JSON.stringify({ name, contact, phone, address })
     });
+    if (!res.ok) {
+        await showApiError(res);
+        if (res.status === 412) loadSuppliers();
+        return;
+    }
 
     closeModal('editSupplierModal');
     loadSuppliers();
 }
 
+// Archive (soft delete) or restore a warehouse or supplier
+async function setArchived(kind, id, version, archive) {
+    if (archive && !confirm('Archive this record? It will be hidden from pickers but kept in history.')) return;
+
+    const res = await fetch(`${API_URL}/${kind}/${id}${archive ? '' : '/restore'}`, {
+        method: archive ? 'DELETE' : 'POST',
+        headers: { 'If-Match': `"${version}"`, 'Idempotency-Key': crypto.randomUUID() }
+    });
+    if (!res.ok) await showApiError(res);
+
+    if (kind === 'warehouses') loadWarehouses();
+    else loadSuppliers();
+}
+
 function openCreateSupplierModal() {
     document.getElementById('createSupplierModal').style.display = 'block';
 }
@@ -237,6 +308,7 @@ function openEditWarehouseModal(id) {
     if (!warehouse) return;
 
     document.getElementById('editWarehouseId').value = warehouse.id;
+    document.getElementById('editWarehouseVersion').value = warehouse.version;
     document.getElementById('editWarehouseName').value = warehouse.name;
     document.getElementById('editWarehouseType').value = warehouse.warehouse_type;
 
@@ -245,14 +317,20 @@ function openEditWarehouseModal(id) {
 
 async function updateWarehouse() {
     const id = document.getElementById('editWarehouseId').value;
+    const version = document.getElementById('editWarehouseVersion').value;
     const name = document.getElementById('editWarehouseName').value;
     const warehouse_type = document.getElementById('editWarehouseType').value;
 
-    await fetch(`${API_URL}/warehouses/${id}`, {
+    const res = await fetch(`${API_URL}/warehouses/${id}`, {
         method: 'PUT',
-        headers: { 'Content-Type': 'application/json' },
+        headers: { 'Content-Type': 'application/json', 'If-Match': `"${version}"` },
         body: JSON.stringify({ name, warehouse_type })
     });
+    if (!res.ok) {
+        await showApiError(res);
+        if (res.status === 412) loadWarehouses();
+        return;
+    }
 
     closeModal('editWarehouseModal');
     loadWarehouses();
@@ -260,9 +338,12 @@ async function updateWarehouse() {
 
 // Stock Batch Management
 async function loadStockBatches() {
-    const res = await fetch(`${API_URL}/stock-batches`);
-    allStockBatches = await res.json();
-    stockBatches = allStockBatches;
+    const params = new URLSearchParams();
+    const warehouseId = document.getElementById('warehouseFilter').value;
+    if (warehouseId !== '') params.set('warehouse_id', warehouseId);
+
+    const res = await fetch(`${API_URL}/stock-batches?${params}`);
+    stockBatches = await res.json();
     renderStockBatches();
 }
 
@@ -276,8 +357,8 @@ function renderStockBatches() {
         const daysLeft = Math.floor((expiryDate - new Date()) / (1000 * 60 * 60 * 24));
 
         let expiryClass = 'expiry-ok';
-        if (daysLeft < 30) expiryClass = 'expiry-critical';
-        else if (daysLeft < 90) expiryClass = 'expiry-warning';
+        if (daysLeft < config.alerts.critical_expiry_days) expiryClass = 'expiry-critical';
+        else if (daysLeft < config.alerts.expiry_days) expiryClass = 'expiry-warning';
 
         const tr = document.createElement('tr');
         tr.innerHTML = `
@@ -285,7 +366,8 @@ function renderStockBatches() {
             <td>${batch.medicine_name}</td>
             <td>${warehouse ? warehouse.name : 'Unknown'}</td>
             <td>${batch.quantity}</td>
-            <td>${formatPrice(batch.price)}</td>
+            <td>${formatPrice(batch.unit_cost)}</td>
+            <td>${formatPrice(batch.selling_price)}</td>
             <td class="${expiryClass}">${formatDate(batch.expiry_date)}</td>
             <td>
                 <button class="btn-sm btn-sell" onclick="openTransferModalForBatch(${batch.id})">Transfer</button>
@@ -296,21 +378,18 @@ function renderStockBatches() {
 }
 
 function filterStockByWarehouse() {
-    const warehouseId = document.getElementById('warehouseFilter').value;
-    if (warehouseId === '') {
-        stockBatches = allStockBatches;
-    } else {
-        stockBatches = allStockBatches.filter(b => b.warehouse_id == warehouseId);
-    }
-    renderStockBatches();
+    loadStockBatches();
 }
 
 async function importBatch() {
     const medicine_id = parseInt(document.getElementById('importMedicineId').value);
     const medicine_name = document.getElementById('importMedicineName').value;
     const warehouse_id = parseInt(document.getElementById('importWarehouse').value);
+    const supplierValue = document.getElementById('importSupplier').value;
+    const supplier_id = supplierValue === '' ? null : parseInt(supplierValue);
     const quantity = parseInt(document.getElementById('importQuantity').value);
-    const price = parseFloat(document.getElementById('importPrice').value);
+    const unit_cost = parseFloat(document.getElementById('importUnitCost').value);
+    const selling_price = parseFloat(document.getElementById('importSellingPrice').value);
     const expiryDateInput = document.getElementById('importExpiryDate').value;
 
     // Convert to ISO 8601 format
@@ -318,8 +397,8 @@ async function importBatch() {
 
     const res = await fetch(`${API_URL}/import-batch`, {
         method: 'POST',
-        headers: { 'Content-Type': 'application/json' },
-        body: JSON.stringify({ medicine_id, medicine_name, warehouse_id, quantity, price, expiry_date })
+        headers: { 'Content-Type': 'application/json', 'Idempotency-Key': crypto.randomUUID() },
+        body: JSON.stringify({ medicine_id, medicine_name, warehouse_id, supplier_id, quantity, unit_cost, selling_price, expiry_date })
     });
 
     if (res.ok) {
@@ -328,8 +407,7 @@ async function importBatch() {
         loadStockBatches();
         loadImportBatches();
     } else {
-        const error = await res.text();
-        alert(error);
+        await showApiError(res);
     }
 }
 
@@ -368,7 +446,7 @@ async function transferBatch() {
 
     const res = await fetch(`${API_URL}/transfer-batch`, {
         method: 'POST',
-        headers: { 'Content-Type': 'application/json' },
+        headers: { 'Content-Type': 'application/json', 'Idempotency-Key': crypto.randomUUID() },
         body: JSON.stringify({ batch_id, to_warehouse_id, quantity })
     });
 
@@ -378,8 +456,7 @@ async function transferBatch() {
         loadStockBatches();
         loadTransfers();
     } else {
-        const error = await res.text();
-        alert(error);
+        await showApiError(res);
     }
 }
 
@@ -404,7 +481,7 @@ function renderExpiringBatches(batches) {
         const expiryDate = new Date(batch.expiry_date);
         const daysLeft = Math.floor((expiryDate - new Date()) / (1000 * 60 * 60 * 24));
 
-        let expiryClass = daysLeft < 30 ? 'expiry-critical' : 'expiry-warning';
+        let expiryClass = daysLeft < config.alerts.critical_expiry_days ? 'expiry-critical' : 'expiry-warning';
 
         const tr = document.createElement('tr');
         tr.innerHTML = `
@@ -432,7 +509,7 @@ async function loadImportBatches() {
             <td>${batch.id}</td>
             <td>${batch.medicine_name}</td>
             <td>${batch.quantity}</td>
-            <td>${formatPrice(batch.price)}</td>
+            <td>${formatPrice(batch.unit_cost)}</td>
             <td>${formatDateTime(batch.timestamp)}</td>
         `;
         tbody.appendChild(tr);
@@ -480,8 +557,21 @@ function openTransferModal() {
 }
 
 // Utility Functions
+async function showApiError(res) {
+    try {
+        const error = await res.json();
+        if (error.errors) {
+            alert(error.errors.map(e => `${e.field}: ${e.message}`).join('\n'));
+        } else {
+            alert(error.message || error.code);
+        }
+    } catch {
+        alert(`Request failed (${res.status})`);
+    }
+}
+
 function formatPrice(price) {
-    return new Intl.NumberFormat('vi-VN', { style: 'currency', currency: 'VND' }).format(price);
+    return new Intl.NumberFormat('vi-VN', { style: 'currency', currency: config.currency }).format(price);
 }
 
 function formatDateTime(timestamp) {
@@ -0,0 +1,171 @@
+//! Measures `/api/sell` throughput against a real server process.
+//!
+//! Run with `cargo bench --bench concurrent_sales`. The server is started in a
+//! temporary directory on a free local port.
+
+use std::{
+    net::{SocketAddr, TcpListener},
+    process::{Child, Command, Stdio},
+    sync::OnceLock,
+    time::{Duration, Instant},
+};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+    net::TcpStream,
+};
+
+static ADDR: OnceLock<SocketAddr> = OnceLock::new();
+const SALES_PER_CLIENT: usize = 200;
+const CONCURRENCY_LEVELS: [usize; 4] = [1, 4, 16, 64];
+
+struct Server(Child);
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+/// Minimal keep-alive HTTP/1.1 client so the benchmark needs no extra dependencies.
+struct Connection {
+    stream: BufReader<TcpStream>,
+}
+
+impl Connection {
+    async fn open() -> std::io::Result<Self> {
+        let stream = TcpStream::connect(addr()).await?;
+        stream.set_nodelay(true)?;
+        Ok(Connection {
+            stream: BufReader::new(stream),
+        })
+    }
+
+    async fn post(&mut self, path: &str, body: &str) -> std::io::Result<u16> {
+        let request = format!(
+            "POST {} HTTP/1.1\r\nHost: localhost\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+            path,
+            body.len(),
+            body
+        );
+        self.stream.get_mut().write_all(request.as_bytes()).await?;
+
+        let mut status_line = String::new();
+        self.stream.read_line(&mut status_line).await?;
+        let status = status_line
+            .split_whitespace()
+            .nth(1)
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(0);
+
+        let mut content_length = 0;
+        loop {
+            let mut line = String::new();
+            self.stream.read_line(&mut line).await?;
+            if line == "\r\n" || line.is_empty() {
+                break;
+            }
+            if let Some((name, value)) = line.split_once(':')
+                && name.eq_ignore_ascii_case("content-length")
+            {
+                content_length = value.trim().parse().unwrap_or(0);
+            }
+        }
+        let mut body = vec![0; content_length];
+        self.stream.read_exact(&mut body).await?;
+        Ok(status)
+    }
+}
+
+fn addr() -> SocketAddr {
+    *ADDR.get_or_init(|| {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("Failed to find a free port");
+        listener.local_addr().unwrap()
+    })
+}
+
+fn start_server() -> Server {
+    let dir = std::env::temp_dir().join(format!("variables-bench-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).expect("Failed to create bench directory");
+    let _ = std::fs::remove_file(dir.join("data.json"));
+
+    let child = Command::new(env!("CARGO_BIN_EXE_variables"))
+        .current_dir(&dir)
+        .arg("--listen")
+        .arg(addr().to_string())
+        .arg("--assets-dir")
+        .arg(concat!(env!("CARGO_MANIFEST_DIR"), "/assets"))
+        .stdout(Stdio::null())
+        .spawn()
+        .expect("Failed to start server");
+    Server(child)
+}
+
+async fn wait_for_server() {
+    for _ in 0..100 {
+        if TcpStream::connect(addr()).await.is_ok() {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    panic!("Server did not start on {}", addr());
+}
+
+async fn run_level(clients: usize) -> (Duration, usize) {
+    let started = Instant::now();
+    let tasks: Vec<_> = (0..clients)
+        .map(|_| {
+            tokio::spawn(async move {
+                let mut conn = Connection::open().await.expect("Failed to connect");
+                let mut ok = 0;
+                for _ in 0..SALES_PER_CLIENT {
+                    if matches!(
+                        conn.post("/api/sell", r#"{"id":1,"amount":1}"#).await,
+                        Ok(200)
+                    ) {
+                        ok += 1;
+                    }
+                }
+                ok
+            })
+        })
+        .collect();
+
+    let mut ok = 0;
+    for task in tasks {
+        ok += task.await.unwrap();
+    }
+    (started.elapsed(), ok)
+}
+
+#[tokio::main]
+async fn main() {
+    let _server = start_server();
+    wait_for_server().await;
+
+    let mut setup = Connection::open().await.expect("Failed to connect");
+    let status = setup
+        .post(
+            "/api/medicines",
+            r#"{"name":"Benchmark","price":1000.0,"quantity":1000000000}"#,
+        )
+        .await
+        .expect("Failed to create medicine");
+    assert_eq!(status, 201, "Failed to create medicine");
+
+    println!(
+        "{:>8} {:>10} {:>10} {:>12}",
+        "clients", "sales", "seconds", "sales/sec"
+    );
+    for clients in CONCURRENCY_LEVELS {
+        let (elapsed, ok) = run_level(clients).await;
+        assert_eq!(ok, clients * SALES_PER_CLIENT, "Some sales failed");
+        println!(
+            "{:>8} {:>10} {:>10.3} {:>12.0}",
+            clients,
+            ok,
+            elapsed.as_secs_f64(),
+            ok as f64 / elapsed.as_secs_f64()
+        );
+    }
+}
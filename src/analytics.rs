@@ -0,0 +1,244 @@
+use chrono::{DateTime, Duration, Local};
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+use crate::models::Pharmacy;
+
+// Cumulative revenue share reached before a product falls to the next class
+const A_SHARE: f64 = 0.80;
+const B_SHARE: f64 = 0.95;
+// Coefficient of variation of weekly demand
+const X_VARIATION: f64 = 0.5;
+const Y_VARIATION: f64 = 1.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+pub enum AbcClass {
+    A,
+    B,
+    C,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+pub enum XyzClass {
+    X,
+    Y,
+    Z,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AbcXyzRow {
+    pub medicine_id: u32,
+    pub medicine_name: String,
+    pub units: u32,
+    pub revenue: f64,
+    /// Share of the period's total revenue.
+    pub revenue_share: f64,
+    /// Share of this product and every product with more revenue.
+    pub cumulative_share: f64,
+    pub weekly_mean: f64,
+    /// Standard deviation of weekly units over their mean; `None` without sales.
+    pub variation: Option<f64>,
+    pub abc: AbcClass,
+    pub xyz: XyzClass,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AbcXyzReport {
+    pub from: DateTime<Local>,
+    pub to: DateTime<Local>,
+    pub weeks: usize,
+    pub revenue: f64,
+    /// Number of products in each combined class, e.g. `"AX"`.
+    pub classes: BTreeMap<String, usize>,
+    pub rows: Vec<AbcXyzRow>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DeadStockBatch {
+    pub batch_id: u32,
+    pub medicine_id: u32,
+    pub medicine_name: String,
+    pub warehouse_id: u32,
+    pub quantity: u32,
+    pub unit_cost: f64,
+    pub value: f64,
+    pub expiry_date: DateTime<Local>,
+    /// Latest receipt, sale or transfer of the batch.
+    pub last_movement: DateTime<Local>,
+    pub idle_days: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DeadStockReport {
+    pub days: i64,
+    pub quantity: u32,
+    pub value: f64,
+    pub batches: Vec<DeadStockBatch>,
+}
+
+impl Pharmacy {
+    /// Classifies products by their share of revenue (A: the top 80%, B: the
+    /// next 15%, C: the rest) and by how much their weekly sales vary (X: up to
+    /// 0.5, Y: up to 1.0, Z: above or never sold). Products in stock without
+    /// sales in the period are included as C.
+    pub fn abc_xyz(&self, from: DateTime<Local>, to: DateTime<Local>) -> AbcXyzReport {
+        let weeks = ((to - from).num_days() as usize).div_ceil(7).max(1);
+        let mut products: BTreeMap<u32, (String, u32, f64, Vec<f64>)> = BTreeMap::new();
+        for sale in self
+            .export_log
+            .iter()
+            .filter(|e| e.timestamp >= from && e.timestamp <= to)
+        {
+            let product = products
+                .entry(sale.medicine_id)
+                .or_insert_with(|| (sale.medicine_name.clone(), 0, 0.0, vec![0.0; weeks]));
+            product.1 += sale.amount;
+            product.2 += sale.revenue();
+            let week = ((sale.timestamp - from).num_days() / 7) as usize;
+            product.3[week.min(weeks - 1)] += sale.amount as f64;
+        }
+        let in_stock = self
+            .stock_batches
+            .iter()
+            .filter(|b| b.quantity > 0)
+            .map(|b| (b.medicine_id, &b.medicine_name))
+            .chain(
+                self.inventory
+                    .iter()
+                    .filter(|m| m.quantity > 0)
+                    .map(|m| (m.id, &m.name)),
+            );
+        for (medicine_id, name) in in_stock {
+            products
+                .entry(medicine_id)
+                .or_insert_with(|| (name.clone(), 0, 0.0, vec![0.0; weeks]));
+        }
+
+        let revenue = products.values().fold(0.0, |sum, p| sum + p.2);
+        let mut ranked: Vec<_> = products.into_iter().collect();
+        ranked.sort_by(|a, b| b.1.2.total_cmp(&a.1.2).then(a.0.cmp(&b.0)));
+
+        let mut classes = BTreeMap::new();
+        let mut cumulative = 0.0;
+        let mut rows = Vec::new();
+        for (medicine_id, (medicine_name, units, product_revenue, weekly)) in ranked {
+            let share = if revenue > 0.0 {
+                product_revenue / revenue
+            } else {
+                0.0
+            };
+            // A product belongs to the class its first unit of revenue falls in
+            let abc = if product_revenue <= 0.0 {
+                AbcClass::C
+            } else if cumulative < A_SHARE {
+                AbcClass::A
+            } else if cumulative < B_SHARE {
+                AbcClass::B
+            } else {
+                AbcClass::C
+            };
+            cumulative += share;
+
+            let weekly_mean = units as f64 / weeks as f64;
+            let variation = (weekly_mean > 0.0).then(|| {
+                let variance = weekly
+                    .iter()
+                    .map(|w| (w - weekly_mean).powi(2))
+                    .sum::<f64>()
+                    / weeks as f64;
+                variance.sqrt() / weekly_mean
+            });
+            let xyz = match variation {
+                Some(v) if v <= X_VARIATION => XyzClass::X,
+                Some(v) if v <= Y_VARIATION => XyzClass::Y,
+                _ => XyzClass::Z,
+            };
+
+            *classes.entry(format!("{:?}{:?}", abc, xyz)).or_default() += 1;
+            rows.push(AbcXyzRow {
+                medicine_id,
+                medicine_name,
+                units,
+                revenue: product_revenue,
+                revenue_share: share,
+                cumulative_share: cumulative,
+                weekly_mean,
+                variation,
+                abc,
+                xyz,
+            });
+        }
+
+        AbcXyzReport {
+            from,
+            to,
+            weeks,
+            revenue,
+            classes,
+            rows,
+        }
+    }
+
+    /// Batches in stock that have not been received, sold from or transferred
+    /// for at least `days` days, most value first.
+    pub fn dead_stock(&self, days: i64, warehouse_id: Option<u32>) -> DeadStockReport {
+        let now = Local::now();
+        let mut last_movement: BTreeMap<u32, DateTime<Local>> = self
+            .stock_batches
+            .iter()
+            .map(|b| (b.id, b.import_date))
+            .collect();
+        let mut touch = |batch_id: u32, at: DateTime<Local>| {
+            if let Some(last) = last_movement.get_mut(&batch_id)
+                && at > *last
+            {
+                *last = at;
+            }
+        };
+        for sale in &self.export_log {
+            for sold in &sale.batches {
+                touch(sold.batch_id, sale.timestamp);
+            }
+        }
+        for transfer in &self.transfer_log {
+            touch(transfer.batch_id, transfer.timestamp);
+            if let Some(id) = transfer.to_batch_id {
+                touch(id, transfer.timestamp);
+            }
+        }
+
+        let mut batches: Vec<DeadStockBatch> = self
+            .stock_batches
+            .iter()
+            .filter(|b| b.quantity > 0 && warehouse_id.is_none_or(|id| id == b.warehouse_id))
+            .filter_map(|b| {
+                let last = last_movement[&b.id];
+                let idle_days = (now - last).num_days();
+                (now - last >= Duration::days(days)).then(|| DeadStockBatch {
+                    batch_id: b.id,
+                    medicine_id: b.medicine_id,
+                    medicine_name: b.medicine_name.clone(),
+                    warehouse_id: b.warehouse_id,
+                    quantity: b.quantity,
+                    unit_cost: b.unit_cost,
+                    value: b.quantity as f64 * b.unit_cost,
+                    expiry_date: b.expiry_date,
+                    last_movement: last,
+                    idle_days,
+                })
+            })
+            .collect();
+        batches.sort_by(|a, b| {
+            b.value
+                .total_cmp(&a.value)
+                .then(a.batch_id.cmp(&b.batch_id))
+        });
+
+        DeadStockReport {
+            days,
+            quantity: batches.iter().map(|b| b.quantity).sum(),
+            value: batches.iter().fold(0.0, |sum, b| sum + b.value),
+            batches,
+        }
+    }
+}
@@ -0,0 +1,300 @@
+use chrono::Local;
+
+use crate::audit::{AuditAction, AuditEntity};
+use crate::error::PharmacyError;
+use crate::models::{Pharmacy, StockBatch, Supplier, Warehouse};
+use crate::purchase_order::PurchaseOrder;
+use crate::store_replenishment::{ProposalStatus, TransferProposal};
+
+// Archiving replaces deletion for master data: history keeps resolving the id,
+// while pickers and new documents only see active records.
+impl Pharmacy {
+    /// A warehouse that exists and may receive stock.
+    pub fn active_warehouse(&self, id: u32) -> Result<&Warehouse, PharmacyError> {
+        let warehouse = self
+            .warehouses
+            .iter()
+            .find(|w| w.id == id)
+            .ok_or(PharmacyError::WarehouseNotFound)?;
+        match warehouse.archived_at {
+            Some(_) => Err(PharmacyError::Archived {
+                entity: "warehouse",
+            }),
+            None => Ok(warehouse),
+        }
+    }
+
+    pub fn active_supplier(&self, id: u32) -> Result<&Supplier, PharmacyError> {
+        let supplier = self
+            .suppliers
+            .iter()
+            .find(|s| s.id == id)
+            .ok_or(PharmacyError::SupplierNotFound)?;
+        match supplier.archived_at {
+            Some(_) => Err(PharmacyError::Archived { entity: "supplier" }),
+            None => Ok(supplier),
+        }
+    }
+
+    /// Medicines only known from batches have no catalog entry to archive, so they always pass.
+    pub fn ensure_medicine_not_archived(&self, id: u32) -> Result<(), PharmacyError> {
+        match self.inventory.iter().find(|m| m.id == id) {
+            Some(m) if m.archived_at.is_some() => {
+                Err(PharmacyError::Archived { entity: "medicine" })
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Archives a medicine once it is out of stock and no open document refers to it.
+    /// Its reorder settings and par levels are deleted, so replenishment stops
+    /// proposing it; restoring the medicine does not bring them back.
+    pub fn archive_medicine(&mut self, user: &str, id: u32) -> Result<(), PharmacyError> {
+        let in_stock = self
+            .inventory
+            .iter()
+            .find(|m| m.id == id)
+            .map(|m| m.quantity);
+        let in_batches = self.units_in_batches(|b| b.medicine_id == id);
+        match in_stock {
+            None => return Err(PharmacyError::MedicineNotFound),
+            Some(units) if units + in_batches > 0 => {
+                return Err(PharmacyError::InUse {
+                    reason: format!("{} units of this medicine are in stock", units + in_batches),
+                });
+            }
+            _ => {}
+        }
+        self.ensure_no_open_orders(|o| o.lines.iter().any(|l| l.medicine_id == id))?;
+        self.ensure_no_pending_proposals(|p| p.lines.iter().any(|l| l.medicine_id == id))?;
+
+        let settings: Vec<u32> = self
+            .reorder_settings
+            .iter()
+            .filter(|s| s.medicine_id == id)
+            .map(|s| s.id)
+            .collect();
+        for setting_id in settings {
+            self.delete_reorder_setting(user, setting_id)?;
+        }
+        let levels: Vec<u32> = self
+            .par_levels
+            .iter()
+            .filter(|p| p.medicine_id == id)
+            .map(|p| p.id)
+            .collect();
+        for level_id in levels {
+            self.delete_par_level(user, level_id)?;
+        }
+        self.set_medicine_archived(user, id, true)
+    }
+
+    pub fn restore_medicine(&mut self, user: &str, id: u32) -> Result<(), PharmacyError> {
+        self.set_medicine_archived(user, id, false)
+    }
+
+    fn set_medicine_archived(
+        &mut self,
+        user: &str,
+        id: u32,
+        archived: bool,
+    ) -> Result<(), PharmacyError> {
+        let medicine = self
+            .inventory
+            .iter_mut()
+            .find(|m| m.id == id)
+            .ok_or(PharmacyError::MedicineNotFound)?;
+        if medicine.archived_at.is_some() == archived {
+            return Ok(());
+        }
+        let before = medicine.clone();
+        medicine.archived_at = archived.then(Local::now);
+        let after = medicine.clone();
+        self.record_audit(
+            user,
+            archive_action(archived),
+            AuditEntity::Medicine,
+            id,
+            Some(&before),
+            Some(&after),
+        );
+        Ok(())
+    }
+
+    /// Archives a warehouse once it is empty. Returns the new version.
+    pub fn archive_warehouse(
+        &mut self,
+        user: &str,
+        id: u32,
+        expected_version: Option<u32>,
+    ) -> Result<u32, PharmacyError> {
+        let units = self.units_in_batches(|b| b.warehouse_id == id);
+        if units > 0 && self.warehouses.iter().any(|w| w.id == id) {
+            return Err(PharmacyError::InUse {
+                reason: format!("the warehouse still holds {} units", units),
+            });
+        }
+        self.ensure_no_open_orders(|o| o.warehouse_id == id)?;
+        self.ensure_no_pending_proposals(|p| p.from_warehouse_id == id || p.to_warehouse_id == id)?;
+        self.set_warehouse_archived(user, id, expected_version, true)
+    }
+
+    pub fn restore_warehouse(
+        &mut self,
+        user: &str,
+        id: u32,
+        expected_version: Option<u32>,
+    ) -> Result<u32, PharmacyError> {
+        self.set_warehouse_archived(user, id, expected_version, false)
+    }
+
+    fn set_warehouse_archived(
+        &mut self,
+        user: &str,
+        id: u32,
+        expected_version: Option<u32>,
+        archived: bool,
+    ) -> Result<u32, PharmacyError> {
+        let warehouse = self
+            .warehouses
+            .iter_mut()
+            .find(|w| w.id == id)
+            .ok_or(PharmacyError::WarehouseNotFound)?;
+        if let Some(expected) = expected_version
+            && expected != warehouse.version
+        {
+            return Err(PharmacyError::VersionMismatch {
+                current: warehouse.version,
+            });
+        }
+        if warehouse.archived_at.is_some() == archived {
+            return Ok(warehouse.version);
+        }
+        let before = warehouse.clone();
+        warehouse.archived_at = archived.then(Local::now);
+        warehouse.version += 1;
+        let after = warehouse.clone();
+        self.record_audit(
+            user,
+            archive_action(archived),
+            AuditEntity::Warehouse,
+            id,
+            Some(&before),
+            Some(&after),
+        );
+        Ok(after.version)
+    }
+
+    /// Archives a supplier once none of its goods are left in stock. Returns the new version.
+    pub fn archive_supplier(
+        &mut self,
+        user: &str,
+        id: u32,
+        expected_version: Option<u32>,
+    ) -> Result<u32, PharmacyError> {
+        let units = self.units_in_batches(|b| b.supplier_id == Some(id));
+        if units > 0 && self.suppliers.iter().any(|s| s.id == id) {
+            return Err(PharmacyError::InUse {
+                reason: format!("{} units from this supplier are in stock", units),
+            });
+        }
+        self.ensure_no_open_orders(|o| o.supplier_id == id)?;
+        self.set_supplier_archived(user, id, expected_version, true)
+    }
+
+    pub fn restore_supplier(
+        &mut self,
+        user: &str,
+        id: u32,
+        expected_version: Option<u32>,
+    ) -> Result<u32, PharmacyError> {
+        self.set_supplier_archived(user, id, expected_version, false)
+    }
+
+    fn set_supplier_archived(
+        &mut self,
+        user: &str,
+        id: u32,
+        expected_version: Option<u32>,
+        archived: bool,
+    ) -> Result<u32, PharmacyError> {
+        let supplier = self
+            .suppliers
+            .iter_mut()
+            .find(|s| s.id == id)
+            .ok_or(PharmacyError::SupplierNotFound)?;
+        if let Some(expected) = expected_version
+            && expected != supplier.version
+        {
+            return Err(PharmacyError::VersionMismatch {
+                current: supplier.version,
+            });
+        }
+        if supplier.archived_at.is_some() == archived {
+            return Ok(supplier.version);
+        }
+        let before = supplier.clone();
+        supplier.archived_at = archived.then(Local::now);
+        supplier.version += 1;
+        let after = supplier.clone();
+        self.record_audit(
+            user,
+            archive_action(archived),
+            AuditEntity::Supplier,
+            id,
+            Some(&before),
+            Some(&after),
+        );
+        Ok(after.version)
+    }
+
+    fn ensure_no_open_orders(
+        &self,
+        matches: impl Fn(&PurchaseOrder) -> bool,
+    ) -> Result<(), PharmacyError> {
+        let open = self
+            .purchase_orders
+            .iter()
+            .filter(|o| o.status.is_open() && matches(o))
+            .count();
+        if open > 0 {
+            return Err(PharmacyError::InUse {
+                reason: format!("{} purchase orders are still open", open),
+            });
+        }
+        Ok(())
+    }
+
+    fn ensure_no_pending_proposals(
+        &self,
+        matches: impl Fn(&TransferProposal) -> bool,
+    ) -> Result<(), PharmacyError> {
+        let pending = self
+            .transfer_proposals
+            .iter()
+            .filter(|p| p.status == ProposalStatus::Pending && matches(p))
+            .count();
+        if pending > 0 {
+            return Err(PharmacyError::InUse {
+                reason: format!("{} transfer proposals are still pending", pending),
+            });
+        }
+        Ok(())
+    }
+
+    fn units_in_batches(&self, matches: impl Fn(&StockBatch) -> bool) -> u32 {
+        self.stock_batches
+            .iter()
+            .filter(|b| matches(b))
+            .map(|b| b.quantity)
+            .sum()
+    }
+}
+
+fn archive_action(archived: bool) -> AuditAction {
+    if archived {
+        AuditAction::Archive
+    } else {
+        AuditAction::Restore
+    }
+}
@@ -0,0 +1,248 @@
+use chrono::{DateTime, Local, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+
+use crate::error::PharmacyError;
+use crate::models::Pharmacy;
+use crate::sequence::Sequence;
+
+// prev_hash of the very first entry in the chain
+const GENESIS_HASH: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub enum AuditEntity {
+    Medicine,
+    Warehouse,
+    Supplier,
+    StockBatch,
+    ImportLog,
+    ExportLog,
+    TransferLog,
+    ReorderSetting,
+    PurchaseOrder,
+    ParLevel,
+    TransferProposal,
+    EInvoice,
+    PriceList,
+    Promotion,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub enum AuditAction {
+    Create,
+    Update,
+    Delete,
+    Archive,
+    Restore,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct AuditEntry {
+    pub id: u32,
+    pub timestamp: DateTime<Local>,
+    pub user: String,
+    pub action: AuditAction,
+    pub entity: AuditEntity,
+    pub entity_id: u32,
+    pub before: Option<Value>,
+    pub after: Option<Value>,
+    pub prev_hash: String,
+    pub hash: String,
+}
+
+impl AuditEntry {
+    // Hash of every field except `hash` itself, chained to the previous entry.
+    // The timestamp is hashed in UTC, so the chain survives a change of the
+    // server's time zone or daylight saving offset.
+    fn compute_hash(&self) -> String {
+        self.hash_with_timestamp(self.timestamp.with_timezone(&Utc).to_rfc3339())
+    }
+
+    // Before schema version 5 the timestamp was hashed in the server's offset
+    fn compute_local_hash(&self) -> String {
+        self.hash_with_timestamp(self.timestamp.to_rfc3339())
+    }
+
+    fn hash_with_timestamp(&self, timestamp: String) -> String {
+        let payload = serde_json::json!({
+            "id": self.id,
+            "timestamp": timestamp,
+            "user": self.user,
+            "action": self.action,
+            "entity": self.entity,
+            "entity_id": self.entity_id,
+            "before": self.before,
+            "after": self.after,
+        });
+
+        let digest = Sha256::new()
+            .chain_update(self.prev_hash.as_bytes())
+            .chain_update(payload.to_string().as_bytes())
+            .finalize();
+        format!("{:x}", digest)
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct AuditFilter {
+    pub entity: Option<AuditEntity>,
+    pub entity_id: Option<u32>,
+    pub user: Option<String>,
+    pub from: Option<DateTime<Local>>,
+    pub to: Option<DateTime<Local>>,
+}
+
+impl Pharmacy {
+    pub fn record_audit<T: Serialize>(
+        &mut self,
+        user: &str,
+        action: AuditAction,
+        entity: AuditEntity,
+        entity_id: u32,
+        before: Option<&T>,
+        after: Option<&T>,
+    ) {
+        let prev_hash = self
+            .audit_log
+            .last()
+            .map(|e| e.hash.clone())
+            .unwrap_or_else(|| GENESIS_HASH.to_string());
+
+        let mut entry = AuditEntry {
+            id: self.next_id(Sequence::AuditLog),
+            timestamp: Local::now(),
+            user: user.to_string(),
+            action,
+            entity,
+            entity_id,
+            before: before.and_then(|v| serde_json::to_value(v).ok()),
+            after: after.and_then(|v| serde_json::to_value(v).ok()),
+            prev_hash,
+            hash: String::new(),
+        };
+        entry.hash = entry.compute_hash();
+        self.audit_log.push(entry);
+    }
+
+    pub fn query_audit(&self, filter: &AuditFilter) -> Vec<AuditEntry> {
+        self.audit_log
+            .iter()
+            .filter(|e| filter.entity.is_none_or(|entity| e.entity == entity))
+            .filter(|e| filter.entity_id.is_none_or(|id| e.entity_id == id))
+            .filter(|e| filter.user.as_deref().is_none_or(|user| e.user == user))
+            .filter(|e| filter.from.is_none_or(|from| e.timestamp >= from))
+            .filter(|e| filter.to.is_none_or(|to| e.timestamp <= to))
+            .cloned()
+            .collect()
+    }
+
+    /// Walks the hash chain and returns the id of the first entry that was tampered with.
+    pub fn verify_audit_chain(&self) -> Result<(), PharmacyError> {
+        let mut prev_hash = GENESIS_HASH;
+        for entry in &self.audit_log {
+            if entry.prev_hash != prev_hash || entry.hash != entry.compute_hash() {
+                return Err(PharmacyError::AuditChainBroken { entry_id: entry.id });
+            }
+            prev_hash = &entry.hash;
+        }
+        Ok(())
+    }
+
+    /// Re-hashes a chain written before schema version 5 with UTC timestamps.
+    /// The old hashes are checked first; a chain that does not verify under them
+    /// is left untouched so `verify_audit_chain` still reports it. Returns the
+    /// number of entries re-hashed.
+    pub fn rehash_audit_chain(&mut self) -> Result<usize, PharmacyError> {
+        let mut prev_hash = GENESIS_HASH;
+        for entry in &self.audit_log {
+            if entry.prev_hash != prev_hash || entry.hash != entry.compute_local_hash() {
+                return Err(PharmacyError::AuditChainBroken { entry_id: entry.id });
+            }
+            prev_hash = &entry.hash;
+        }
+
+        let mut prev_hash = GENESIS_HASH.to_string();
+        for entry in &mut self.audit_log {
+            entry.prev_hash = prev_hash;
+            entry.hash = entry.compute_hash();
+            prev_hash = entry.hash.clone();
+        }
+        Ok(self.audit_log.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::FixedOffset;
+
+    fn pharmacy_with_history() -> Pharmacy {
+        let mut pharmacy = Pharmacy::new();
+        pharmacy.add_medicine("lan", "Paracetamol".to_string(), None, 1000.0, 10, None);
+        pharmacy.add_medicine("lan", "Ibuprofen".to_string(), None, 2000.0, 5, None);
+        pharmacy
+    }
+
+    // What a server in another offset sees: the same instants, written differently
+    fn shift_offsets(pharmacy: &mut Pharmacy, offset: FixedOffset) {
+        for entry in &mut pharmacy.audit_log {
+            entry.timestamp =
+                DateTime::from_naive_utc_and_offset(entry.timestamp.naive_utc(), offset);
+        }
+    }
+
+    // The chain as a build before schema version 5 running at +07:00 wrote it
+    fn hash_in_local_time(pharmacy: &mut Pharmacy) {
+        shift_offsets(pharmacy, FixedOffset::east_opt(7 * 3600).unwrap());
+        let mut prev_hash = GENESIS_HASH.to_string();
+        for entry in &mut pharmacy.audit_log {
+            entry.prev_hash = prev_hash;
+            entry.hash = entry.compute_local_hash();
+            prev_hash = entry.hash.clone();
+        }
+    }
+
+    #[test]
+    fn chain_verifies_after_an_offset_change() {
+        let mut pharmacy = pharmacy_with_history();
+        let before = pharmacy.audit_log[0].timestamp.to_rfc3339();
+        for hours in [-3, 5, 14] {
+            shift_offsets(&mut pharmacy, FixedOffset::east_opt(hours * 3600).unwrap());
+            assert_eq!(pharmacy.verify_audit_chain(), Ok(()), "at {:+}h", hours);
+        }
+        assert_ne!(pharmacy.audit_log[0].timestamp.to_rfc3339(), before);
+    }
+
+    #[test]
+    fn rehashing_refuses_a_chain_that_was_tampered_with() {
+        let mut pharmacy = pharmacy_with_history();
+        hash_in_local_time(&mut pharmacy);
+        pharmacy.audit_log[1].user = "mallory".to_string();
+        assert_eq!(
+            pharmacy.rehash_audit_chain(),
+            Err(PharmacyError::AuditChainBroken { entry_id: 2 })
+        );
+    }
+
+    #[test]
+    fn tampered_entries_break_the_chain() {
+        let mut pharmacy = pharmacy_with_history();
+        pharmacy.audit_log[0].user = "mallory".to_string();
+        assert_eq!(
+            pharmacy.verify_audit_chain(),
+            Err(PharmacyError::AuditChainBroken { entry_id: 1 })
+        );
+    }
+
+    #[test]
+    fn chains_hashed_in_local_time_are_rehashed_in_utc() {
+        let mut pharmacy = pharmacy_with_history();
+        hash_in_local_time(&mut pharmacy);
+        let legacy_hash = pharmacy.audit_log[1].hash.clone();
+
+        assert_eq!(pharmacy.rehash_audit_chain(), Ok(2));
+        assert_ne!(pharmacy.audit_log[1].hash, legacy_hash);
+        assert_eq!(pharmacy.verify_audit_chain(), Ok(()));
+    }
+}
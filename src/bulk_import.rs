@@ -0,0 +1,564 @@
+use calamine::{Data, Reader, Xlsx, XlsxError, open_workbook_from_rs};
+use chrono::{DateTime, Local, NaiveDate, NaiveDateTime, NaiveTime};
+use chrono_tz::Tz;
+use clap::ValueEnum;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
+use std::io::Cursor;
+
+use crate::error::PharmacyError;
+use crate::models::Pharmacy;
+use crate::tax::VatRate;
+use crate::validation::{FieldError, Validate, Validator};
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum ImportKind {
+    Medicines,
+    Batches,
+    Suppliers,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum SheetFormat {
+    Csv,
+    Xlsx,
+}
+
+impl SheetFormat {
+    /// XLSX files are zip archives; anything else is read as CSV.
+    pub fn detect(data: &[u8]) -> Self {
+        if data.starts_with(b"PK\x03\x04") {
+            SheetFormat::Xlsx
+        } else {
+            SheetFormat::Csv
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+enum FieldType {
+    Text,
+    OptionalText,
+    Integer,
+    OptionalInteger,
+    Number,
+    OptionalNumber,
+    Date,
+}
+
+impl ImportKind {
+    /// Fields of the request payload each row becomes, in the order shown to users.
+    fn fields(self) -> &'static [(&'static str, FieldType)] {
+        use FieldType::*;
+        match self {
+            ImportKind::Medicines => &[
+                ("name", Text),
+                ("category", OptionalText),
+                ("price", Number),
+                ("quantity", Integer),
+                ("vat_rate", OptionalInteger),
+            ],
+            ImportKind::Batches => &[
+                ("medicine_id", Integer),
+                ("medicine_name", Text),
+                ("warehouse_id", Integer),
+                ("supplier_id", OptionalInteger),
+                ("quantity", Integer),
+                ("unit_cost", Number),
+                ("selling_price", OptionalNumber),
+                ("expiry_date", Date),
+            ],
+            ImportKind::Suppliers => &[
+                ("name", Text),
+                ("contact", Text),
+                ("phone", Text),
+                ("address", Text),
+            ],
+        }
+    }
+}
+
+/// A request payload that can also be created from a spreadsheet row.
+pub trait BulkRow: DeserializeOwned + Validate {
+    /// Applies the row the way its API endpoint would and returns the new record's id.
+    fn apply(self, pharmacy: &mut Pharmacy, user: &str) -> Result<u32, PharmacyError>;
+}
+
+/// Cells as text, with the spreadsheet row number of each data row.
+#[derive(Debug)]
+pub struct Sheet {
+    pub headers: Vec<String>,
+    pub rows: Vec<(usize, Vec<String>)>,
+}
+
+impl Sheet {
+    pub fn read(data: &[u8], format: SheetFormat, sheet: Option<&str>) -> Result<Sheet, String> {
+        match format {
+            SheetFormat::Csv => read_csv(data),
+            SheetFormat::Xlsx => read_xlsx(data, sheet),
+        }
+    }
+}
+
+fn read_csv(data: &[u8]) -> Result<Sheet, String> {
+    let mut reader = csv::ReaderBuilder::new()
+        .flexible(true)
+        .trim(csv::Trim::All)
+        .from_reader(data);
+    let headers = reader
+        .headers()
+        .map_err(|e| e.to_string())?
+        .iter()
+        .map(str::to_string)
+        .collect();
+    let mut rows = Vec::new();
+    for (index, record) in reader.records().enumerate() {
+        let record = record.map_err(|e| e.to_string())?;
+        let line = record.position().map_or(index + 2, |p| p.line() as usize);
+        rows.push((line, record.iter().map(str::to_string).collect()));
+    }
+    Ok(Sheet { headers, rows })
+}
+
+fn read_xlsx(data: &[u8], sheet: Option<&str>) -> Result<Sheet, String> {
+    let mut workbook: Xlsx<_> =
+        open_workbook_from_rs(Cursor::new(data)).map_err(|e: XlsxError| e.to_string())?;
+    let name = match sheet {
+        Some(name) => name.to_string(),
+        None => workbook
+            .sheet_names()
+            .first()
+            .cloned()
+            .ok_or("the workbook has no sheets")?,
+    };
+    let range = workbook
+        .worksheet_range(&name)
+        .map_err(|e| format!("sheet {:?}: {}", name, e))?;
+    let first_row = range.start().map_or(0, |(row, _)| row as usize);
+
+    let mut rows = range.rows().enumerate().map(|(index, cells)| {
+        (
+            first_row + index + 1,
+            cells.iter().map(cell_text).collect::<Vec<_>>(),
+        )
+    });
+    let headers = rows.next().map(|(_, cells)| cells).unwrap_or_default();
+    Ok(Sheet {
+        headers,
+        rows: rows.collect(),
+    })
+}
+
+fn cell_text(cell: &Data) -> String {
+    match cell {
+        Data::Empty => String::new(),
+        // Whole numbers come back as floats; write them without a fraction
+        Data::Float(f) if f.fract() == 0.0 && f.abs() < 1e15 => format!("{}", *f as i64),
+        Data::DateTime(dt) => match dt.as_datetime() {
+            Some(at) if at.time() == NaiveTime::MIN => at.date().format("%Y-%m-%d").to_string(),
+            Some(at) => at.format("%Y-%m-%dT%H:%M:%S").to_string(),
+            None => dt.to_string(),
+        },
+        other => other.to_string().trim().to_string(),
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct RowResult {
+    pub row: usize,
+    /// Id the record gets, or would get in a dry run.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<u32>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub errors: Vec<FieldError>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BulkImportReport {
+    pub kind: ImportKind,
+    pub dry_run: bool,
+    pub committed: bool,
+    pub valid: usize,
+    pub invalid: usize,
+    pub rows: Vec<RowResult>,
+}
+
+/// Validates and applies every row of `sheet` on a copy of the data, each row
+/// seeing the ones before it. Unless `dry_run` is set, the copy replaces the
+/// data when every row succeeded; otherwise nothing changes and all row errors
+/// are returned, each field prefixed with its row number.
+///
+/// `mapping` names the column of each field; unmapped fields are read from the
+/// column with the field's own name, ignoring case.
+pub fn bulk_import<T: BulkRow>(
+    pharmacy: &mut Pharmacy,
+    user: &str,
+    kind: ImportKind,
+    sheet: &Sheet,
+    mapping: &BTreeMap<String, String>,
+    tz: Tz,
+    dry_run: bool,
+) -> Result<BulkImportReport, PharmacyError> {
+    let columns = resolve_columns(kind, &sheet.headers, mapping)?;
+
+    let mut working = pharmacy.clone();
+    let mut results = Vec::new();
+    for (row, cells) in &sheet.rows {
+        if cells.iter().all(|c| c.trim().is_empty()) {
+            continue;
+        }
+        let outcome = row_payload(kind, &columns, cells, tz)
+            .and_then(|payload| {
+                serde_json::from_value::<T>(payload).map_err(|e| {
+                    vec![FieldError {
+                        field: "row".to_string(),
+                        code: "INVALID_ROW",
+                        message: e.to_string(),
+                    }]
+                })
+            })
+            .and_then(|request| {
+                request.validate(&working).map_err(field_errors)?;
+                request.apply(&mut working, user).map_err(field_errors)
+            });
+        results.push(match outcome {
+            Ok(id) => RowResult {
+                row: *row,
+                id: Some(id),
+                errors: Vec::new(),
+            },
+            Err(errors) => RowResult {
+                row: *row,
+                id: None,
+                errors,
+            },
+        });
+    }
+
+    let invalid = results.iter().filter(|r| !r.errors.is_empty()).count();
+    if invalid > 0 && !dry_run {
+        return Err(PharmacyError::Validation(
+            results
+                .into_iter()
+                .flat_map(|r| {
+                    let row = r.row;
+                    r.errors.into_iter().map(move |e| FieldError {
+                        field: format!("row {}: {}", row, e.field),
+                        ..e
+                    })
+                })
+                .collect(),
+        ));
+    }
+    let committed = !dry_run;
+    if committed {
+        *pharmacy = working;
+    }
+    Ok(BulkImportReport {
+        kind,
+        dry_run,
+        committed,
+        valid: results.len() - invalid,
+        invalid,
+        rows: results,
+    })
+}
+
+/// Validates and applies a spreadsheet of `kind` rows; see [`bulk_import`].
+pub fn import_sheet(
+    pharmacy: &mut Pharmacy,
+    user: &str,
+    kind: ImportKind,
+    sheet: &Sheet,
+    mapping: &BTreeMap<String, String>,
+    tz: Tz,
+    dry_run: bool,
+) -> Result<BulkImportReport, PharmacyError> {
+    match kind {
+        ImportKind::Medicines => {
+            bulk_import::<AddMedicineRequest>(pharmacy, user, kind, sheet, mapping, tz, dry_run)
+        }
+        ImportKind::Batches => {
+            bulk_import::<ImportBatchRequest>(pharmacy, user, kind, sheet, mapping, tz, dry_run)
+        }
+        ImportKind::Suppliers => {
+            bulk_import::<CreateSupplierRequest>(pharmacy, user, kind, sheet, mapping, tz, dry_run)
+        }
+    }
+}
+
+// Column index of each field, or `None` when the sheet does not have it
+fn resolve_columns(
+    kind: ImportKind,
+    headers: &[String],
+    mapping: &BTreeMap<String, String>,
+) -> Result<Vec<Option<usize>>, PharmacyError> {
+    let fields = kind.fields();
+    let find = |name: &str| {
+        headers
+            .iter()
+            .position(|h| h.trim().eq_ignore_ascii_case(name.trim()))
+    };
+
+    let mut errors = Vec::new();
+    for field in mapping.keys() {
+        if !fields.iter().any(|(name, _)| name == field) {
+            errors.push(FieldError {
+                field: format!("map.{}", field),
+                code: "UNKNOWN_FIELD",
+                message: format!(
+                    "is not a {} field; expected one of {}",
+                    format!("{:?}", kind).to_lowercase(),
+                    fields
+                        .iter()
+                        .map(|(name, _)| *name)
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                ),
+            });
+        }
+    }
+
+    let mut columns = Vec::new();
+    for (name, field_type) in fields {
+        let column = match mapping.get(*name) {
+            Some(header) => {
+                let column = find(header);
+                if column.is_none() {
+                    errors.push(FieldError {
+                        field: format!("map.{}", name),
+                        code: "UNKNOWN_COLUMN",
+                        message: format!("the sheet has no column {:?}", header),
+                    });
+                }
+                column
+            }
+            None => find(name),
+        };
+        let optional = matches!(
+            field_type,
+            FieldType::OptionalText | FieldType::OptionalInteger | FieldType::OptionalNumber
+        );
+        if column.is_none() && !optional && !mapping.contains_key(*name) {
+            errors.push(FieldError {
+                field: (*name).to_string(),
+                code: "MISSING_COLUMN",
+                message: "no column for this field; add one or map it".to_string(),
+            });
+        }
+        columns.push(column);
+    }
+
+    if errors.is_empty() {
+        Ok(columns)
+    } else {
+        Err(PharmacyError::Validation(errors))
+    }
+}
+
+fn row_payload(
+    kind: ImportKind,
+    columns: &[Option<usize>],
+    cells: &[String],
+    tz: Tz,
+) -> Result<Value, Vec<FieldError>> {
+    let mut payload = Map::new();
+    let mut errors = Vec::new();
+    for ((name, field_type), column) in kind.fields().iter().zip(columns) {
+        let text = column
+            .and_then(|c| cells.get(c))
+            .map(|c| c.trim())
+            .unwrap_or("");
+        let error = |code, message: &str| FieldError {
+            field: (*name).to_string(),
+            code,
+            message: message.to_string(),
+        };
+        let value = match field_type {
+            FieldType::Text => Value::from(text),
+            _ if text.is_empty() => {
+                if matches!(
+                    field_type,
+                    FieldType::Integer | FieldType::Number | FieldType::Date
+                ) {
+                    errors.push(error("REQUIRED", "must not be empty"));
+                }
+                continue;
+            }
+            FieldType::OptionalText => Value::from(text),
+            FieldType::Integer | FieldType::OptionalInteger => match text.parse::<u32>() {
+                Ok(n) => Value::from(n),
+                Err(_) => {
+                    errors.push(error("INVALID_NUMBER", "must be a whole number"));
+                    continue;
+                }
+            },
+            FieldType::Number | FieldType::OptionalNumber => match text.parse::<f64>() {
+                Ok(n) if n.is_finite() => Value::from(n),
+                _ => {
+                    errors.push(error("INVALID_NUMBER", "must be a number"));
+                    continue;
+                }
+            },
+            FieldType::Date => Value::from(expiry_text(text, tz)),
+        };
+        payload.insert((*name).to_string(), value);
+    }
+    if errors.is_empty() {
+        Ok(Value::Object(payload))
+    } else {
+        Err(errors)
+    }
+}
+
+// Spreadsheets usually hold plain dates; they mean midnight in the pharmacy's
+// time zone. Anything unparseable is passed on for the validator to report.
+fn expiry_text(text: &str, tz: Tz) -> String {
+    if DateTime::parse_from_rfc3339(text).is_ok() {
+        return text.to_string();
+    }
+    let local = NaiveDate::parse_from_str(text, "%Y-%m-%d")
+        .map(|d| d.and_time(NaiveTime::MIN))
+        .or_else(|_| NaiveDateTime::parse_from_str(text, "%Y-%m-%dT%H:%M:%S"))
+        .ok()
+        .and_then(|at| at.and_local_timezone(tz).earliest());
+    match local {
+        Some(at) => at.to_rfc3339(),
+        None => text.to_string(),
+    }
+}
+
+fn field_errors(error: PharmacyError) -> Vec<FieldError> {
+    match error {
+        PharmacyError::Validation(errors) => errors,
+        other => vec![FieldError {
+            field: "row".to_string(),
+            code: other.code(),
+            message: other.to_string(),
+        }],
+    }
+}
+
+#[derive(Deserialize)]
+pub struct AddMedicineRequest {
+    name: String,
+    #[serde(default)]
+    category: Option<String>,
+    price: f64,
+    quantity: u32,
+    #[serde(default)]
+    vat_rate: Option<VatRate>,
+}
+
+impl Validate for AddMedicineRequest {
+    fn validate(&self, _pharmacy: &Pharmacy) -> Result<(), PharmacyError> {
+        let mut validator = Validator::new();
+        validator
+            .not_blank("name", &self.name)
+            .price("price", self.price);
+        if let Some(category) = &self.category {
+            validator.not_blank("category", category);
+        }
+        validator.finish()
+    }
+}
+
+impl BulkRow for AddMedicineRequest {
+    fn apply(self, pharmacy: &mut Pharmacy, user: &str) -> Result<u32, PharmacyError> {
+        Ok(pharmacy.add_medicine(
+            user,
+            self.name,
+            self.category.map(|c| c.trim().to_string()),
+            self.price,
+            self.quantity,
+            self.vat_rate,
+        ))
+    }
+}
+
+#[derive(Deserialize)]
+pub struct ImportBatchRequest {
+    medicine_id: u32,
+    medicine_name: String,
+    warehouse_id: u32,
+    supplier_id: Option<u32>,
+    quantity: u32,
+    #[serde(alias = "price")]
+    unit_cost: f64,
+    // Clients that predate the split send one price; it is used for both
+    selling_price: Option<f64>,
+    expiry_date: String, // ISO 8601 format
+}
+
+impl Validate for ImportBatchRequest {
+    fn validate(&self, pharmacy: &Pharmacy) -> Result<(), PharmacyError> {
+        let name_matches = pharmacy
+            .known_medicine_name(self.medicine_id)
+            .is_none_or(|known| known.trim().eq_ignore_ascii_case(self.medicine_name.trim()));
+
+        Validator::new()
+            .not_blank("medicine_name", &self.medicine_name)
+            .check(
+                "medicine_name",
+                name_matches,
+                "MEDICINE_MISMATCH",
+                "does not match the name on record for medicine_id",
+            )
+            .positive("quantity", self.quantity)
+            .price("unit_cost", self.unit_cost)
+            .price(
+                "selling_price",
+                self.selling_price.unwrap_or(self.unit_cost),
+            )
+            .future_date("expiry_date", &self.expiry_date)
+            .finish()
+    }
+}
+
+impl BulkRow for ImportBatchRequest {
+    fn apply(self, pharmacy: &mut Pharmacy, user: &str) -> Result<u32, PharmacyError> {
+        // Parse expiry date
+        let expiry_date = DateTime::parse_from_rfc3339(&self.expiry_date)
+            .map_err(|_| PharmacyError::InvalidDate)?
+            .with_timezone(&Local);
+
+        pharmacy.import_batch(
+            user,
+            self.medicine_id,
+            self.medicine_name,
+            self.warehouse_id,
+            self.supplier_id,
+            self.quantity,
+            self.unit_cost,
+            self.selling_price.unwrap_or(self.unit_cost),
+            expiry_date,
+        )
+    }
+}
+
+#[derive(Deserialize)]
+pub struct CreateSupplierRequest {
+    name: String,
+    contact: String,
+    phone: String,
+    address: String,
+}
+
+impl Validate for CreateSupplierRequest {
+    fn validate(&self, _pharmacy: &Pharmacy) -> Result<(), PharmacyError> {
+        Validator::new()
+            .not_blank("name", &self.name)
+            .phone("phone", &self.phone)
+            .finish()
+    }
+}
+
+impl BulkRow for CreateSupplierRequest {
+    fn apply(self, pharmacy: &mut Pharmacy, user: &str) -> Result<u32, PharmacyError> {
+        Ok(pharmacy.add_supplier(user, self.name, self.contact, self.phone, self.address))
+    }
+}
@@ -0,0 +1,500 @@
+use chrono::Local;
+use clap::{Parser, Subcommand, ValueEnum};
+use serde::Serialize;
+use std::{
+    collections::BTreeMap,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use crate::bulk_import::{BulkImportReport, ImportKind, Sheet, SheetFormat, import_sheet};
+use crate::config::{Config, ConfigOverrides};
+use crate::error::PharmacyError;
+use crate::models::Pharmacy;
+use crate::state::{read_snapshot, write_snapshot};
+
+#[derive(Parser)]
+#[command(about = "Pharmacy management server and maintenance tools")]
+pub struct Cli {
+    #[command(flatten)]
+    pub overrides: ConfigOverrides,
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+/// Everything except `serve` works on the data file directly; stop the server first
+/// when changing data, or it will overwrite the result on its next save.
+#[derive(Subcommand)]
+pub enum Command {
+    /// Run the web server (the default)
+    Serve,
+    /// Write the data as JSON to a file, or to stdout
+    Export {
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Replace the data with an exported JSON file
+    Import {
+        input: PathBuf,
+        /// Overwrite data that already has records
+        #[arg(long)]
+        force: bool,
+    },
+    /// Copy the data file into a timestamped backup
+    Backup {
+        #[arg(long, default_value = "backups")]
+        dir: PathBuf,
+    },
+    /// Replace the data with a backup; the current data is backed up first
+    Restore {
+        backup: PathBuf,
+        #[arg(long, default_value = "backups")]
+        dir: PathBuf,
+    },
+    /// Upgrade the data file to the format of this build
+    Migrate {
+        /// Report what would change without writing
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Check data integrity; exits with status 1 when errors are found
+    Verify {
+        /// Apply the automatic fixes before reporting
+        #[arg(long)]
+        fix: bool,
+    },
+    /// Print a stock summary with expiry and low-stock alerts
+    Report {
+        #[arg(long, value_enum, default_value_t = ReportFormat::Text)]
+        format: ReportFormat,
+    },
+    /// Add medicines, batches or suppliers from a CSV or XLSX file; nothing is
+    /// written unless every row is valid
+    BulkImport {
+        #[arg(value_enum)]
+        kind: ImportKind,
+        file: PathBuf,
+        /// Column holding a field, as FIELD=COLUMN; repeat for each field
+        #[arg(long = "map", value_parser = parse_mapping)]
+        mapping: Vec<(String, String)>,
+        /// Worksheet of an XLSX file (default: the first)
+        #[arg(long)]
+        sheet: Option<String>,
+        /// Detected from the file contents when omitted
+        #[arg(long, value_enum)]
+        format: Option<SheetFormat>,
+        /// Validate every row and show the result without writing
+        #[arg(long)]
+        dry_run: bool,
+        /// Name recorded in the audit log
+        #[arg(long, default_value = "bulk-import")]
+        user: String,
+    },
+}
+
+fn parse_mapping(value: &str) -> Result<(String, String), String> {
+    value
+        .split_once('=')
+        .map(|(field, column)| (field.trim().to_string(), column.trim().to_string()))
+        .ok_or_else(|| format!("{:?} is not FIELD=COLUMN", value))
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum ReportFormat {
+    Text,
+    Json,
+}
+
+/// Runs an offline command. `Ok(false)` means it ran but found problems.
+pub fn run(command: Command, config: &Config) -> Result<bool, String> {
+    match command {
+        Command::Serve => unreachable!("serve is handled by main"),
+        Command::Export { output } => export(config, output.as_deref()),
+        Command::Import { input, force } => import(config, &input, force),
+        Command::Backup { dir } => backup(config, &dir).map(|path| {
+            println!("Backed up to {}", path.display());
+            true
+        }),
+        Command::Restore { backup, dir } => restore(config, &backup, &dir),
+        Command::Migrate { dry_run } => migrate(config, dry_run),
+        Command::Verify { fix } => verify(config, fix),
+        Command::Report { format } => report(config, format),
+        Command::BulkImport {
+            kind,
+            file,
+            mapping,
+            sheet,
+            format,
+            dry_run,
+            user,
+        } => bulk_import(
+            config,
+            kind,
+            &file,
+            mapping.into_iter().collect(),
+            sheet.as_deref(),
+            format,
+            dry_run,
+            &user,
+        ),
+    }
+}
+
+fn export(config: &Config, output: Option<&Path>) -> Result<bool, String> {
+    let pharmacy = read_snapshot(&config.data_file)?;
+    match output {
+        Some(path) => {
+            write_snapshot(path, &pharmacy).map_err(|e| e.to_string())?;
+            eprintln!("Exported to {}", path.display());
+        }
+        None => serde_json::to_writer_pretty(io::stdout().lock(), &pharmacy)
+            .map_err(|e| e.to_string())?,
+    }
+    Ok(true)
+}
+
+fn import(config: &Config, input: &Path, force: bool) -> Result<bool, String> {
+    if !force
+        && let Ok(current) = read_snapshot(&config.data_file)
+        && !is_empty(&current)
+    {
+        return Err(format!(
+            "{} already has data; use `restore` for backups or pass --force",
+            config.data_file.display()
+        ));
+    }
+    replace_data(config, load_incoming(input)?)?;
+    println!("Imported {}", input.display());
+    Ok(true)
+}
+
+fn restore(config: &Config, backup_file: &Path, dir: &Path) -> Result<bool, String> {
+    // Load the backup before touching anything
+    let pharmacy = load_incoming(backup_file)?;
+    if config.data_file.exists() {
+        let saved = backup(config, dir)?;
+        println!("Current data saved to {}", saved.display());
+    }
+    replace_data(config, pharmacy)?;
+    println!("Restored {}", backup_file.display());
+    Ok(true)
+}
+
+// Incoming files are upgraded and their audit chain checked before they replace the data
+fn load_incoming(input: &Path) -> Result<Pharmacy, String> {
+    let mut pharmacy = read_snapshot(input)?;
+    pharmacy.migrate()?;
+    pharmacy
+        .verify_audit_chain()
+        .map_err(|e| format!("{}: {}", input.display(), e))?;
+    Ok(pharmacy)
+}
+
+fn replace_data(config: &Config, pharmacy: Pharmacy) -> Result<(), String> {
+    write_snapshot(&config.data_file, &pharmacy).map_err(|e| e.to_string())
+}
+
+fn backup(config: &Config, dir: &Path) -> Result<PathBuf, String> {
+    // Round-trip through the parser so a corrupt data file is never mistaken for a good backup
+    let pharmacy = read_snapshot(&config.data_file)?;
+    fs::create_dir_all(dir).map_err(|e| format!("{}: {}", dir.display(), e))?;
+    let stem = config
+        .data_file
+        .file_stem()
+        .and_then(|s| s.to_str())
+        .unwrap_or("data");
+    let stamp = Local::now().format("%Y%m%d-%H%M%S");
+    let mut path = dir.join(format!("{}-{}.json", stem, stamp));
+    // Never overwrite an earlier backup taken in the same second
+    let mut n = 1;
+    while path.exists() {
+        path = dir.join(format!("{}-{}-{}.json", stem, stamp, n));
+        n += 1;
+    }
+    write_snapshot(&path, &pharmacy).map_err(|e| e.to_string())?;
+    Ok(path)
+}
+
+fn migrate(config: &Config, dry_run: bool) -> Result<bool, String> {
+    let mut pharmacy = read_snapshot(&config.data_file)?;
+    let report = pharmacy.migrate()?;
+    if report.from == report.to {
+        println!("Already at schema version {}", report.to);
+        return Ok(true);
+    }
+    println!("Schema version {} -> {}", report.from, report.to);
+    for note in &report.notes {
+        println!("  - {}", note);
+    }
+    if !dry_run {
+        write_snapshot(&config.data_file, &pharmacy).map_err(|e| e.to_string())?;
+    }
+    Ok(true)
+}
+
+fn verify(config: &Config, fix: bool) -> Result<bool, String> {
+    let mut pharmacy = read_snapshot(&config.data_file)?;
+    if pharmacy.needs_migration() {
+        println!(
+            "Data uses schema version {}; run `migrate` first",
+            pharmacy.schema_version
+        );
+    }
+    if fix {
+        let fixed = pharmacy.fix_integrity("integrity-fix");
+        if !fixed.is_empty() {
+            write_snapshot(&config.data_file, &pharmacy).map_err(|e| e.to_string())?;
+        }
+        for issue in &fixed {
+            println!("  fixed: {}", issue.message);
+        }
+        println!("{} issue(s) fixed", fixed.len());
+    }
+
+    let report = pharmacy.check_integrity();
+    for issue in &report.issues {
+        println!(
+            "  {:<7} {:<22} {}",
+            format!("{:?}", issue.severity).to_lowercase(),
+            issue.code,
+            issue.message
+        );
+        println!(
+            "          -> {}{}",
+            issue.suggestion,
+            if issue.fix.is_some() {
+                " (fixable with --fix)"
+            } else {
+                ""
+            }
+        );
+    }
+    println!("{} error(s), {} warning(s)", report.errors, report.warnings);
+    Ok(report.ok)
+}
+
+#[allow(clippy::too_many_arguments)]
+fn bulk_import(
+    config: &Config,
+    kind: ImportKind,
+    file: &Path,
+    mapping: BTreeMap<String, String>,
+    sheet: Option<&str>,
+    format: Option<SheetFormat>,
+    dry_run: bool,
+    user: &str,
+) -> Result<bool, String> {
+    let mut pharmacy = read_snapshot(&config.data_file)?;
+    if pharmacy.needs_migration() {
+        return Err(format!(
+            "data uses schema version {}; run `migrate` first",
+            pharmacy.schema_version
+        ));
+    }
+    let data = fs::read(file).map_err(|e| format!("{}: {}", file.display(), e))?;
+    let format = format.unwrap_or_else(|| SheetFormat::detect(&data));
+    let sheet =
+        Sheet::read(&data, format, sheet).map_err(|e| format!("{}: {}", file.display(), e))?;
+
+    match import_sheet(
+        &mut pharmacy,
+        user,
+        kind,
+        &sheet,
+        &mapping,
+        config.tz(),
+        dry_run,
+    ) {
+        Ok(report) => {
+            print_bulk_import(&report);
+            if report.committed {
+                write_snapshot(&config.data_file, &pharmacy).map_err(|e| e.to_string())?;
+                println!("Imported {} row(s)", report.valid);
+            } else {
+                println!("Dry run; nothing was written");
+            }
+            Ok(report.invalid == 0)
+        }
+        Err(PharmacyError::Validation(errors)) => {
+            for error in &errors {
+                println!("  {}: {}", error.field, error.message);
+            }
+            println!("Nothing was imported");
+            Ok(false)
+        }
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+fn print_bulk_import(report: &BulkImportReport) {
+    for row in &report.rows {
+        match row.id {
+            Some(id) => println!("  row {}: ok (id {})", row.row, id),
+            None => {
+                for error in &row.errors {
+                    println!("  row {}: {}: {}", row.row, error.field, error.message);
+                }
+            }
+        }
+    }
+    println!("{} valid, {} invalid", report.valid, report.invalid);
+}
+
+#[derive(Serialize)]
+struct WarehouseStock {
+    warehouse_id: u32,
+    name: String,
+    batches: usize,
+    units: u32,
+    value: f64,
+}
+
+#[derive(Serialize)]
+struct ExpiringBatch {
+    batch_id: u32,
+    medicine_name: String,
+    warehouse_id: u32,
+    quantity: u32,
+    days_left: i64,
+    critical: bool,
+}
+
+#[derive(Serialize)]
+struct LowStock {
+    medicine_id: u32,
+    medicine_name: String,
+    units: u32,
+}
+
+#[derive(Serialize)]
+struct StockReport {
+    generated_at: String,
+    currency: String,
+    warehouses: Vec<WarehouseStock>,
+    expiring: Vec<ExpiringBatch>,
+    low_stock: Vec<LowStock>,
+}
+
+fn report(config: &Config, format: ReportFormat) -> Result<bool, String> {
+    let pharmacy = read_snapshot(&config.data_file)?;
+    let now = Local::now();
+
+    let warehouses = pharmacy
+        .warehouses
+        .iter()
+        .map(|w| {
+            let batches = pharmacy.get_stock_by_warehouse(w.id);
+            WarehouseStock {
+                warehouse_id: w.id,
+                name: w.name.clone(),
+                batches: batches.len(),
+                units: batches.iter().map(|b| b.quantity).sum(),
+                value: batches
+                    .iter()
+                    .map(|b| b.unit_cost * b.quantity as f64)
+                    .sum(),
+            }
+        })
+        .collect();
+
+    let mut expiring: Vec<_> = pharmacy
+        .get_expiring_batches(config.alerts.expiry_days)
+        .into_iter()
+        .map(|b| {
+            let days_left = (b.expiry_date - now).num_days();
+            ExpiringBatch {
+                batch_id: b.id,
+                medicine_name: b.medicine_name,
+                warehouse_id: b.warehouse_id,
+                quantity: b.quantity,
+                days_left,
+                critical: days_left < config.alerts.critical_expiry_days,
+            }
+        })
+        .collect();
+    expiring.sort_by_key(|b| b.days_left);
+
+    let mut units: BTreeMap<u32, (String, u32)> = BTreeMap::new();
+    for batch in &pharmacy.stock_batches {
+        let entry = units
+            .entry(batch.medicine_id)
+            .or_insert_with(|| (batch.medicine_name.clone(), 0));
+        entry.1 += batch.quantity;
+    }
+    let low_stock = units
+        .into_iter()
+        .filter(|(_, (_, units))| *units < config.alerts.low_stock_quantity)
+        .map(|(medicine_id, (medicine_name, units))| LowStock {
+            medicine_id,
+            medicine_name,
+            units,
+        })
+        .collect();
+
+    let report = StockReport {
+        generated_at: now.to_rfc3339(),
+        currency: config.currency.clone(),
+        warehouses,
+        expiring,
+        low_stock,
+    };
+    match format {
+        ReportFormat::Json => {
+            println!(
+                "{}",
+                serde_json::to_string_pretty(&report).map_err(|e| e.to_string())?
+            );
+        }
+        ReportFormat::Text => print_report(&report, config.alerts.expiry_days),
+    }
+    Ok(true)
+}
+
+fn print_report(report: &StockReport, expiry_days: i64) {
+    println!("Stock report {}", report.generated_at);
+    println!();
+    println!(
+        "{:<6} {:<24} {:>8} {:>10} {:>16}",
+        "ID", "Warehouse", "Batches", "Units", "Value"
+    );
+    for w in &report.warehouses {
+        println!(
+            "{:<6} {:<24} {:>8} {:>10} {:>12.0} {}",
+            w.warehouse_id, w.name, w.batches, w.units, w.value, report.currency
+        );
+    }
+
+    println!();
+    println!(
+        "Expiring within {} days: {}",
+        expiry_days,
+        report.expiring.len()
+    );
+    for b in &report.expiring {
+        println!(
+            "  {} batch {} {} ({} units, warehouse {}) in {} days",
+            if b.critical { "!" } else { " " },
+            b.batch_id,
+            b.medicine_name,
+            b.quantity,
+            b.warehouse_id,
+            b.days_left
+        );
+    }
+
+    println!();
+    println!("Low stock: {}", report.low_stock.len());
+    for m in &report.low_stock {
+        println!(
+            "  medicine {} {}: {} units",
+            m.medicine_id, m.medicine_name, m.units
+        );
+    }
+}
+
+fn is_empty(pharmacy: &Pharmacy) -> bool {
+    pharmacy.inventory.is_empty()
+        && pharmacy.warehouses.is_empty()
+        && pharmacy.stock_batches.is_empty()
+        && pharmacy.suppliers.is_empty()
+}
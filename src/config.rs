@@ -0,0 +1,512 @@
+use chrono_tz::Tz;
+use clap::{Args, ValueEnum};
+use serde::{Deserialize, Serialize};
+use std::{fs, io::ErrorKind, net::SocketAddr, path::PathBuf};
+
+use crate::tax::VatRate;
+
+const DEFAULT_CONFIG_FILE: &str = "config.json";
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct AlertThresholds {
+    /// Batches expiring within this many days are listed as expiring soon.
+    pub expiry_days: i64,
+    /// Batches expiring within this many days are highlighted as critical.
+    pub critical_expiry_days: i64,
+    pub low_stock_quantity: u32,
+}
+
+impl Default for AlertThresholds {
+    fn default() -> Self {
+        AlertThresholds {
+            expiry_days: 90,
+            critical_expiry_days: 30,
+            low_stock_quantity: 10,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct ReplenishmentSettings {
+    /// Sales velocity is averaged over this many past days.
+    pub sales_window_days: i64,
+    /// Days between placing a purchase order and receiving the goods.
+    pub lead_time_days: i64,
+    /// Demand forecasts average this many recent weeks.
+    pub moving_average_weeks: usize,
+}
+
+impl Default for ReplenishmentSettings {
+    fn default() -> Self {
+        ReplenishmentSettings {
+            sales_window_days: 30,
+            lead_time_days: 7,
+            moving_average_weeks: 8,
+        }
+    }
+}
+
+/// Clearance markdowns on batches sold from the store close to expiry. The
+/// horizons are the expiry alert thresholds.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct PricingSettings {
+    /// Percent off batches expiring within `alerts.expiry_days`; 0 turns it off.
+    pub clearance_percent: u8,
+    /// Percent off batches expiring within `alerts.critical_expiry_days`.
+    pub critical_clearance_percent: u8,
+}
+
+/// Pharmacy details and fonts for printed documents.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct DocumentSettings {
+    /// Printed in bold at the top of every document.
+    pub pharmacy_name: String,
+    /// Lines under the name, e.g. address, phone and tax code.
+    pub header_lines: Vec<String>,
+    /// Lines at the bottom of every document, e.g. a return policy.
+    pub footer_lines: Vec<String>,
+    /// TrueType fonts embedded in the PDFs; they must cover Vietnamese.
+    /// The DejaVu fonts in `assets_dir` when not set.
+    pub font_file: Option<PathBuf>,
+    pub bold_font_file: Option<PathBuf>,
+}
+
+impl Default for DocumentSettings {
+    fn default() -> Self {
+        DocumentSettings {
+            pharmacy_name: "Pharmacy".to_string(),
+            header_lines: Vec::new(),
+            footer_lines: Vec::new(),
+            font_file: None,
+            bold_font_file: None,
+        }
+    }
+}
+
+/// How sales are taxed.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct TaxSettings {
+    /// VAT rate of products that have none of their own.
+    pub default_vat_rate: VatRate,
+    /// Whether selling prices already include VAT, as shelf prices usually do,
+    /// or VAT is added at the till.
+    pub prices_include_vat: bool,
+}
+
+impl Default for TaxSettings {
+    fn default() -> Self {
+        TaxSettings {
+            default_vat_rate: VatRate::Five,
+            prices_include_vat: true,
+        }
+    }
+}
+
+/// Service that e-invoices are submitted to for a tax authority code.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum EInvoiceProviderKind {
+    /// Accepts every well-formed invoice locally; for testing
+    Mock,
+}
+
+/// Seller details and numbering of e-invoices (hóa đơn điện tử).
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct EInvoiceSettings {
+    /// Registered business name; `documents.pharmacy_name` when blank.
+    pub seller_name: String,
+    pub seller_tax_code: String,
+    pub seller_address: String,
+    pub seller_phone: String,
+    /// Invoice form number (ký hiệu mẫu số), `1` for VAT invoices.
+    pub template: String,
+    /// Invoice series (ký hiệu hóa đơn) registered with the tax authority, e.g. `C26TAA`.
+    pub series: String,
+    /// Payment method printed on invoices, e.g. `TM/CK` for cash or transfer.
+    pub payment_method: String,
+    pub provider: EInvoiceProviderKind,
+    /// Deprecated: the VAT rate of every invoice line before products had their
+    /// own. Read as `tax.default_vat_rate` when that is not set.
+    #[serde(skip_serializing)]
+    pub vat_rate: Option<VatRate>,
+}
+
+impl Default for EInvoiceSettings {
+    fn default() -> Self {
+        EInvoiceSettings {
+            seller_name: String::new(),
+            seller_tax_code: String::new(),
+            seller_address: String::new(),
+            seller_phone: String::new(),
+            template: "1".to_string(),
+            series: String::new(),
+            payment_method: "TM/CK".to_string(),
+            provider: EInvoiceProviderKind::Mock,
+            vat_rate: None,
+        }
+    }
+}
+
+/// Checks the 10-digit tax code of a company, or the 13-character `0123456789-001`
+/// form of a branch.
+pub fn is_tax_code(code: &str) -> bool {
+    let (main, branch) = code.split_once('-').unwrap_or((code, "000"));
+    main.len() == 10
+        && branch.len() == 3
+        && main
+            .chars()
+            .chain(branch.chars())
+            .all(|c| c.is_ascii_digit())
+}
+
+/// Checks an invoice series: `C` (with a tax authority code) or `K` (without),
+/// two digits of the year, a letter for the invoice type and two letters chosen
+/// by the seller.
+fn is_invoice_series(series: &str) -> bool {
+    let chars: Vec<char> = series.chars().collect();
+    chars.len() == 6
+        && matches!(chars[0], 'C' | 'K')
+        && chars[1..3].iter().all(|c| c.is_ascii_digit())
+        && chars[3..].iter().all(|c| c.is_ascii_uppercase())
+}
+
+/// Date and number conventions of CSV and XLSX exports.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Locale {
+    /// `31/12/2026 14:30`, `1.234,50`, `;` between CSV fields
+    Vi,
+    /// `12/31/2026 14:30`, `1,234.50`, `,` between CSV fields
+    En,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub listen: SocketAddr,
+    pub data_file: PathBuf,
+    pub assets_dir: PathBuf,
+    pub default_warehouse_id: Option<u32>,
+    pub currency: String,
+    pub timezone: String, // IANA name, e.g. "Asia/Ho_Chi_Minh"
+    pub locale: Locale,
+    pub alerts: AlertThresholds,
+    pub replenishment: ReplenishmentSettings,
+    pub documents: DocumentSettings,
+    pub pricing: PricingSettings,
+    pub tax: TaxSettings,
+    pub einvoice: EInvoiceSettings,
+    pub idempotency_window_hours: i64,
+    /// Deprecated: read by the old console menu, which no longer exists.
+    #[serde(skip_serializing)]
+    pub cancel_keyword: Option<String>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            listen: SocketAddr::from(([127, 0, 0, 1], 3000)),
+            data_file: PathBuf::from("data.json"),
+            assets_dir: PathBuf::from("assets"),
+            default_warehouse_id: None,
+            currency: "VND".to_string(),
+            timezone: "Asia/Ho_Chi_Minh".to_string(),
+            locale: Locale::Vi,
+            alerts: AlertThresholds::default(),
+            replenishment: ReplenishmentSettings::default(),
+            documents: DocumentSettings::default(),
+            pricing: PricingSettings::default(),
+            tax: TaxSettings::default(),
+            einvoice: EInvoiceSettings::default(),
+            idempotency_window_hours: 24,
+            cancel_keyword: None,
+        }
+    }
+}
+
+/// Command-line flags; each one can also be set through its environment variable.
+/// Precedence: flag > environment > config file > built-in default.
+#[derive(Debug, Args)]
+pub struct ConfigOverrides {
+    /// Path to the JSON configuration file [default: config.json, if it exists]
+    #[arg(long, env = "PHARMACY_CONFIG", global = true)]
+    pub config: Option<PathBuf>,
+    #[arg(long, env = "PHARMACY_LISTEN", global = true)]
+    pub listen: Option<SocketAddr>,
+    #[arg(long, env = "PHARMACY_DATA_FILE", global = true)]
+    pub data_file: Option<PathBuf>,
+    #[arg(long, env = "PHARMACY_ASSETS_DIR", global = true)]
+    pub assets_dir: Option<PathBuf>,
+    #[arg(long, env = "PHARMACY_DEFAULT_WAREHOUSE", global = true)]
+    pub default_warehouse_id: Option<u32>,
+    #[arg(long, env = "PHARMACY_CURRENCY", global = true)]
+    pub currency: Option<String>,
+    #[arg(long, env = "PHARMACY_TIMEZONE", global = true)]
+    pub timezone: Option<String>,
+    #[arg(long, env = "PHARMACY_LOCALE", value_enum, global = true)]
+    pub locale: Option<Locale>,
+    #[arg(long, env = "PHARMACY_EXPIRY_DAYS", global = true)]
+    pub expiry_days: Option<i64>,
+    #[arg(long, env = "PHARMACY_CRITICAL_EXPIRY_DAYS", global = true)]
+    pub critical_expiry_days: Option<i64>,
+    #[arg(long, env = "PHARMACY_LOW_STOCK_QUANTITY", global = true)]
+    pub low_stock_quantity: Option<u32>,
+    #[arg(long, env = "PHARMACY_SALES_WINDOW_DAYS", global = true)]
+    pub sales_window_days: Option<i64>,
+    #[arg(long, env = "PHARMACY_LEAD_TIME_DAYS", global = true)]
+    pub lead_time_days: Option<i64>,
+    #[arg(long, env = "PHARMACY_MOVING_AVERAGE_WEEKS", global = true)]
+    pub moving_average_weeks: Option<usize>,
+    #[arg(long, env = "PHARMACY_NAME", global = true)]
+    pub pharmacy_name: Option<String>,
+    #[arg(long, env = "PHARMACY_FONT_FILE", global = true)]
+    pub font_file: Option<PathBuf>,
+    #[arg(long, env = "PHARMACY_BOLD_FONT_FILE", global = true)]
+    pub bold_font_file: Option<PathBuf>,
+    #[arg(long, env = "PHARMACY_CLEARANCE_PERCENT", global = true)]
+    pub clearance_percent: Option<u8>,
+    #[arg(long, env = "PHARMACY_CRITICAL_CLEARANCE_PERCENT", global = true)]
+    pub critical_clearance_percent: Option<u8>,
+    #[arg(long, env = "PHARMACY_DEFAULT_VAT_RATE", value_parser = VatRate::parse, global = true)]
+    pub default_vat_rate: Option<VatRate>,
+    #[arg(long, env = "PHARMACY_PRICES_INCLUDE_VAT", global = true)]
+    pub prices_include_vat: Option<bool>,
+    #[arg(long, env = "PHARMACY_SELLER_TAX_CODE", global = true)]
+    pub seller_tax_code: Option<String>,
+    #[arg(long, env = "PHARMACY_INVOICE_SERIES", global = true)]
+    pub invoice_series: Option<String>,
+    #[arg(long, env = "PHARMACY_EINVOICE_PROVIDER", value_enum, global = true)]
+    pub einvoice_provider: Option<EInvoiceProviderKind>,
+    #[arg(long, env = "PHARMACY_IDEMPOTENCY_WINDOW_HOURS", global = true)]
+    pub idempotency_window_hours: Option<i64>,
+}
+
+impl Config {
+    /// Regular and bold document fonts, by default from `assets_dir`, so they are
+    /// found whatever directory the server runs in.
+    pub fn font_files(&self) -> (PathBuf, PathBuf) {
+        let fonts = self.assets_dir.join("fonts");
+        let documents = &self.documents;
+        (
+            documents
+                .font_file
+                .clone()
+                .unwrap_or_else(|| fonts.join("DejaVuSansCondensed.ttf")),
+            documents
+                .bold_font_file
+                .clone()
+                .unwrap_or_else(|| fonts.join("DejaVuSansCondensed-Bold.ttf")),
+        )
+    }
+
+    /// The configured time zone; `load` has already checked that it parses.
+    pub fn tz(&self) -> Tz {
+        self.timezone.parse().unwrap_or(Tz::UTC)
+    }
+
+    /// Reads the config file, applies overrides and validates the result. Without
+    /// `--config` a missing `config.json` means the built-in defaults; a file that
+    /// was asked for must exist. Paths only the server uses, such as
+    /// `assets_dir`, are checked when it starts.
+    pub fn load(overrides: &ConfigOverrides) -> Result<Config, Vec<String>> {
+        let path = overrides
+            .config
+            .clone()
+            .unwrap_or_else(|| PathBuf::from(DEFAULT_CONFIG_FILE));
+        let mut config = match fs::read_to_string(&path) {
+            Ok(data) => {
+                let mut config: Config = serde_json::from_str(&data)
+                    .map_err(|e| vec![format!("{}: {}", path.display(), e)])?;
+                config.apply_deprecated(&data);
+                config
+            }
+            Err(e) if e.kind() == ErrorKind::NotFound && overrides.config.is_none() => {
+                Config::default()
+            }
+            Err(e) => return Err(vec![format!("{}: {}", path.display(), e)]),
+        };
+        config.apply(overrides);
+        config.validate()?;
+        Ok(config)
+    }
+
+    // Keys kept so older config files still load
+    fn apply_deprecated(&mut self, data: &str) {
+        if self.cancel_keyword.take().is_some() {
+            eprintln!("cancel_keyword is deprecated and ignored");
+        }
+        if let Some(rate) = self.einvoice.vat_rate.take() {
+            eprintln!("einvoice.vat_rate is deprecated; use tax.default_vat_rate");
+            let tax_rate_set = serde_json::from_str::<serde_json::Value>(data)
+                .is_ok_and(|v| v.pointer("/tax/default_vat_rate").is_some());
+            if !tax_rate_set {
+                self.tax.default_vat_rate = rate;
+            }
+        }
+    }
+
+    fn apply(&mut self, overrides: &ConfigOverrides) {
+        if let Some(listen) = overrides.listen {
+            self.listen = listen;
+        }
+        if let Some(data_file) = &overrides.data_file {
+            self.data_file = data_file.clone();
+        }
+        if let Some(assets_dir) = &overrides.assets_dir {
+            self.assets_dir = assets_dir.clone();
+        }
+        if let Some(id) = overrides.default_warehouse_id {
+            self.default_warehouse_id = Some(id);
+        }
+        if let Some(currency) = &overrides.currency {
+            self.currency = currency.clone();
+        }
+        if let Some(timezone) = &overrides.timezone {
+            self.timezone = timezone.clone();
+        }
+        if let Some(locale) = overrides.locale {
+            self.locale = locale;
+        }
+        if let Some(days) = overrides.expiry_days {
+            self.alerts.expiry_days = days;
+        }
+        if let Some(days) = overrides.critical_expiry_days {
+            self.alerts.critical_expiry_days = days;
+        }
+        if let Some(quantity) = overrides.low_stock_quantity {
+            self.alerts.low_stock_quantity = quantity;
+        }
+        if let Some(days) = overrides.sales_window_days {
+            self.replenishment.sales_window_days = days;
+        }
+        if let Some(days) = overrides.lead_time_days {
+            self.replenishment.lead_time_days = days;
+        }
+        if let Some(weeks) = overrides.moving_average_weeks {
+            self.replenishment.moving_average_weeks = weeks;
+        }
+        if let Some(name) = &overrides.pharmacy_name {
+            self.documents.pharmacy_name = name.clone();
+        }
+        if let Some(path) = &overrides.font_file {
+            self.documents.font_file = Some(path.clone());
+        }
+        if let Some(path) = &overrides.bold_font_file {
+            self.documents.bold_font_file = Some(path.clone());
+        }
+        if let Some(percent) = overrides.clearance_percent {
+            self.pricing.clearance_percent = percent;
+        }
+        if let Some(percent) = overrides.critical_clearance_percent {
+            self.pricing.critical_clearance_percent = percent;
+        }
+        if let Some(rate) = overrides.default_vat_rate {
+            self.tax.default_vat_rate = rate;
+        }
+        if let Some(included) = overrides.prices_include_vat {
+            self.tax.prices_include_vat = included;
+        }
+        if let Some(code) = &overrides.seller_tax_code {
+            self.einvoice.seller_tax_code = code.clone();
+        }
+        if let Some(series) = &overrides.invoice_series {
+            self.einvoice.series = series.clone();
+        }
+        if let Some(provider) = overrides.einvoice_provider {
+            self.einvoice.provider = provider;
+        }
+        if let Some(hours) = overrides.idempotency_window_hours {
+            self.idempotency_window_hours = hours;
+        }
+    }
+
+    fn validate(&self) -> Result<(), Vec<String>> {
+        let mut problems = Vec::new();
+
+        if let Some(parent) = self.data_file.parent()
+            && !parent.as_os_str().is_empty()
+            && !parent.is_dir()
+        {
+            problems.push(format!(
+                "data_file: directory {} does not exist",
+                parent.display()
+            ));
+        }
+        if self.currency.len() != 3 || !self.currency.chars().all(|c| c.is_ascii_uppercase()) {
+            problems.push(format!(
+                "currency: {:?} is not an ISO 4217 code such as \"VND\"",
+                self.currency
+            ));
+        }
+        if self.timezone.parse::<Tz>().is_err() {
+            problems.push(format!(
+                "timezone: {:?} is not an IANA time zone such as \"Asia/Ho_Chi_Minh\"",
+                self.timezone
+            ));
+        }
+        if self.alerts.expiry_days <= 0 {
+            problems.push("alerts.expiry_days: must be greater than zero".to_string());
+        }
+        if self.alerts.critical_expiry_days <= 0
+            || self.alerts.critical_expiry_days > self.alerts.expiry_days
+        {
+            problems.push(
+                "alerts.critical_expiry_days: must be between 1 and alerts.expiry_days".to_string(),
+            );
+        }
+        if self.replenishment.sales_window_days <= 0 {
+            problems.push("replenishment.sales_window_days: must be greater than zero".to_string());
+        }
+        if self.replenishment.lead_time_days < 0 {
+            problems.push("replenishment.lead_time_days: must not be negative".to_string());
+        }
+        if self.replenishment.moving_average_weeks == 0 {
+            problems
+                .push("replenishment.moving_average_weeks: must be greater than zero".to_string());
+        }
+        if self.documents.pharmacy_name.trim().is_empty() {
+            problems.push("documents.pharmacy_name: must not be blank".to_string());
+        }
+        if self.pricing.clearance_percent >= 100 {
+            problems.push("pricing.clearance_percent: must be below 100".to_string());
+        }
+        if self.pricing.critical_clearance_percent >= 100 {
+            problems.push("pricing.critical_clearance_percent: must be below 100".to_string());
+        }
+        // Seller details may be left blank until e-invoices are issued, but
+        // whatever is set must be usable
+        let einvoice = &self.einvoice;
+        if !einvoice.seller_tax_code.is_empty() && !is_tax_code(&einvoice.seller_tax_code) {
+            problems.push(format!(
+                "einvoice.seller_tax_code: {:?} is not a tax code such as \"0101234567\"",
+                einvoice.seller_tax_code
+            ));
+        }
+        if !einvoice.series.is_empty() && !is_invoice_series(&einvoice.series) {
+            problems.push(format!(
+                "einvoice.series: {:?} is not an invoice series such as \"C26TAA\"",
+                einvoice.series
+            ));
+        }
+        if !matches!(einvoice.template.as_str(), "1" | "2") {
+            problems.push(
+                "einvoice.template: must be 1 (VAT invoice) or 2 (sales invoice)".to_string(),
+            );
+        }
+        if self.idempotency_window_hours <= 0 {
+            problems.push("idempotency_window_hours: must be greater than zero".to_string());
+        }
+
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(problems)
+        }
+    }
+}
@@ -0,0 +1,591 @@
+use axum::body::Body;
+use axum::http::{StatusCode, header};
+use axum::response::{IntoResponse, Response};
+use chrono::{DateTime, Local};
+use serde::Deserialize;
+use std::sync::Arc;
+
+use crate::config::{Config, Locale};
+use crate::error::PharmacyError;
+use crate::models::Pharmacy;
+use crate::pdf::{self, Align, Block, Column, Document, Fonts, Paper};
+use crate::pricing::PriceRule;
+use crate::purchase_order::PurchaseOrderStatus;
+
+/// `paper` and `locale` query parameters of the document endpoints.
+#[derive(Debug, Deserialize, Default)]
+pub struct DocumentQuery {
+    pub paper: Option<Paper>,
+    pub locale: Option<Locale>,
+}
+
+impl DocumentQuery {
+    pub fn locale(&self, config: &Config) -> Locale {
+        self.locale.unwrap_or(config.locale)
+    }
+
+    /// The requested paper, or `default` when none was asked for.
+    pub fn paper(&self, default: Paper) -> Paper {
+        self.paper.unwrap_or(default)
+    }
+}
+
+/// Renders `document` off the async runtime and sends it for display in the
+/// browser, ready to print.
+pub async fn pdf_response(
+    document: Document,
+    paper: Paper,
+    fonts: Arc<Fonts>,
+    name: &str,
+) -> Result<Response, PharmacyError> {
+    let data = tokio::task::spawn_blocking(move || pdf::render(&document, paper, &fonts))
+        .await
+        .map_err(|e| PharmacyError::Export(e.to_string()))?
+        .map_err(PharmacyError::Export)?;
+    Ok((
+        StatusCode::OK,
+        [
+            (header::CONTENT_TYPE, "application/pdf".to_string()),
+            (
+                header::CONTENT_DISPOSITION,
+                format!("inline; filename=\"{}.pdf\"", name),
+            ),
+        ],
+        Body::from(data),
+    )
+        .into_response())
+}
+
+/// Fixed wording of the documents in one language.
+struct Labels {
+    receipt: &'static str,
+    goods_receipt: &'static str,
+    transfer_note: &'static str,
+    purchase_order: &'static str,
+    number: &'static str,
+    date: &'static str,
+    cashier: &'static str,
+    warehouse: &'static str,
+    catalog: &'static str,
+    supplier: &'static str,
+    address: &'static str,
+    phone: &'static str,
+    from_warehouse: &'static str,
+    to_warehouse: &'static str,
+    deliver_to: &'static str,
+    status: &'static str,
+    line_number: &'static str,
+    medicine: &'static str,
+    batch: &'static str,
+    expiry: &'static str,
+    quantity: &'static str,
+    unit_price: &'static str,
+    unit_cost: &'static str,
+    amount: &'static str,
+    discount: &'static str,
+    clearance: &'static str,
+    subtotal: &'static str,
+    vat: &'static str,
+    total: &'static str,
+    delivered_by: &'static str,
+    storekeeper: &'static str,
+    accountant: &'static str,
+    issued_by: &'static str,
+    received_by: &'static str,
+    prepared_by: &'static str,
+    approved_by: &'static str,
+    draft: &'static str,
+    ordered: &'static str,
+    received: &'static str,
+    cancelled: &'static str,
+}
+
+const VI: Labels = Labels {
+    receipt: "HÓA ĐƠN BÁN LẺ",
+    goods_receipt: "PHIẾU NHẬP KHO",
+    transfer_note: "PHIẾU CHUYỂN KHO",
+    purchase_order: "ĐƠN ĐẶT HÀNG",
+    number: "Số",
+    date: "Ngày",
+    cashier: "Thu ngân",
+    warehouse: "Kho",
+    catalog: "Danh mục",
+    supplier: "Nhà cung cấp",
+    address: "Địa chỉ",
+    phone: "Điện thoại",
+    from_warehouse: "Từ kho",
+    to_warehouse: "Đến kho",
+    deliver_to: "Giao đến kho",
+    status: "Trạng thái",
+    line_number: "STT",
+    medicine: "Tên thuốc",
+    batch: "Lô",
+    expiry: "HSD",
+    quantity: "SL",
+    unit_price: "Đơn giá",
+    unit_cost: "Giá nhập",
+    amount: "Thành tiền",
+    discount: "Giảm giá",
+    clearance: "hàng cận hạn",
+    subtotal: "Tiền hàng chưa thuế",
+    vat: "Thuế GTGT",
+    total: "Tổng cộng",
+    delivered_by: "Người giao hàng",
+    storekeeper: "Thủ kho",
+    accountant: "Kế toán",
+    issued_by: "Người xuất",
+    received_by: "Người nhận",
+    prepared_by: "Người lập",
+    approved_by: "Người duyệt",
+    draft: "Nháp",
+    ordered: "Đã đặt",
+    received: "Đã nhận",
+    cancelled: "Đã hủy",
+};
+
+const EN: Labels = Labels {
+    receipt: "SALES RECEIPT",
+    goods_receipt: "GOODS RECEIPT NOTE",
+    transfer_note: "INTERNAL TRANSFER NOTE",
+    purchase_order: "PURCHASE ORDER",
+    number: "No.",
+    date: "Date",
+    cashier: "Cashier",
+    warehouse: "Warehouse",
+    catalog: "Catalog",
+    supplier: "Supplier",
+    address: "Address",
+    phone: "Phone",
+    from_warehouse: "From",
+    to_warehouse: "To",
+    deliver_to: "Deliver to",
+    status: "Status",
+    line_number: "#",
+    medicine: "Medicine",
+    batch: "Batch",
+    expiry: "Expiry",
+    quantity: "Qty",
+    unit_price: "Unit price",
+    unit_cost: "Unit cost",
+    amount: "Amount",
+    discount: "Discount",
+    clearance: "clearance",
+    subtotal: "Subtotal",
+    vat: "VAT",
+    total: "Total",
+    delivered_by: "Delivered by",
+    storekeeper: "Storekeeper",
+    accountant: "Accountant",
+    issued_by: "Issued by",
+    received_by: "Received by",
+    prepared_by: "Prepared by",
+    approved_by: "Approved by",
+    draft: "Draft",
+    ordered: "Ordered",
+    received: "Received",
+    cancelled: "Cancelled",
+};
+
+/// Formats values and frames documents for one configuration and locale.
+struct Printer<'a> {
+    config: &'a Config,
+    locale: Locale,
+    labels: &'static Labels,
+}
+
+impl<'a> Printer<'a> {
+    fn new(config: &'a Config, locale: Locale) -> Self {
+        let labels = match locale {
+            Locale::Vi => &VI,
+            Locale::En => &EN,
+        };
+        Printer {
+            config,
+            locale,
+            labels,
+        }
+    }
+
+    fn money(&self, value: f64) -> String {
+        format!("{} {}", self.locale.number(value), self.config.currency)
+    }
+
+    fn time(&self, at: DateTime<Local>) -> String {
+        at.with_timezone(&self.config.tz())
+            .format(self.locale.date_pattern())
+            .to_string()
+    }
+
+    fn day(&self, at: DateTime<Local>) -> String {
+        at.with_timezone(&self.config.tz())
+            .format(self.locale.day_pattern())
+            .to_string()
+    }
+
+    fn status(&self, status: PurchaseOrderStatus) -> &'static str {
+        match status {
+            PurchaseOrderStatus::Draft => self.labels.draft,
+            PurchaseOrderStatus::Ordered => self.labels.ordered,
+            PurchaseOrderStatus::Received => self.labels.received,
+            PurchaseOrderStatus::Cancelled => self.labels.cancelled,
+        }
+    }
+
+    /// The pharmacy header, the document title and then `body`, closed by the footer.
+    fn document(&self, title: &str, body: Vec<Block>) -> Document {
+        let settings = &self.config.documents;
+        let mut blocks = vec![Block::Text {
+            text: settings.pharmacy_name.clone(),
+            bold: true,
+            align: Align::Center,
+        }];
+        blocks.extend(settings.header_lines.iter().map(|line| Block::Text {
+            text: line.clone(),
+            bold: false,
+            align: Align::Center,
+        }));
+        blocks.push(Block::Rule);
+        blocks.push(Block::Title(title.to_string()));
+        blocks.extend(body);
+        if !settings.footer_lines.is_empty() {
+            blocks.push(Block::Space);
+            blocks.push(Block::Rule);
+            blocks.extend(settings.footer_lines.iter().map(|line| Block::Text {
+                text: line.clone(),
+                bold: false,
+                align: Align::Center,
+            }));
+        }
+        Document {
+            title: title.to_string(),
+            blocks,
+        }
+    }
+
+    fn total(&self, value: f64) -> Block {
+        Block::Amount {
+            label: self.labels.total.to_string(),
+            value: self.money(value),
+            bold: true,
+        }
+    }
+}
+
+fn column(title: &'static str, weight: f32, align: Align) -> Column {
+    Column {
+        title,
+        weight,
+        align,
+    }
+}
+
+impl Pharmacy {
+    fn warehouse_label(&self, id: u32) -> String {
+        self.warehouses
+            .iter()
+            .find(|w| w.id == id)
+            .map_or_else(|| format!("#{}", id), |w| w.name.clone())
+    }
+
+    fn batch_expiry(&self, batch_id: u32) -> Option<DateTime<Local>> {
+        self.stock_batches
+            .iter()
+            .find(|b| b.id == batch_id)
+            .map(|b| b.expiry_date)
+    }
+
+    fn supplier_fields(&self, labels: &Labels, supplier_id: u32) -> Vec<Block> {
+        match self.suppliers.iter().find(|s| s.id == supplier_id) {
+            Some(supplier) => vec![
+                Block::Field(labels.supplier, supplier.name.clone()),
+                Block::Field(labels.address, supplier.address.clone()),
+                Block::Field(labels.phone, supplier.phone.clone()),
+            ],
+            None => vec![Block::Field(labels.supplier, format!("#{}", supplier_id))],
+        }
+    }
+
+    /// Receipt for one sale. On thermal paper each item takes two lines, the
+    /// name and then quantity, price and amount; on A4 items form a table.
+    pub fn sale_receipt(
+        &self,
+        sale_id: u32,
+        paper: Paper,
+        config: &Config,
+        locale: Locale,
+    ) -> Result<Document, PharmacyError> {
+        let sale = self
+            .export_log
+            .iter()
+            .find(|e| e.id == sale_id)
+            .ok_or(PharmacyError::SaleNotFound)?;
+        let printer = Printer::new(config, locale);
+        let labels = printer.labels;
+        // Lines show the price before any discount, which follows on its own
+        let unit_price = sale.pricing.as_ref().map_or(sale.price, |p| p.list_price);
+        let line_amount = unit_price * sale.amount as f64;
+
+        let mut body = vec![
+            Block::Field(labels.number, sale.id.to_string()),
+            Block::Field(labels.date, printer.time(sale.timestamp)),
+        ];
+        if let Some(cashier) = &sale.cashier {
+            body.push(Block::Field(labels.cashier, cashier.clone()));
+        }
+        body.push(Block::Field(
+            labels.warehouse,
+            sale.warehouse_id
+                .map_or_else(|| labels.catalog.to_string(), |id| self.warehouse_label(id)),
+        ));
+        body.push(Block::Rule);
+
+        if paper.is_thermal() {
+            body.push(Block::Text {
+                text: sale.medicine_name.clone(),
+                bold: false,
+                align: Align::Left,
+            });
+            body.push(Block::Amount {
+                label: format!("{} x {}", sale.amount, printer.locale.number(unit_price)),
+                value: printer.locale.number(line_amount),
+                bold: false,
+            });
+        } else {
+            body.push(Block::Table {
+                columns: vec![
+                    column(labels.line_number, 0.6, Align::Left),
+                    column(labels.medicine, 5.0, Align::Left),
+                    column(labels.quantity, 1.0, Align::Right),
+                    column(labels.unit_price, 2.0, Align::Right),
+                    column(labels.amount, 2.4, Align::Right),
+                ],
+                rows: vec![vec![
+                    "1".to_string(),
+                    sale.medicine_name.clone(),
+                    sale.amount.to_string(),
+                    printer.locale.number(unit_price),
+                    printer.locale.number(line_amount),
+                ]],
+            });
+        }
+        // Batch and expiry of what was handed over, for recalls
+        for sold in &sale.batches {
+            let expiry = self
+                .batch_expiry(sold.batch_id)
+                .map(|at| format!(", {} {}", labels.expiry, printer.day(at)))
+                .unwrap_or_default();
+            body.push(Block::Text {
+                text: format!(
+                    "{} #{}{}: {}",
+                    labels.batch, sold.batch_id, expiry, sold.quantity
+                ),
+                bold: false,
+                align: Align::Left,
+            });
+        }
+        body.push(Block::Rule);
+        if let Some(pricing) = sale.pricing.as_ref().filter(|p| p.discount > 0.0) {
+            let reason = match &pricing.rule {
+                PriceRule::Promotion { name, .. } => name.as_str(),
+                PriceRule::Clearance { .. } => labels.clearance,
+                PriceRule::ListPrice => "",
+            };
+            body.push(Block::Amount {
+                label: format!("{} ({})", labels.discount, reason),
+                value: printer.money(-pricing.discount),
+                bold: false,
+            });
+        }
+        if let Some(tax) = &sale.tax {
+            body.push(Block::Amount {
+                label: labels.subtotal.to_string(),
+                value: printer.money(tax.net),
+                bold: false,
+            });
+            body.push(Block::Amount {
+                label: format!("{} {}", labels.vat, tax.vat_rate),
+                value: printer.money(tax.vat),
+                bold: false,
+            });
+        }
+        body.push(printer.total(sale.revenue()));
+
+        Ok(printer.document(labels.receipt, body))
+    }
+
+    /// Goods receipt note for one import, with room for the delivery person,
+    /// storekeeper and accountant to sign.
+    pub fn goods_receipt_note(
+        &self,
+        import_id: u32,
+        config: &Config,
+        locale: Locale,
+    ) -> Result<Document, PharmacyError> {
+        let import = self
+            .import_log
+            .iter()
+            .find(|i| i.id == import_id)
+            .ok_or(PharmacyError::ImportNotFound)?;
+        let printer = Printer::new(config, locale);
+        let labels = printer.labels;
+        let amount = import.unit_cost * import.quantity as f64;
+
+        let mut body = vec![
+            Block::Field(labels.number, import.id.to_string()),
+            Block::Field(labels.date, printer.time(import.timestamp)),
+        ];
+        if let Some(supplier_id) = import.supplier_id {
+            body.extend(self.supplier_fields(labels, supplier_id));
+        }
+        body.push(Block::Field(
+            labels.warehouse,
+            import
+                .warehouse_id
+                .map_or_else(|| labels.catalog.to_string(), |id| self.warehouse_label(id)),
+        ));
+        body.push(Block::Space);
+        body.push(Block::Table {
+            columns: vec![
+                column(labels.medicine, 4.0, Align::Left),
+                column(labels.batch, 1.0, Align::Left),
+                column(labels.expiry, 1.6, Align::Left),
+                column(labels.quantity, 1.0, Align::Right),
+                column(labels.unit_cost, 1.8, Align::Right),
+                column(labels.amount, 2.2, Align::Right),
+            ],
+            rows: vec![vec![
+                import.medicine_name.clone(),
+                import.batch_id.map(|id| id.to_string()).unwrap_or_default(),
+                import
+                    .batch_id
+                    .and_then(|id| self.batch_expiry(id))
+                    .map(|at| printer.day(at))
+                    .unwrap_or_default(),
+                import.quantity.to_string(),
+                printer.locale.number(import.unit_cost),
+                printer.locale.number(amount),
+            ]],
+        });
+        body.push(Block::Rule);
+        body.push(printer.total(amount));
+        body.push(Block::Space);
+        body.push(Block::Signatures(vec![
+            labels.delivered_by,
+            labels.storekeeper,
+            labels.accountant,
+        ]));
+
+        Ok(printer.document(labels.goods_receipt, body))
+    }
+
+    /// Note for one internal transfer, signed by whoever issues and receives the goods.
+    pub fn transfer_note(
+        &self,
+        transfer_id: u32,
+        config: &Config,
+        locale: Locale,
+    ) -> Result<Document, PharmacyError> {
+        let transfer = self
+            .transfer_log
+            .iter()
+            .find(|t| t.id == transfer_id)
+            .ok_or(PharmacyError::TransferNotFound)?;
+        let printer = Printer::new(config, locale);
+        let labels = printer.labels;
+
+        let body = vec![
+            Block::Field(labels.number, transfer.id.to_string()),
+            Block::Field(labels.date, printer.time(transfer.timestamp)),
+            Block::Field(
+                labels.from_warehouse,
+                self.warehouse_label(transfer.from_warehouse_id),
+            ),
+            Block::Field(
+                labels.to_warehouse,
+                self.warehouse_label(transfer.to_warehouse_id),
+            ),
+            Block::Space,
+            Block::Table {
+                columns: vec![
+                    column(labels.medicine, 5.0, Align::Left),
+                    column(labels.batch, 1.2, Align::Left),
+                    column(labels.expiry, 1.8, Align::Left),
+                    column(labels.quantity, 1.2, Align::Right),
+                ],
+                rows: vec![vec![
+                    transfer.medicine_name.clone(),
+                    transfer.batch_id.to_string(),
+                    self.batch_expiry(transfer.batch_id)
+                        .map(|at| printer.day(at))
+                        .unwrap_or_default(),
+                    transfer.quantity.to_string(),
+                ]],
+            },
+            Block::Rule,
+            Block::Space,
+            Block::Signatures(vec![labels.issued_by, labels.received_by]),
+        ];
+
+        Ok(printer.document(labels.transfer_note, body))
+    }
+
+    /// A purchase order to send to its supplier.
+    pub fn purchase_order_document(
+        &self,
+        order_id: u32,
+        config: &Config,
+        locale: Locale,
+    ) -> Result<Document, PharmacyError> {
+        let order = self
+            .purchase_orders
+            .iter()
+            .find(|o| o.id == order_id)
+            .ok_or(PharmacyError::PurchaseOrderNotFound)?;
+        let printer = Printer::new(config, locale);
+        let labels = printer.labels;
+
+        let mut body = vec![
+            Block::Field(labels.number, order.id.to_string()),
+            Block::Field(labels.date, printer.time(order.created_at)),
+            Block::Field(labels.status, printer.status(order.status).to_string()),
+        ];
+        body.extend(self.supplier_fields(labels, order.supplier_id));
+        body.push(Block::Field(
+            labels.deliver_to,
+            self.warehouse_label(order.warehouse_id),
+        ));
+        body.push(Block::Space);
+        body.push(Block::Table {
+            columns: vec![
+                column(labels.line_number, 0.6, Align::Left),
+                column(labels.medicine, 5.0, Align::Left),
+                column(labels.quantity, 1.0, Align::Right),
+                column(labels.unit_cost, 2.0, Align::Right),
+                column(labels.amount, 2.4, Align::Right),
+            ],
+            rows: order
+                .lines
+                .iter()
+                .enumerate()
+                .map(|(i, line)| {
+                    vec![
+                        (i + 1).to_string(),
+                        line.medicine_name.clone(),
+                        line.quantity.to_string(),
+                        printer.locale.number(line.unit_cost),
+                        printer.locale.number(line.unit_cost * line.quantity as f64),
+                    ]
+                })
+                .collect(),
+        });
+        body.push(Block::Rule);
+        body.push(printer.total(order.total_cost()));
+        body.push(Block::Space);
+        body.push(Block::Signatures(vec![
+            labels.prepared_by,
+            labels.approved_by,
+        ]));
+
+        Ok(printer.document(labels.purchase_order, body))
+    }
+}
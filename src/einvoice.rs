@@ -0,0 +1,530 @@
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+
+use crate::audit::{AuditAction, AuditEntity};
+use crate::config::Config;
+use crate::error::PharmacyError;
+use crate::models::{ExportBatch, Pharmacy};
+use crate::sequence::Sequence;
+use crate::tax::{LineTax, VatRate, round_money};
+use crate::validation::FieldError;
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum EInvoiceKind {
+    Original,
+    /// Takes the place of an earlier invoice, which stops counting.
+    Replacement,
+    /// Corrects an earlier invoice by the differences on its lines.
+    Adjustment,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum EInvoiceStatus {
+    /// Not yet sent to the provider.
+    Pending,
+    /// Registered by the tax authority, which gave it a code.
+    Accepted,
+    /// Refused or not delivered; can be submitted again.
+    Rejected,
+}
+
+/// The seller as registered with the tax authority, copied onto each invoice.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Seller {
+    pub name: String,
+    pub tax_code: String,
+    #[serde(default)]
+    pub address: String,
+    #[serde(default)]
+    pub phone: String,
+}
+
+/// Buyer details; all of them may be left out for a walk-in customer.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct Buyer {
+    /// The person buying.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// The company buying, required with a tax code.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub company: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tax_code: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub address: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub email: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct EInvoiceLine {
+    /// Line number; adjustment lines carry the number of the line they correct.
+    pub line: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sale_id: Option<u32>,
+    pub medicine_id: u32,
+    pub name: String,
+    /// On adjustments this and the amounts below are differences, negative
+    /// when they reduce the original.
+    pub quantity: i64,
+    /// Price per unit before VAT.
+    pub unit_price: f64,
+    /// Amount before VAT.
+    pub amount: f64,
+    pub vat_rate: VatRate,
+    pub vat_amount: f64,
+}
+
+/// The invoice a replacement or adjustment refers to, as printed on it.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct RelatedInvoice {
+    pub id: u32,
+    pub template: String,
+    pub series: String,
+    pub number: u32,
+    pub issued_at: DateTime<Local>,
+    pub reason: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct EInvoice {
+    pub id: u32,
+    pub kind: EInvoiceKind,
+    /// Form number (ký hiệu mẫu số).
+    pub template: String,
+    /// Series (ký hiệu hóa đơn); numbers run from 1 within each template and series.
+    pub series: String,
+    pub number: u32,
+    pub issued_at: DateTime<Local>,
+    pub currency: String,
+    pub payment_method: String,
+    pub seller: Seller,
+    pub buyer: Buyer,
+    pub lines: Vec<EInvoiceLine>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub related: Option<RelatedInvoice>,
+    pub status: EInvoiceStatus,
+    /// Code the tax authority gave the invoice (mã của cơ quan thuế).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tax_authority_code: Option<String>,
+    /// Why the last submission failed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub submitted_at: Option<DateTime<Local>>,
+    /// Provider the invoice was last submitted through.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub provider: Option<String>,
+    /// Replacement issued for this invoice.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub replaced_by: Option<u32>,
+    /// Adjustments issued for this invoice, oldest first.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub adjusted_by: Vec<u32>,
+}
+
+impl EInvoice {
+    pub fn subtotal(&self) -> f64 {
+        self.lines.iter().fold(0.0, |sum, l| sum + l.amount)
+    }
+
+    pub fn vat_total(&self) -> f64 {
+        self.lines.iter().fold(0.0, |sum, l| sum + l.vat_amount)
+    }
+
+    pub fn total(&self) -> f64 {
+        self.subtotal() + self.vat_total()
+    }
+
+    /// Whether this invoice still stands for the sales on it.
+    fn covers_sales(&self) -> bool {
+        self.kind != EInvoiceKind::Adjustment && self.replaced_by.is_none()
+    }
+}
+
+/// New quantity or unit price (before VAT) for a line of an earlier invoice.
+#[derive(Debug, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct LineCorrection {
+    pub line: u32,
+    pub quantity: Option<u32>,
+    pub unit_price: Option<f64>,
+}
+
+/// Unit prices keep two decimals whatever the currency.
+fn round_price(value: f64) -> f64 {
+    (value * 100.0).round() / 100.0
+}
+
+fn line_error(index: usize, field: &str, code: &'static str, message: &str) -> PharmacyError {
+    PharmacyError::Validation(vec![FieldError {
+        field: format!("lines[{}].{}", index, field),
+        code,
+        message: message.to_string(),
+    }])
+}
+
+impl Pharmacy {
+    pub fn einvoice(&self, id: u32) -> Result<&EInvoice, PharmacyError> {
+        self.einvoices
+            .iter()
+            .find(|i| i.id == id)
+            .ok_or(PharmacyError::EInvoiceNotFound)
+    }
+
+    /// The invoice currently standing for a sale, if any.
+    pub fn invoice_for_sale(&self, sale_id: u32) -> Option<&EInvoice> {
+        self.einvoices
+            .iter()
+            .find(|i| i.covers_sales() && i.lines.iter().any(|l| l.sale_id == Some(sale_id)))
+    }
+
+    /// Issues an invoice for completed sales. Sale prices are what the customer
+    /// paid, VAT included; each line shows the amount before VAT and the VAT.
+    pub fn issue_einvoice(
+        &mut self,
+        user: &str,
+        sale_ids: &[u32],
+        buyer: Buyer,
+        config: &Config,
+    ) -> Result<u32, PharmacyError> {
+        let seller = seller(config)?;
+        let mut lines = Vec::new();
+        for (index, &sale_id) in sale_ids.iter().enumerate() {
+            let sale = self
+                .export_log
+                .iter()
+                .find(|s| s.id == sale_id)
+                .ok_or(PharmacyError::SaleNotFound)?;
+            if let Some(invoice) = self.invoice_for_sale(sale_id) {
+                return Err(PharmacyError::SaleAlreadyInvoiced {
+                    invoice_id: invoice.id,
+                });
+            }
+            // Sales made before VAT was recorded are taxed as they would be today
+            let tax = sale
+                .tax
+                .unwrap_or_else(|| self.line_tax(sale.medicine_id, sale.revenue(), config));
+            lines.push(sale_line(index as u32 + 1, sale, tax));
+        }
+        let invoice = self.new_einvoice(EInvoiceKind::Original, seller, buyer, lines, None, config);
+        Ok(self.push_einvoice(user, invoice))
+    }
+
+    /// Issues a replacement for an accepted invoice, with a new buyer and/or
+    /// corrected lines. Lines corrected to a quantity of zero are left out.
+    pub fn replace_einvoice(
+        &mut self,
+        user: &str,
+        id: u32,
+        reason: String,
+        buyer: Option<Buyer>,
+        corrections: &[LineCorrection],
+        config: &Config,
+    ) -> Result<u32, PharmacyError> {
+        let seller = seller(config)?;
+        let original = self.adjustable_einvoice(id)?;
+        let mut lines = self.current_lines(original);
+        for (index, correction) in corrections.iter().enumerate() {
+            let line = lines
+                .iter_mut()
+                .find(|l| l.line == correction.line)
+                .ok_or_else(|| {
+                    line_error(index, "line", "UNKNOWN_LINE", "is not on the invoice")
+                })?;
+            *line = corrected(line, correction, &config.currency);
+        }
+        lines.retain(|l| l.quantity != 0);
+        if lines.is_empty() {
+            return Err(line_error(
+                0,
+                "quantity",
+                "NO_LINES",
+                "a replacement must keep at least one line",
+            ));
+        }
+        for (number, line) in lines.iter_mut().enumerate() {
+            line.line = number as u32 + 1;
+        }
+
+        let buyer = buyer.unwrap_or_else(|| original.buyer.clone());
+        let related = related(original, reason);
+        let invoice = self.new_einvoice(
+            EInvoiceKind::Replacement,
+            seller,
+            buyer,
+            lines,
+            Some(related),
+            config,
+        );
+        let replacement_id = self.push_einvoice(user, invoice);
+        self.update_einvoice(user, id, |original| {
+            original.replaced_by = Some(replacement_id)
+        });
+        Ok(replacement_id)
+    }
+
+    /// Issues an adjustment for an accepted invoice. Each correction gives the
+    /// new quantity and/or price of a line; the adjustment holds the differences.
+    pub fn adjust_einvoice(
+        &mut self,
+        user: &str,
+        id: u32,
+        reason: String,
+        corrections: &[LineCorrection],
+        config: &Config,
+    ) -> Result<u32, PharmacyError> {
+        let seller = seller(config)?;
+        let original = self.adjustable_einvoice(id)?;
+        let current = self.current_lines(original);
+        let mut lines = Vec::new();
+        for (index, correction) in corrections.iter().enumerate() {
+            let line = current
+                .iter()
+                .find(|l| l.line == correction.line)
+                .ok_or_else(|| {
+                    line_error(index, "line", "UNKNOWN_LINE", "is not on the invoice")
+                })?;
+            let new = corrected(line, correction, &config.currency);
+            let difference = EInvoiceLine {
+                quantity: new.quantity - line.quantity,
+                unit_price: round_price(new.unit_price - line.unit_price),
+                amount: new.amount - line.amount,
+                vat_amount: new.vat_amount - line.vat_amount,
+                ..new
+            };
+            if difference.quantity != 0 || difference.amount != 0.0 {
+                lines.push(difference);
+            }
+        }
+        if lines.is_empty() {
+            return Err(line_error(
+                0,
+                "line",
+                "NOTHING_TO_ADJUST",
+                "the corrections do not change the invoice",
+            ));
+        }
+
+        let buyer = original.buyer.clone();
+        let related = related(original, reason);
+        let invoice = self.new_einvoice(
+            EInvoiceKind::Adjustment,
+            seller,
+            buyer,
+            lines,
+            Some(related),
+            config,
+        );
+        let adjustment_id = self.push_einvoice(user, invoice);
+        self.update_einvoice(user, id, |original| {
+            original.adjusted_by.push(adjustment_id)
+        });
+        Ok(adjustment_id)
+    }
+
+    /// Stores the provider's answer. Once accepted an invoice stays accepted,
+    /// whatever a later duplicate submission says.
+    pub fn record_einvoice_submission(
+        &mut self,
+        user: &str,
+        id: u32,
+        provider: &str,
+        outcome: Result<String, String>,
+    ) -> Result<EInvoice, PharmacyError> {
+        let invoice = self.einvoice(id)?;
+        if invoice.status == EInvoiceStatus::Accepted {
+            return Ok(invoice.clone());
+        }
+        self.update_einvoice(user, id, |invoice| {
+            invoice.submitted_at = Some(Local::now());
+            invoice.provider = Some(provider.to_string());
+            match outcome {
+                Ok(code) => {
+                    invoice.status = EInvoiceStatus::Accepted;
+                    invoice.tax_authority_code = Some(code);
+                    invoice.error = None;
+                }
+                Err(reason) => {
+                    invoice.status = EInvoiceStatus::Rejected;
+                    invoice.error = Some(reason);
+                }
+            }
+        });
+        self.einvoice(id).cloned()
+    }
+
+    fn adjustable_einvoice(&self, id: u32) -> Result<&EInvoice, PharmacyError> {
+        let invoice = self.einvoice(id)?;
+        let reason = if invoice.kind == EInvoiceKind::Adjustment {
+            "it is an adjustment; adjust or replace the invoice it corrects".to_string()
+        } else if let Some(replacement) = invoice.replaced_by {
+            format!("it was replaced by e-invoice {}", replacement)
+        } else if invoice.status != EInvoiceStatus::Accepted {
+            "it has not been accepted by the tax authority yet".to_string()
+        } else {
+            return Ok(invoice);
+        };
+        Err(PharmacyError::EInvoiceNotAdjustable { reason })
+    }
+
+    /// Lines of an invoice with every adjustment issued for it added in.
+    fn current_lines(&self, invoice: &EInvoice) -> Vec<EInvoiceLine> {
+        let mut lines = invoice.lines.clone();
+        let adjustments = self
+            .einvoices
+            .iter()
+            .filter(|i| invoice.adjusted_by.contains(&i.id));
+        for difference in adjustments.flat_map(|a| &a.lines) {
+            if let Some(line) = lines.iter_mut().find(|l| l.line == difference.line) {
+                line.quantity += difference.quantity;
+                line.unit_price = round_price(line.unit_price + difference.unit_price);
+                line.amount += difference.amount;
+                line.vat_amount += difference.vat_amount;
+            }
+        }
+        lines
+    }
+
+    fn next_einvoice_number(&self, template: &str, series: &str) -> u32 {
+        self.einvoices
+            .iter()
+            .filter(|i| i.template == template && i.series == series)
+            .map(|i| i.number)
+            .max()
+            .unwrap_or(0)
+            + 1
+    }
+
+    fn new_einvoice(
+        &mut self,
+        kind: EInvoiceKind,
+        seller: Seller,
+        buyer: Buyer,
+        lines: Vec<EInvoiceLine>,
+        related: Option<RelatedInvoice>,
+        config: &Config,
+    ) -> EInvoice {
+        let settings = &config.einvoice;
+        EInvoice {
+            id: self.next_id(Sequence::EInvoice),
+            kind,
+            template: settings.template.clone(),
+            series: settings.series.clone(),
+            number: self.next_einvoice_number(&settings.template, &settings.series),
+            issued_at: Local::now(),
+            currency: config.currency.clone(),
+            payment_method: settings.payment_method.clone(),
+            seller,
+            buyer,
+            lines,
+            related,
+            status: EInvoiceStatus::Pending,
+            tax_authority_code: None,
+            error: None,
+            submitted_at: None,
+            provider: None,
+            replaced_by: None,
+            adjusted_by: Vec::new(),
+        }
+    }
+
+    fn push_einvoice(&mut self, user: &str, invoice: EInvoice) -> u32 {
+        let id = invoice.id;
+        self.record_audit(
+            user,
+            AuditAction::Create,
+            AuditEntity::EInvoice,
+            id,
+            None,
+            Some(&invoice),
+        );
+        self.einvoices.push(invoice);
+        id
+    }
+
+    fn update_einvoice(&mut self, user: &str, id: u32, change: impl FnOnce(&mut EInvoice)) {
+        let Some(invoice) = self.einvoices.iter_mut().find(|i| i.id == id) else {
+            return;
+        };
+        let before = invoice.clone();
+        change(invoice);
+        let after = invoice.clone();
+        self.record_audit(
+            user,
+            AuditAction::Update,
+            AuditEntity::EInvoice,
+            id,
+            Some(&before),
+            Some(&after),
+        );
+    }
+}
+
+/// The configured seller, or the settings still missing to issue invoices.
+fn seller(config: &Config) -> Result<Seller, PharmacyError> {
+    let settings = &config.einvoice;
+    let mut missing = Vec::new();
+    if settings.seller_tax_code.is_empty() {
+        missing.push("einvoice.seller_tax_code");
+    }
+    if settings.series.is_empty() {
+        missing.push("einvoice.series");
+    }
+    if !missing.is_empty() {
+        return Err(PharmacyError::EInvoiceNotConfigured { missing });
+    }
+    let name = if settings.seller_name.trim().is_empty() {
+        config.documents.pharmacy_name.clone()
+    } else {
+        settings.seller_name.clone()
+    };
+    Ok(Seller {
+        name,
+        tax_code: settings.seller_tax_code.clone(),
+        address: settings.seller_address.clone(),
+        phone: settings.seller_phone.clone(),
+    })
+}
+
+fn sale_line(line: u32, sale: &ExportBatch, tax: LineTax) -> EInvoiceLine {
+    EInvoiceLine {
+        line,
+        sale_id: Some(sale.id),
+        medicine_id: sale.medicine_id,
+        name: sale.medicine_name.clone(),
+        quantity: sale.amount as i64,
+        unit_price: round_price(tax.net / sale.amount as f64),
+        amount: tax.net,
+        vat_rate: tax.vat_rate,
+        vat_amount: tax.vat,
+    }
+}
+
+/// `line` with a new quantity and/or unit price, its amounts worked out again.
+fn corrected(line: &EInvoiceLine, correction: &LineCorrection, currency: &str) -> EInvoiceLine {
+    let quantity = correction.quantity.map_or(line.quantity, i64::from);
+    let unit_price = correction.unit_price.unwrap_or(line.unit_price);
+    let amount = round_money(quantity as f64 * unit_price, currency);
+    EInvoiceLine {
+        quantity,
+        unit_price,
+        amount,
+        vat_amount: round_money(amount * line.vat_rate.percent() as f64 / 100.0, currency),
+        ..line.clone()
+    }
+}
+
+fn related(invoice: &EInvoice, reason: String) -> RelatedInvoice {
+    RelatedInvoice {
+        id: invoice.id,
+        template: invoice.template.clone(),
+        series: invoice.series.clone(),
+        number: invoice.number,
+        issued_at: invoice.issued_at,
+        reason,
+    }
+}
@@ -0,0 +1,287 @@
+use chrono_tz::Tz;
+use quick_xml::Writer;
+use quick_xml::events::{BytesDecl, BytesText, Event};
+use std::collections::BTreeMap;
+use std::io;
+
+use crate::einvoice::{EInvoice, EInvoiceKind, EInvoiceLine, RelatedInvoice};
+use crate::tax::{VatRate, round_money};
+
+/// Version of the tax authority's invoice data format the XML follows.
+const FORMAT_VERSION: &str = "2.0.1";
+
+type XmlWriter = Writer<Vec<u8>>;
+
+/// The invoice in the national e-invoice XML format (HDon), unsigned. Dates are
+/// written in `tz`.
+pub fn render(invoice: &EInvoice, tz: Tz) -> String {
+    let mut writer = Writer::new_with_indent(Vec::new(), b' ', 2);
+    // Writing to a Vec cannot fail
+    let _ = write_invoice(&mut writer, invoice, tz);
+    String::from_utf8(writer.into_inner()).unwrap_or_default()
+}
+
+fn write_invoice(writer: &mut XmlWriter, invoice: &EInvoice, tz: Tz) -> io::Result<()> {
+    writer.write_event(Event::Decl(BytesDecl::new("1.0", Some("UTF-8"), None)))?;
+    writer.create_element("HDon").write_inner_content(|w| {
+        w.create_element("DLHDon")
+            .with_attribute(("Id", "data"))
+            .write_inner_content(|w| {
+                write_general(w, invoice, tz)?;
+                write_content(w, invoice)
+            })?;
+        // Filled in when the seller signs the invoice
+        w.create_element("DSCKS").write_empty()?;
+        Ok(())
+    })?;
+    Ok(())
+}
+
+/// General information (TTChung): form, series, number, date and currency.
+fn write_general(w: &mut XmlWriter, invoice: &EInvoice, tz: Tz) -> io::Result<()> {
+    w.create_element("TTChung").write_inner_content(|w| {
+        field(w, "PBan", FORMAT_VERSION)?;
+        field(w, "THDon", invoice_title(invoice))?;
+        field(w, "KHMSHDon", &invoice.template)?;
+        field(w, "KHHDon", &invoice.series)?;
+        field(w, "SHDon", &invoice.number.to_string())?;
+        field(w, "NLap", &date(invoice.issued_at, tz))?;
+        field(w, "DVTTe", &invoice.currency)?;
+        field(w, "TGia", "1")?;
+        field(w, "HTTToan", &invoice.payment_method)?;
+        if let Some(related) = &invoice.related {
+            write_related(w, invoice.kind, related, tz)?;
+        }
+        Ok(())
+    })?;
+    Ok(())
+}
+
+/// The invoice replaced or adjusted (TTHDLQuan).
+fn write_related(
+    w: &mut XmlWriter,
+    kind: EInvoiceKind,
+    related: &RelatedInvoice,
+    tz: Tz,
+) -> io::Result<()> {
+    // 1: replacement, 2: adjustment
+    let nature = if kind == EInvoiceKind::Adjustment {
+        "2"
+    } else {
+        "1"
+    };
+    w.create_element("TTHDLQuan").write_inner_content(|w| {
+        field(w, "TCHDon", nature)?;
+        // 1: an invoice issued under Decree 123/2020
+        field(w, "LHDCLQuan", "1")?;
+        field(w, "KHMSHDCLQuan", &related.template)?;
+        field(w, "KHHDCLQuan", &related.series)?;
+        field(w, "SHDCLQuan", &related.number.to_string())?;
+        field(w, "NLHDCLQuan", &date(related.issued_at, tz))?;
+        field(w, "GChu", &related.reason)?;
+        Ok(())
+    })?;
+    Ok(())
+}
+
+/// Invoice content (NDHDon): seller, buyer, lines and totals.
+fn write_content(w: &mut XmlWriter, invoice: &EInvoice) -> io::Result<()> {
+    w.create_element("NDHDon").write_inner_content(|w| {
+        let seller = &invoice.seller;
+        w.create_element("NBan").write_inner_content(|w| {
+            field(w, "Ten", &seller.name)?;
+            field(w, "MST", &seller.tax_code)?;
+            field(w, "DChi", &seller.address)?;
+            optional(w, "SDThoai", Some(&seller.phone))
+        })?;
+
+        let buyer = &invoice.buyer;
+        w.create_element("NMua").write_inner_content(|w| {
+            optional(w, "Ten", buyer.company.as_ref())?;
+            optional(w, "MST", buyer.tax_code.as_ref())?;
+            optional(w, "DChi", buyer.address.as_ref())?;
+            optional(w, "HVTNMHang", buyer.name.as_ref())?;
+            optional(w, "DCTDTu", buyer.email.as_ref())
+        })?;
+
+        w.create_element("DSHHDVu").write_inner_content(|w| {
+            for line in &invoice.lines {
+                write_line(w, line, &invoice.currency)?;
+            }
+            Ok(())
+        })?;
+
+        write_totals(w, invoice)
+    })?;
+    Ok(())
+}
+
+fn write_line(w: &mut XmlWriter, line: &EInvoiceLine, currency: &str) -> io::Result<()> {
+    w.create_element("HHDVu").write_inner_content(|w| {
+        // 1: goods or services
+        field(w, "TChat", "1")?;
+        field(w, "STT", &line.line.to_string())?;
+        field(w, "MHHDVu", &line.medicine_id.to_string())?;
+        field(w, "THHDVu", &line.name)?;
+        field(w, "SLuong", &line.quantity.to_string())?;
+        field(w, "DGia", &decimal(line.unit_price))?;
+        field(w, "ThTien", &amount(line.amount, currency))?;
+        field(w, "TSuat", &rate(line.vat_rate))?;
+        Ok(())
+    })?;
+    Ok(())
+}
+
+/// Totals per VAT rate and for the whole invoice, with the amount in words.
+fn write_totals(w: &mut XmlWriter, invoice: &EInvoice) -> io::Result<()> {
+    let currency = &invoice.currency;
+    let mut by_rate: BTreeMap<VatRate, (f64, f64)> = BTreeMap::new();
+    for line in &invoice.lines {
+        let totals = by_rate.entry(line.vat_rate).or_default();
+        totals.0 += line.amount;
+        totals.1 += line.vat_amount;
+    }
+    w.create_element("TToan").write_inner_content(|w| {
+        w.create_element("THTTLTSuat").write_inner_content(|w| {
+            for (vat_rate, (subtotal, vat)) in &by_rate {
+                w.create_element("LTSuat").write_inner_content(|w| {
+                    field(w, "TSuat", &rate(*vat_rate))?;
+                    field(w, "ThTien", &amount(*subtotal, currency))?;
+                    field(w, "TThue", &amount(*vat, currency))?;
+                    Ok(())
+                })?;
+            }
+            Ok(())
+        })?;
+        field(w, "TgTCThue", &amount(invoice.subtotal(), currency))?;
+        field(w, "TgTThue", &amount(invoice.vat_total(), currency))?;
+        field(w, "TgTTTBSo", &amount(invoice.total(), currency))?;
+        field(w, "TgTTTBChu", &amount_in_words(invoice.total(), currency))?;
+        Ok(())
+    })?;
+    Ok(())
+}
+
+fn field(w: &mut XmlWriter, name: &str, value: &str) -> io::Result<()> {
+    w.create_element(name)
+        .write_text_content(BytesText::new(value))?;
+    Ok(())
+}
+
+/// Writes the element only when there is a value.
+fn optional(w: &mut XmlWriter, name: &str, value: Option<&String>) -> io::Result<()> {
+    match value.map(|v| v.trim()).filter(|v| !v.is_empty()) {
+        Some(value) => field(w, name, value),
+        None => Ok(()),
+    }
+}
+
+fn invoice_title(invoice: &EInvoice) -> &'static str {
+    if invoice.template == "2" {
+        "Hóa đơn bán hàng"
+    } else {
+        "Hóa đơn giá trị gia tăng"
+    }
+}
+
+fn date(at: chrono::DateTime<chrono::Local>, tz: Tz) -> String {
+    at.with_timezone(&tz).format("%Y-%m-%d").to_string()
+}
+
+fn rate(vat_rate: VatRate) -> String {
+    vat_rate.to_string()
+}
+
+/// A plain decimal with at most two places, without trailing zeros.
+fn decimal(value: f64) -> String {
+    let text = format!("{:.2}", value);
+    match text.trim_end_matches('0').trim_end_matches('.') {
+        "-0" => "0".to_string(),
+        trimmed => trimmed.to_string(),
+    }
+}
+
+fn amount(value: f64, currency: &str) -> String {
+    decimal(round_money(value, currency))
+}
+
+const DIGITS: [&str; 10] = [
+    "không", "một", "hai", "ba", "bốn", "năm", "sáu", "bảy", "tám", "chín",
+];
+
+/// The total read out in Vietnamese, e.g. `Một trăm linh năm nghìn đồng`.
+/// Only whole units are read; negative totals of adjustments start with `Âm`.
+pub fn amount_in_words(value: f64, currency: &str) -> String {
+    let whole = value.abs().round() as u64;
+    let unit = if currency == "VND" {
+        "đồng"
+    } else {
+        currency
+    };
+    let mut words = number_in_words(whole);
+    if value < 0.0 && whole > 0 {
+        words = format!("âm {}", words);
+    }
+    let mut chars = words.chars();
+    let capitalized = match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    };
+    format!("{} {}", capitalized, unit)
+}
+
+/// Names of the three-digit groups; above a thousand billion they combine with tỷ.
+const SCALES: [&str; 7] = ["", "nghìn", "triệu", "tỷ", "nghìn tỷ", "triệu tỷ", "tỷ tỷ"];
+
+fn number_in_words(number: u64) -> String {
+    if number == 0 {
+        return DIGITS[0].to_string();
+    }
+    // Groups of three digits, lowest first
+    let mut groups = Vec::new();
+    let mut rest = number;
+    while rest > 0 {
+        groups.push(rest % 1000);
+        rest /= 1000;
+    }
+    let mut parts = Vec::new();
+    for (index, &group) in groups.iter().enumerate().rev() {
+        if group == 0 {
+            continue;
+        }
+        let leading = index == groups.len() - 1;
+        parts.push(group_in_words(group, !leading));
+        if !SCALES[index].is_empty() {
+            parts.push(SCALES[index].to_string());
+        }
+    }
+    parts.join(" ")
+}
+
+/// Reads up to three digits. Inside a larger number the hundreds are always
+/// read, so 1005 is `một nghìn không trăm linh năm`.
+fn group_in_words(group: u64, full: bool) -> String {
+    let (hundreds, tens, units) = (group / 100, group / 10 % 10, group % 10);
+    let mut words = Vec::new();
+    if hundreds > 0 || full {
+        words.push(DIGITS[hundreds as usize]);
+        words.push("trăm");
+    }
+    match tens {
+        0 if units > 0 && (hundreds > 0 || full) => words.push("linh"),
+        0 => {}
+        1 => words.push("mười"),
+        t => {
+            words.push(DIGITS[t as usize]);
+            words.push("mươi");
+        }
+    }
+    match units {
+        0 => {}
+        1 if tens > 1 => words.push("mốt"),
+        4 if tens > 1 => words.push("tư"),
+        5 if tens > 0 => words.push("lăm"),
+        u => words.push(DIGITS[u as usize]),
+    }
+    words.join(" ")
+}
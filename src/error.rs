@@ -0,0 +1,246 @@
+use axum::{
+    Json,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use serde_json::json;
+use std::fmt;
+
+use crate::validation::FieldError;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum PharmacyError {
+    MedicineNotFound,
+    WarehouseNotFound,
+    StoreWarehouseNotFound,
+    SupplierNotFound,
+    BatchNotFound,
+    SaleNotFound,
+    ImportNotFound,
+    TransferNotFound,
+    PurchaseOrderNotFound,
+    ReorderSettingNotFound,
+    ParLevelNotFound,
+    TransferProposalNotFound,
+    EInvoiceNotFound,
+    PriceListNotFound,
+    PromotionNotFound,
+    NotAStore,
+    InsufficientStock { available: u32 },
+    Archived { entity: &'static str },
+    InUse { reason: String },
+    InvalidStatusTransition { from: String, to: String },
+    SaleAlreadyInvoiced { invoice_id: u32 },
+    EInvoiceNotAdjustable { reason: String },
+    EInvoiceNotConfigured { missing: Vec<&'static str> },
+    PriceListExists { price_list_id: u32 },
+    PreconditionRequired,
+    VersionMismatch { current: u32 },
+    IdempotencyKeyReused,
+    IdempotencyKeyInProgress,
+    InvalidDate,
+    Validation(Vec<FieldError>),
+    AuditChainBroken { entry_id: u32 },
+    Persistence(String),
+    Export(String),
+}
+
+impl PharmacyError {
+    /// Stable, machine-readable code sent to API clients.
+    pub fn code(&self) -> &'static str {
+        match self {
+            PharmacyError::MedicineNotFound => "MEDICINE_NOT_FOUND",
+            PharmacyError::WarehouseNotFound => "WAREHOUSE_NOT_FOUND",
+            PharmacyError::StoreWarehouseNotFound => "STORE_WAREHOUSE_NOT_FOUND",
+            PharmacyError::SupplierNotFound => "SUPPLIER_NOT_FOUND",
+            PharmacyError::BatchNotFound => "BATCH_NOT_FOUND",
+            PharmacyError::SaleNotFound => "SALE_NOT_FOUND",
+            PharmacyError::ImportNotFound => "IMPORT_NOT_FOUND",
+            PharmacyError::TransferNotFound => "TRANSFER_NOT_FOUND",
+            PharmacyError::PurchaseOrderNotFound => "PURCHASE_ORDER_NOT_FOUND",
+            PharmacyError::ReorderSettingNotFound => "REORDER_SETTING_NOT_FOUND",
+            PharmacyError::ParLevelNotFound => "PAR_LEVEL_NOT_FOUND",
+            PharmacyError::TransferProposalNotFound => "TRANSFER_PROPOSAL_NOT_FOUND",
+            PharmacyError::EInvoiceNotFound => "EINVOICE_NOT_FOUND",
+            PharmacyError::PriceListNotFound => "PRICE_LIST_NOT_FOUND",
+            PharmacyError::PromotionNotFound => "PROMOTION_NOT_FOUND",
+            PharmacyError::NotAStore => "NOT_A_STORE_WAREHOUSE",
+            PharmacyError::InsufficientStock { .. } => "INSUFFICIENT_STOCK",
+            PharmacyError::Archived { .. } => "ENTITY_ARCHIVED",
+            PharmacyError::InUse { .. } => "ENTITY_IN_USE",
+            PharmacyError::InvalidStatusTransition { .. } => "INVALID_STATUS_TRANSITION",
+            PharmacyError::SaleAlreadyInvoiced { .. } => "SALE_ALREADY_INVOICED",
+            PharmacyError::EInvoiceNotAdjustable { .. } => "EINVOICE_NOT_ADJUSTABLE",
+            PharmacyError::EInvoiceNotConfigured { .. } => "EINVOICE_NOT_CONFIGURED",
+            PharmacyError::PriceListExists { .. } => "PRICE_LIST_EXISTS",
+            PharmacyError::PreconditionRequired => "PRECONDITION_REQUIRED",
+            PharmacyError::VersionMismatch { .. } => "VERSION_MISMATCH",
+            PharmacyError::IdempotencyKeyReused => "IDEMPOTENCY_KEY_REUSED",
+            PharmacyError::IdempotencyKeyInProgress => "IDEMPOTENCY_KEY_IN_PROGRESS",
+            PharmacyError::InvalidDate => "INVALID_DATE",
+            PharmacyError::Validation(_) => "VALIDATION_FAILED",
+            PharmacyError::AuditChainBroken { .. } => "AUDIT_CHAIN_BROKEN",
+            PharmacyError::Persistence(_) => "PERSISTENCE_FAILED",
+            PharmacyError::Export(_) => "EXPORT_FAILED",
+        }
+    }
+
+    pub fn status(&self) -> StatusCode {
+        match self {
+            PharmacyError::MedicineNotFound
+            | PharmacyError::WarehouseNotFound
+            | PharmacyError::StoreWarehouseNotFound
+            | PharmacyError::SupplierNotFound
+            | PharmacyError::BatchNotFound
+            | PharmacyError::SaleNotFound
+            | PharmacyError::ImportNotFound
+            | PharmacyError::TransferNotFound
+            | PharmacyError::PurchaseOrderNotFound
+            | PharmacyError::ReorderSettingNotFound
+            | PharmacyError::ParLevelNotFound
+            | PharmacyError::TransferProposalNotFound
+            | PharmacyError::EInvoiceNotFound
+            | PharmacyError::PriceListNotFound
+            | PharmacyError::PromotionNotFound => StatusCode::NOT_FOUND,
+            PharmacyError::InsufficientStock { .. }
+            | PharmacyError::Archived { .. }
+            | PharmacyError::InUse { .. }
+            | PharmacyError::InvalidStatusTransition { .. }
+            | PharmacyError::SaleAlreadyInvoiced { .. }
+            | PharmacyError::EInvoiceNotAdjustable { .. }
+            | PharmacyError::EInvoiceNotConfigured { .. }
+            | PharmacyError::PriceListExists { .. }
+            | PharmacyError::IdempotencyKeyInProgress
+            | PharmacyError::AuditChainBroken { .. } => StatusCode::CONFLICT,
+            PharmacyError::PreconditionRequired => StatusCode::PRECONDITION_REQUIRED,
+            PharmacyError::VersionMismatch { .. } => StatusCode::PRECONDITION_FAILED,
+            PharmacyError::InvalidDate
+            | PharmacyError::NotAStore
+            | PharmacyError::Validation(_)
+            | PharmacyError::IdempotencyKeyReused => StatusCode::UNPROCESSABLE_ENTITY,
+            PharmacyError::Persistence(_) | PharmacyError::Export(_) => {
+                StatusCode::INTERNAL_SERVER_ERROR
+            }
+        }
+    }
+}
+
+impl fmt::Display for PharmacyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PharmacyError::MedicineNotFound => write!(f, "Medicine not found"),
+            PharmacyError::WarehouseNotFound => write!(f, "Warehouse not found"),
+            PharmacyError::StoreWarehouseNotFound => write!(f, "Store warehouse not found"),
+            PharmacyError::SupplierNotFound => write!(f, "Supplier not found"),
+            PharmacyError::BatchNotFound => write!(f, "Batch not found"),
+            PharmacyError::SaleNotFound => write!(f, "Sale not found"),
+            PharmacyError::ImportNotFound => write!(f, "Import not found"),
+            PharmacyError::TransferNotFound => write!(f, "Transfer not found"),
+            PharmacyError::PurchaseOrderNotFound => write!(f, "Purchase order not found"),
+            PharmacyError::ReorderSettingNotFound => write!(f, "Reorder setting not found"),
+            PharmacyError::ParLevelNotFound => write!(f, "Par level not found"),
+            PharmacyError::TransferProposalNotFound => write!(f, "Transfer proposal not found"),
+            PharmacyError::EInvoiceNotFound => write!(f, "E-invoice not found"),
+            PharmacyError::PriceListNotFound => write!(f, "Price list not found"),
+            PharmacyError::PromotionNotFound => write!(f, "Promotion not found"),
+            PharmacyError::NotAStore => {
+                write!(f, "Par levels can only be set for Store warehouses")
+            }
+            PharmacyError::InsufficientStock { available } => {
+                write!(f, "Insufficient quantity. Available: {}", available)
+            }
+            PharmacyError::Archived { entity } => {
+                write!(f, "The {} is archived; restore it first", entity)
+            }
+            PharmacyError::InUse { reason } => write!(f, "Cannot archive: {}", reason),
+            PharmacyError::InvalidStatusTransition { from, to } => {
+                write!(f, "Cannot change status from {} to {}", from, to)
+            }
+            PharmacyError::SaleAlreadyInvoiced { invoice_id } => {
+                write!(f, "The sale is already on e-invoice {}", invoice_id)
+            }
+            PharmacyError::EInvoiceNotAdjustable { reason } => {
+                write!(f, "Cannot replace or adjust the e-invoice: {}", reason)
+            }
+            PharmacyError::EInvoiceNotConfigured { missing } => write!(
+                f,
+                "E-invoices cannot be issued until these settings are configured: {}",
+                missing.join(", ")
+            ),
+            PharmacyError::PriceListExists { price_list_id } => write!(
+                f,
+                "Price list {} already covers this channel and customer group",
+                price_list_id
+            ),
+            PharmacyError::PreconditionRequired => {
+                write!(f, "An If-Match header with the current version is required")
+            }
+            PharmacyError::VersionMismatch { current } => write!(
+                f,
+                "The record was changed by someone else. Current version: {}",
+                current
+            ),
+            PharmacyError::IdempotencyKeyReused => write!(
+                f,
+                "Idempotency-Key was already used for a different request"
+            ),
+            PharmacyError::IdempotencyKeyInProgress => write!(
+                f,
+                "A request with this Idempotency-Key is still being processed"
+            ),
+            PharmacyError::InvalidDate => write!(f, "Invalid date format"),
+            PharmacyError::Validation(errors) => {
+                let fields: Vec<String> = errors
+                    .iter()
+                    .map(|e| format!("{} {}", e.field, e.message))
+                    .collect();
+                write!(f, "Validation failed: {}", fields.join("; "))
+            }
+            PharmacyError::AuditChainBroken { entry_id } => {
+                write!(f, "Audit chain broken at entry {}", entry_id)
+            }
+            PharmacyError::Persistence(reason) => write!(f, "Failed to save data: {}", reason),
+            PharmacyError::Export(reason) => write!(f, "Failed to build the file: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for PharmacyError {}
+
+impl IntoResponse for PharmacyError {
+    fn into_response(self) -> Response {
+        let mut body = json!({
+            "code": self.code(),
+            "message": self.to_string(),
+        });
+        match &self {
+            PharmacyError::InsufficientStock { available } => {
+                body["available"] = json!(available);
+            }
+            PharmacyError::VersionMismatch { current } => {
+                body["current_version"] = json!(current);
+            }
+            PharmacyError::InvalidStatusTransition { from, to } => {
+                body["from"] = json!(from);
+                body["to"] = json!(to);
+            }
+            PharmacyError::SaleAlreadyInvoiced { invoice_id } => {
+                body["invoice_id"] = json!(invoice_id);
+            }
+            PharmacyError::EInvoiceNotConfigured { missing } => {
+                body["missing"] = json!(missing);
+            }
+            PharmacyError::PriceListExists { price_list_id } => {
+                body["price_list_id"] = json!(price_list_id);
+            }
+            PharmacyError::Validation(errors) => {
+                body["errors"] = json!(errors);
+            }
+            PharmacyError::AuditChainBroken { entry_id } => {
+                body["entry_id"] = json!(entry_id);
+            }
+            _ => {}
+        }
+        (self.status(), Json(body)).into_response()
+    }
+}
@@ -0,0 +1,725 @@
+use axum::body::Body;
+use axum::http::{StatusCode, header};
+use axum::response::{IntoResponse, Response};
+use chrono::{DateTime, Local, NaiveDateTime};
+use chrono_tz::Tz;
+use futures_util::stream;
+use rust_xlsxwriter::{Format, Workbook, XlsxError};
+use serde::{Deserialize, Serialize};
+use std::{env, fs, io, path::Path};
+use tokio::io::AsyncReadExt;
+use uuid::Uuid;
+
+use crate::analytics::{AbcXyzRow, DeadStockBatch};
+use crate::config::{Config, Locale};
+use crate::error::PharmacyError;
+use crate::forecast::BatchExpiryRisk;
+use crate::models::{ExportBatch, ImportBatch, InternalTransfer, StockBatch, Supplier};
+use crate::pricing::{ClearanceItem, PriceRule};
+use crate::sales::SalesRow;
+use crate::stock_card::StockMovement;
+use crate::tax::{VatRate, VatRow};
+use crate::valuation::{ValuationLine, ValuationReport};
+
+// CSV rows serialized per body chunk
+const CSV_CHUNK_ROWS: usize = 500;
+// XLSX bytes read from disk per body chunk
+const FILE_CHUNK_BYTES: usize = 64 * 1024;
+// Lets Excel detect UTF-8, so Vietnamese names survive opening the file
+const UTF8_BOM: &[u8] = b"\xEF\xBB\xBF";
+
+#[derive(Debug, Deserialize, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    #[default]
+    Json,
+    Csv,
+    Xlsx,
+}
+
+/// `format` and `locale` query parameters of anything that can be downloaded.
+#[derive(Debug, Deserialize, Default)]
+pub struct ExportQuery {
+    #[serde(default)]
+    pub format: ExportFormat,
+    pub locale: Option<Locale>,
+}
+
+pub enum Cell {
+    Text(String),
+    Integer(i64),
+    Number(f64),
+    Date(DateTime<Local>),
+    Empty,
+}
+
+impl From<String> for Cell {
+    fn from(value: String) -> Self {
+        Cell::Text(value)
+    }
+}
+
+impl From<u32> for Cell {
+    fn from(value: u32) -> Self {
+        Cell::Integer(value as i64)
+    }
+}
+
+impl From<i64> for Cell {
+    fn from(value: i64) -> Self {
+        Cell::Integer(value)
+    }
+}
+
+impl From<f64> for Cell {
+    fn from(value: f64) -> Self {
+        Cell::Number(value)
+    }
+}
+
+impl From<DateTime<Local>> for Cell {
+    fn from(value: DateTime<Local>) -> Self {
+        Cell::Date(value)
+    }
+}
+
+impl From<VatRate> for Cell {
+    fn from(value: VatRate) -> Self {
+        Cell::Integer(value.percent() as i64)
+    }
+}
+
+impl<T: Into<Cell>> From<Option<T>> for Cell {
+    fn from(value: Option<T>) -> Self {
+        value.map_or(Cell::Empty, Into::into)
+    }
+}
+
+/// A record that becomes one spreadsheet row.
+pub trait ExportRow {
+    const HEADERS: &'static [&'static str];
+    fn cells(&self) -> Vec<Cell>;
+}
+
+impl Locale {
+    fn csv_delimiter(self) -> u8 {
+        match self {
+            Locale::Vi => b';',
+            Locale::En => b',',
+        }
+    }
+
+    pub fn date_pattern(self) -> &'static str {
+        match self {
+            Locale::Vi => "%d/%m/%Y %H:%M",
+            Locale::En => "%m/%d/%Y %H:%M",
+        }
+    }
+
+    pub fn day_pattern(self) -> &'static str {
+        match self {
+            Locale::Vi => "%d/%m/%Y",
+            Locale::En => "%m/%d/%Y",
+        }
+    }
+
+    fn excel_date_format(self) -> &'static str {
+        match self {
+            Locale::Vi => "dd/mm/yyyy hh:mm",
+            Locale::En => "mm/dd/yyyy hh:mm",
+        }
+    }
+
+    fn separators(self) -> (char, char) {
+        match self {
+            Locale::Vi => ('.', ','),
+            Locale::En => (',', '.'),
+        }
+    }
+
+    /// Two decimals with thousands grouped, e.g. `1.234,50`.
+    pub fn number(self, value: f64) -> String {
+        let (thousands, decimal) = self.separators();
+        let fixed = format!("{:.2}", value.abs());
+        let (whole, fraction) = fixed.split_once('.').unwrap_or((&fixed, "00"));
+        let mut grouped = String::new();
+        for (i, digit) in whole.chars().enumerate() {
+            if i > 0 && (whole.len() - i) % 3 == 0 {
+                grouped.push(thousands);
+            }
+            grouped.push(digit);
+        }
+        let sign = if value < 0.0 && fixed != "0.00" {
+            "-"
+        } else {
+            ""
+        };
+        format!("{}{}{}{}", sign, grouped, decimal, fraction)
+    }
+}
+
+impl Cell {
+    fn csv_text(&self, locale: Locale, tz: Tz) -> String {
+        match self {
+            // Spreadsheets would run text like `=HYPERLINK(...)` as a formula
+            Cell::Text(text) if text.starts_with(['=', '+', '-', '@', '\t', '\r']) => {
+                format!("'{}", text)
+            }
+            Cell::Text(text) => text.clone(),
+            Cell::Integer(n) => n.to_string(),
+            Cell::Number(n) => locale.number(*n),
+            Cell::Date(at) => at
+                .with_timezone(&tz)
+                .format(locale.date_pattern())
+                .to_string(),
+            Cell::Empty => String::new(),
+        }
+    }
+}
+
+/// The serialized name of an enum value, e.g. `transfer_in`.
+fn label<T: Serialize>(value: &T) -> String {
+    match serde_json::to_value(value) {
+        Ok(serde_json::Value::String(name)) => name,
+        _ => String::new(),
+    }
+}
+
+impl ExportQuery {
+    pub fn locale(&self, config: &Config) -> Locale {
+        self.locale.unwrap_or(config.locale)
+    }
+
+    /// The rows as a CSV or XLSX file named `name`. Callers answer `json`
+    /// themselves, since its shape differs per endpoint.
+    ///
+    /// `rows` are collected by the caller, so they are held in memory once;
+    /// the file itself is streamed and never held whole.
+    pub async fn download<T: ExportRow + Send + 'static>(
+        &self,
+        rows: Vec<T>,
+        name: &str,
+        config: &Config,
+    ) -> Result<Response, PharmacyError> {
+        let locale = self.locale(config);
+        let tz = config.tz();
+        if self.format != ExportFormat::Xlsx {
+            return Ok(attachment(
+                "text/csv; charset=utf-8",
+                &format!("{}.csv", name),
+                csv_body(rows, locale, tz),
+            ));
+        }
+        let file = tokio::task::spawn_blocking(move || xlsx_file(&rows, locale, tz))
+            .await
+            .map_err(|e| PharmacyError::Export(e.to_string()))??;
+        Ok(attachment(
+            "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet",
+            &format!("{}.xlsx", name),
+            file_body(tokio::fs::File::from_std(file)),
+        ))
+    }
+}
+
+fn attachment(content_type: &str, filename: &str, body: Body) -> Response {
+    (
+        StatusCode::OK,
+        [
+            (header::CONTENT_TYPE, content_type.to_string()),
+            (
+                header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"{}\"", filename),
+            ),
+        ],
+        body,
+    )
+        .into_response()
+}
+
+// Rows are serialized a chunk at a time as the client reads, so the whole
+// file never has to be held in memory
+fn csv_body<T: ExportRow + Send + 'static>(rows: Vec<T>, locale: Locale, tz: Tz) -> Body {
+    let mut first = true;
+    let mut rows = rows.into_iter().peekable();
+    let chunks = std::iter::from_fn(move || {
+        if !first && rows.peek().is_none() {
+            return None;
+        }
+        let mut chunk = || -> csv::Result<Vec<u8>> {
+            let buffer = if first { UTF8_BOM.to_vec() } else { Vec::new() };
+            let mut writer = csv::WriterBuilder::new()
+                .delimiter(locale.csv_delimiter())
+                .from_writer(buffer);
+            if first {
+                first = false;
+                writer.write_record(T::HEADERS)?;
+            }
+            for row in rows.by_ref().take(CSV_CHUNK_ROWS) {
+                let cells = row.cells();
+                writer.write_record(cells.iter().map(|c| c.csv_text(locale, tz)))?;
+            }
+            writer
+                .into_inner()
+                .map_err(|e| csv::Error::from(e.into_error()))
+        };
+        Some(chunk().map_err(io::Error::other))
+    });
+    Body::from_stream(stream::iter(chunks))
+}
+
+// Written to a temporary file that is unlinked once open, so it is removed
+// when the response has been sent or dropped
+fn xlsx_file<T: ExportRow>(rows: &[T], locale: Locale, tz: Tz) -> Result<fs::File, PharmacyError> {
+    let path = env::temp_dir().join(format!("export-{}.xlsx", Uuid::new_v4()));
+    let file = xlsx(rows, locale, tz, &path)
+        .map_err(|e| PharmacyError::Export(e.to_string()))
+        .and_then(|()| fs::File::open(&path).map_err(|e| PharmacyError::Export(e.to_string())));
+    let _ = fs::remove_file(&path);
+    file
+}
+
+fn file_body(file: tokio::fs::File) -> Body {
+    let chunks = stream::unfold(Some(file), |file| async move {
+        let mut file = file?;
+        let mut buffer = vec![0; FILE_CHUNK_BYTES];
+        match file.read(&mut buffer).await {
+            Ok(0) => None,
+            Ok(read) => {
+                buffer.truncate(read);
+                Some((Ok(buffer), Some(file)))
+            }
+            Err(e) => Some((Err(e), None)),
+        }
+    });
+    Body::from_stream(chunks)
+}
+
+fn xlsx<T: ExportRow>(rows: &[T], locale: Locale, tz: Tz, path: &Path) -> Result<(), XlsxError> {
+    let mut workbook = Workbook::new();
+    let bold = Format::new().set_bold();
+    let number = Format::new().set_num_format("#,##0.00");
+    let date = Format::new().set_num_format(locale.excel_date_format());
+
+    // Rows go to a temporary file as they are written instead of staying in memory
+    let sheet = workbook.add_worksheet_with_constant_memory();
+    for (col, header) in T::HEADERS.iter().enumerate() {
+        sheet.write_string_with_format(0, col as u16, *header, &bold)?;
+    }
+    for (index, row) in rows.iter().enumerate() {
+        let r = index as u32 + 1;
+        for (col, cell) in row.cells().into_iter().enumerate() {
+            let col = col as u16;
+            match cell {
+                Cell::Text(text) => {
+                    sheet.write_string(r, col, text)?;
+                }
+                Cell::Integer(n) => {
+                    sheet.write_number(r, col, n as f64)?;
+                }
+                Cell::Number(n) => {
+                    sheet.write_number_with_format(r, col, n, &number)?;
+                }
+                Cell::Date(at) => {
+                    let local: NaiveDateTime = at.with_timezone(&tz).naive_local();
+                    sheet.write_datetime_with_format(r, col, local, &date)?;
+                }
+                Cell::Empty => {}
+            }
+        }
+    }
+    sheet.set_freeze_panes(1, 0)?;
+    workbook.save(path)
+}
+
+impl ExportRow for StockBatch {
+    const HEADERS: &'static [&'static str] = &[
+        "id",
+        "medicine_id",
+        "medicine_name",
+        "warehouse_id",
+        "supplier_id",
+        "quantity",
+        "unit_cost",
+        "selling_price",
+        "expiry_date",
+        "import_date",
+    ];
+
+    fn cells(&self) -> Vec<Cell> {
+        vec![
+            self.id.into(),
+            self.medicine_id.into(),
+            self.medicine_name.clone().into(),
+            self.warehouse_id.into(),
+            self.supplier_id.into(),
+            self.quantity.into(),
+            self.unit_cost.into(),
+            self.selling_price.into(),
+            self.expiry_date.into(),
+            self.import_date.into(),
+        ]
+    }
+}
+
+impl ExportRow for ImportBatch {
+    const HEADERS: &'static [&'static str] = &[
+        "id",
+        "medicine_id",
+        "medicine_name",
+        "warehouse_id",
+        "supplier_id",
+        "batch_id",
+        "quantity",
+        "unit_cost",
+        "selling_price",
+        "timestamp",
+    ];
+
+    fn cells(&self) -> Vec<Cell> {
+        vec![
+            self.id.into(),
+            self.medicine_id.into(),
+            self.medicine_name.clone().into(),
+            self.warehouse_id.into(),
+            self.supplier_id.into(),
+            self.batch_id.into(),
+            self.quantity.into(),
+            self.unit_cost.into(),
+            self.selling_price.into(),
+            self.timestamp.into(),
+        ]
+    }
+}
+
+impl ExportRow for ExportBatch {
+    const HEADERS: &'static [&'static str] = &[
+        "id",
+        "medicine_id",
+        "medicine_name",
+        "warehouse_id",
+        "amount",
+        "price",
+        "list_price",
+        "discount",
+        "price_list_id",
+        "price_rule",
+        "promotion_id",
+        "net_revenue",
+        "vat_rate",
+        "vat",
+        "revenue",
+        "cogs",
+        "cashier",
+        "timestamp",
+    ];
+
+    fn cells(&self) -> Vec<Cell> {
+        let pricing = self.pricing.as_ref();
+        vec![
+            self.id.into(),
+            self.medicine_id.into(),
+            self.medicine_name.clone().into(),
+            self.warehouse_id.into(),
+            self.amount.into(),
+            self.price.into(),
+            pricing.map(|p| p.list_price).into(),
+            pricing.map(|p| p.discount).into(),
+            pricing.and_then(|p| p.price_list_id).into(),
+            pricing.map(|p| p.rule.kind().to_string()).into(),
+            pricing
+                .and_then(|p| match p.rule {
+                    PriceRule::Promotion { promotion_id, .. } => Some(promotion_id),
+                    _ => None,
+                })
+                .into(),
+            self.net_revenue().into(),
+            self.tax.map(|t| t.vat_rate).into(),
+            self.tax.map(|t| t.vat).into(),
+            self.revenue().into(),
+            self.cogs().into(),
+            self.cashier.clone().into(),
+            self.timestamp.into(),
+        ]
+    }
+}
+
+impl ExportRow for InternalTransfer {
+    const HEADERS: &'static [&'static str] = &[
+        "id",
+        "medicine_id",
+        "medicine_name",
+        "from_warehouse_id",
+        "to_warehouse_id",
+        "quantity",
+        "batch_id",
+        "to_batch_id",
+        "timestamp",
+    ];
+
+    fn cells(&self) -> Vec<Cell> {
+        vec![
+            self.id.into(),
+            self.medicine_id.into(),
+            self.medicine_name.clone().into(),
+            self.from_warehouse_id.into(),
+            self.to_warehouse_id.into(),
+            self.quantity.into(),
+            self.batch_id.into(),
+            self.to_batch_id.into(),
+            self.timestamp.into(),
+        ]
+    }
+}
+
+impl ExportRow for Supplier {
+    const HEADERS: &'static [&'static str] =
+        &["id", "name", "contact", "phone", "address", "archived_at"];
+
+    fn cells(&self) -> Vec<Cell> {
+        vec![
+            self.id.into(),
+            self.name.clone().into(),
+            self.contact.clone().into(),
+            self.phone.clone().into(),
+            self.address.clone().into(),
+            self.archived_at.into(),
+        ]
+    }
+}
+
+impl ExportRow for SalesRow {
+    const HEADERS: &'static [&'static str] = &[
+        "period",
+        "key",
+        "name",
+        "units",
+        "revenue",
+        "cogs",
+        "gross_margin",
+        "margin_percent",
+        "uncosted_units",
+    ];
+
+    fn cells(&self) -> Vec<Cell> {
+        vec![
+            self.period.clone().into(),
+            self.key.clone().into(),
+            self.name.clone().into(),
+            self.units.into(),
+            self.revenue.into(),
+            self.cogs.into(),
+            self.gross_margin.into(),
+            self.margin_percent.into(),
+            self.uncosted_units.into(),
+        ]
+    }
+}
+
+impl ExportRow for ClearanceItem {
+    const HEADERS: &'static [&'static str] = &[
+        "batch_id",
+        "medicine_id",
+        "medicine_name",
+        "warehouse_id",
+        "quantity",
+        "expiry_date",
+        "days_to_expiry",
+        "percent",
+        "price",
+        "clearance_price",
+    ];
+
+    fn cells(&self) -> Vec<Cell> {
+        vec![
+            self.batch_id.into(),
+            self.medicine_id.into(),
+            self.medicine_name.clone().into(),
+            self.warehouse_id.into(),
+            self.quantity.into(),
+            self.expiry_date.into(),
+            self.days_to_expiry.into(),
+            u32::from(self.percent).into(),
+            self.price.into(),
+            self.clearance_price.into(),
+        ]
+    }
+}
+
+impl ExportRow for VatRow {
+    const HEADERS: &'static [&'static str] =
+        &["period", "vat_rate", "sales", "net", "vat", "gross"];
+
+    fn cells(&self) -> Vec<Cell> {
+        vec![
+            self.period.clone().into(),
+            self.vat_rate.into(),
+            self.sales.into(),
+            self.net.into(),
+            self.vat.into(),
+            self.gross.into(),
+        ]
+    }
+}
+
+/// One medicine in one warehouse of a valuation report.
+pub struct ValuationRow {
+    pub warehouse_id: u32,
+    pub warehouse_name: String,
+    pub line: ValuationLine,
+}
+
+impl ValuationReport {
+    pub fn into_rows(self) -> Vec<ValuationRow> {
+        self.warehouses
+            .into_iter()
+            .flat_map(|w| {
+                let (warehouse_id, warehouse_name) = (w.warehouse_id, w.warehouse_name);
+                w.lines.into_iter().map(move |line| ValuationRow {
+                    warehouse_id,
+                    warehouse_name: warehouse_name.clone(),
+                    line,
+                })
+            })
+            .collect()
+    }
+}
+
+impl ExportRow for ValuationRow {
+    const HEADERS: &'static [&'static str] = &[
+        "warehouse_id",
+        "warehouse_name",
+        "medicine_id",
+        "medicine_name",
+        "quantity",
+        "fifo_value",
+        "average_unit_cost",
+        "average_value",
+    ];
+
+    fn cells(&self) -> Vec<Cell> {
+        vec![
+            self.warehouse_id.into(),
+            self.warehouse_name.clone().into(),
+            self.line.medicine_id.into(),
+            self.line.medicine_name.clone().into(),
+            self.line.quantity.into(),
+            self.line.fifo_value.into(),
+            self.line.average_unit_cost.into(),
+            self.line.average_value.into(),
+        ]
+    }
+}
+
+impl ExportRow for StockMovement {
+    const HEADERS: &'static [&'static str] = &[
+        "timestamp",
+        "kind",
+        "reference",
+        "warehouse_id",
+        "batch_id",
+        "quantity_in",
+        "quantity_out",
+        "balance",
+    ];
+
+    fn cells(&self) -> Vec<Cell> {
+        vec![
+            self.timestamp.into(),
+            label(&self.kind).into(),
+            self.reference.into(),
+            self.warehouse_id.into(),
+            self.batch_id.into(),
+            self.quantity_in.into(),
+            self.quantity_out.into(),
+            self.balance.into(),
+        ]
+    }
+}
+
+impl ExportRow for BatchExpiryRisk {
+    const HEADERS: &'static [&'static str] = &[
+        "batch_id",
+        "medicine_id",
+        "medicine_name",
+        "warehouse_id",
+        "expiry_date",
+        "quantity",
+        "expected_unsold",
+        "sells_before_expiry",
+        "value_at_risk",
+    ];
+
+    fn cells(&self) -> Vec<Cell> {
+        vec![
+            self.batch_id.into(),
+            self.medicine_id.into(),
+            self.medicine_name.clone().into(),
+            self.warehouse_id.into(),
+            self.expiry_date.into(),
+            self.quantity.into(),
+            self.expected_unsold.into(),
+            self.sells_before_expiry.to_string().into(),
+            self.value_at_risk.into(),
+        ]
+    }
+}
+
+impl ExportRow for AbcXyzRow {
+    const HEADERS: &'static [&'static str] = &[
+        "medicine_id",
+        "medicine_name",
+        "units",
+        "revenue",
+        "revenue_share",
+        "cumulative_share",
+        "weekly_mean",
+        "variation",
+        "abc",
+        "xyz",
+    ];
+
+    fn cells(&self) -> Vec<Cell> {
+        vec![
+            self.medicine_id.into(),
+            self.medicine_name.clone().into(),
+            self.units.into(),
+            self.revenue.into(),
+            self.revenue_share.into(),
+            self.cumulative_share.into(),
+            self.weekly_mean.into(),
+            self.variation.into(),
+            label(&self.abc).into(),
+            label(&self.xyz).into(),
+        ]
+    }
+}
+
+impl ExportRow for DeadStockBatch {
+    const HEADERS: &'static [&'static str] = &[
+        "batch_id",
+        "medicine_id",
+        "medicine_name",
+        "warehouse_id",
+        "quantity",
+        "unit_cost",
+        "value",
+        "expiry_date",
+        "last_movement",
+        "idle_days",
+    ];
+
+    fn cells(&self) -> Vec<Cell> {
+        vec![
+            self.batch_id.into(),
+            self.medicine_id.into(),
+            self.medicine_name.clone().into(),
+            self.warehouse_id.into(),
+            self.quantity.into(),
+            self.unit_cost.into(),
+            self.value.into(),
+            self.expiry_date.into(),
+            self.last_movement.into(),
+            self.idle_days.into(),
+        ]
+    }
+}
@@ -0,0 +1,249 @@
+use axum::{
+    extract::{
+        FromRequest, FromRequestParts, RawPathParams, Request,
+        path::ErrorKind,
+        rejection::{JsonRejection, PathRejection, QueryRejection},
+    },
+    http::request::Parts,
+    response::{IntoResponse, Response},
+};
+use serde::{Serialize, de::DeserializeOwned};
+use std::error::Error;
+
+use crate::error::PharmacyError;
+use crate::validation::FieldError;
+
+// Drop-in replacements for axum's extractors that reject malformed requests
+// with the same JSON error body as every other validation failure.
+
+/// JSON request body, or JSON response.
+pub struct Json<T>(pub T);
+
+impl<T: DeserializeOwned, S: Send + Sync> FromRequest<S> for Json<T> {
+    type Rejection = PharmacyError;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        match axum::Json::<T>::from_request(req, state).await {
+            Ok(axum::Json(value)) => Ok(Json(value)),
+            Err(rejection) => Err(json_error(rejection)),
+        }
+    }
+}
+
+impl<T: Serialize> IntoResponse for Json<T> {
+    fn into_response(self) -> Response {
+        axum::Json(self.0).into_response()
+    }
+}
+
+/// Query string parameters.
+pub struct Query<T>(pub T);
+
+impl<T: DeserializeOwned, S: Send + Sync> FromRequestParts<S> for Query<T> {
+    type Rejection = PharmacyError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        match axum::extract::Query::<T>::from_request_parts(parts, state).await {
+            Ok(axum::extract::Query(value)) => Ok(Query(value)),
+            Err(rejection) => Err(query_error(rejection)),
+        }
+    }
+}
+
+/// Path parameters.
+pub struct Path<T>(pub T);
+
+impl<T: DeserializeOwned + Send, S: Send + Sync> FromRequestParts<S> for Path<T> {
+    type Rejection = PharmacyError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        match axum::extract::Path::<T>::from_request_parts(parts, state).await {
+            Ok(axum::extract::Path(value)) => Ok(Path(value)),
+            Err(rejection) => {
+                // A single value like `Path<u32>` is parsed without its name
+                let params = RawPathParams::from_request_parts(parts, state).await.ok();
+                let only_key = params
+                    .as_ref()
+                    .filter(|p| p.iter().count() == 1)
+                    .and_then(|p| p.iter().next())
+                    .map(|(key, _)| key.to_string());
+                Err(path_error(rejection, only_key))
+            }
+        }
+    }
+}
+
+fn json_error(rejection: JsonRejection) -> PharmacyError {
+    match rejection {
+        JsonRejection::JsonDataError(_) => {
+            match serde_error::<serde_json::Error>(&rejection, "body") {
+                Some(error) => error,
+                None => invalid("body", "INVALID_VALUE", rejection.body_text()),
+            }
+        }
+        JsonRejection::JsonSyntaxError(_) => {
+            let message = rejection
+                .source()
+                .and_then(Error::source)
+                .map(ToString::to_string)
+                .unwrap_or_else(|| rejection.body_text());
+            invalid("body", "INVALID_JSON", message)
+        }
+        JsonRejection::MissingJsonContentType(_) => invalid(
+            "body",
+            "INVALID_CONTENT_TYPE",
+            "must be sent with Content-Type: application/json".to_string(),
+        ),
+        _ => invalid("body", "INVALID_BODY", rejection.body_text()),
+    }
+}
+
+fn query_error(rejection: QueryRejection) -> PharmacyError {
+    match serde_error::<serde_urlencoded::de::Error>(&rejection, "query") {
+        Some(error) => error,
+        None => invalid("query", "INVALID_VALUE", rejection.body_text()),
+    }
+}
+
+fn path_error(rejection: PathRejection, only_key: Option<String>) -> PharmacyError {
+    let PathRejection::FailedToDeserializePathParams(error) = rejection else {
+        return invalid("path", "INVALID_VALUE", rejection.body_text());
+    };
+    let field = match error.kind() {
+        ErrorKind::ParseErrorAtKey { key, .. }
+        | ErrorKind::DeserializeError { key, .. }
+        | ErrorKind::InvalidUtf8InPathParam { key } => key.clone(),
+        _ => only_key.unwrap_or_else(|| "path".to_string()),
+    };
+    let message = match error.kind() {
+        ErrorKind::ParseErrorAtKey {
+            value,
+            expected_type,
+            ..
+        }
+        | ErrorKind::ParseErrorAtIndex {
+            value,
+            expected_type,
+            ..
+        }
+        | ErrorKind::ParseError {
+            value,
+            expected_type,
+        } => format!("`{}` is not a valid {}", value, expected_type),
+        kind => kind.to_string(),
+    };
+    invalid(&field, "INVALID_VALUE", message)
+}
+
+// Json and Query deserialize through serde_path_to_error, so the rejection's
+// source chain holds the path of the offending field.
+fn serde_error<E: Error + 'static>(
+    rejection: &(dyn Error + 'static),
+    whole: &str,
+) -> Option<PharmacyError> {
+    let mut source = rejection.source();
+    while let Some(error) = source {
+        if let Some(error) = error.downcast_ref::<serde_path_to_error::Error<E>>() {
+            let path = error.path().to_string();
+            let field = if path == "." { whole } else { &path };
+            return Some(invalid(field, "INVALID_VALUE", error.inner().to_string()));
+        }
+        source = error.source();
+    }
+    None
+}
+
+fn invalid(field: &str, code: &'static str, message: String) -> PharmacyError {
+    PharmacyError::Validation(vec![FieldError {
+        field: field.to_string(),
+        code,
+        message,
+    }])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{
+        Router,
+        body::{Body, to_bytes},
+        http::{StatusCode, header},
+        routing::{get, post},
+    };
+    use serde::Deserialize;
+    use serde_json::Value;
+    use tower::ServiceExt;
+
+    #[derive(Deserialize)]
+    struct Sale {
+        quantity: u32,
+    }
+
+    fn app() -> Router {
+        Router::new()
+            .route(
+                "/sales",
+                post(|Json(sale): Json<Sale>| async move { sale.quantity.to_string() }),
+            )
+            .route("/sales/{id}", get(|Path(_): Path<u32>| async {}))
+            .route(
+                "/search",
+                get(|Query(sale): Query<Sale>| async move { sale.quantity.to_string() }),
+            )
+    }
+
+    async fn rejection(request: Request<Body>) -> (StatusCode, Value) {
+        let response = app().oneshot(request).await.unwrap();
+        let status = response.status();
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    fn post_json(body: &str) -> Request<Body> {
+        Request::post("/sales")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap()
+    }
+
+    fn field_error(body: &Value) -> (&str, &str) {
+        let error = &body["errors"][0];
+        (
+            error["field"].as_str().unwrap(),
+            error["code"].as_str().unwrap(),
+        )
+    }
+
+    #[tokio::test]
+    async fn malformed_json_is_a_validation_error() {
+        let (status, body) = rejection(post_json("{\"quantity\": ")).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(body["code"], "VALIDATION_FAILED");
+        assert_eq!(field_error(&body), ("body", "INVALID_JSON"));
+    }
+
+    #[tokio::test]
+    async fn out_of_range_json_values_name_the_field() {
+        let (status, body) = rejection(post_json("{\"quantity\": -1}")).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(field_error(&body), ("quantity", "INVALID_VALUE"));
+    }
+
+    #[tokio::test]
+    async fn non_numeric_path_ids_name_the_parameter() {
+        let request = Request::get("/sales/abc").body(Body::empty()).unwrap();
+        let (status, body) = rejection(request).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(field_error(&body), ("id", "INVALID_VALUE"));
+    }
+
+    #[tokio::test]
+    async fn invalid_query_values_name_the_parameter() {
+        let request = Request::get("/search?quantity=-1")
+            .body(Body::empty())
+            .unwrap();
+        let (status, body) = rejection(request).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(field_error(&body), ("quantity", "INVALID_VALUE"));
+    }
+}
@@ -0,0 +1,366 @@
+use chrono::{DateTime, Datelike, Duration, Local, NaiveDate};
+use chrono_tz::Tz;
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+use crate::error::PharmacyError;
+use crate::models::Pharmacy;
+
+// Two-sided 95% band under a normal approximation
+const Z_95: f64 = 1.96;
+// Seasonal indices need every month of the year in the history
+const SEASONAL_MIN_WEEKS: usize = 52;
+// Expiry risk is not simulated further ahead than this
+const MAX_SIMULATION_DAYS: i64 = 3 * 365;
+
+/// Expected demand per day: a deseasonalised moving average, scaled by the
+/// seasonal index of the month, with the spread of recent weeks around it.
+#[derive(Debug, Clone)]
+pub struct DemandModel {
+    /// Average weekly demand with seasonality taken out.
+    pub baseline: f64,
+    /// Demand in each month relative to the average, January first; all 1.0
+    /// when there is less than a year of history.
+    pub seasonal_index: [f64; 12],
+    /// Standard deviation of recent weekly demand around the model.
+    pub weekly_deviation: f64,
+}
+
+impl DemandModel {
+    pub fn seasonal(&self) -> bool {
+        self.seasonal_index.iter().any(|i| *i != 1.0)
+    }
+
+    pub fn daily(&self, day: NaiveDate) -> f64 {
+        self.baseline * self.seasonal_index[day.month0() as usize] / 7.0
+    }
+
+    fn daily_band(&self, day: NaiveDate) -> (f64, f64) {
+        let daily = self.daily(day);
+        let spread = Z_95 * self.weekly_deviation / 7.0;
+        ((daily - spread).max(0.0), daily + spread)
+    }
+
+    /// First day on which `stock` units are used up at `rate`, within `days` days of `start`.
+    fn runs_out(
+        &self,
+        stock: f64,
+        start: NaiveDate,
+        days: i64,
+        rate: impl Fn(NaiveDate) -> f64,
+    ) -> Option<NaiveDate> {
+        let mut left = stock;
+        for offset in 0..days {
+            let day = start + Duration::days(offset);
+            left -= rate(day);
+            if left <= 0.0 {
+                return Some(day);
+            }
+        }
+        None
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct WeeklyDemand {
+    pub week_start: NaiveDate,
+    pub units: f64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct WeeklyForecast {
+    pub week_start: NaiveDate,
+    pub units: f64,
+    pub low: f64,
+    pub high: f64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Forecast {
+    pub medicine_id: u32,
+    pub medicine_name: String,
+    pub warehouse_id: Option<u32>,
+    pub baseline_weekly: f64,
+    pub seasonal: bool,
+    pub history: Vec<WeeklyDemand>,
+    pub weeks: Vec<WeeklyForecast>,
+    pub on_hand: u32,
+    /// Day stock runs out at the expected demand; `None` if not within the forecast.
+    pub stock_out_date: Option<NaiveDate>,
+    /// Same at the upper end of the band.
+    pub stock_out_earliest: Option<NaiveDate>,
+    /// Same at the lower end of the band.
+    pub stock_out_latest: Option<NaiveDate>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BatchExpiryRisk {
+    pub batch_id: u32,
+    pub medicine_id: u32,
+    pub medicine_name: String,
+    pub warehouse_id: u32,
+    pub expiry_date: DateTime<Local>,
+    pub quantity: u32,
+    /// Units expected to be left when the batch expires, selling earliest expiry first.
+    pub expected_unsold: u32,
+    pub sells_before_expiry: bool,
+    pub value_at_risk: f64,
+}
+
+impl Pharmacy {
+    /// Units sold per completed week, oldest first, with weeks without sales
+    /// as zero. Weeks start on Monday in time zone `tz`.
+    pub fn weekly_demand(
+        &self,
+        medicine_id: u32,
+        warehouse_id: Option<u32>,
+        tz: Tz,
+    ) -> Vec<WeeklyDemand> {
+        let mut weeks: BTreeMap<NaiveDate, f64> = BTreeMap::new();
+        for sale in self.export_log.iter().filter(|e| {
+            e.medicine_id == medicine_id && warehouse_id.is_none_or(|id| e.warehouse_id == Some(id))
+        }) {
+            *weeks
+                .entry(week_start(sale.timestamp.with_timezone(&tz).date_naive()))
+                .or_default() += sale.amount as f64;
+        }
+        let Some(first) = weeks.keys().next().copied() else {
+            return Vec::new();
+        };
+
+        let current = week_start(Local::now().with_timezone(&tz).date_naive());
+        let mut history = Vec::new();
+        let mut week = first;
+        while week < current {
+            history.push(WeeklyDemand {
+                week_start: week,
+                units: weeks.get(&week).copied().unwrap_or(0.0),
+            });
+            week += Duration::weeks(1);
+        }
+        history
+    }
+
+    pub fn demand_model(
+        &self,
+        medicine_id: u32,
+        warehouse_id: Option<u32>,
+        tz: Tz,
+        moving_average_weeks: usize,
+    ) -> DemandModel {
+        model_from(
+            &self.weekly_demand(medicine_id, warehouse_id, tz),
+            moving_average_weeks,
+        )
+    }
+
+    /// Units of a medicine expected to sell in the `days` days from today.
+    pub fn forecast_demand(
+        &self,
+        medicine_id: u32,
+        warehouse_id: Option<u32>,
+        tz: Tz,
+        moving_average_weeks: usize,
+        days: i64,
+    ) -> f64 {
+        let model = self.demand_model(medicine_id, warehouse_id, tz, moving_average_weeks);
+        let today = Local::now().with_timezone(&tz).date_naive();
+        (0..days)
+            .map(|offset| model.daily(today + Duration::days(offset)))
+            .sum()
+    }
+
+    /// Weekly demand for the next `weeks` weeks with a 95% band, and when the
+    /// stock on hand is expected to run out. Without a warehouse, sales and
+    /// stock of all warehouses and the catalog count.
+    pub fn forecast(
+        &self,
+        medicine_id: u32,
+        warehouse_id: Option<u32>,
+        tz: Tz,
+        moving_average_weeks: usize,
+        weeks: u32,
+    ) -> Result<Forecast, PharmacyError> {
+        let medicine_name = self
+            .known_medicine_name(medicine_id)
+            .ok_or(PharmacyError::MedicineNotFound)?
+            .to_string();
+        let history = self.weekly_demand(medicine_id, warehouse_id, tz);
+        let model = model_from(&history, moving_average_weeks);
+        let today = Local::now().with_timezone(&tz).date_naive();
+
+        let first_week = week_start(today);
+        let forecast_weeks = (0..weeks)
+            .map(|n| {
+                let start = first_week + Duration::weeks(n as i64);
+                let days = (0..7).map(|d| start + Duration::days(d));
+                let (units, low, high) = days.fold((0.0, 0.0, 0.0), |(u, l, h), day| {
+                    let (low, high) = model.daily_band(day);
+                    (u + model.daily(day), l + low, h + high)
+                });
+                WeeklyForecast {
+                    week_start: start,
+                    units,
+                    low,
+                    high,
+                }
+            })
+            .collect();
+
+        let catalog: u32 = match warehouse_id {
+            Some(_) => 0,
+            None => self
+                .inventory
+                .iter()
+                .filter(|m| m.id == medicine_id)
+                .map(|m| m.quantity)
+                .sum(),
+        };
+        let on_hand = catalog
+            + self
+                .stock_batches
+                .iter()
+                .filter(|b| {
+                    b.medicine_id == medicine_id
+                        && warehouse_id.is_none_or(|id| id == b.warehouse_id)
+                })
+                .map(|b| b.quantity)
+                .sum::<u32>();
+        let horizon = (first_week + Duration::weeks(weeks as i64) - today).num_days();
+        let stock = on_hand as f64;
+        Ok(Forecast {
+            medicine_id,
+            medicine_name,
+            warehouse_id,
+            baseline_weekly: model.baseline,
+            seasonal: model.seasonal(),
+            history,
+            weeks: forecast_weeks,
+            on_hand,
+            stock_out_date: model.runs_out(stock, today, horizon, |d| model.daily(d)),
+            stock_out_earliest: model.runs_out(stock, today, horizon, |d| model.daily_band(d).1),
+            stock_out_latest: model.runs_out(stock, today, horizon, |d| model.daily_band(d).0),
+        })
+    }
+
+    /// For each batch in stock, how much is expected to be left when it expires.
+    /// Each medicine's forecast demand, company-wide, consumes its batches
+    /// earliest expiry first; `warehouse_id` only limits which batches are listed.
+    pub fn expiry_risk(
+        &self,
+        warehouse_id: Option<u32>,
+        tz: Tz,
+        moving_average_weeks: usize,
+    ) -> Vec<BatchExpiryRisk> {
+        let now = Local::now();
+        let today = now.with_timezone(&tz).date_naive();
+        let mut by_medicine: BTreeMap<u32, Vec<_>> = BTreeMap::new();
+        for batch in self
+            .stock_batches
+            .iter()
+            .filter(|b| b.quantity > 0 && b.expiry_date > now)
+        {
+            by_medicine
+                .entry(batch.medicine_id)
+                .or_default()
+                .push(batch);
+        }
+
+        let mut risks = Vec::new();
+        for (medicine_id, mut batches) in by_medicine {
+            let model = self.demand_model(medicine_id, None, tz, moving_average_weeks);
+            batches.sort_by_key(|b| (b.expiry_date, b.id));
+
+            // Demand carried into the next batch once the current one runs out
+            let mut day = today;
+            let mut carried = 0.0;
+            for batch in batches {
+                let expires = batch.expiry_date.with_timezone(&tz).date_naive();
+                let mut left = batch.quantity as f64 - carried;
+                carried = 0.0;
+                while left > 0.0 && day < expires && (day - today).num_days() < MAX_SIMULATION_DAYS
+                {
+                    left -= model.daily(day);
+                    day += Duration::days(1);
+                }
+                if left < 0.0 {
+                    carried = -left;
+                }
+                let expected_unsold = left.max(0.0).round() as u32;
+                if warehouse_id.is_none_or(|id| id == batch.warehouse_id) {
+                    risks.push(BatchExpiryRisk {
+                        batch_id: batch.id,
+                        medicine_id,
+                        medicine_name: batch.medicine_name.clone(),
+                        warehouse_id: batch.warehouse_id,
+                        expiry_date: batch.expiry_date,
+                        quantity: batch.quantity,
+                        expected_unsold,
+                        sells_before_expiry: expected_unsold == 0,
+                        value_at_risk: expected_unsold as f64 * batch.unit_cost,
+                    });
+                }
+            }
+        }
+        risks.sort_by_key(|r| (r.expiry_date, r.batch_id));
+        risks
+    }
+}
+
+fn week_start(day: NaiveDate) -> NaiveDate {
+    day - Duration::days(day.weekday().num_days_from_monday() as i64)
+}
+
+// A week belongs to the month its Thursday falls in, as ISO weeks belong to years
+fn week_month(week_start: NaiveDate) -> usize {
+    (week_start + Duration::days(3)).month0() as usize
+}
+
+fn model_from(history: &[WeeklyDemand], moving_average_weeks: usize) -> DemandModel {
+    let mut seasonal_index = [1.0; 12];
+    let mean = history.iter().map(|w| w.units).sum::<f64>() / history.len().max(1) as f64;
+    if history.len() >= SEASONAL_MIN_WEEKS && mean > 0.0 {
+        let mut totals = [(0.0, 0usize); 12];
+        for week in history {
+            let month = &mut totals[week_month(week.week_start)];
+            month.0 += week.units;
+            month.1 += 1;
+        }
+        for (index, (units, count)) in seasonal_index.iter_mut().zip(totals) {
+            if count > 0 {
+                *index = units / count as f64 / mean;
+            }
+        }
+    }
+
+    // Months that never sell carry no information about the level
+    let recent = &history[history.len().saturating_sub(moving_average_weeks)..];
+    let deseasonalised: Vec<f64> = recent
+        .iter()
+        .filter_map(|w| {
+            let index = seasonal_index[week_month(w.week_start)];
+            (index > 0.0).then(|| w.units / index)
+        })
+        .collect();
+    let baseline = if deseasonalised.is_empty() {
+        0.0
+    } else {
+        deseasonalised.iter().sum::<f64>() / deseasonalised.len() as f64
+    };
+
+    let residuals: Vec<f64> = recent
+        .iter()
+        .map(|w| w.units - baseline * seasonal_index[week_month(w.week_start)])
+        .collect();
+    let weekly_deviation = if residuals.len() > 1 {
+        (residuals.iter().map(|r| r * r).sum::<f64>() / (residuals.len() - 1) as f64).sqrt()
+    } else {
+        0.0
+    };
+
+    DemandModel {
+        baseline,
+        seasonal_index,
+        weekly_deviation,
+    }
+}
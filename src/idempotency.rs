@@ -0,0 +1,176 @@
+use axum::{
+    body::{Body, Bytes, to_bytes},
+    extract::{Request, State},
+    http::{HeaderMap, HeaderValue, Method, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use chrono::{DateTime, Duration, Local};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use crate::error::PharmacyError;
+use crate::state;
+
+const IDEMPOTENCY_HEADER: &str = "idempotency-key";
+const REPLAYED_HEADER: &str = "idempotent-replayed";
+
+// Request bodies larger than this are not buffered for fingerprinting
+const MAX_BODY_BYTES: usize = 1024 * 1024;
+
+enum Outcome {
+    InFlight,
+    Completed {
+        status: StatusCode,
+        headers: HeaderMap,
+        body: Bytes,
+    },
+}
+
+struct Record {
+    fingerprint: String,
+    created_at: DateTime<Local>,
+    outcome: Outcome,
+}
+
+/// First response seen for each `Idempotency-Key`, kept for `window`.
+#[derive(Clone)]
+pub struct IdempotencyStore {
+    records: Arc<Mutex<HashMap<String, Record>>>,
+    window: Duration,
+}
+
+impl IdempotencyStore {
+    pub fn new(window: Duration) -> Self {
+        IdempotencyStore {
+            records: Arc::new(Mutex::new(HashMap::new())),
+            window,
+        }
+    }
+
+    fn records(&self) -> std::sync::MutexGuard<'_, HashMap<String, Record>> {
+        // A panic while holding this lock cannot leave a record half-written
+        self.records.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+// Drops the in-flight record if the request never completes (cancelled or panicked)
+struct InFlightGuard {
+    store: IdempotencyStore,
+    key: String,
+    armed: bool,
+}
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        if self.armed {
+            self.store.records().remove(&self.key);
+        }
+    }
+}
+
+/// Replays the stored response for a repeated `Idempotency-Key` on POST requests.
+/// Requests without the header pass straight through.
+pub async fn middleware(
+    State(store): State<IdempotencyStore>,
+    request: Request,
+    next: Next,
+) -> Response {
+    if request.method() != Method::POST {
+        return next.run(request).await;
+    }
+    let Some(key) = request
+        .headers()
+        .get(IDEMPOTENCY_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.trim().to_string())
+        .filter(|v| !v.is_empty())
+    else {
+        return next.run(request).await;
+    };
+
+    let (parts, body) = request.into_parts();
+    let Ok(body) = to_bytes(body, MAX_BODY_BYTES).await else {
+        return StatusCode::PAYLOAD_TOO_LARGE.into_response();
+    };
+    let fingerprint = format!(
+        "{:x}",
+        Sha256::new()
+            .chain_update(parts.method.as_str().as_bytes())
+            .chain_update(parts.uri.to_string().as_bytes())
+            .chain_update(&body)
+            .finalize()
+    );
+
+    {
+        let mut records = store.records();
+        let cutoff = Local::now() - store.window;
+        records.retain(|_, r| r.created_at > cutoff);
+
+        match records.get(&key) {
+            Some(record) if record.fingerprint != fingerprint => {
+                return PharmacyError::IdempotencyKeyReused.into_response();
+            }
+            Some(Record {
+                outcome: Outcome::InFlight,
+                ..
+            }) => return PharmacyError::IdempotencyKeyInProgress.into_response(),
+            Some(Record {
+                outcome:
+                    Outcome::Completed {
+                        status,
+                        headers,
+                        body,
+                    },
+                ..
+            }) => {
+                let mut response = (*status, headers.clone(), body.clone()).into_response();
+                response
+                    .headers_mut()
+                    .insert(REPLAYED_HEADER, HeaderValue::from_static("true"));
+                return response;
+            }
+            None => {
+                records.insert(
+                    key.clone(),
+                    Record {
+                        fingerprint,
+                        created_at: Local::now(),
+                        outcome: Outcome::InFlight,
+                    },
+                );
+            }
+        }
+    }
+
+    let mut guard = InFlightGuard {
+        store: store.clone(),
+        key: key.clone(),
+        armed: true,
+    };
+    let (response, committed) =
+        state::tracking_commits(next.run(Request::from_parts(parts, Body::from(body)))).await;
+    let (parts, body) = response.into_parts();
+    let body = to_bytes(body, usize::MAX).await.unwrap_or_default();
+
+    // Server-side failures leave the guard armed so the client can retry,
+    // unless a change was already saved and a retry would apply it twice
+    if parts.status.is_server_error() && !committed {
+        return Response::from_parts(parts, Body::from(body));
+    }
+    guard.armed = false;
+
+    let mut records = store.records();
+    if let Some(record) = records.get_mut(&key) {
+        record.outcome = Outcome::Completed {
+            status: parts.status,
+            headers: parts.headers.clone(),
+            body: body.clone(),
+        };
+    }
+
+    Response::from_parts(parts, Body::from(body))
+}
@@ -0,0 +1,678 @@
+use serde::Serialize;
+use std::collections::{BTreeMap, HashSet};
+
+use crate::audit::{AuditAction, AuditEntity};
+use crate::error::PharmacyError;
+use crate::models::Pharmacy;
+use crate::sequence::{ALL_SEQUENCES, Sequence};
+
+#[derive(Debug, Serialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+/// The collection an issue was found in.
+#[derive(Debug, Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Record {
+    Medicine,
+    Warehouse,
+    Supplier,
+    StockBatch,
+    ImportLog,
+    ExportLog,
+    TransferLog,
+    AuditLog,
+    ReorderSetting,
+    PurchaseOrder,
+    ParLevel,
+    TransferProposal,
+    EInvoice,
+    PriceList,
+    Promotion,
+}
+
+/// A repair that is safe to apply without a human decision.
+#[derive(Debug, Serialize, Clone, PartialEq)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Fix {
+    SetMedicineName {
+        record: Record,
+        id: u32,
+        name: String,
+    },
+    ClearSupplier {
+        record: Record,
+        id: u32,
+    },
+    AdvanceSequence {
+        sequence: Sequence,
+    },
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct IntegrityIssue {
+    pub severity: Severity,
+    pub code: &'static str,
+    pub record: Record,
+    pub id: u32,
+    pub message: String,
+    pub suggestion: String,
+    /// Present when `fix_integrity` can repair the issue automatically.
+    pub fix: Option<Fix>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct IntegrityReport {
+    pub ok: bool,
+    pub errors: usize,
+    pub warnings: usize,
+    pub issues: Vec<IntegrityIssue>,
+}
+
+impl IntegrityReport {
+    fn new(issues: Vec<IntegrityIssue>) -> Self {
+        let errors = issues
+            .iter()
+            .filter(|i| i.severity == Severity::Error)
+            .count();
+        IntegrityReport {
+            ok: errors == 0,
+            errors,
+            warnings: issues.len() - errors,
+            issues,
+        }
+    }
+}
+
+#[derive(Default)]
+struct Checker {
+    issues: Vec<IntegrityIssue>,
+}
+
+impl Checker {
+    fn report(
+        &mut self,
+        severity: Severity,
+        code: &'static str,
+        (record, id): (Record, u32),
+        message: String,
+        suggestion: &str,
+    ) -> &mut IntegrityIssue {
+        self.issues.push(IntegrityIssue {
+            severity,
+            code,
+            record,
+            id,
+            message,
+            suggestion: suggestion.to_string(),
+            fix: None,
+        });
+        self.issues.last_mut().unwrap()
+    }
+
+    fn duplicates(&mut self, record: Record, ids: impl Iterator<Item = u32>) {
+        let mut seen = HashSet::new();
+        let mut reported = HashSet::new();
+        for id in ids {
+            if !seen.insert(id) && reported.insert(id) {
+                self.report(
+                    Severity::Error,
+                    "DUPLICATE_ID",
+                    (record, id),
+                    format!("{:?} id {} is used more than once", record, id),
+                    "Renumber the later record; references to this id are ambiguous",
+                );
+            }
+        }
+    }
+}
+
+impl Pharmacy {
+    /// Checks references between collections, duplicate ids, balances and the audit chain.
+    pub fn check_integrity(&self) -> IntegrityReport {
+        let mut c = Checker::default();
+        let warehouse_exists = |id: u32| self.warehouses.iter().any(|w| w.id == id);
+        let supplier_exists = |id: u32| self.suppliers.iter().any(|s| s.id == id);
+        let batch_exists = |id: u32| self.stock_batches.iter().any(|b| b.id == id);
+
+        c.duplicates(Record::Medicine, self.inventory.iter().map(|m| m.id));
+        c.duplicates(Record::Warehouse, self.warehouses.iter().map(|w| w.id));
+        c.duplicates(Record::Supplier, self.suppliers.iter().map(|s| s.id));
+        c.duplicates(Record::StockBatch, self.stock_batches.iter().map(|b| b.id));
+        c.duplicates(Record::ImportLog, self.import_log.iter().map(|e| e.id));
+        c.duplicates(Record::ExportLog, self.export_log.iter().map(|e| e.id));
+        c.duplicates(Record::TransferLog, self.transfer_log.iter().map(|t| t.id));
+        c.duplicates(Record::AuditLog, self.audit_log.iter().map(|e| e.id));
+        c.duplicates(
+            Record::ReorderSetting,
+            self.reorder_settings.iter().map(|s| s.id),
+        );
+        c.duplicates(
+            Record::PurchaseOrder,
+            self.purchase_orders.iter().map(|o| o.id),
+        );
+        c.duplicates(Record::ParLevel, self.par_levels.iter().map(|p| p.id));
+        c.duplicates(
+            Record::TransferProposal,
+            self.transfer_proposals.iter().map(|p| p.id),
+        );
+        c.duplicates(Record::EInvoice, self.einvoices.iter().map(|i| i.id));
+        c.duplicates(Record::PriceList, self.price_lists.iter().map(|l| l.id));
+        c.duplicates(Record::Promotion, self.promotions.iter().map(|p| p.id));
+
+        for batch in &self.stock_batches {
+            let at = (Record::StockBatch, batch.id);
+            if !warehouse_exists(batch.warehouse_id) {
+                c.report(
+                    Severity::Error,
+                    "DANGLING_WAREHOUSE",
+                    at,
+                    format!(
+                        "Stock batch {} is in unknown warehouse {}",
+                        batch.id, batch.warehouse_id
+                    ),
+                    "Recreate the warehouse or move the batch to an existing one",
+                );
+            }
+            if let Some(supplier_id) = batch.supplier_id
+                && !supplier_exists(supplier_id)
+            {
+                c.report(
+                    Severity::Warning,
+                    "DANGLING_SUPPLIER",
+                    at,
+                    format!(
+                        "Stock batch {} references unknown supplier {}",
+                        batch.id, supplier_id
+                    ),
+                    "Clear the supplier or recreate it",
+                )
+                .fix = Some(Fix::ClearSupplier {
+                    record: Record::StockBatch,
+                    id: batch.id,
+                });
+            }
+            for (label, price) in [
+                ("unit cost", batch.unit_cost),
+                ("selling price", batch.selling_price),
+            ] {
+                if !price.is_finite() || price < 0.0 {
+                    c.report(
+                        Severity::Error,
+                        "INVALID_PRICE",
+                        at,
+                        format!("Stock batch {} has {} {}", batch.id, label, price),
+                        "Correct the price from the supplier invoice",
+                    );
+                }
+            }
+        }
+
+        for medicine in &self.inventory {
+            if !medicine.price.is_finite() || medicine.price < 0.0 {
+                c.report(
+                    Severity::Error,
+                    "INVALID_PRICE",
+                    (Record::Medicine, medicine.id),
+                    format!("Medicine {} has price {}", medicine.id, medicine.price),
+                    "Correct the selling price",
+                );
+            }
+        }
+
+        // Every record that copies a medicine name should agree with the catalog
+        let names = self
+            .stock_batches
+            .iter()
+            .map(|b| (Record::StockBatch, b.id, b.medicine_id, &b.medicine_name))
+            .chain(
+                self.import_log
+                    .iter()
+                    .map(|e| (Record::ImportLog, e.id, e.medicine_id, &e.medicine_name)),
+            )
+            .chain(
+                self.export_log
+                    .iter()
+                    .map(|e| (Record::ExportLog, e.id, e.medicine_id, &e.medicine_name)),
+            )
+            .chain(
+                self.transfer_log
+                    .iter()
+                    .map(|t| (Record::TransferLog, t.id, t.medicine_id, &t.medicine_name)),
+            );
+        for (record, id, medicine_id, name) in names {
+            match self.known_medicine_name(medicine_id) {
+                Some(known) if known != name => {
+                    // Only the catalog is authoritative; a name taken from another batch may be the wrong one
+                    let in_catalog = self.inventory.iter().any(|m| m.id == medicine_id);
+                    c.report(
+                        Severity::Warning,
+                        "MEDICINE_NAME_MISMATCH",
+                        (record, id),
+                        format!(
+                            "{:?} {} names medicine {} {:?}, but it is {:?}",
+                            record, id, medicine_id, name, known
+                        ),
+                        if in_catalog {
+                            "Rename it to match the catalog"
+                        } else {
+                            "Decide which name is correct and rename the other records"
+                        },
+                    )
+                    .fix = in_catalog.then(|| Fix::SetMedicineName {
+                        record,
+                        id,
+                        name: known.to_string(),
+                    });
+                }
+                None if record != Record::StockBatch => {
+                    c.report(
+                        Severity::Warning,
+                        "ORPHANED_LOG",
+                        (record, id),
+                        format!(
+                            "{:?} {} refers to unknown medicine {}",
+                            record, id, medicine_id
+                        ),
+                        "Keep it for history if the medicine was deleted on purpose",
+                    );
+                }
+                _ => {}
+            }
+        }
+
+        for entry in &self.import_log {
+            let at = (Record::ImportLog, entry.id);
+            if let Some(id) = entry.warehouse_id
+                && !warehouse_exists(id)
+            {
+                c.report(
+                    Severity::Error,
+                    "DANGLING_WAREHOUSE",
+                    at,
+                    format!(
+                        "Import log {} references unknown warehouse {}",
+                        entry.id, id
+                    ),
+                    "Recreate the warehouse",
+                );
+            }
+            if let Some(id) = entry.supplier_id
+                && !supplier_exists(id)
+            {
+                c.report(
+                    Severity::Warning,
+                    "DANGLING_SUPPLIER",
+                    at,
+                    format!("Import log {} references unknown supplier {}", entry.id, id),
+                    "Clear the supplier or recreate it",
+                )
+                .fix = Some(Fix::ClearSupplier {
+                    record: Record::ImportLog,
+                    id: entry.id,
+                });
+            }
+        }
+        for entry in &self.export_log {
+            if let Some(id) = entry.warehouse_id
+                && !warehouse_exists(id)
+            {
+                c.report(
+                    Severity::Error,
+                    "DANGLING_WAREHOUSE",
+                    (Record::ExportLog, entry.id),
+                    format!(
+                        "Export log {} references unknown warehouse {}",
+                        entry.id, id
+                    ),
+                    "Recreate the warehouse",
+                );
+            }
+        }
+        for transfer in &self.transfer_log {
+            let at = (Record::TransferLog, transfer.id);
+            if !batch_exists(transfer.batch_id) {
+                c.report(
+                    Severity::Warning,
+                    "ORPHANED_LOG",
+                    at,
+                    format!(
+                        "Transfer {} refers to unknown batch {}",
+                        transfer.id, transfer.batch_id
+                    ),
+                    "Keep it for history; the source batch can no longer be traced",
+                );
+            }
+            for id in [transfer.from_warehouse_id, transfer.to_warehouse_id] {
+                if !warehouse_exists(id) {
+                    c.report(
+                        Severity::Error,
+                        "DANGLING_WAREHOUSE",
+                        at,
+                        format!(
+                            "Transfer {} references unknown warehouse {}",
+                            transfer.id, id
+                        ),
+                        "Recreate the warehouse",
+                    );
+                }
+            }
+        }
+
+        for setting in &self.reorder_settings {
+            if !warehouse_exists(setting.warehouse_id) {
+                c.report(
+                    Severity::Warning,
+                    "DANGLING_WAREHOUSE",
+                    (Record::ReorderSetting, setting.id),
+                    format!(
+                        "Reorder setting {} references unknown warehouse {}",
+                        setting.id, setting.warehouse_id
+                    ),
+                    "Delete the setting",
+                );
+            }
+        }
+        for level in &self.par_levels {
+            if !warehouse_exists(level.warehouse_id) {
+                c.report(
+                    Severity::Warning,
+                    "DANGLING_WAREHOUSE",
+                    (Record::ParLevel, level.id),
+                    format!(
+                        "Par level {} references unknown warehouse {}",
+                        level.id, level.warehouse_id
+                    ),
+                    "Delete the par level",
+                );
+            }
+        }
+        for invoice in &self.einvoices {
+            let sale_ids = invoice.lines.iter().filter_map(|l| l.sale_id);
+            for id in sale_ids.filter(|&id| !self.export_log.iter().any(|s| s.id == id)) {
+                c.report(
+                    Severity::Warning,
+                    "DANGLING_SALE",
+                    (Record::EInvoice, invoice.id),
+                    format!("E-invoice {} references unknown sale {}", invoice.id, id),
+                    "Keep the invoice as issued; restore the sale from a backup if it is needed",
+                );
+            }
+        }
+        for proposal in &self.transfer_proposals {
+            for id in [proposal.from_warehouse_id, proposal.to_warehouse_id] {
+                if !warehouse_exists(id) {
+                    c.report(
+                        Severity::Warning,
+                        "DANGLING_WAREHOUSE",
+                        (Record::TransferProposal, proposal.id),
+                        format!(
+                            "Transfer proposal {} references unknown warehouse {}",
+                            proposal.id, id
+                        ),
+                        "Reject the proposal if it is still pending",
+                    );
+                }
+            }
+        }
+        for order in &self.purchase_orders {
+            let at = (Record::PurchaseOrder, order.id);
+            if !warehouse_exists(order.warehouse_id) {
+                c.report(
+                    Severity::Error,
+                    "DANGLING_WAREHOUSE",
+                    at,
+                    format!(
+                        "Purchase order {} is for unknown warehouse {}",
+                        order.id, order.warehouse_id
+                    ),
+                    "Recreate the warehouse or cancel the order",
+                );
+            }
+            if !supplier_exists(order.supplier_id) {
+                c.report(
+                    Severity::Error,
+                    "DANGLING_SUPPLIER",
+                    at,
+                    format!(
+                        "Purchase order {} is from unknown supplier {}",
+                        order.id, order.supplier_id
+                    ),
+                    "Recreate the supplier or cancel the order",
+                );
+            }
+        }
+
+        for sequence in ALL_SEQUENCES {
+            let (last, max) = (self.sequences.last(sequence), self.max_id(sequence));
+            if last < max {
+                c.report(
+                    Severity::Error,
+                    "SEQUENCE_BEHIND",
+                    (sequence_record(sequence), max),
+                    format!(
+                        "The {:?} sequence is at {} but id {} is already in use",
+                        sequence, last, max
+                    ),
+                    "Advance the sequence past the highest id",
+                )
+                .fix = Some(Fix::AdvanceSequence { sequence });
+            }
+        }
+
+        self.check_balances(&mut c);
+
+        if let Err(PharmacyError::AuditChainBroken { entry_id }) = self.verify_audit_chain() {
+            c.report(
+                Severity::Error,
+                "AUDIT_CHAIN_BROKEN",
+                (Record::AuditLog, entry_id),
+                format!("Audit entry {} was modified or removed", entry_id),
+                "Restore the data file from a backup taken before the change",
+            );
+        }
+
+        IntegrityReport::new(c.issues)
+    }
+
+    // Stock on hand should equal everything received minus everything sold.
+    // Catalog medicines and warehouse batches keep separate books: their log
+    // entries are told apart by whether they name a warehouse.
+    fn check_balances(&self, c: &mut Checker) {
+        let mut catalog: BTreeMap<u32, i64> = BTreeMap::new();
+        let mut batches: BTreeMap<u32, i64> = BTreeMap::new();
+        for entry in &self.import_log {
+            let book = if entry.warehouse_id.is_some() {
+                &mut batches
+            } else {
+                &mut catalog
+            };
+            *book.entry(entry.medicine_id).or_default() += entry.quantity as i64;
+        }
+        for entry in &self.export_log {
+            let book = if entry.warehouse_id.is_some() {
+                &mut batches
+            } else {
+                &mut catalog
+            };
+            *book.entry(entry.medicine_id).or_default() -= entry.amount as i64;
+        }
+
+        for medicine in &self.inventory {
+            let expected = catalog.get(&medicine.id).copied().unwrap_or(0);
+            if expected != medicine.quantity as i64 {
+                c.report(
+                    Severity::Warning,
+                    "BALANCE_MISMATCH",
+                    (Record::Medicine, medicine.id),
+                    format!(
+                        "Medicine {} has {} units, but its logs add up to {}",
+                        medicine.id, medicine.quantity, expected
+                    ),
+                    "Count the stock and record the difference as an adjustment",
+                );
+            }
+        }
+
+        let mut on_hand: BTreeMap<u32, i64> = BTreeMap::new();
+        for batch in &self.stock_batches {
+            *on_hand.entry(batch.medicine_id).or_default() += batch.quantity as i64;
+        }
+        for (medicine_id, expected) in batches {
+            let actual = on_hand.get(&medicine_id).copied().unwrap_or(0);
+            if expected < 0 {
+                c.report(
+                    Severity::Error,
+                    "NEGATIVE_BALANCE",
+                    (Record::Medicine, medicine_id),
+                    format!(
+                        "More units of medicine {} were sold from warehouses than received ({})",
+                        medicine_id, expected
+                    ),
+                    "Look for missing import records",
+                );
+            } else if expected != actual {
+                c.report(
+                    Severity::Warning,
+                    "BALANCE_MISMATCH",
+                    (Record::Medicine, medicine_id),
+                    format!(
+                        "Batches of medicine {} hold {} units, but its logs add up to {}",
+                        medicine_id, actual, expected
+                    ),
+                    "Count the stock and record the difference as an adjustment",
+                );
+            }
+        }
+    }
+
+    /// Applies every automatic fix from a fresh check and returns the issues it repaired.
+    pub fn fix_integrity(&mut self, user: &str) -> Vec<IntegrityIssue> {
+        let mut fixable: Vec<IntegrityIssue> = Vec::new();
+        for issue in self.check_integrity().issues {
+            // Records sharing a duplicate id report the same fix once each
+            if issue.fix.is_some() && !fixable.iter().any(|f| f.fix == issue.fix) {
+                fixable.push(issue);
+            }
+        }
+        for issue in &fixable {
+            if let Some(fix) = &issue.fix {
+                self.apply_fix(user, fix);
+            }
+        }
+        fixable
+    }
+
+    fn apply_fix(&mut self, user: &str, fix: &Fix) {
+        match fix {
+            Fix::SetMedicineName { record, id, name } => match record {
+                Record::StockBatch => self.update_record(
+                    user,
+                    AuditEntity::StockBatch,
+                    *id,
+                    |p| &mut p.stock_batches,
+                    |b| b.id,
+                    |b| b.medicine_name = name.clone(),
+                ),
+                Record::ImportLog => self.update_record(
+                    user,
+                    AuditEntity::ImportLog,
+                    *id,
+                    |p| &mut p.import_log,
+                    |e| e.id,
+                    |e| e.medicine_name = name.clone(),
+                ),
+                Record::ExportLog => self.update_record(
+                    user,
+                    AuditEntity::ExportLog,
+                    *id,
+                    |p| &mut p.export_log,
+                    |e| e.id,
+                    |e| e.medicine_name = name.clone(),
+                ),
+                Record::TransferLog => self.update_record(
+                    user,
+                    AuditEntity::TransferLog,
+                    *id,
+                    |p| &mut p.transfer_log,
+                    |t| t.id,
+                    |t| t.medicine_name = name.clone(),
+                ),
+                _ => {}
+            },
+            Fix::ClearSupplier { record, id } => match record {
+                Record::StockBatch => self.update_record(
+                    user,
+                    AuditEntity::StockBatch,
+                    *id,
+                    |p| &mut p.stock_batches,
+                    |b| b.id,
+                    |b| b.supplier_id = None,
+                ),
+                Record::ImportLog => self.update_record(
+                    user,
+                    AuditEntity::ImportLog,
+                    *id,
+                    |p| &mut p.import_log,
+                    |e| e.id,
+                    |e| e.supplier_id = None,
+                ),
+                _ => {}
+            },
+            Fix::AdvanceSequence { .. } => {
+                self.advance_sequences();
+            }
+        }
+    }
+
+    // Every repaired record, logs included, leaves a before/after entry in the audit log
+    fn update_record<T: Clone + Serialize>(
+        &mut self,
+        user: &str,
+        entity: AuditEntity,
+        id: u32,
+        records: impl Fn(&mut Pharmacy) -> &mut Vec<T>,
+        id_of: impl Fn(&T) -> u32,
+        change: impl Fn(&mut T),
+    ) {
+        let mut changes = Vec::new();
+        for record in records(self).iter_mut().filter(|r| id_of(r) == id) {
+            let before = record.clone();
+            change(record);
+            changes.push((before, record.clone()));
+        }
+        for (before, after) in changes {
+            self.record_audit(
+                user,
+                AuditAction::Update,
+                entity,
+                id,
+                Some(&before),
+                Some(&after),
+            );
+        }
+    }
+}
+
+fn sequence_record(sequence: Sequence) -> Record {
+    match sequence {
+        Sequence::Medicine => Record::Medicine,
+        Sequence::Warehouse => Record::Warehouse,
+        Sequence::Supplier => Record::Supplier,
+        Sequence::StockBatch => Record::StockBatch,
+        Sequence::ImportLog => Record::ImportLog,
+        Sequence::ExportLog => Record::ExportLog,
+        Sequence::TransferLog => Record::TransferLog,
+        Sequence::AuditLog => Record::AuditLog,
+        Sequence::ReorderSetting => Record::ReorderSetting,
+        Sequence::PurchaseOrder => Record::PurchaseOrder,
+        Sequence::ParLevel => Record::ParLevel,
+        Sequence::TransferProposal => Record::TransferProposal,
+        Sequence::EInvoice => Record::EInvoice,
+        Sequence::PriceList => Record::PriceList,
+        Sequence::Promotion => Record::Promotion,
+    }
+}
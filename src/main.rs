@@ -1,40 +1,248 @@
+mod analytics;
+mod archive;
+mod audit;
+mod bulk_import;
+mod cli;
+mod config;
+mod documents;
+mod einvoice;
+mod einvoice_provider;
+mod einvoice_xml;
+mod error;
+mod export;
+mod extract;
+mod forecast;
+mod idempotency;
+mod integrity;
+mod migrate;
 mod models;
+mod pdf;
+mod pricing;
+mod purchase_order;
+mod query;
+mod replenishment;
+mod sales;
+mod sequence;
+mod state;
+mod stock_card;
+mod store_replenishment;
+mod tax;
+mod validation;
+mod valuation;
 
+use analytics::{AbcXyzReport, DeadStockReport};
+use audit::{AuditEntity, AuditEntry, AuditFilter};
 use axum::{
-    Json, Router,
-    extract::{Path, State},
-    http::StatusCode,
-    response::{Html, IntoResponse},
+    Router,
+    body::Bytes,
+    extract::{FromRef, FromRequestParts, State},
+    http::{StatusCode, header, request::Parts},
+    middleware,
+    response::{Html, IntoResponse, Response},
     routing::{delete, get, post, put},
 };
-use chrono::{DateTime, Local};
+use bulk_import::{
+    AddMedicineRequest, BulkRow, CreateSupplierRequest, ImportBatchRequest, ImportKind, Sheet,
+    SheetFormat, import_sheet,
+};
+use chrono::{Duration, Local};
+use clap::Parser;
+use cli::{Cli, Command};
+use config::Config;
+use documents::{DocumentQuery, pdf_response};
+use einvoice::{Buyer, EInvoice, EInvoiceKind, EInvoiceStatus, LineCorrection};
+use einvoice_provider::EInvoiceProvider;
+use error::PharmacyError;
+use export::{ExportFormat, ExportQuery};
+use extract::{Json, Path, Query};
+use forecast::{BatchExpiryRisk, Forecast};
+use idempotency::IdempotencyStore;
+use integrity::{IntegrityIssue, IntegrityReport};
 use models::{
-    ExportBatch, ImportBatch, InternalTransfer, Medicine, Pharmacy, StockBatch, Supplier,
-    Warehouse, WarehouseType,
+    Medicine, Pharmacy, StockBatch, Supplier, SupplierPatch, Warehouse, WarehousePatch,
+    WarehouseType,
 };
-use serde::Deserialize;
-use std::{
-    fs,
-    net::SocketAddr,
-    sync::{Arc, Mutex},
+use pdf::{Fonts, Paper};
+use pricing::{
+    Channel, ClearanceItem, ListPrice, Offer, PriceContext, PriceList, Promotion, Quote,
 };
+use purchase_order::{PurchaseOrder, PurchaseOrderStatus};
+use query::{ListQuery, parse_optional_date, parse_report_date, parse_report_start};
+use replenishment::{
+    ReorderSetting, ReorderSettingInput, ReplenishmentParams, SupplierSuggestions,
+};
+use sales::{GroupBy, Period};
+use serde::Deserialize;
+use serde_json::json;
+use state::SharedPharmacy;
+use std::{collections::BTreeMap, convert::Infallible, fs, process, sync::Arc};
+use store_replenishment::{ParLevel, ProposalStatus, TransferProposal};
+use tax::VatRate;
 use tower_http::services::ServeDir;
+use validation::{FieldError, Validate, Validator};
+
+type AppState = Arc<SharedPharmacy>;
+
+/// Everything handlers can extract with `State`: the pharmacy data, the
+/// configuration, the fonts for printed documents or the e-invoice provider.
+#[derive(Clone)]
+struct AppContext {
+    pharmacy: AppState,
+    config: Arc<Config>,
+    fonts: Arc<Fonts>,
+    einvoice_provider: Arc<dyn EInvoiceProvider>,
+}
+
+impl FromRef<AppContext> for AppState {
+    fn from_ref(context: &AppContext) -> Self {
+        context.pharmacy.clone()
+    }
+}
+
+impl FromRef<AppContext> for Arc<Config> {
+    fn from_ref(context: &AppContext) -> Self {
+        context.config.clone()
+    }
+}
+
+impl FromRef<AppContext> for Arc<Fonts> {
+    fn from_ref(context: &AppContext) -> Self {
+        context.fonts.clone()
+    }
+}
 
-const DATA_FILE: &str = "data.json";
+impl FromRef<AppContext> for Arc<dyn EInvoiceProvider> {
+    fn from_ref(context: &AppContext) -> Self {
+        context.einvoice_provider.clone()
+    }
+}
+
+/// Staff member performing the request, taken from the `X-User` header.
+struct Actor(String);
+
+impl<S: Send + Sync> FromRequestParts<S> for Actor {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let user = parts
+            .headers
+            .get("x-user")
+            .and_then(|v| v.to_str().ok())
+            .map(str::trim)
+            .filter(|v| !v.is_empty())
+            .unwrap_or("anonymous");
+        Ok(Actor(user.to_string()))
+    }
+}
+
+/// Version the client expects to overwrite, from the `If-Match` header.
+/// `If-Match: *` yields `None` and skips the version check.
+struct IfMatch(Option<u32>);
 
-type AppState = Arc<Mutex<Pharmacy>>;
+impl<S: Send + Sync> FromRequestParts<S> for IfMatch {
+    type Rejection = PharmacyError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let value = parts
+            .headers
+            .get(header::IF_MATCH)
+            .and_then(|v| v.to_str().ok())
+            .map(str::trim)
+            .ok_or(PharmacyError::PreconditionRequired)?;
+
+        if value == "*" {
+            return Ok(IfMatch(None));
+        }
+        value
+            .trim_start_matches("W/")
+            .trim_matches('"')
+            .parse()
+            .map(|version| IfMatch(Some(version)))
+            .map_err(|_| PharmacyError::PreconditionRequired)
+    }
+}
+
+fn etag(version: u32) -> [(header::HeaderName, String); 1] {
+    [(header::ETAG, format!("\"{}\"", version))]
+}
 
 #[tokio::main]
 async fn main() {
+    let cli = Cli::parse();
+    let config = Config::load(&cli.overrides).unwrap_or_else(|problems| exit_invalid(problems));
+
+    match cli.command.unwrap_or(Command::Serve) {
+        Command::Serve => serve(config).await,
+        command => match cli::run(command, &config) {
+            Ok(true) => {}
+            Ok(false) => process::exit(1),
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                process::exit(1);
+            }
+        },
+    }
+}
+
+fn exit_invalid(problems: Vec<String>) -> ! {
+    eprintln!("Invalid configuration:");
+    for problem in problems {
+        eprintln!("  - {}", problem);
+    }
+    process::exit(1);
+}
+
+async fn serve(config: Config) {
     // Load initial data
-    let pharmacy = load_data();
-    let state = Arc::new(Mutex::new(pharmacy));
+    let state = SharedPharmacy::open(&config.data_file).unwrap_or_else(|e| {
+        eprintln!("{}; refusing to start", e);
+        process::exit(1);
+    });
+    let mut problems = Vec::new();
+    if !config.assets_dir.is_dir() {
+        problems.push(format!(
+            "assets_dir: {} is not a directory",
+            config.assets_dir.display()
+        ));
+    }
+    if let Some(id) = config.default_warehouse_id
+        && let Err(e) = state.read().active_warehouse(id)
+    {
+        problems.push(format!("default_warehouse_id: warehouse {}: {}", id, e));
+    }
+    let fonts = Fonts::load(&config, &mut problems);
+    if !problems.is_empty() {
+        exit_invalid(problems);
+    }
+    {
+        let pharmacy = state.read();
+        let integrity = pharmacy.check_integrity();
+        if !integrity.issues.is_empty() {
+            eprintln!(
+                "Integrity check: {} error(s), {} warning(s); run the `verify` command for details",
+                integrity.errors, integrity.warnings
+            );
+        }
+    }
+    let idempotency = IdempotencyStore::new(Duration::hours(config.idempotency_window_hours));
+    let addr = config.listen;
+    let context = AppContext {
+        pharmacy: state,
+        config: Arc::new(config.clone()),
+        fonts: Arc::new(fonts),
+        einvoice_provider: einvoice_provider::provider(&config.einvoice),
+    };
 
     // Define routes
     let app = Router::new()
         .route("/api/medicines", get(list_medicines).post(add_medicine))
-        .route("/api/medicines/{id}", delete(delete_medicine))
+        .route("/api/medicines/{id}", delete(archive_medicine))
+        .route("/api/medicines/{id}/restore", post(restore_medicine))
+        .route("/api/medicines/{id}/vat-rate", post(set_medicine_vat_rate))
+        .route("/api/medicines/{id}/stock-card", get(get_stock_card))
+        .route("/api/medicines/{id}/forecast", get(get_forecast))
         .route("/api/sell", post(sell_medicine))
+        .route("/api/sell-batch", post(sell_batch_handler))
         .route("/api/batches/import", get(get_import_batches))
         .route("/api/batches/export", get(get_export_batches))
         // Warehouse routes
@@ -42,21 +250,119 @@ async fn main() {
             "/api/warehouses",
             get(list_warehouses).post(create_warehouse),
         )
-        .route("/api/warehouses/{id}", put(edit_warehouse))
+        .route(
+            "/api/warehouses/{id}",
+            get(get_warehouse)
+                .put(edit_warehouse)
+                .patch(patch_warehouse)
+                .delete(archive_warehouse),
+        )
+        .route("/api/warehouses/{id}/restore", post(restore_warehouse))
         .route("/api/stock-batches", get(list_stock_batches))
         .route("/api/import-batch", post(import_batch_handler))
         .route("/api/transfer-batch", post(transfer_batch_handler))
+        .route("/api/bulk-import/{kind}", post(bulk_import_handler))
         .route("/api/expiring-batches", get(get_expiring_batches))
         .route("/api/transfers", get(get_transfers))
         // Supplier routes
         .route("/api/suppliers", get(list_suppliers).post(create_supplier))
-        .route("/api/suppliers/{id}", put(edit_supplier))
-        .nest_service("/assets", ServeDir::new("assets"))
+        .route(
+            "/api/suppliers/{id}",
+            get(get_supplier)
+                .put(edit_supplier)
+                .patch(patch_supplier)
+                .delete(archive_supplier),
+        )
+        .route("/api/suppliers/{id}/restore", post(restore_supplier))
+        // Replenishment routes
+        .route(
+            "/api/reorder-settings",
+            get(list_reorder_settings).put(set_reorder_setting),
+        )
+        .route("/api/reorder-settings/{id}", delete(delete_reorder_setting))
+        .route(
+            "/api/replenishment/purchase-suggestions",
+            get(get_purchase_suggestions),
+        )
+        .route("/api/par-levels", get(list_par_levels).put(set_par_level))
+        .route("/api/par-levels/{id}", delete(delete_par_level))
+        .route("/api/transfer-proposals", get(list_transfer_proposals))
+        .route(
+            "/api/transfer-proposals/generate",
+            post(generate_transfer_proposals),
+        )
+        .route(
+            "/api/transfer-proposals/{id}/approve",
+            post(approve_transfer_proposal),
+        )
+        .route(
+            "/api/transfer-proposals/{id}/reject",
+            post(reject_transfer_proposal),
+        )
+        .route("/api/purchase-orders", get(list_purchase_orders))
+        .route("/api/purchase-orders/drafts", post(draft_purchase_orders))
+        .route("/api/purchase-orders/{id}", get(get_purchase_order))
+        .route(
+            "/api/purchase-orders/{id}/status",
+            post(set_purchase_order_status),
+        )
+        // Audit routes
+        .route("/api/audit", get(query_audit))
+        .route("/api/audit/verify", get(verify_audit))
+        // Report routes
+        .route("/api/reports/valuation", get(get_valuation))
+        .route("/api/reports/sales", get(get_sales_report))
+        .route("/api/reports/vat", get(get_vat_report))
+        .route("/api/reports/expiry-risk", get(get_expiry_risk))
+        .route("/api/reports/abc-xyz", get(get_abc_xyz))
+        .route("/api/reports/dead-stock", get(get_dead_stock))
+        // Printable documents
+        .route("/api/documents/receipts/{id}", get(get_sale_receipt))
+        .route(
+            "/api/documents/goods-receipts/{id}",
+            get(get_goods_receipt_note),
+        )
+        .route("/api/documents/transfers/{id}", get(get_transfer_note))
+        .route(
+            "/api/documents/purchase-orders/{id}",
+            get(get_purchase_order_document),
+        )
+        // E-invoice routes
+        .route("/api/einvoices", get(list_einvoices).post(issue_einvoice))
+        .route("/api/einvoices/{id}", get(get_einvoice))
+        .route("/api/einvoices/{id}/xml", get(get_einvoice_xml))
+        .route("/api/einvoices/{id}/submit", post(submit_einvoice))
+        .route("/api/einvoices/{id}/replace", post(replace_einvoice))
+        .route("/api/einvoices/{id}/adjust", post(adjust_einvoice))
+        // Pricing routes
+        .route(
+            "/api/price-lists",
+            get(list_price_lists).post(create_price_list),
+        )
+        .route(
+            "/api/price-lists/{id}",
+            get(get_price_list).delete(delete_price_list),
+        )
+        .route("/api/price-lists/{id}/prices", put(set_list_prices))
+        .route(
+            "/api/promotions",
+            get(list_promotions).post(create_promotion),
+        )
+        .route("/api/promotions/{id}/end", post(end_promotion))
+        .route("/api/pricing/quote", get(get_quote))
+        .route("/api/pricing/clearance", get(get_clearance))
+        // Admin routes
+        .route("/api/admin/integrity", get(check_integrity))
+        .route("/api/admin/integrity/fix", post(fix_integrity))
+        .route("/api/config", get(get_client_config))
+        .nest_service("/assets", ServeDir::new(&config.assets_dir))
         .route("/", get(index_handler))
-        .with_state(state.clone());
+        .layer(middleware::from_fn_with_state(
+            idempotency,
+            idempotency::middleware,
+        ))
+        .with_state(context);
 
-    // Address
-    let addr = SocketAddr::from(([127, 0, 0, 1], 3000));
     println!("Listening on http://{}", addr);
 
     // Start server
@@ -64,81 +370,303 @@ async fn main() {
     axum::serve(listener, app).await.unwrap();
 }
 
-async fn index_handler() -> impl IntoResponse {
-    match fs::read_to_string("assets/index.html") {
+async fn index_handler(State(config): State<Arc<Config>>) -> impl IntoResponse {
+    match fs::read_to_string(config.assets_dir.join("index.html")) {
         Ok(html) => Html(html).into_response(),
         Err(_) => (StatusCode::NOT_FOUND, "Index file not found").into_response(),
     }
 }
 
-async fn list_medicines(State(state): State<AppState>) -> Json<Vec<Medicine>> {
-    let pharmacy = state.lock().unwrap();
-    Json(pharmacy.inventory.clone())
+// Settings the web UI needs; file paths and the listen address stay private
+async fn get_client_config(State(config): State<Arc<Config>>) -> Json<serde_json::Value> {
+    Json(json!({
+        "currency": config.currency,
+        "timezone": config.timezone,
+        "locale": config.locale,
+        "default_warehouse_id": config.default_warehouse_id,
+        "alerts": config.alerts,
+    }))
 }
 
-#[derive(Deserialize)]
-struct AddMedicineRequest {
-    name: String,
-    price: f64,
-    quantity: u32,
+/// Archived master data is left out of lists unless `?include_archived=true`.
+#[derive(Deserialize, Default)]
+struct ArchivedQuery {
+    #[serde(default)]
+    include_archived: bool,
+}
+
+async fn list_medicines(
+    State(state): State<AppState>,
+    Query(query): Query<ArchivedQuery>,
+) -> Json<Vec<Medicine>> {
+    let pharmacy = state.read();
+    Json(
+        pharmacy
+            .inventory
+            .iter()
+            .filter(|m| query.include_archived || m.archived_at.is_none())
+            .cloned()
+            .collect(),
+    )
 }
 
 async fn add_medicine(
     State(state): State<AppState>,
+    Actor(user): Actor,
     Json(payload): Json<AddMedicineRequest>,
-) -> impl IntoResponse {
-    let mut pharmacy = state.lock().unwrap();
-    pharmacy.add_medicine(payload.name, payload.price, payload.quantity);
-    save_data(&pharmacy);
-    StatusCode::CREATED
-}
-
-async fn delete_medicine(State(state): State<AppState>, Path(id): Path<u32>) -> impl IntoResponse {
-    let mut pharmacy = state.lock().unwrap();
-    match pharmacy.delete_medicine(id) {
-        Ok(_) => {
-            save_data(&pharmacy);
-            StatusCode::OK
+) -> Result<StatusCode, PharmacyError> {
+    state
+        .update(|pharmacy| {
+            payload.validate(pharmacy)?;
+            payload.apply(pharmacy, &user)
+        })
+        .await?;
+    Ok(StatusCode::CREATED)
+}
+
+async fn archive_medicine(
+    State(state): State<AppState>,
+    Actor(user): Actor,
+    Path(id): Path<u32>,
+) -> Result<StatusCode, PharmacyError> {
+    state
+        .update(|pharmacy| pharmacy.archive_medicine(&user, id))
+        .await?;
+    Ok(StatusCode::OK)
+}
+
+#[derive(Deserialize)]
+struct VatRateRequest {
+    /// `null` goes back to the configured default rate. Required, so an empty
+    /// body cannot clear the rate by accident.
+    #[serde(deserialize_with = "Option::deserialize")]
+    vat_rate: Option<VatRate>,
+}
+
+async fn set_medicine_vat_rate(
+    State(state): State<AppState>,
+    Actor(user): Actor,
+    Path(id): Path<u32>,
+    Json(payload): Json<VatRateRequest>,
+) -> Result<StatusCode, PharmacyError> {
+    state
+        .update(|pharmacy| pharmacy.set_medicine_vat_rate(&user, id, payload.vat_rate))
+        .await?;
+    Ok(StatusCode::OK)
+}
+
+async fn restore_medicine(
+    State(state): State<AppState>,
+    Actor(user): Actor,
+    Path(id): Path<u32>,
+) -> Result<StatusCode, PharmacyError> {
+    state
+        .update(|pharmacy| pharmacy.restore_medicine(&user, id))
+        .await?;
+    Ok(StatusCode::OK)
+}
+
+#[derive(Deserialize)]
+struct StockCardQuery {
+    warehouse_id: Option<u32>,
+    from: Option<String>,
+    to: Option<String>,
+}
+
+async fn get_stock_card(
+    State(state): State<AppState>,
+    State(config): State<Arc<Config>>,
+    Path(id): Path<u32>,
+    Query(query): Query<StockCardQuery>,
+    Query(export): Query<ExportQuery>,
+) -> Result<Response, PharmacyError> {
+    let tz = config.tz();
+    let from = query
+        .from
+        .as_deref()
+        .map(|v| parse_report_start(v, tz))
+        .transpose()?;
+    let to = query
+        .to
+        .as_deref()
+        .map(|v| parse_report_date(v, tz))
+        .transpose()?;
+    let card = {
+        let pharmacy = state.read();
+        if let Some(warehouse_id) = query.warehouse_id
+            && !pharmacy.warehouses.iter().any(|w| w.id == warehouse_id)
+        {
+            return Err(PharmacyError::WarehouseNotFound);
         }
-        Err(_) => StatusCode::NOT_FOUND,
+        pharmacy.stock_card(id, query.warehouse_id, from, to)?
+    };
+    if export.format == ExportFormat::Json {
+        return Ok(Json(card).into_response());
+    }
+    export
+        .download(card.movements, &format!("stock-card-{}", id), &config)
+        .await
+}
+
+#[derive(Deserialize)]
+struct ForecastQuery {
+    warehouse_id: Option<u32>,
+    #[serde(default = "default_forecast_weeks")]
+    weeks: u32,
+}
+
+fn default_forecast_weeks() -> u32 {
+    12
+}
+
+async fn get_forecast(
+    State(state): State<AppState>,
+    State(config): State<Arc<Config>>,
+    Path(id): Path<u32>,
+    Query(query): Query<ForecastQuery>,
+) -> Result<Json<Forecast>, PharmacyError> {
+    Validator::new()
+        .check(
+            "weeks",
+            (1..=104).contains(&query.weeks),
+            "OUT_OF_RANGE",
+            "must be between 1 and 104",
+        )
+        .finish()?;
+    let pharmacy = state.read();
+    if let Some(warehouse_id) = query.warehouse_id
+        && !pharmacy.warehouses.iter().any(|w| w.id == warehouse_id)
+    {
+        return Err(PharmacyError::WarehouseNotFound);
     }
+    pharmacy
+        .forecast(
+            id,
+            query.warehouse_id,
+            config.tz(),
+            config.replenishment.moving_average_weeks,
+            query.weeks,
+        )
+        .map(Json)
 }
 
 #[derive(Deserialize)]
 struct SellRequest {
     id: u32,
     amount: u32,
+    #[serde(flatten)]
+    customer: PriceContext,
+}
+
+impl Validate for SellRequest {
+    fn validate(&self, _pharmacy: &Pharmacy) -> Result<(), PharmacyError> {
+        Validator::new().positive("amount", self.amount).finish()
+    }
 }
 
 async fn sell_medicine(
     State(state): State<AppState>,
+    State(config): State<Arc<Config>>,
+    Actor(user): Actor,
     Json(payload): Json<SellRequest>,
-) -> impl IntoResponse {
-    let mut pharmacy = state.lock().unwrap();
-    match pharmacy.sell_medicine(payload.id, payload.amount) {
-        Ok(_) => {
-            save_data(&pharmacy);
-            StatusCode::OK.into_response()
-        }
-        Err(e) => (StatusCode::BAD_REQUEST, e).into_response(),
+) -> Result<StatusCode, PharmacyError> {
+    state
+        .update(|pharmacy| {
+            payload.validate(pharmacy)?;
+            pharmacy.sell_medicine(
+                &user,
+                payload.id,
+                payload.amount,
+                &payload.customer,
+                &config,
+            )
+        })
+        .await?;
+    Ok(StatusCode::OK)
+}
+
+#[derive(Deserialize)]
+struct SellBatchRequest {
+    medicine_id: u32,
+    quantity: u32,
+    #[serde(flatten)]
+    customer: PriceContext,
+}
+
+impl Validate for SellBatchRequest {
+    fn validate(&self, _pharmacy: &Pharmacy) -> Result<(), PharmacyError> {
+        Validator::new()
+            .positive("quantity", self.quantity)
+            .finish()
     }
 }
 
-async fn get_import_batches(State(state): State<AppState>) -> Json<Vec<ImportBatch>> {
-    let pharmacy = state.lock().unwrap();
-    Json(pharmacy.import_log.clone())
+/// Sells from the store warehouse, earliest expiry first, then proposes
+/// transfers for anything the sale took below par.
+async fn sell_batch_handler(
+    State(state): State<AppState>,
+    State(config): State<Arc<Config>>,
+    Actor(user): Actor,
+    Json(payload): Json<SellBatchRequest>,
+) -> Result<StatusCode, PharmacyError> {
+    state
+        .update(|pharmacy| {
+            payload.validate(pharmacy)?;
+            pharmacy.sell_with_fefo(
+                &user,
+                payload.medicine_id,
+                payload.quantity,
+                &payload.customer,
+                &config,
+            )?;
+            pharmacy.propose_store_transfers(&user, None, config.alerts.critical_expiry_days);
+            Ok(())
+        })
+        .await?;
+    Ok(StatusCode::OK)
+}
+
+async fn get_import_batches(
+    State(state): State<AppState>,
+    State(config): State<Arc<Config>>,
+    Query(query): Query<ListQuery>,
+    Query(export): Query<ExportQuery>,
+) -> Result<Response, PharmacyError> {
+    let page = query.apply(&state.read().import_log)?;
+    if export.format == ExportFormat::Json {
+        return Ok(page.into_response());
+    }
+    export.download(page.items, "import-batches", &config).await
 }
 
-async fn get_export_batches(State(state): State<AppState>) -> Json<Vec<ExportBatch>> {
-    let pharmacy = state.lock().unwrap();
-    Json(pharmacy.export_log.clone())
+async fn get_export_batches(
+    State(state): State<AppState>,
+    State(config): State<Arc<Config>>,
+    Query(query): Query<ListQuery>,
+    Query(export): Query<ExportQuery>,
+) -> Result<Response, PharmacyError> {
+    let page = query.apply(&state.read().export_log)?;
+    if export.format == ExportFormat::Json {
+        return Ok(page.into_response());
+    }
+    export.download(page.items, "export-batches", &config).await
 }
 
 // Warehouse handlers
 
-async fn list_warehouses(State(state): State<AppState>) -> Json<Vec<Warehouse>> {
-    let pharmacy = state.lock().unwrap();
-    Json(pharmacy.warehouses.clone())
+async fn list_warehouses(
+    State(state): State<AppState>,
+    Query(query): Query<ArchivedQuery>,
+) -> Json<Vec<Warehouse>> {
+    let pharmacy = state.read();
+    Json(
+        pharmacy
+            .warehouses
+            .iter()
+            .filter(|w| query.include_archived || w.archived_at.is_none())
+            .cloned()
+            .collect(),
+    )
 }
 
 #[derive(Deserialize)]
@@ -153,72 +681,215 @@ struct EditWarehouseRequest {
     warehouse_type: WarehouseType,
 }
 
+impl Validate for CreateWarehouseRequest {
+    fn validate(&self, _pharmacy: &Pharmacy) -> Result<(), PharmacyError> {
+        Validator::new().not_blank("name", &self.name).finish()
+    }
+}
+
+impl Validate for EditWarehouseRequest {
+    fn validate(&self, _pharmacy: &Pharmacy) -> Result<(), PharmacyError> {
+        Validator::new().not_blank("name", &self.name).finish()
+    }
+}
+
+impl Validate for WarehousePatch {
+    fn validate(&self, _pharmacy: &Pharmacy) -> Result<(), PharmacyError> {
+        let mut v = Validator::new();
+        if let Some(name) = &self.name {
+            v.not_blank("name", name);
+        }
+        v.finish()
+    }
+}
+
+async fn get_warehouse(
+    State(state): State<AppState>,
+    Path(id): Path<u32>,
+) -> Result<impl IntoResponse, PharmacyError> {
+    let pharmacy = state.read();
+    let warehouse = pharmacy
+        .warehouses
+        .iter()
+        .find(|w| w.id == id)
+        .ok_or(PharmacyError::WarehouseNotFound)?;
+    Ok((etag(warehouse.version), Json(warehouse.clone())))
+}
+
 async fn create_warehouse(
     State(state): State<AppState>,
+    Actor(user): Actor,
     Json(payload): Json<CreateWarehouseRequest>,
-) -> impl IntoResponse {
-    let mut pharmacy = state.lock().unwrap();
-    let id = pharmacy.add_warehouse(payload.name, payload.warehouse_type);
-    save_data(&pharmacy);
-    (StatusCode::CREATED, Json(id))
+) -> Result<impl IntoResponse, PharmacyError> {
+    let id = state
+        .update(|pharmacy| {
+            payload.validate(pharmacy)?;
+            Ok(pharmacy.add_warehouse(&user, payload.name, payload.warehouse_type))
+        })
+        .await?;
+    Ok((StatusCode::CREATED, Json(id)))
 }
 
 async fn edit_warehouse(
     State(state): State<AppState>,
+    Actor(user): Actor,
+    IfMatch(version): IfMatch,
     Path(id): Path<u32>,
     Json(payload): Json<EditWarehouseRequest>,
-) -> impl IntoResponse {
-    let mut pharmacy = state.lock().unwrap();
-    match pharmacy.edit_warehouse(id, payload.name, payload.warehouse_type) {
-        Ok(_) => {
-            save_data(&pharmacy);
-            StatusCode::OK.into_response()
-        }
-        Err(e) => (StatusCode::BAD_REQUEST, e).into_response(),
+) -> Result<impl IntoResponse, PharmacyError> {
+    let new_version = state
+        .update(|pharmacy| {
+            payload.validate(pharmacy)?;
+            let patch = WarehousePatch {
+                name: Some(payload.name),
+                warehouse_type: Some(payload.warehouse_type),
+            };
+            pharmacy.edit_warehouse(&user, id, version, patch)
+        })
+        .await?;
+    Ok((StatusCode::OK, etag(new_version)))
+}
+
+async fn patch_warehouse(
+    State(state): State<AppState>,
+    Actor(user): Actor,
+    IfMatch(version): IfMatch,
+    Path(id): Path<u32>,
+    Json(payload): Json<WarehousePatch>,
+) -> Result<impl IntoResponse, PharmacyError> {
+    let new_version = state
+        .update(|pharmacy| {
+            payload.validate(pharmacy)?;
+            pharmacy.edit_warehouse(&user, id, version, payload)
+        })
+        .await?;
+    Ok((StatusCode::OK, etag(new_version)))
+}
+
+async fn archive_warehouse(
+    State(state): State<AppState>,
+    State(config): State<Arc<Config>>,
+    Actor(user): Actor,
+    IfMatch(version): IfMatch,
+    Path(id): Path<u32>,
+) -> Result<impl IntoResponse, PharmacyError> {
+    if config.default_warehouse_id == Some(id) {
+        return Err(PharmacyError::InUse {
+            reason: "it is the default warehouse in the configuration".to_string(),
+        });
     }
+    let new_version = state
+        .update(|pharmacy| pharmacy.archive_warehouse(&user, id, version))
+        .await?;
+    Ok((StatusCode::OK, etag(new_version)))
 }
 
-async fn list_stock_batches(State(state): State<AppState>) -> Json<Vec<StockBatch>> {
-    let pharmacy = state.lock().unwrap();
-    Json(pharmacy.stock_batches.clone())
+async fn restore_warehouse(
+    State(state): State<AppState>,
+    Actor(user): Actor,
+    IfMatch(version): IfMatch,
+    Path(id): Path<u32>,
+) -> Result<impl IntoResponse, PharmacyError> {
+    let new_version = state
+        .update(|pharmacy| pharmacy.restore_warehouse(&user, id, version))
+        .await?;
+    Ok((StatusCode::OK, etag(new_version)))
 }
 
-#[derive(Deserialize)]
-struct ImportBatchRequest {
-    medicine_id: u32,
-    medicine_name: String,
-    warehouse_id: u32,
-    quantity: u32,
-    price: f64,
-    expiry_date: String, // ISO 8601 format
+async fn list_stock_batches(
+    State(state): State<AppState>,
+    State(config): State<Arc<Config>>,
+    Query(query): Query<ListQuery>,
+    Query(export): Query<ExportQuery>,
+) -> Result<Response, PharmacyError> {
+    let page = query.apply(&state.read().stock_batches)?;
+    if export.format == ExportFormat::Json {
+        return Ok(page.into_response());
+    }
+    export.download(page.items, "stock-batches", &config).await
 }
 
 async fn import_batch_handler(
     State(state): State<AppState>,
+    Actor(user): Actor,
     Json(payload): Json<ImportBatchRequest>,
-) -> impl IntoResponse {
-    let mut pharmacy = state.lock().unwrap();
-
-    // Parse expiry date
-    let expiry_date = match DateTime::parse_from_rfc3339(&payload.expiry_date) {
-        Ok(dt) => dt.with_timezone(&Local),
-        Err(_) => return (StatusCode::BAD_REQUEST, "Invalid date format").into_response(),
-    };
+) -> Result<impl IntoResponse, PharmacyError> {
+    let batch_id = state
+        .update(|pharmacy| {
+            payload.validate(pharmacy)?;
+            payload.apply(pharmacy, &user)
+        })
+        .await?;
+    Ok((StatusCode::CREATED, Json(batch_id)))
+}
 
-    match pharmacy.import_batch(
-        payload.medicine_id,
-        payload.medicine_name,
-        payload.warehouse_id,
-        payload.quantity,
-        payload.price,
-        expiry_date,
-    ) {
-        Ok(batch_id) => {
-            save_data(&pharmacy);
-            (StatusCode::CREATED, Json(batch_id)).into_response()
+/// Query of a bulk import: `format`, `sheet`, `dry_run`, and `map.<field>=<column>` pairs.
+async fn bulk_import_handler(
+    State(state): State<AppState>,
+    State(config): State<Arc<Config>>,
+    Actor(user): Actor,
+    Path(kind): Path<ImportKind>,
+    Query(params): Query<Vec<(String, String)>>,
+    body: Bytes,
+) -> Result<impl IntoResponse, PharmacyError> {
+    let mut format = None;
+    let mut sheet_name = None;
+    let mut dry_run = false;
+    let mut mapping = BTreeMap::new();
+    let mut validator = Validator::new();
+    for (key, value) in params {
+        match key.as_str() {
+            "format" => match value.as_str() {
+                "csv" => format = Some(SheetFormat::Csv),
+                "xlsx" => format = Some(SheetFormat::Xlsx),
+                _ => {
+                    validator.check("format", false, "INVALID_FORMAT", "must be csv or xlsx");
+                }
+            },
+            "sheet" => sheet_name = Some(value),
+            "dry_run" => dry_run = value == "true" || value == "1",
+            _ => match key.strip_prefix("map.") {
+                Some(field) => {
+                    mapping.insert(field.to_string(), value);
+                }
+                None => {
+                    validator.check(&key, false, "UNKNOWN_PARAMETER", "is not a known parameter");
+                }
+            },
         }
-        Err(e) => (StatusCode::BAD_REQUEST, e).into_response(),
     }
+    validator.finish()?;
+
+    let format = format.unwrap_or_else(|| SheetFormat::detect(&body));
+    let sheet = Sheet::read(&body, format, sheet_name.as_deref()).map_err(|message| {
+        PharmacyError::Validation(vec![FieldError {
+            field: "file".to_string(),
+            code: "UNREADABLE_FILE",
+            message,
+        }])
+    })?;
+    let tz = config.tz();
+    let report = if dry_run {
+        import_sheet(
+            &mut Pharmacy::clone(&state.read()),
+            &user,
+            kind,
+            &sheet,
+            &mapping,
+            tz,
+            true,
+        )?
+    } else {
+        state
+            .update(|pharmacy| import_sheet(pharmacy, &user, kind, &sheet, &mapping, tz, false))
+            .await?
+    };
+    let status = if dry_run {
+        StatusCode::OK
+    } else {
+        StatusCode::CREATED
+    };
+    Ok((status, Json(report)))
 }
 
 #[derive(Deserialize)]
@@ -228,99 +899,1375 @@ struct TransferBatchRequest {
     quantity: u32,
 }
 
+impl Validate for TransferBatchRequest {
+    fn validate(&self, _pharmacy: &Pharmacy) -> Result<(), PharmacyError> {
+        Validator::new()
+            .positive("quantity", self.quantity)
+            .finish()
+    }
+}
+
 async fn transfer_batch_handler(
     State(state): State<AppState>,
+    Actor(user): Actor,
     Json(payload): Json<TransferBatchRequest>,
-) -> impl IntoResponse {
-    let mut pharmacy = state.lock().unwrap();
+) -> Result<StatusCode, PharmacyError> {
+    state
+        .update(|pharmacy| {
+            payload.validate(pharmacy)?;
 
-    match pharmacy.transfer_batch(payload.batch_id, payload.to_warehouse_id, payload.quantity) {
-        Ok(_) => {
-            save_data(&pharmacy);
-            StatusCode::OK.into_response()
-        }
-        Err(e) => (StatusCode::BAD_REQUEST, e).into_response(),
-    }
+            pharmacy.transfer_batch(
+                &user,
+                payload.batch_id,
+                payload.to_warehouse_id,
+                payload.quantity,
+            )
+        })
+        .await?;
+    Ok(StatusCode::OK)
 }
 
-async fn get_expiring_batches(State(state): State<AppState>) -> Json<Vec<StockBatch>> {
-    let pharmacy = state.lock().unwrap();
-    Json(pharmacy.get_expiring_batches(90))
+async fn get_expiring_batches(
+    State(state): State<AppState>,
+    State(config): State<Arc<Config>>,
+) -> Json<Vec<StockBatch>> {
+    let pharmacy = state.read();
+    Json(pharmacy.get_expiring_batches(config.alerts.expiry_days))
 }
 
-async fn get_transfers(State(state): State<AppState>) -> Json<Vec<InternalTransfer>> {
-    let pharmacy = state.lock().unwrap();
-    Json(pharmacy.transfer_log.clone())
+async fn get_transfers(
+    State(state): State<AppState>,
+    State(config): State<Arc<Config>>,
+    Query(query): Query<ListQuery>,
+    Query(export): Query<ExportQuery>,
+) -> Result<Response, PharmacyError> {
+    let page = query.apply(&state.read().transfer_log)?;
+    if export.format == ExportFormat::Json {
+        return Ok(page.into_response());
+    }
+    export.download(page.items, "transfers", &config).await
 }
 
 // Supplier handlers
 
-async fn list_suppliers(State(state): State<AppState>) -> Json<Vec<Supplier>> {
-    let pharmacy = state.lock().unwrap();
-    Json(pharmacy.suppliers.clone())
+async fn list_suppliers(
+    State(state): State<AppState>,
+    State(config): State<Arc<Config>>,
+    Query(query): Query<ArchivedQuery>,
+    Query(export): Query<ExportQuery>,
+) -> Result<Response, PharmacyError> {
+    let suppliers: Vec<Supplier> = state
+        .read()
+        .suppliers
+        .iter()
+        .filter(|s| query.include_archived || s.archived_at.is_none())
+        .cloned()
+        .collect();
+    if export.format == ExportFormat::Json {
+        return Ok(Json(suppliers).into_response());
+    }
+    export.download(suppliers, "suppliers", &config).await
 }
 
 #[derive(Deserialize)]
-struct CreateSupplierRequest {
+struct EditSupplierRequest {
     name: String,
     contact: String,
     phone: String,
     address: String,
 }
 
-#[derive(Deserialize)]
-struct EditSupplierRequest {
-    name: String,
-    contact: String,
-    phone: String,
-    address: String,
+impl Validate for EditSupplierRequest {
+    fn validate(&self, _pharmacy: &Pharmacy) -> Result<(), PharmacyError> {
+        Validator::new()
+            .not_blank("name", &self.name)
+            .phone("phone", &self.phone)
+            .finish()
+    }
+}
+
+impl Validate for SupplierPatch {
+    fn validate(&self, _pharmacy: &Pharmacy) -> Result<(), PharmacyError> {
+        let mut v = Validator::new();
+        if let Some(name) = &self.name {
+            v.not_blank("name", name);
+        }
+        if let Some(phone) = &self.phone {
+            v.phone("phone", phone);
+        }
+        v.finish()
+    }
+}
+
+async fn get_supplier(
+    State(state): State<AppState>,
+    Path(id): Path<u32>,
+) -> Result<impl IntoResponse, PharmacyError> {
+    let pharmacy = state.read();
+    let supplier = pharmacy
+        .suppliers
+        .iter()
+        .find(|s| s.id == id)
+        .ok_or(PharmacyError::SupplierNotFound)?;
+    Ok((etag(supplier.version), Json(supplier.clone())))
 }
 
 async fn create_supplier(
     State(state): State<AppState>,
+    Actor(user): Actor,
     Json(payload): Json<CreateSupplierRequest>,
-) -> impl IntoResponse {
-    let mut pharmacy = state.lock().unwrap();
-    let id = pharmacy.add_supplier(
-        payload.name,
-        payload.contact,
-        payload.phone,
-        payload.address,
-    );
-    save_data(&pharmacy);
-    (StatusCode::CREATED, Json(id))
+) -> Result<impl IntoResponse, PharmacyError> {
+    let id = state
+        .update(|pharmacy| {
+            payload.validate(pharmacy)?;
+            payload.apply(pharmacy, &user)
+        })
+        .await?;
+    Ok((StatusCode::CREATED, Json(id)))
 }
 
 async fn edit_supplier(
     State(state): State<AppState>,
+    Actor(user): Actor,
+    IfMatch(version): IfMatch,
     Path(id): Path<u32>,
     Json(payload): Json<EditSupplierRequest>,
-) -> impl IntoResponse {
-    let mut pharmacy = state.lock().unwrap();
-    match pharmacy.edit_supplier(
-        id,
-        payload.name,
-        payload.contact,
-        payload.phone,
-        payload.address,
-    ) {
-        Ok(_) => {
-            save_data(&pharmacy);
-            StatusCode::OK.into_response()
-        }
-        Err(e) => (StatusCode::BAD_REQUEST, e).into_response(),
-    }
+) -> Result<impl IntoResponse, PharmacyError> {
+    let new_version = state
+        .update(|pharmacy| {
+            payload.validate(pharmacy)?;
+            let patch = SupplierPatch {
+                name: Some(payload.name),
+                contact: Some(payload.contact),
+                phone: Some(payload.phone),
+                address: Some(payload.address),
+            };
+            pharmacy.edit_supplier(&user, id, version, patch)
+        })
+        .await?;
+    Ok((StatusCode::OK, etag(new_version)))
 }
 
-fn load_data() -> Pharmacy {
-    if let Ok(data) = fs::read_to_string(DATA_FILE) {
-        serde_json::from_str(&data).unwrap_or_else(|_| Pharmacy::new())
-    } else {
-        Pharmacy::new()
+async fn patch_supplier(
+    State(state): State<AppState>,
+    Actor(user): Actor,
+    IfMatch(version): IfMatch,
+    Path(id): Path<u32>,
+    Json(payload): Json<SupplierPatch>,
+) -> Result<impl IntoResponse, PharmacyError> {
+    let new_version = state
+        .update(|pharmacy| {
+            payload.validate(pharmacy)?;
+            pharmacy.edit_supplier(&user, id, version, payload)
+        })
+        .await?;
+    Ok((StatusCode::OK, etag(new_version)))
+}
+
+async fn archive_supplier(
+    State(state): State<AppState>,
+    Actor(user): Actor,
+    IfMatch(version): IfMatch,
+    Path(id): Path<u32>,
+) -> Result<impl IntoResponse, PharmacyError> {
+    let new_version = state
+        .update(|pharmacy| pharmacy.archive_supplier(&user, id, version))
+        .await?;
+    Ok((StatusCode::OK, etag(new_version)))
+}
+
+async fn restore_supplier(
+    State(state): State<AppState>,
+    Actor(user): Actor,
+    IfMatch(version): IfMatch,
+    Path(id): Path<u32>,
+) -> Result<impl IntoResponse, PharmacyError> {
+    let new_version = state
+        .update(|pharmacy| pharmacy.restore_supplier(&user, id, version))
+        .await?;
+    Ok((StatusCode::OK, etag(new_version)))
+}
+
+// Replenishment handlers
+
+#[derive(Deserialize)]
+struct ReorderSettingsQuery {
+    medicine_id: Option<u32>,
+    warehouse_id: Option<u32>,
+}
+
+async fn list_reorder_settings(
+    State(state): State<AppState>,
+    Query(query): Query<ReorderSettingsQuery>,
+) -> Json<Vec<ReorderSetting>> {
+    let pharmacy = state.read();
+    Json(
+        pharmacy
+            .reorder_settings
+            .iter()
+            .filter(|s| {
+                query.medicine_id.is_none_or(|id| id == s.medicine_id)
+                    && query.warehouse_id.is_none_or(|id| id == s.warehouse_id)
+            })
+            .cloned()
+            .collect(),
+    )
+}
+
+impl Validate for ReorderSettingInput {
+    fn validate(&self, _pharmacy: &Pharmacy) -> Result<(), PharmacyError> {
+        Validator::new()
+            .positive("max", self.max)
+            .check(
+                "reorder_point",
+                self.min <= self.reorder_point && self.reorder_point <= self.max,
+                "OUT_OF_RANGE",
+                "must be between min and max",
+            )
+            .finish()
     }
 }
 
-fn save_data(pharmacy: &Pharmacy) {
-    let data = serde_json::to_string_pretty(pharmacy).expect("Failed to serialize data");
-    fs::write(DATA_FILE, data).expect("Failed to write data file");
+/// Creates or replaces the setting for the medicine and warehouse in the body.
+async fn set_reorder_setting(
+    State(state): State<AppState>,
+    Actor(user): Actor,
+    Json(payload): Json<ReorderSettingInput>,
+) -> Result<Json<u32>, PharmacyError> {
+    let id = state
+        .update(|pharmacy| {
+            payload.validate(pharmacy)?;
+            pharmacy.set_reorder_setting(&user, payload)
+        })
+        .await?;
+    Ok(Json(id))
+}
+
+async fn delete_reorder_setting(
+    State(state): State<AppState>,
+    Actor(user): Actor,
+    Path(id): Path<u32>,
+) -> Result<StatusCode, PharmacyError> {
+    state
+        .update(|pharmacy| pharmacy.delete_reorder_setting(&user, id))
+        .await?;
+    Ok(StatusCode::OK)
+}
+
+#[derive(Deserialize)]
+struct ReplenishmentQuery {
+    warehouse_id: Option<u32>,
+}
+
+async fn get_purchase_suggestions(
+    State(state): State<AppState>,
+    State(config): State<Arc<Config>>,
+    Query(query): Query<ReplenishmentQuery>,
+) -> Json<Vec<SupplierSuggestions>> {
+    Json(state.read().purchase_suggestions(
+        ReplenishmentParams::from_config(&config),
+        query.warehouse_id,
+    ))
+}
+
+/// Creates draft purchase orders from the current suggestions and returns them.
+async fn draft_purchase_orders(
+    State(state): State<AppState>,
+    State(config): State<Arc<Config>>,
+    Actor(user): Actor,
+    Query(query): Query<ReplenishmentQuery>,
+) -> Result<impl IntoResponse, PharmacyError> {
+    let params = ReplenishmentParams::from_config(&config);
+    let orders = state
+        .update(|pharmacy| {
+            let ids = pharmacy.draft_purchase_orders(&user, params, query.warehouse_id)?;
+            Ok(pharmacy
+                .purchase_orders
+                .iter()
+                .filter(|o| ids.contains(&o.id))
+                .cloned()
+                .collect::<Vec<_>>())
+        })
+        .await?;
+    Ok((StatusCode::CREATED, Json(orders)))
+}
+
+#[derive(Deserialize)]
+struct ParLevelsQuery {
+    warehouse_id: Option<u32>,
+    medicine_id: Option<u32>,
+}
+
+async fn list_par_levels(
+    State(state): State<AppState>,
+    Query(query): Query<ParLevelsQuery>,
+) -> Json<Vec<ParLevel>> {
+    let pharmacy = state.read();
+    Json(
+        pharmacy
+            .par_levels
+            .iter()
+            .filter(|p| {
+                query.warehouse_id.is_none_or(|id| id == p.warehouse_id)
+                    && query.medicine_id.is_none_or(|id| id == p.medicine_id)
+            })
+            .cloned()
+            .collect(),
+    )
+}
+
+#[derive(Deserialize)]
+struct ParLevelRequest {
+    warehouse_id: u32,
+    medicine_id: u32,
+    par: u32,
+}
+
+/// Creates or replaces the par level for the store and medicine in the body.
+async fn set_par_level(
+    State(state): State<AppState>,
+    Actor(user): Actor,
+    Json(payload): Json<ParLevelRequest>,
+) -> Result<Json<u32>, PharmacyError> {
+    let id = state
+        .update(|pharmacy| {
+            pharmacy.set_par_level(
+                &user,
+                payload.warehouse_id,
+                payload.medicine_id,
+                payload.par,
+            )
+        })
+        .await?;
+    Ok(Json(id))
+}
+
+async fn delete_par_level(
+    State(state): State<AppState>,
+    Actor(user): Actor,
+    Path(id): Path<u32>,
+) -> Result<StatusCode, PharmacyError> {
+    state
+        .update(|pharmacy| pharmacy.delete_par_level(&user, id))
+        .await?;
+    Ok(StatusCode::OK)
+}
+
+#[derive(Deserialize)]
+struct TransferProposalsQuery {
+    status: Option<ProposalStatus>,
+    warehouse_id: Option<u32>,
+}
+
+async fn list_transfer_proposals(
+    State(state): State<AppState>,
+    Query(query): Query<TransferProposalsQuery>,
+) -> Json<Vec<TransferProposal>> {
+    let pharmacy = state.read();
+    Json(
+        pharmacy
+            .transfer_proposals
+            .iter()
+            .filter(|p| {
+                query.status.is_none_or(|status| status == p.status)
+                    && query
+                        .warehouse_id
+                        .is_none_or(|id| id == p.from_warehouse_id || id == p.to_warehouse_id)
+            })
+            .cloned()
+            .collect(),
+    )
+}
+
+/// Proposes transfers for stores below par (optionally one `warehouse_id`) and returns them.
+async fn generate_transfer_proposals(
+    State(state): State<AppState>,
+    State(config): State<Arc<Config>>,
+    Actor(user): Actor,
+    Query(query): Query<ReplenishmentQuery>,
+) -> Result<impl IntoResponse, PharmacyError> {
+    let proposals = state
+        .update(|pharmacy| {
+            let ids = pharmacy.propose_store_transfers(
+                &user,
+                query.warehouse_id,
+                config.alerts.critical_expiry_days,
+            );
+            Ok(pharmacy
+                .transfer_proposals
+                .iter()
+                .filter(|p| ids.contains(&p.id))
+                .cloned()
+                .collect::<Vec<_>>())
+        })
+        .await?;
+    Ok((StatusCode::CREATED, Json(proposals)))
+}
+
+async fn approve_transfer_proposal(
+    State(state): State<AppState>,
+    Actor(user): Actor,
+    Path(id): Path<u32>,
+) -> Result<StatusCode, PharmacyError> {
+    state
+        .update(|pharmacy| pharmacy.approve_transfer_proposal(&user, id))
+        .await?;
+    Ok(StatusCode::OK)
+}
+
+async fn reject_transfer_proposal(
+    State(state): State<AppState>,
+    Actor(user): Actor,
+    Path(id): Path<u32>,
+) -> Result<StatusCode, PharmacyError> {
+    state
+        .update(|pharmacy| pharmacy.reject_transfer_proposal(&user, id))
+        .await?;
+    Ok(StatusCode::OK)
+}
+
+#[derive(Deserialize)]
+struct PurchaseOrdersQuery {
+    status: Option<PurchaseOrderStatus>,
+    supplier_id: Option<u32>,
+    warehouse_id: Option<u32>,
+}
+
+async fn list_purchase_orders(
+    State(state): State<AppState>,
+    Query(query): Query<PurchaseOrdersQuery>,
+) -> Json<Vec<PurchaseOrder>> {
+    let pharmacy = state.read();
+    Json(
+        pharmacy
+            .purchase_orders
+            .iter()
+            .filter(|o| {
+                query.status.is_none_or(|status| status == o.status)
+                    && query.supplier_id.is_none_or(|id| id == o.supplier_id)
+                    && query.warehouse_id.is_none_or(|id| id == o.warehouse_id)
+            })
+            .cloned()
+            .collect(),
+    )
+}
+
+async fn get_purchase_order(
+    State(state): State<AppState>,
+    Path(id): Path<u32>,
+) -> Result<Json<PurchaseOrder>, PharmacyError> {
+    state
+        .read()
+        .purchase_orders
+        .iter()
+        .find(|o| o.id == id)
+        .cloned()
+        .map(Json)
+        .ok_or(PharmacyError::PurchaseOrderNotFound)
+}
+
+#[derive(Deserialize)]
+struct PurchaseOrderStatusRequest {
+    status: PurchaseOrderStatus,
+}
+
+async fn set_purchase_order_status(
+    State(state): State<AppState>,
+    Actor(user): Actor,
+    Path(id): Path<u32>,
+    Json(payload): Json<PurchaseOrderStatusRequest>,
+) -> Result<StatusCode, PharmacyError> {
+    state
+        .update(|pharmacy| pharmacy.set_purchase_order_status(&user, id, payload.status))
+        .await?;
+    Ok(StatusCode::OK)
+}
+
+// Audit handlers
+
+#[derive(Deserialize)]
+struct AuditQuery {
+    entity: Option<AuditEntity>,
+    entity_id: Option<u32>,
+    user: Option<String>,
+    from: Option<String>, // ISO 8601 format
+    to: Option<String>,   // ISO 8601 format
+}
+
+async fn query_audit(
+    State(state): State<AppState>,
+    Query(query): Query<AuditQuery>,
+) -> Result<Json<Vec<AuditEntry>>, PharmacyError> {
+    let filter = AuditFilter {
+        entity: query.entity,
+        entity_id: query.entity_id,
+        user: query.user,
+        from: parse_optional_date(query.from.as_deref())?,
+        to: parse_optional_date(query.to.as_deref())?,
+    };
+    let pharmacy = state.read();
+    Ok(Json(pharmacy.query_audit(&filter)))
+}
+
+async fn verify_audit(State(state): State<AppState>) -> Result<StatusCode, PharmacyError> {
+    let pharmacy = state.read();
+    pharmacy.verify_audit_chain()?;
+    Ok(StatusCode::OK)
+}
+
+async fn check_integrity(State(state): State<AppState>) -> Json<IntegrityReport> {
+    Json(state.read().check_integrity())
+}
+
+async fn fix_integrity(
+    State(state): State<AppState>,
+    Actor(user): Actor,
+) -> Result<Json<Vec<IntegrityIssue>>, PharmacyError> {
+    let fixed = state
+        .update(|pharmacy| Ok(pharmacy.fix_integrity(&user)))
+        .await?;
+    Ok(Json(fixed))
+}
+
+#[derive(Deserialize)]
+struct ValuationQuery {
+    as_of: Option<String>,
+    warehouse_id: Option<u32>,
+}
+
+async fn get_valuation(
+    State(state): State<AppState>,
+    State(config): State<Arc<Config>>,
+    Query(query): Query<ValuationQuery>,
+    Query(export): Query<ExportQuery>,
+) -> Result<Response, PharmacyError> {
+    let as_of = match query.as_of.as_deref() {
+        Some(value) => parse_report_date(value, config.tz())?,
+        None => Local::now(),
+    };
+    if let Some(id) = query.warehouse_id
+        && !state.read().warehouses.iter().any(|w| w.id == id)
+    {
+        return Err(PharmacyError::WarehouseNotFound);
+    }
+    let report = state.read().valuation(as_of, query.warehouse_id);
+    if export.format == ExportFormat::Json {
+        return Ok(Json(report).into_response());
+    }
+    export
+        .download(report.into_rows(), "valuation", &config)
+        .await
+}
+
+#[derive(Deserialize)]
+struct ExpiryRiskQuery {
+    warehouse_id: Option<u32>,
+    /// Only batches expected to expire with units left.
+    #[serde(default)]
+    at_risk: bool,
+}
+
+async fn get_expiry_risk(
+    State(state): State<AppState>,
+    State(config): State<Arc<Config>>,
+    Query(query): Query<ExpiryRiskQuery>,
+    Query(export): Query<ExportQuery>,
+) -> Result<Response, PharmacyError> {
+    let mut risks: Vec<BatchExpiryRisk> = {
+        let pharmacy = state.read();
+        if let Some(id) = query.warehouse_id
+            && !pharmacy.warehouses.iter().any(|w| w.id == id)
+        {
+            return Err(PharmacyError::WarehouseNotFound);
+        }
+        pharmacy.expiry_risk(
+            query.warehouse_id,
+            config.tz(),
+            config.replenishment.moving_average_weeks,
+        )
+    };
+    if query.at_risk {
+        risks.retain(|r| !r.sells_before_expiry);
+    }
+    if export.format == ExportFormat::Json {
+        return Ok(Json(risks).into_response());
+    }
+    export.download(risks, "expiry-risk", &config).await
+}
+
+#[derive(Deserialize)]
+struct AbcXyzQuery {
+    from: Option<String>,
+    to: Option<String>,
+}
+
+async fn get_abc_xyz(
+    State(state): State<AppState>,
+    State(config): State<Arc<Config>>,
+    Query(query): Query<AbcXyzQuery>,
+    Query(export): Query<ExportQuery>,
+) -> Result<Response, PharmacyError> {
+    let tz = config.tz();
+    let to = match query.to.as_deref() {
+        Some(value) => parse_report_date(value, tz)?,
+        None => Local::now(),
+    };
+    let from = match query.from.as_deref() {
+        Some(value) => parse_report_start(value, tz)?,
+        None => to - Duration::days(90),
+    };
+    Validator::new()
+        .check("from", from < to, "INVALID_RANGE", "must be before to")
+        .check(
+            "from",
+            to - from <= Duration::days(5 * 366),
+            "OUT_OF_RANGE",
+            "must be at most 5 years before to",
+        )
+        .finish()?;
+    let report: AbcXyzReport = state.read().abc_xyz(from, to);
+    if export.format == ExportFormat::Json {
+        return Ok(Json(report).into_response());
+    }
+    export.download(report.rows, "abc-xyz", &config).await
+}
+
+#[derive(Deserialize)]
+struct DeadStockQuery {
+    #[serde(default = "default_dead_stock_days")]
+    days: i64,
+    warehouse_id: Option<u32>,
+}
+
+fn default_dead_stock_days() -> i64 {
+    90
+}
+
+async fn get_dead_stock(
+    State(state): State<AppState>,
+    State(config): State<Arc<Config>>,
+    Query(query): Query<DeadStockQuery>,
+    Query(export): Query<ExportQuery>,
+) -> Result<Response, PharmacyError> {
+    Validator::new()
+        .check(
+            "days",
+            (1..=3650).contains(&query.days),
+            "OUT_OF_RANGE",
+            "must be between 1 and 3650",
+        )
+        .finish()?;
+    let report: DeadStockReport = {
+        let pharmacy = state.read();
+        if let Some(id) = query.warehouse_id
+            && !pharmacy.warehouses.iter().any(|w| w.id == id)
+        {
+            return Err(PharmacyError::WarehouseNotFound);
+        }
+        pharmacy.dead_stock(query.days, query.warehouse_id)
+    };
+    if export.format == ExportFormat::Json {
+        return Ok(Json(report).into_response());
+    }
+    export.download(report.batches, "dead-stock", &config).await
+}
+
+#[derive(Deserialize)]
+struct SalesQuery {
+    from: Option<String>,
+    to: Option<String>,
+    #[serde(default = "default_period")]
+    period: Period,
+    #[serde(default = "default_group_by")]
+    group_by: GroupBy,
+}
+
+fn default_period() -> Period {
+    Period::Day
+}
+
+fn default_group_by() -> GroupBy {
+    GroupBy::Product
+}
+
+async fn get_sales_report(
+    State(state): State<AppState>,
+    State(config): State<Arc<Config>>,
+    Query(query): Query<SalesQuery>,
+    Query(export): Query<ExportQuery>,
+) -> Result<Response, PharmacyError> {
+    let tz = config.tz();
+    let from = query
+        .from
+        .as_deref()
+        .map(|v| parse_report_start(v, tz))
+        .transpose()?;
+    let to = query
+        .to
+        .as_deref()
+        .map(|v| parse_report_date(v, tz))
+        .transpose()?;
+    let report = state
+        .read()
+        .sales_report(from, to, query.period, query.group_by, tz);
+    if export.format == ExportFormat::Json {
+        return Ok(Json(report).into_response());
+    }
+    export.download(report.rows, "sales", &config).await
+}
+
+#[derive(Deserialize)]
+struct VatQuery {
+    from: Option<String>,
+    to: Option<String>,
+    #[serde(default = "default_vat_period")]
+    period: Period,
+}
+
+fn default_vat_period() -> Period {
+    Period::Month
+}
+
+async fn get_vat_report(
+    State(state): State<AppState>,
+    State(config): State<Arc<Config>>,
+    Query(query): Query<VatQuery>,
+    Query(export): Query<ExportQuery>,
+) -> Result<Response, PharmacyError> {
+    let tz = config.tz();
+    let from = query
+        .from
+        .as_deref()
+        .map(|v| parse_report_start(v, tz))
+        .transpose()?;
+    let to = query
+        .to
+        .as_deref()
+        .map(|v| parse_report_date(v, tz))
+        .transpose()?;
+    let report = state.read().vat_report(from, to, query.period, tz);
+    if export.format == ExportFormat::Json {
+        return Ok(Json(report).into_response());
+    }
+    export.download(report.rows, "vat", &config).await
+}
+
+// Document handlers
+
+async fn get_sale_receipt(
+    State(state): State<AppState>,
+    State(config): State<Arc<Config>>,
+    State(fonts): State<Arc<Fonts>>,
+    Path(id): Path<u32>,
+    Query(query): Query<DocumentQuery>,
+) -> Result<Response, PharmacyError> {
+    let paper = query.paper(Paper::Thermal80);
+    let document = state
+        .read()
+        .sale_receipt(id, paper, &config, query.locale(&config))?;
+    pdf_response(document, paper, fonts, &format!("receipt-{}", id)).await
+}
+
+async fn get_goods_receipt_note(
+    State(state): State<AppState>,
+    State(config): State<Arc<Config>>,
+    State(fonts): State<Arc<Fonts>>,
+    Path(id): Path<u32>,
+    Query(query): Query<DocumentQuery>,
+) -> Result<Response, PharmacyError> {
+    let document = state
+        .read()
+        .goods_receipt_note(id, &config, query.locale(&config))?;
+    let name = format!("goods-receipt-{}", id);
+    pdf_response(document, query.paper(Paper::A4), fonts, &name).await
+}
+
+async fn get_transfer_note(
+    State(state): State<AppState>,
+    State(config): State<Arc<Config>>,
+    State(fonts): State<Arc<Fonts>>,
+    Path(id): Path<u32>,
+    Query(query): Query<DocumentQuery>,
+) -> Result<Response, PharmacyError> {
+    let document = state
+        .read()
+        .transfer_note(id, &config, query.locale(&config))?;
+    let name = format!("transfer-{}", id);
+    pdf_response(document, query.paper(Paper::A4), fonts, &name).await
+}
+
+async fn get_purchase_order_document(
+    State(state): State<AppState>,
+    State(config): State<Arc<Config>>,
+    State(fonts): State<Arc<Fonts>>,
+    Path(id): Path<u32>,
+    Query(query): Query<DocumentQuery>,
+) -> Result<Response, PharmacyError> {
+    let document = state
+        .read()
+        .purchase_order_document(id, &config, query.locale(&config))?;
+    let name = format!("purchase-order-{}", id);
+    pdf_response(document, query.paper(Paper::A4), fonts, &name).await
+}
+
+// E-invoice handlers
+
+#[derive(Deserialize)]
+struct EInvoicesQuery {
+    status: Option<EInvoiceStatus>,
+    kind: Option<EInvoiceKind>,
+    sale_id: Option<u32>,
+}
+
+async fn list_einvoices(
+    State(state): State<AppState>,
+    Query(query): Query<EInvoicesQuery>,
+) -> Json<Vec<EInvoice>> {
+    let pharmacy = state.read();
+    Json(
+        pharmacy
+            .einvoices
+            .iter()
+            .filter(|i| {
+                query.status.is_none_or(|status| status == i.status)
+                    && query.kind.is_none_or(|kind| kind == i.kind)
+                    && query
+                        .sale_id
+                        .is_none_or(|id| i.lines.iter().any(|l| l.sale_id == Some(id)))
+            })
+            .cloned()
+            .collect(),
+    )
+}
+
+async fn get_einvoice(
+    State(state): State<AppState>,
+    Path(id): Path<u32>,
+) -> Result<Json<EInvoice>, PharmacyError> {
+    state.read().einvoice(id).cloned().map(Json)
+}
+
+async fn get_einvoice_xml(
+    State(state): State<AppState>,
+    State(config): State<Arc<Config>>,
+    Path(id): Path<u32>,
+) -> Result<Response, PharmacyError> {
+    let xml = einvoice_xml::render(state.read().einvoice(id)?, config.tz());
+    Ok((
+        StatusCode::OK,
+        [
+            (
+                header::CONTENT_TYPE,
+                "application/xml; charset=utf-8".to_string(),
+            ),
+            (
+                header::CONTENT_DISPOSITION,
+                format!("inline; filename=\"einvoice-{}.xml\"", id),
+            ),
+        ],
+        xml,
+    )
+        .into_response())
+}
+
+fn check_buyer(validator: &mut Validator, buyer: &Buyer) {
+    if let Some(tax_code) = &buyer.tax_code {
+        validator.tax_code("buyer.tax_code", tax_code).check(
+            "buyer.company",
+            buyer
+                .company
+                .as_deref()
+                .is_some_and(|c| !c.trim().is_empty()),
+            "REQUIRED",
+            "is required with a tax code",
+        );
+    }
+    if let Some(email) = &buyer.email {
+        validator.check(
+            "buyer.email",
+            email.contains('@'),
+            "INVALID_EMAIL",
+            "must be an email address",
+        );
+    }
+}
+
+fn check_corrections(validator: &mut Validator, lines: &[LineCorrection]) {
+    for (index, line) in lines.iter().enumerate() {
+        if let Some(price) = line.unit_price {
+            validator.price(&format!("lines[{}].unit_price", index), price);
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct IssueEInvoiceRequest {
+    sale_ids: Vec<u32>,
+    #[serde(default)]
+    buyer: Buyer,
+}
+
+impl Validate for IssueEInvoiceRequest {
+    fn validate(&self, _pharmacy: &Pharmacy) -> Result<(), PharmacyError> {
+        let mut validator = Validator::new();
+        let mut seen = std::collections::HashSet::new();
+        validator
+            .check(
+                "sale_ids",
+                !self.sale_ids.is_empty(),
+                "REQUIRED",
+                "must name at least one sale",
+            )
+            .check(
+                "sale_ids",
+                self.sale_ids.iter().all(|id| seen.insert(id)),
+                "DUPLICATE",
+                "must not repeat a sale",
+            );
+        check_buyer(&mut validator, &self.buyer);
+        validator.finish()
+    }
+}
+
+/// Sends an invoice to the provider and records the answer. Accepted
+/// invoices are returned as they are, without sending them again.
+async fn submit_to_provider(
+    state: &AppState,
+    config: &Config,
+    provider: &dyn EInvoiceProvider,
+    user: &str,
+    id: u32,
+) -> Result<EInvoice, PharmacyError> {
+    let invoice = state.read().einvoice(id)?.clone();
+    if invoice.status == EInvoiceStatus::Accepted {
+        return Ok(invoice);
+    }
+    let xml = einvoice_xml::render(&invoice, config.tz());
+    let outcome = provider.submit(&invoice, &xml).await;
+    state
+        .update(|pharmacy| pharmacy.record_einvoice_submission(user, id, provider.name(), outcome))
+        .await
+}
+
+async fn issue_einvoice(
+    State(state): State<AppState>,
+    State(config): State<Arc<Config>>,
+    State(provider): State<Arc<dyn EInvoiceProvider>>,
+    Actor(user): Actor,
+    Json(payload): Json<IssueEInvoiceRequest>,
+) -> Result<(StatusCode, Json<EInvoice>), PharmacyError> {
+    let id = state
+        .update(|pharmacy| {
+            payload.validate(pharmacy)?;
+            pharmacy.issue_einvoice(&user, &payload.sale_ids, payload.buyer, &config)
+        })
+        .await?;
+    let invoice = submit_to_provider(&state, &config, provider.as_ref(), &user, id).await?;
+    Ok((StatusCode::CREATED, Json(invoice)))
+}
+
+async fn submit_einvoice(
+    State(state): State<AppState>,
+    State(config): State<Arc<Config>>,
+    State(provider): State<Arc<dyn EInvoiceProvider>>,
+    Actor(user): Actor,
+    Path(id): Path<u32>,
+) -> Result<Json<EInvoice>, PharmacyError> {
+    submit_to_provider(&state, &config, provider.as_ref(), &user, id)
+        .await
+        .map(Json)
+}
+
+#[derive(Deserialize)]
+struct ReplaceEInvoiceRequest {
+    reason: String,
+    buyer: Option<Buyer>,
+    #[serde(default)]
+    lines: Vec<LineCorrection>,
+}
+
+impl Validate for ReplaceEInvoiceRequest {
+    fn validate(&self, _pharmacy: &Pharmacy) -> Result<(), PharmacyError> {
+        let mut validator = Validator::new();
+        validator.not_blank("reason", &self.reason);
+        if let Some(buyer) = &self.buyer {
+            check_buyer(&mut validator, buyer);
+        }
+        check_corrections(&mut validator, &self.lines);
+        validator.finish()
+    }
+}
+
+async fn replace_einvoice(
+    State(state): State<AppState>,
+    State(config): State<Arc<Config>>,
+    State(provider): State<Arc<dyn EInvoiceProvider>>,
+    Actor(user): Actor,
+    Path(id): Path<u32>,
+    Json(payload): Json<ReplaceEInvoiceRequest>,
+) -> Result<(StatusCode, Json<EInvoice>), PharmacyError> {
+    let replacement_id = state
+        .update(|pharmacy| {
+            payload.validate(pharmacy)?;
+            pharmacy.replace_einvoice(
+                &user,
+                id,
+                payload.reason.trim().to_string(),
+                payload.buyer,
+                &payload.lines,
+                &config,
+            )
+        })
+        .await?;
+    let invoice =
+        submit_to_provider(&state, &config, provider.as_ref(), &user, replacement_id).await?;
+    Ok((StatusCode::CREATED, Json(invoice)))
+}
+
+#[derive(Deserialize)]
+struct AdjustEInvoiceRequest {
+    reason: String,
+    lines: Vec<LineCorrection>,
+}
+
+impl Validate for AdjustEInvoiceRequest {
+    fn validate(&self, _pharmacy: &Pharmacy) -> Result<(), PharmacyError> {
+        let mut validator = Validator::new();
+        validator.not_blank("reason", &self.reason).check(
+            "lines",
+            !self.lines.is_empty(),
+            "REQUIRED",
+            "must correct at least one line",
+        );
+        check_corrections(&mut validator, &self.lines);
+        validator.finish()
+    }
+}
+
+async fn adjust_einvoice(
+    State(state): State<AppState>,
+    State(config): State<Arc<Config>>,
+    State(provider): State<Arc<dyn EInvoiceProvider>>,
+    Actor(user): Actor,
+    Path(id): Path<u32>,
+    Json(payload): Json<AdjustEInvoiceRequest>,
+) -> Result<(StatusCode, Json<EInvoice>), PharmacyError> {
+    let adjustment_id = state
+        .update(|pharmacy| {
+            payload.validate(pharmacy)?;
+            pharmacy.adjust_einvoice(
+                &user,
+                id,
+                payload.reason.trim().to_string(),
+                &payload.lines,
+                &config,
+            )
+        })
+        .await?;
+    let invoice =
+        submit_to_provider(&state, &config, provider.as_ref(), &user, adjustment_id).await?;
+    Ok((StatusCode::CREATED, Json(invoice)))
+}
+
+// Pricing handlers
+
+#[derive(Deserialize)]
+struct PriceListsQuery {
+    channel: Option<Channel>,
+}
+
+async fn list_price_lists(
+    State(state): State<AppState>,
+    Query(query): Query<PriceListsQuery>,
+) -> Json<Vec<PriceList>> {
+    let pharmacy = state.read();
+    Json(
+        pharmacy
+            .price_lists
+            .iter()
+            .filter(|l| query.channel.is_none_or(|channel| channel == l.channel))
+            .cloned()
+            .collect(),
+    )
+}
+
+async fn get_price_list(
+    State(state): State<AppState>,
+    Path(id): Path<u32>,
+) -> Result<Json<PriceList>, PharmacyError> {
+    state.read().price_list(id).cloned().map(Json)
+}
+
+#[derive(Deserialize)]
+struct CreatePriceListRequest {
+    name: String,
+    #[serde(default)]
+    channel: Channel,
+    #[serde(default)]
+    customer_group: Option<String>,
+    #[serde(default)]
+    prices: Vec<ListPrice>,
+}
+
+impl Validate for CreatePriceListRequest {
+    fn validate(&self, _pharmacy: &Pharmacy) -> Result<(), PharmacyError> {
+        let mut validator = Validator::new();
+        validator.not_blank("name", &self.name);
+        for (index, price) in self.prices.iter().enumerate() {
+            validator.price(&format!("prices[{}].price", index), price.price);
+        }
+        validator.finish()
+    }
+}
+
+async fn create_price_list(
+    State(state): State<AppState>,
+    Actor(user): Actor,
+    Json(payload): Json<CreatePriceListRequest>,
+) -> Result<(StatusCode, Json<u32>), PharmacyError> {
+    let id = state
+        .update(|pharmacy| {
+            payload.validate(pharmacy)?;
+            pharmacy.create_price_list(
+                &user,
+                payload.name,
+                payload.channel,
+                payload.customer_group,
+                payload.prices,
+            )
+        })
+        .await?;
+    Ok((StatusCode::CREATED, Json(id)))
+}
+
+#[derive(Deserialize)]
+struct ListPriceInput {
+    medicine_id: u32,
+    /// `null` takes the medicine off the list.
+    price: Option<f64>,
+}
+
+#[derive(Deserialize)]
+struct SetListPricesRequest {
+    prices: Vec<ListPriceInput>,
+}
+
+impl Validate for SetListPricesRequest {
+    fn validate(&self, _pharmacy: &Pharmacy) -> Result<(), PharmacyError> {
+        let mut validator = Validator::new();
+        for (index, input) in self.prices.iter().enumerate() {
+            if let Some(price) = input.price {
+                validator.price(&format!("prices[{}].price", index), price);
+            }
+        }
+        validator.finish()
+    }
+}
+
+/// Sets the prices in the body; medicines not in it keep theirs.
+async fn set_list_prices(
+    State(state): State<AppState>,
+    Actor(user): Actor,
+    Path(id): Path<u32>,
+    Json(payload): Json<SetListPricesRequest>,
+) -> Result<Json<PriceList>, PharmacyError> {
+    let list = state
+        .update(|pharmacy| {
+            payload.validate(pharmacy)?;
+            let prices = payload
+                .prices
+                .iter()
+                .map(|p| (p.medicine_id, p.price))
+                .collect();
+            pharmacy.set_list_prices(&user, id, prices)
+        })
+        .await?;
+    Ok(Json(list))
+}
+
+async fn delete_price_list(
+    State(state): State<AppState>,
+    Actor(user): Actor,
+    Path(id): Path<u32>,
+) -> Result<StatusCode, PharmacyError> {
+    state
+        .update(|pharmacy| pharmacy.delete_price_list(&user, id))
+        .await?;
+    Ok(StatusCode::OK)
+}
+
+#[derive(Deserialize)]
+struct PromotionsQuery {
+    active: Option<bool>,
+}
+
+async fn list_promotions(
+    State(state): State<AppState>,
+    Query(query): Query<PromotionsQuery>,
+) -> Json<Vec<Promotion>> {
+    let now = Local::now();
+    let pharmacy = state.read();
+    Json(
+        pharmacy
+            .promotions
+            .iter()
+            .filter(|p| query.active.is_none_or(|active| active == p.is_active(now)))
+            .cloned()
+            .collect(),
+    )
+}
+
+#[derive(Deserialize)]
+struct CreatePromotionRequest {
+    name: String,
+    offer: Offer,
+    #[serde(default)]
+    medicine_ids: Vec<u32>,
+    #[serde(default)]
+    channel: Option<Channel>,
+    #[serde(default)]
+    customer_group: Option<String>,
+    starts_at: Option<String>,
+    ends_at: Option<String>,
+}
+
+impl Validate for CreatePromotionRequest {
+    fn validate(&self, _pharmacy: &Pharmacy) -> Result<(), PharmacyError> {
+        let mut validator = Validator::new();
+        validator.not_blank("name", &self.name);
+        match self.offer {
+            Offer::PercentOff { percent } => {
+                validator.check(
+                    "offer.percent",
+                    percent > 0.0 && percent <= 100.0,
+                    "OUT_OF_RANGE",
+                    "must be above 0 and at most 100",
+                );
+            }
+            Offer::AmountOff { amount } => {
+                validator.price("offer.amount", amount);
+            }
+            Offer::BuyXGetY { buy, get } => {
+                validator
+                    .positive("offer.buy", buy)
+                    .positive("offer.get", get);
+            }
+        }
+        validator.finish()
+    }
+}
+
+async fn create_promotion(
+    State(state): State<AppState>,
+    State(config): State<Arc<Config>>,
+    Actor(user): Actor,
+    Json(payload): Json<CreatePromotionRequest>,
+) -> Result<(StatusCode, Json<u32>), PharmacyError> {
+    let tz = config.tz();
+    let starts_at = payload
+        .starts_at
+        .as_deref()
+        .map(|v| parse_report_start(v, tz))
+        .transpose()?;
+    let ends_at = payload
+        .ends_at
+        .as_deref()
+        .map(|v| parse_report_date(v, tz))
+        .transpose()?;
+    if let Some(end) = ends_at {
+        Validator::new()
+            .check(
+                "ends_at",
+                starts_at.unwrap_or_else(Local::now) < end,
+                "INVALID_RANGE",
+                "must be after starts_at",
+            )
+            .finish()?;
+    }
+    let id = state
+        .update(|pharmacy| {
+            payload.validate(pharmacy)?;
+            pharmacy.create_promotion(
+                &user,
+                payload.name,
+                payload.offer,
+                payload.medicine_ids,
+                payload.channel,
+                payload.customer_group,
+                starts_at,
+                ends_at,
+            )
+        })
+        .await?;
+    Ok((StatusCode::CREATED, Json(id)))
+}
+
+async fn end_promotion(
+    State(state): State<AppState>,
+    Actor(user): Actor,
+    Path(id): Path<u32>,
+) -> Result<Json<Promotion>, PharmacyError> {
+    let promotion = state
+        .update(|pharmacy| pharmacy.end_promotion(&user, id))
+        .await?;
+    Ok(Json(promotion))
+}
+
+#[derive(Deserialize, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+enum SaleSource {
+    /// `POST /api/sell`
+    #[default]
+    Catalog,
+    /// `POST /api/sell-batch`
+    Store,
+}
+
+#[derive(Deserialize)]
+struct QuoteQuery {
+    medicine_id: u32,
+    quantity: u32,
+    #[serde(default)]
+    source: SaleSource,
+    #[serde(default)]
+    channel: Channel,
+    customer_group: Option<String>,
+}
+
+/// What a sale would charge now, without making it.
+async fn get_quote(
+    State(state): State<AppState>,
+    State(config): State<Arc<Config>>,
+    Query(query): Query<QuoteQuery>,
+) -> Result<Json<Quote>, PharmacyError> {
+    Validator::new()
+        .positive("quantity", query.quantity)
+        .finish()?;
+    let pharmacy = state.read();
+    let units = match query.source {
+        SaleSource::Catalog => pharmacy.catalog_units(query.medicine_id, query.quantity)?,
+        SaleSource::Store => pharmacy.store_units(query.medicine_id, query.quantity)?,
+    };
+    Ok(Json(pharmacy.price_line(
+        query.medicine_id,
+        &units,
+        &PriceContext {
+            channel: query.channel,
+            customer_group: query.customer_group.clone(),
+        },
+        Local::now(),
+        &config,
+    )))
+}
+
+async fn get_clearance(
+    State(state): State<AppState>,
+    State(config): State<Arc<Config>>,
+    Query(export): Query<ExportQuery>,
+) -> Result<Response, PharmacyError> {
+    let items: Vec<ClearanceItem> = state.read().clearance(&config);
+    if export.format == ExportFormat::Json {
+        return Ok(Json(items).into_response());
+    }
+    export.download(items, "clearance", &config).await
 }
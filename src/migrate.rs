@@ -0,0 +1,309 @@
+use chrono::{DateTime, Duration, Local};
+use uuid::Uuid;
+
+use crate::models::Pharmacy;
+
+/// Data format written by this build. Bump it together with a new step in `Pharmacy::migrate`.
+pub const SCHEMA_VERSION: u32 = 5;
+
+#[derive(Debug)]
+pub struct MigrationReport {
+    pub from: u32,
+    pub to: u32,
+    pub notes: Vec<String>,
+}
+
+impl Pharmacy {
+    pub fn needs_migration(&self) -> bool {
+        self.schema_version < SCHEMA_VERSION
+    }
+
+    /// Upgrades data written by an older build to `SCHEMA_VERSION`, one step at a time.
+    /// Data from a newer build is refused rather than silently losing fields.
+    pub fn migrate(&mut self) -> Result<MigrationReport, String> {
+        if self.schema_version > SCHEMA_VERSION {
+            return Err(format!(
+                "data uses schema version {}, but this build only understands up to {}",
+                self.schema_version, SCHEMA_VERSION
+            ));
+        }
+
+        let mut report = MigrationReport {
+            from: self.schema_version,
+            to: SCHEMA_VERSION,
+            notes: Vec::new(),
+        };
+        if self.schema_version < 1 {
+            self.migrate_to_v1(&mut report.notes);
+            self.schema_version = 1;
+        }
+        if self.schema_version < 2 {
+            self.migrate_to_v2(&mut report.notes);
+            self.schema_version = 2;
+        }
+        if self.schema_version < 3 {
+            self.migrate_to_v3(&mut report.notes);
+            self.schema_version = 3;
+        }
+        if self.schema_version < 4 {
+            self.migrate_to_v4(&mut report.notes);
+            self.schema_version = 4;
+        }
+        if self.schema_version < 5 {
+            self.migrate_to_v5(&mut report.notes);
+            self.schema_version = 5;
+        }
+        Ok(report)
+    }
+
+    // Import log entries written before warehouses were recorded on them get the
+    // warehouse and supplier of the stock batch created by the same import.
+    fn migrate_to_v1(&mut self, notes: &mut Vec<String>) {
+        let mut filled = 0;
+        let mut unmatched = 0;
+        for entry in self
+            .import_log
+            .iter_mut()
+            .filter(|e| e.warehouse_id.is_none())
+        {
+            let batch = self.stock_batches.iter().find(|b| {
+                b.medicine_id == entry.medicine_id
+                    && (b.import_date - entry.timestamp).abs() < Duration::seconds(1)
+            });
+            match batch {
+                Some(batch) => {
+                    entry.warehouse_id = Some(batch.warehouse_id);
+                    entry.supplier_id = entry.supplier_id.or(batch.supplier_id);
+                    filled += 1;
+                }
+                None => unmatched += 1,
+            }
+        }
+        if filled > 0 {
+            notes.push(format!(
+                "Filled in the warehouse of {} import log entries",
+                filled
+            ));
+        }
+        if unmatched > 0 {
+            notes.push(format!(
+                "{} import log entries have no matching stock batch and keep no warehouse",
+                unmatched
+            ));
+        }
+    }
+
+    // Ids used to be derived from the last record, so sequences start just past
+    // every id still on record; master data gets the UUID new records carry.
+    fn migrate_to_v2(&mut self, notes: &mut Vec<String>) {
+        let advanced = self.advance_sequences();
+        if !advanced.is_empty() {
+            notes.push(format!(
+                "Started id sequences after existing ids for {:?}",
+                advanced
+            ));
+        }
+
+        let mut assigned = 0;
+        let uuids = self
+            .inventory
+            .iter_mut()
+            .map(|m| &mut m.uuid)
+            .chain(self.warehouses.iter_mut().map(|w| &mut w.uuid))
+            .chain(self.suppliers.iter_mut().map(|s| &mut s.uuid));
+        for uuid in uuids.filter(|u| u.is_none()) {
+            *uuid = Some(Uuid::new_v4());
+            assigned += 1;
+        }
+        if assigned > 0 {
+            notes.push(format!(
+                "Assigned UUIDs to {} medicines, warehouses and suppliers",
+                assigned
+            ));
+        }
+    }
+
+    // Batches and import logs had one `price`; it becomes both the unit cost and
+    // the selling price until someone enters the real figures.
+    fn migrate_to_v3(&mut self, notes: &mut Vec<String>) {
+        let batches = self
+            .stock_batches
+            .iter_mut()
+            .filter_map(|b| b.split_legacy_price().then_some(()))
+            .count();
+        let imports = self
+            .import_log
+            .iter_mut()
+            .filter_map(|e| e.split_legacy_price().then_some(()))
+            .count();
+        if batches + imports > 0 {
+            notes.push(format!(
+                "Copied the single price of {} stock batches and {} import log entries into unit cost and selling price",
+                batches, imports
+            ));
+        }
+    }
+
+    // Imports and transfers did not record the batch they created; it is the
+    // batch of the same medicine in the same warehouse created at the same time.
+    fn migrate_to_v4(&mut self, notes: &mut Vec<String>) {
+        let mut claimed: Vec<u32> = self
+            .import_log
+            .iter()
+            .filter_map(|e| e.batch_id)
+            .chain(self.transfer_log.iter().filter_map(|t| t.to_batch_id))
+            .collect();
+        let mut created_batch = |medicine_id: u32, warehouse_id: u32, at: DateTime<Local>| {
+            let batch = self.stock_batches.iter().find(|b| {
+                b.medicine_id == medicine_id
+                    && b.warehouse_id == warehouse_id
+                    && (b.import_date - at).abs() < Duration::seconds(1)
+                    && !claimed.contains(&b.id)
+            })?;
+            claimed.push(batch.id);
+            Some(batch.id)
+        };
+
+        let mut imports = 0;
+        for entry in self.import_log.iter_mut().filter(|e| e.batch_id.is_none()) {
+            if let Some(warehouse_id) = entry.warehouse_id {
+                entry.batch_id = created_batch(entry.medicine_id, warehouse_id, entry.timestamp);
+                imports += entry.batch_id.is_some() as usize;
+            }
+        }
+        let mut transfers = 0;
+        for transfer in self
+            .transfer_log
+            .iter_mut()
+            .filter(|t| t.to_batch_id.is_none())
+        {
+            transfer.to_batch_id = created_batch(
+                transfer.medicine_id,
+                transfer.to_warehouse_id,
+                transfer.timestamp,
+            );
+            transfers += transfer.to_batch_id.is_some() as usize;
+        }
+        if imports + transfers > 0 {
+            notes.push(format!(
+                "Linked {} import log entries and {} transfers to the stock batches they created",
+                imports, transfers
+            ));
+        }
+    }
+
+    // Audit hashes covered the timestamp in the server's offset, so a time zone
+    // or daylight saving change broke the chain; they now cover it in UTC.
+    fn migrate_to_v5(&mut self, notes: &mut Vec<String>) {
+        match self.rehash_audit_chain() {
+            Ok(0) => {}
+            Ok(rehashed) => notes.push(format!(
+                "Re-hashed {} audit log entries with UTC timestamps",
+                rehashed
+            )),
+            Err(e) => notes.push(format!("{}; the audit log was left as it was", e)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::sequence::Sequence;
+
+    // data.json as the first release wrote it: no schema version, a single
+    // price per batch, and logs without warehouses or batch links
+    const BASELINE_DATA: &str = include_str!("../tests/fixtures/data_v0.json");
+
+    fn baseline() -> Pharmacy {
+        serde_json::from_str(BASELINE_DATA).unwrap()
+    }
+
+    #[test]
+    fn baseline_data_is_upgraded_to_the_current_schema() {
+        let mut pharmacy = baseline();
+        assert_eq!(pharmacy.schema_version, 0);
+        assert!(pharmacy.needs_migration());
+
+        let report = pharmacy.migrate().unwrap();
+        assert_eq!((report.from, report.to), (0, SCHEMA_VERSION));
+        assert_eq!(pharmacy.schema_version, SCHEMA_VERSION);
+        assert!(!pharmacy.needs_migration());
+
+        let import = &pharmacy.import_log[0];
+        assert_eq!(import.warehouse_id, Some(1));
+        assert_eq!(import.batch_id, Some(1));
+        assert_eq!((import.unit_cost, import.selling_price), (1200.0, 1200.0));
+        assert_eq!(pharmacy.transfer_log[0].to_batch_id, Some(2));
+        for batch in &pharmacy.stock_batches {
+            assert_eq!((batch.unit_cost, batch.selling_price), (1200.0, 1200.0));
+        }
+        assert!(pharmacy.inventory.iter().all(|m| m.uuid.is_some()));
+        assert!(pharmacy.warehouses.iter().all(|w| w.uuid.is_some()));
+        assert!(pharmacy.suppliers.iter().all(|s| s.uuid.is_some()));
+        assert_eq!(pharmacy.next_id(Sequence::Medicine), 3);
+        assert_eq!(pharmacy.next_id(Sequence::StockBatch), 3);
+    }
+
+    #[test]
+    fn baseline_records_are_preserved() {
+        let mut pharmacy = baseline();
+        pharmacy.migrate().unwrap();
+
+        let medicines: Vec<_> = pharmacy
+            .inventory
+            .iter()
+            .map(|m| (m.id, m.name.as_str(), m.price))
+            .collect();
+        assert_eq!(
+            medicines,
+            [
+                (1, "Paracetamol 500mg", 1500.0),
+                (2, "Amoxicillin 250mg", 3000.0)
+            ]
+        );
+        let warehouses: Vec<_> = pharmacy
+            .warehouses
+            .iter()
+            .map(|w| (w.id, w.name.as_str()))
+            .collect();
+        assert_eq!(warehouses, [(1, "Kho chính"), (2, "Nhà thuốc số 1")]);
+        let batches: Vec<_> = pharmacy
+            .stock_batches
+            .iter()
+            .map(|b| (b.id, b.warehouse_id, b.quantity))
+            .collect();
+        assert_eq!(batches, [(1, 1, 75), (2, 2, 20)]);
+        assert_eq!(pharmacy.export_log[0].amount, 5);
+        assert_eq!(pharmacy.transfer_log[0].batch_id, 1);
+        let supplier = &pharmacy.suppliers[0];
+        assert_eq!(supplier.name, "cpc2");
+        assert_eq!(supplier.phone, "0354956266");
+        assert_eq!(
+            supplier.address,
+            "xóm Thanh Hùng, xã Giao Thanh, Giao Thuỷ, Nam Định"
+        );
+    }
+
+    #[test]
+    fn migrating_twice_changes_nothing() {
+        let mut pharmacy = baseline();
+        pharmacy.migrate().unwrap();
+        let upgraded = serde_json::to_value(&pharmacy).unwrap();
+
+        let report = pharmacy.migrate().unwrap();
+        assert!(report.notes.is_empty());
+        assert_eq!(serde_json::to_value(&pharmacy).unwrap(), upgraded);
+    }
+
+    #[test]
+    fn data_from_a_newer_build_is_refused() {
+        let mut pharmacy = baseline();
+        pharmacy.schema_version = SCHEMA_VERSION + 1;
+
+        assert!(pharmacy.migrate().is_err());
+        assert_eq!(pharmacy.schema_version, SCHEMA_VERSION + 1);
+        assert_eq!(pharmacy.import_log[0].warehouse_id, None);
+    }
+}
@@ -1,12 +1,36 @@
 use chrono::{DateTime, Local};
 use serde::{Deserialize, Serialize};
 
+use crate::audit::{AuditAction, AuditEntity, AuditEntry};
+use crate::config::Config;
+use crate::einvoice::EInvoice;
+use crate::error::PharmacyError;
+use crate::migrate::SCHEMA_VERSION;
+use crate::pricing::{LinePricing, PriceContext, PriceList, Promotion, Units};
+use crate::purchase_order::PurchaseOrder;
+use crate::replenishment::ReorderSetting;
+use crate::sequence::{Sequence, Sequences};
+use crate::store_replenishment::{ParLevel, TransferProposal};
+use crate::tax::{LineTax, VatRate};
+use uuid::Uuid;
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Medicine {
     pub id: u32,
     pub name: String,
     pub price: f64,
     pub quantity: u32, // This will be deprecated, kept for backward compatibility
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub category: Option<String>,
+    /// Stable identity across branches; local ids may collide when data is merged.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub uuid: Option<Uuid>,
+    /// Set while archived: hidden from pickers but still resolvable by id.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub archived_at: Option<DateTime<Local>>,
+    /// VAT category; without one the configured default rate applies.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub vat_rate: Option<VatRate>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -20,6 +44,19 @@ pub struct Warehouse {
     pub id: u32,
     pub name: String,
     pub warehouse_type: WarehouseType,
+    #[serde(default = "initial_version")]
+    pub version: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub uuid: Option<Uuid>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub archived_at: Option<DateTime<Local>>,
+}
+
+// Partial update; fields left as None keep their current value
+#[derive(Debug, Deserialize, Clone)]
+pub struct WarehousePatch {
+    pub name: Option<String>,
+    pub warehouse_type: Option<WarehouseType>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -28,10 +65,19 @@ pub struct StockBatch {
     pub medicine_id: u32,
     pub medicine_name: String,
     pub warehouse_id: u32,
+    #[serde(default)]
+    pub supplier_id: Option<u32>,
     pub quantity: u32,
-    pub price: f64,
+    /// Purchase cost per unit, used for valuation.
+    #[serde(default)]
+    pub unit_cost: f64,
+    #[serde(default)]
+    pub selling_price: f64,
     pub expiry_date: DateTime<Local>,
     pub import_date: DateTime<Local>,
+    /// Single price written before cost and selling price were split; see `split_legacy_price`.
+    #[serde(default, rename = "price", skip_serializing)]
+    pub legacy_price: Option<f64>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -39,9 +85,49 @@ pub struct ImportBatch {
     pub id: u32,
     pub medicine_id: u32,
     pub medicine_name: String,
+    #[serde(default)]
+    pub warehouse_id: Option<u32>,
+    #[serde(default)]
+    pub supplier_id: Option<u32>,
+    /// Stock batch created by this import; `None` for catalog additions.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub batch_id: Option<u32>,
     pub quantity: u32,
-    pub price: f64,
+    #[serde(default)]
+    pub unit_cost: f64,
+    #[serde(default)]
+    pub selling_price: f64,
     pub timestamp: DateTime<Local>,
+    #[serde(default, rename = "price", skip_serializing)]
+    pub legacy_price: Option<f64>,
+}
+
+impl StockBatch {
+    /// Moves a pre-split `price` into both cost and selling price; old data
+    /// never said which of the two it was. Returns whether anything changed.
+    pub fn split_legacy_price(&mut self) -> bool {
+        match self.legacy_price.take() {
+            Some(price) => {
+                self.unit_cost = price;
+                self.selling_price = price;
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+impl ImportBatch {
+    pub fn split_legacy_price(&mut self) -> bool {
+        match self.legacy_price.take() {
+            Some(price) => {
+                self.unit_cost = price;
+                self.selling_price = price;
+                true
+            }
+            None => false,
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -49,11 +135,64 @@ pub struct ExportBatch {
     pub id: u32,
     pub medicine_id: u32,
     pub medicine_name: String,
+    #[serde(default)]
+    pub warehouse_id: Option<u32>,
     pub amount: u32,
+    /// Selling price per unit.
     pub price: f64,
+    /// Average purchase cost per unit of the batches sold from; unknown for catalog sales.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub unit_cost: Option<f64>,
+    /// User who made the sale; missing on sales recorded before it was tracked.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cashier: Option<String>,
+    /// Batches consumed, each at its own cost, so COGS does not depend on averages.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub batches: Vec<SoldBatch>,
+    /// Price rule applied; missing on sales recorded before pricing rules.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pricing: Option<LinePricing>,
+    /// VAT on the line; missing on sales recorded before it was tracked.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tax: Option<LineTax>,
     pub timestamp: DateTime<Local>,
 }
 
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SoldBatch {
+    pub batch_id: u32,
+    pub quantity: u32,
+    pub unit_cost: f64,
+    pub selling_price: f64,
+}
+
+impl ExportBatch {
+    /// What the customer paid, VAT included.
+    pub fn revenue(&self) -> f64 {
+        match &self.tax {
+            Some(tax) => tax.gross(),
+            None => self.price * self.amount as f64,
+        }
+    }
+
+    /// Revenue before VAT, or `None` for sales made before VAT was recorded.
+    pub fn net_revenue(&self) -> Option<f64> {
+        self.tax.map(|tax| tax.net)
+    }
+
+    /// Cost of goods sold, or `None` when the sale carries no cost.
+    pub fn cogs(&self) -> Option<f64> {
+        if !self.batches.is_empty() {
+            return Some(
+                self.batches
+                    .iter()
+                    .fold(0.0, |sum, b| sum + b.unit_cost * b.quantity as f64),
+            );
+        }
+        self.unit_cost.map(|cost| cost * self.amount as f64)
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct InternalTransfer {
     pub id: u32,
@@ -63,6 +202,9 @@ pub struct InternalTransfer {
     pub to_warehouse_id: u32,
     pub quantity: u32,
     pub batch_id: u32,
+    /// Batch created in the destination warehouse.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub to_batch_id: Option<u32>,
     pub timestamp: DateTime<Local>,
 }
 
@@ -73,6 +215,25 @@ pub struct Supplier {
     pub contact: String,
     pub phone: String,
     pub address: String,
+    #[serde(default = "initial_version")]
+    pub version: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub uuid: Option<Uuid>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub archived_at: Option<DateTime<Local>>,
+}
+
+// Partial update; fields left as None keep their current value
+#[derive(Debug, Deserialize, Clone)]
+pub struct SupplierPatch {
+    pub name: Option<String>,
+    pub contact: Option<String>,
+    pub phone: Option<String>,
+    pub address: Option<String>,
+}
+
+fn initial_version() -> u32 {
+    1
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -84,6 +245,27 @@ pub struct Pharmacy {
     pub export_log: Vec<ExportBatch>,
     pub transfer_log: Vec<InternalTransfer>,
     pub suppliers: Vec<Supplier>,
+    #[serde(default)]
+    pub audit_log: Vec<AuditEntry>,
+    /// Data format version; files written before versioning read as 0.
+    #[serde(default)]
+    pub schema_version: u32,
+    #[serde(default)]
+    pub sequences: Sequences,
+    #[serde(default)]
+    pub reorder_settings: Vec<ReorderSetting>,
+    #[serde(default)]
+    pub purchase_orders: Vec<PurchaseOrder>,
+    #[serde(default)]
+    pub par_levels: Vec<ParLevel>,
+    #[serde(default)]
+    pub transfer_proposals: Vec<TransferProposal>,
+    #[serde(default)]
+    pub einvoices: Vec<EInvoice>,
+    #[serde(default)]
+    pub price_lists: Vec<PriceList>,
+    #[serde(default)]
+    pub promotions: Vec<Promotion>,
 }
 
 impl Pharmacy {
@@ -96,192 +278,335 @@ impl Pharmacy {
             export_log: Vec::new(),
             transfer_log: Vec::new(),
             suppliers: Vec::new(),
+            audit_log: Vec::new(),
+            schema_version: SCHEMA_VERSION,
+            sequences: Sequences::default(),
+            reorder_settings: Vec::new(),
+            purchase_orders: Vec::new(),
+            par_levels: Vec::new(),
+            transfer_proposals: Vec::new(),
+            einvoices: Vec::new(),
+            price_lists: Vec::new(),
+            promotions: Vec::new(),
         }
     }
 
-    pub fn add_medicine(&mut self, name: String, price: f64, quantity: u32) {
+    pub fn add_medicine(
+        &mut self,
+        user: &str,
+        name: String,
+        category: Option<String>,
+        price: f64,
+        quantity: u32,
+        vat_rate: Option<VatRate>,
+    ) -> u32 {
         // Check if medicine exists (simple check by name for now, or just create new ID)
         // For this task, we assume adding new medicine creates a new entry.
         // In "Advanced Inventory", we will handle deduplication.
 
-        let id = if let Some(last) = self.inventory.last() {
-            last.id + 1
-        } else {
-            1
-        };
+        let id = self.next_id(Sequence::Medicine);
 
         let medicine = Medicine {
             id,
             name: name.clone(),
             price,
             quantity,
+            category,
+            uuid: Some(Uuid::new_v4()),
+            archived_at: None,
+            vat_rate,
         };
+        self.record_audit(
+            user,
+            AuditAction::Create,
+            AuditEntity::Medicine,
+            id,
+            None,
+            Some(&medicine),
+        );
         self.inventory.push(medicine);
 
         // Record Import Batch
-        let batch_id = self.import_log.len() as u32 + 1;
+        let batch_id = self.next_id(Sequence::ImportLog);
         let batch = ImportBatch {
             id: batch_id,
             medicine_id: id,
             medicine_name: name,
+            warehouse_id: None,
+            supplier_id: None,
+            batch_id: None,
             quantity,
-            price,
+            unit_cost: price,
+            selling_price: price,
             timestamp: Local::now(),
+            legacy_price: None,
         };
         self.import_log.push(batch);
+        id
     }
 
-    pub fn sell_medicine(&mut self, id: u32, amount: u32) -> Result<(), String> {
+    pub fn sell_medicine(
+        &mut self,
+        user: &str,
+        id: u32,
+        amount: u32,
+        context: &PriceContext,
+        config: &Config,
+    ) -> Result<(), PharmacyError> {
+        self.ensure_medicine_not_archived(id)?;
+        let units = self.catalog_units(id, amount)?;
+        let quote = self.price_line(id, &units, context, Local::now(), config);
+        let tax = self.line_tax(id, quote.amount, config);
         if let Some(med) = self.inventory.iter_mut().find(|m| m.id == id) {
             if med.quantity >= amount {
+                let before = med.clone();
                 med.quantity -= amount;
+                let after = med.clone();
 
                 // Record Export Batch
-                let batch_id = self.export_log.len() as u32 + 1;
+                let batch_id = self.next_id(Sequence::ExportLog);
                 let batch = ExportBatch {
                     id: batch_id,
                     medicine_id: id,
-                    medicine_name: med.name.clone(),
+                    medicine_name: after.name.clone(),
+                    warehouse_id: None,
                     amount,
-                    price: med.price,
+                    price: quote.price,
+                    unit_cost: None,
+                    cashier: Some(user.to_string()),
+                    batches: Vec::new(),
+                    pricing: Some(quote.pricing),
+                    tax: Some(tax),
                     timestamp: Local::now(),
                 };
                 self.export_log.push(batch);
+                self.record_audit(
+                    user,
+                    AuditAction::Update,
+                    AuditEntity::Medicine,
+                    id,
+                    Some(&before),
+                    Some(&after),
+                );
 
                 Ok(())
             } else {
-                Err(format!("Not enough quantity. Available: {}", med.quantity))
+                Err(PharmacyError::InsufficientStock {
+                    available: med.quantity,
+                })
             }
         } else {
-            Err("Medicine not found".to_string())
-        }
-    }
-
-    pub fn delete_medicine(&mut self, id: u32) -> Result<(), String> {
-        if let Some(pos) = self.inventory.iter().position(|m| m.id == id) {
-            self.inventory.remove(pos);
-            Ok(())
-        } else {
-            Err("Medicine not found".to_string())
+            Err(PharmacyError::MedicineNotFound)
         }
     }
 
     // Warehouse Management Methods
 
+    /// Applies `patch` if the warehouse is still at `expected_version` (None skips the check)
+    /// and returns the new version.
     pub fn edit_warehouse(
         &mut self,
+        user: &str,
         id: u32,
-        name: String,
-        warehouse_type: WarehouseType,
-    ) -> Result<(), String> {
+        expected_version: Option<u32>,
+        patch: WarehousePatch,
+    ) -> Result<u32, PharmacyError> {
         if let Some(wh) = self.warehouses.iter_mut().find(|w| w.id == id) {
-            wh.name = name;
-            wh.warehouse_type = warehouse_type;
-            Ok(())
+            if let Some(expected) = expected_version
+                && expected != wh.version
+            {
+                return Err(PharmacyError::VersionMismatch {
+                    current: wh.version,
+                });
+            }
+
+            let before = wh.clone();
+            if let Some(name) = patch.name {
+                wh.name = name;
+            }
+            if let Some(warehouse_type) = patch.warehouse_type {
+                wh.warehouse_type = warehouse_type;
+            }
+            wh.version += 1;
+            let after = wh.clone();
+            self.record_audit(
+                user,
+                AuditAction::Update,
+                AuditEntity::Warehouse,
+                id,
+                Some(&before),
+                Some(&after),
+            );
+            Ok(after.version)
         } else {
-            Err("Warehouse not found".to_string())
+            Err(PharmacyError::WarehouseNotFound)
         }
     }
 
     pub fn add_supplier(
         &mut self,
+        user: &str,
         name: String,
         contact: String,
         phone: String,
         address: String,
     ) -> u32 {
-        let id = if let Some(last) = self.suppliers.last() {
-            last.id + 1
-        } else {
-            1
-        };
+        let id = self.next_id(Sequence::Supplier);
         let supplier = Supplier {
             id,
             name,
             contact,
             phone,
             address,
+            version: initial_version(),
+            uuid: Some(Uuid::new_v4()),
+            archived_at: None,
         };
+        self.record_audit(
+            user,
+            AuditAction::Create,
+            AuditEntity::Supplier,
+            id,
+            None,
+            Some(&supplier),
+        );
         self.suppliers.push(supplier);
         id
     }
 
+    /// Applies `patch` if the supplier is still at `expected_version` (None skips the check)
+    /// and returns the new version.
     pub fn edit_supplier(
         &mut self,
+        user: &str,
         id: u32,
-        name: String,
-        contact: String,
-        phone: String,
-        address: String,
-    ) -> Result<(), String> {
+        expected_version: Option<u32>,
+        patch: SupplierPatch,
+    ) -> Result<u32, PharmacyError> {
         if let Some(supplier) = self.suppliers.iter_mut().find(|s| s.id == id) {
-            supplier.name = name;
-            supplier.contact = contact;
-            supplier.phone = phone;
-            supplier.address = address;
-            Ok(())
+            if let Some(expected) = expected_version
+                && expected != supplier.version
+            {
+                return Err(PharmacyError::VersionMismatch {
+                    current: supplier.version,
+                });
+            }
+
+            let before = supplier.clone();
+            if let Some(name) = patch.name {
+                supplier.name = name;
+            }
+            if let Some(contact) = patch.contact {
+                supplier.contact = contact;
+            }
+            if let Some(phone) = patch.phone {
+                supplier.phone = phone;
+            }
+            if let Some(address) = patch.address {
+                supplier.address = address;
+            }
+            supplier.version += 1;
+            let after = supplier.clone();
+            self.record_audit(
+                user,
+                AuditAction::Update,
+                AuditEntity::Supplier,
+                id,
+                Some(&before),
+                Some(&after),
+            );
+            Ok(after.version)
         } else {
-            Err("Supplier not found".to_string())
+            Err(PharmacyError::SupplierNotFound)
         }
     }
 
     // Restore add_warehouse method
-    pub fn add_warehouse(&mut self, name: String, warehouse_type: WarehouseType) -> u32 {
-        let id = if let Some(last) = self.warehouses.last() {
-            last.id + 1
-        } else {
-            1
-        };
+    pub fn add_warehouse(
+        &mut self,
+        user: &str,
+        name: String,
+        warehouse_type: WarehouseType,
+    ) -> u32 {
+        let id = self.next_id(Sequence::Warehouse);
         let warehouse = Warehouse {
             id,
             name,
             warehouse_type,
+            version: initial_version(),
+            uuid: Some(Uuid::new_v4()),
+            archived_at: None,
         };
+        self.record_audit(
+            user,
+            AuditAction::Create,
+            AuditEntity::Warehouse,
+            id,
+            None,
+            Some(&warehouse),
+        );
         self.warehouses.push(warehouse);
         id
     }
 
+    #[allow(clippy::too_many_arguments)]
     pub fn import_batch(
         &mut self,
+        user: &str,
         medicine_id: u32,
         medicine_name: String,
         warehouse_id: u32,
+        supplier_id: Option<u32>,
         quantity: u32,
-        price: f64,
+        unit_cost: f64,
+        selling_price: f64,
         expiry_date: DateTime<Local>,
-    ) -> Result<u32, String> {
-        // Verify warehouse exists
-        if !self.warehouses.iter().any(|w| w.id == warehouse_id) {
-            return Err("Warehouse not found".to_string());
+    ) -> Result<u32, PharmacyError> {
+        self.active_warehouse(warehouse_id)?;
+        if let Some(supplier_id) = supplier_id {
+            self.active_supplier(supplier_id)?;
         }
+        self.ensure_medicine_not_archived(medicine_id)?;
 
-        let batch_id = if let Some(last) = self.stock_batches.last() {
-            last.id + 1
-        } else {
-            1
-        };
+        let batch_id = self.next_id(Sequence::StockBatch);
 
         let batch = StockBatch {
             id: batch_id,
             medicine_id,
             medicine_name: medicine_name.clone(),
             warehouse_id,
+            supplier_id,
             quantity,
-            price,
+            unit_cost,
+            selling_price,
             expiry_date,
             import_date: Local::now(),
+            legacy_price: None,
         };
+        self.record_audit(
+            user,
+            AuditAction::Create,
+            AuditEntity::StockBatch,
+            batch_id,
+            None,
+            Some(&batch),
+        );
         self.stock_batches.push(batch);
 
         // Log import
-        let log_id = self.import_log.len() as u32 + 1;
+        let log_id = self.next_id(Sequence::ImportLog);
         let import_log = ImportBatch {
             id: log_id,
             medicine_id,
             medicine_name,
+            warehouse_id: Some(warehouse_id),
+            supplier_id,
+            batch_id: Some(batch_id),
             quantity,
-            price,
+            unit_cost,
+            selling_price,
             timestamp: Local::now(),
+            legacy_price: None,
         };
         self.import_log.push(import_log);
 
@@ -290,54 +615,75 @@ impl Pharmacy {
 
     pub fn transfer_batch(
         &mut self,
+        user: &str,
         batch_id: u32,
         to_warehouse_id: u32,
         quantity: u32,
-    ) -> Result<(), String> {
+    ) -> Result<(), PharmacyError> {
+        self.active_warehouse(to_warehouse_id)?;
+
         // Find the source batch
         let source_batch = self
             .stock_batches
             .iter_mut()
             .find(|b| b.id == batch_id)
-            .ok_or("Batch not found")?;
+            .ok_or(PharmacyError::BatchNotFound)?;
 
         if source_batch.quantity < quantity {
-            return Err(format!(
-                "Insufficient quantity. Available: {}",
-                source_batch.quantity
-            ));
+            return Err(PharmacyError::InsufficientStock {
+                available: source_batch.quantity,
+            });
         }
 
         let from_warehouse_id = source_batch.warehouse_id;
         let medicine_id = source_batch.medicine_id;
         let medicine_name = source_batch.medicine_name.clone();
-        let price = source_batch.price;
+        let supplier_id = source_batch.supplier_id;
+        let unit_cost = source_batch.unit_cost;
+        let selling_price = source_batch.selling_price;
         let expiry_date = source_batch.expiry_date;
 
         // Decrease source batch quantity
+        let source_before = source_batch.clone();
         source_batch.quantity -= quantity;
+        let source_after = source_batch.clone();
+        self.record_audit(
+            user,
+            AuditAction::Update,
+            AuditEntity::StockBatch,
+            batch_id,
+            Some(&source_before),
+            Some(&source_after),
+        );
 
         // Create new batch in destination warehouse
-        let new_batch_id = if let Some(last) = self.stock_batches.last() {
-            last.id + 1
-        } else {
-            1
-        };
+        let new_batch_id = self.next_id(Sequence::StockBatch);
 
         let new_batch = StockBatch {
             id: new_batch_id,
             medicine_id,
             medicine_name: medicine_name.clone(),
             warehouse_id: to_warehouse_id,
+            supplier_id,
             quantity,
-            price,
+            unit_cost,
+            selling_price,
             expiry_date,
             import_date: Local::now(),
+            legacy_price: None,
         };
+        self.record_audit(
+            user,
+            AuditAction::Create,
+            AuditEntity::StockBatch,
+            new_batch_id,
+            None,
+            Some(&new_batch),
+        );
         self.stock_batches.push(new_batch);
 
         // Log transfer
-        let transfer_id = self.transfer_log.len() as u32 + 1;
+        let transfer_id = self.next_id(Sequence::TransferLog);
         let transfer = InternalTransfer {
             id: transfer_id,
             medicine_id,
@@ -346,6 +692,7 @@ impl Pharmacy {
             to_warehouse_id,
             quantity,
             batch_id,
+            to_batch_id: Some(new_batch_id),
             timestamp: Local::now(),
         };
         self.transfer_log.push(transfer);
@@ -353,14 +700,22 @@ impl Pharmacy {
         Ok(())
     }
 
-    #[allow(dead_code)]
-    pub fn sell_with_fefo(&mut self, medicine_id: u32, quantity: u32) -> Result<(), String> {
+    pub fn sell_with_fefo(
+        &mut self,
+        user: &str,
+        medicine_id: u32,
+        quantity: u32,
+        context: &PriceContext,
+        config: &Config,
+    ) -> Result<(), PharmacyError> {
+        self.ensure_medicine_not_archived(medicine_id)?;
         // Find store warehouse
         let store_warehouse = self
             .warehouses
             .iter()
-            .find(|w| matches!(w.warehouse_type, WarehouseType::Store))
-            .ok_or("Store warehouse not found")?;
+            .find(|w| matches!(w.warehouse_type, WarehouseType::Store) && w.archived_at.is_none())
+            .ok_or(PharmacyError::StoreWarehouseNotFound)?;
+        let store_warehouse_id = store_warehouse.id;
 
         // Get all batches for this medicine in store, sorted by expiry date (FEFO)
         let mut available_batches: Vec<_> = self
@@ -368,16 +723,24 @@ impl Pharmacy {
             .iter_mut()
             .filter(|b| {
                 b.medicine_id == medicine_id
-                    && b.warehouse_id == store_warehouse.id
+                    && b.warehouse_id == store_warehouse_id
                     && b.quantity > 0
             })
             .collect();
 
         available_batches.sort_by_key(|b| b.expiry_date);
 
+        // Check before touching any batch so a failed sale leaves stock as it was
+        let available: u32 = available_batches.iter().map(|b| b.quantity).sum();
+        if available < quantity {
+            return Err(PharmacyError::InsufficientStock { available });
+        }
+
         let mut remaining = quantity;
         let mut medicine_name = String::new();
-        let mut total_price = 0.0;
+        let mut total_cost = 0.0;
+        let mut sold = Vec::new();
+        let mut changes = Vec::new();
 
         for batch in available_batches {
             if remaining == 0 {
@@ -389,27 +752,64 @@ impl Pharmacy {
             }
 
             let to_sell = remaining.min(batch.quantity);
+            let before = batch.clone();
             batch.quantity -= to_sell;
             remaining -= to_sell;
-            total_price += batch.price * to_sell as f64;
+            total_cost += batch.unit_cost * to_sell as f64;
+            sold.push(SoldBatch {
+                batch_id: batch.id,
+                quantity: to_sell,
+                unit_cost: batch.unit_cost,
+                selling_price: batch.selling_price,
+            });
+            changes.push((before, batch.clone()));
         }
 
-        if remaining > 0 {
-            return Err(format!("Insufficient stock. Short by {} units", remaining));
-        }
+        // Price the batches taken; near-expiry ones may be marked down
+        let units: Vec<Units> = sold
+            .iter()
+            .map(|s| Units {
+                batch_id: Some(s.batch_id),
+                quantity: s.quantity,
+                price: s.selling_price,
+                expiry_date: changes
+                    .iter()
+                    .find(|(_, after)| after.id == s.batch_id)
+                    .map(|(_, after)| after.expiry_date),
+            })
+            .collect();
+        let quote = self.price_line(medicine_id, &units, context, Local::now(), config);
+        let tax = self.line_tax(medicine_id, quote.amount, config);
 
         // Log export
-        let export_id = self.export_log.len() as u32 + 1;
+        let export_id = self.next_id(Sequence::ExportLog);
         let export = ExportBatch {
             id: export_id,
             medicine_id,
             medicine_name,
+            warehouse_id: Some(store_warehouse_id),
             amount: quantity,
-            price: total_price / quantity as f64, // Average price
+            price: quote.price,
+            unit_cost: Some(total_cost / quantity as f64),
+            cashier: Some(user.to_string()),
+            batches: sold,
+            pricing: Some(quote.pricing),
+            tax: Some(tax),
             timestamp: Local::now(),
         };
         self.export_log.push(export);
 
+        for (before, after) in changes {
+            self.record_audit(
+                user,
+                AuditAction::Update,
+                AuditEntity::StockBatch,
+                after.id,
+                Some(&before),
+                Some(&after),
+            );
+        }
+
         Ok(())
     }
 
@@ -424,7 +824,6 @@ impl Pharmacy {
             .collect()
     }
 
-    #[allow(dead_code)]
     pub fn get_stock_by_warehouse(&self, warehouse_id: u32) -> Vec<StockBatch> {
         self.stock_batches
             .iter()
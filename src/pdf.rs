@@ -0,0 +1,590 @@
+use printpdf::lopdf::{self, Object};
+use printpdf::{
+    CustomPdfConformance, IndirectFontRef, Line, Mm, PdfConformance, PdfDocument,
+    PdfLayerReference, Point,
+};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fs;
+use subsetter::Profile;
+use ttf_parser::Face;
+use unicode_normalization::UnicodeNormalization;
+
+use crate::config::Config;
+
+// Every letter with a Vietnamese diacritic; a font must have all of them
+const VIETNAMESE: &str = "ĂÂĐÊÔƠƯăâđêôơưÀẢÃÁẠẰẲẴẮẶẦẨẪẤẬÈẺẼÉẸỀỂỄẾỆÌỈĨÍỊÒỎÕÓỌỒỔỖỐỘỜỞỠỚỢÙỦŨÚỤỪỬỮỨỰỲỶỸÝỴ\
+àảãáạằẳẵắặầẩẫấậèẻẽéẹềểễếệìỉĩíịòỏõóọồổỗốộờởỡớợùủũúụừửữứựỳỷỹýỵ";
+const PT_TO_MM: f32 = 25.4 / 72.0;
+const LINE_SPACING: f32 = 1.35;
+const TITLE_SCALE: f32 = 1.4;
+const SIGNATURE_SPACE_MM: f32 = 22.0;
+
+/// Regular and bold TrueType fonts, loaded once when the server starts.
+pub struct Fonts {
+    regular: Vec<u8>,
+    bold: Vec<u8>,
+}
+
+impl Fonts {
+    /// Reads both font files and checks that they can print Vietnamese; any
+    /// problem is added to `problems`.
+    pub fn load(config: &Config, problems: &mut Vec<String>) -> Fonts {
+        let mut read = |key: &str, path: &std::path::Path| match fs::read(path) {
+            Ok(data) => {
+                match Face::parse(&data, 0) {
+                    Ok(face) => {
+                        let missing: String = VIETNAMESE
+                            .chars()
+                            .filter(|c| face.glyph_index(*c).is_none())
+                            .collect();
+                        if !missing.is_empty() {
+                            problems.push(format!(
+                                "documents.{}: {} has no glyphs for {}",
+                                key,
+                                path.display(),
+                                missing
+                            ));
+                        }
+                    }
+                    Err(e) => problems.push(format!(
+                        "documents.{}: {} is not a TrueType font: {}",
+                        key,
+                        path.display(),
+                        e
+                    )),
+                }
+                data
+            }
+            Err(e) => {
+                problems.push(format!("documents.{}: {}: {}", key, path.display(), e));
+                Vec::new()
+            }
+        };
+        let (font_file, bold_font_file) = config.font_files();
+        let regular = read("font_file", &font_file);
+        let bold = read("bold_font_file", &bold_font_file);
+        Fonts { regular, bold }
+    }
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq)]
+pub enum Paper {
+    #[serde(rename = "58mm")]
+    Thermal58,
+    #[serde(rename = "80mm")]
+    Thermal80,
+    #[serde(rename = "a4")]
+    A4,
+}
+
+impl Paper {
+    pub fn is_thermal(self) -> bool {
+        self != Paper::A4
+    }
+
+    fn width(self) -> f32 {
+        match self {
+            Paper::Thermal58 => 58.0,
+            Paper::Thermal80 => 80.0,
+            Paper::A4 => 210.0,
+        }
+    }
+
+    /// Thermal rolls have no fixed length; the page is as long as the content.
+    fn height(self) -> Option<f32> {
+        match self {
+            Paper::A4 => Some(297.0),
+            _ => None,
+        }
+    }
+
+    fn margin(self) -> f32 {
+        match self {
+            Paper::Thermal58 => 4.0,
+            Paper::Thermal80 => 4.0,
+            Paper::A4 => 15.0,
+        }
+    }
+
+    fn font_size(self) -> f32 {
+        match self {
+            Paper::Thermal58 => 7.0,
+            Paper::Thermal80 => 8.5,
+            Paper::A4 => 10.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Align {
+    Left,
+    Center,
+    Right,
+}
+
+pub struct Column {
+    pub title: &'static str,
+    /// Share of the table width, relative to the other columns.
+    pub weight: f32,
+    pub align: Align,
+}
+
+pub enum Block {
+    /// Large, bold and centered.
+    Title(String),
+    Text {
+        text: String,
+        bold: bool,
+        align: Align,
+    },
+    /// `label: value` on one line.
+    Field(&'static str, String),
+    /// Label on the left and value on the right, as in totals.
+    Amount {
+        label: String,
+        value: String,
+        bold: bool,
+    },
+    /// Rows are repeated under the header on every page they spill onto.
+    Table {
+        columns: Vec<Column>,
+        rows: Vec<Vec<String>>,
+    },
+    Rule,
+    Space,
+    /// Side-by-side roles with room to sign under each.
+    Signatures(Vec<&'static str>),
+}
+
+pub struct Document {
+    pub title: String,
+    pub blocks: Vec<Block>,
+}
+
+enum Mark {
+    Text {
+        x: f32,
+        y: f32,
+        size: f32,
+        bold: bool,
+        text: String,
+    },
+    Rule {
+        x1: f32,
+        x2: f32,
+        y: f32,
+    },
+}
+
+struct Layout<'a> {
+    regular: Face<'a>,
+    bold: Face<'a>,
+    paper: Paper,
+    size: f32,
+    pages: Vec<Vec<Mark>>,
+    // Distance from the top of the current page, in millimetres
+    y: f32,
+}
+
+impl Layout<'_> {
+    fn left(&self) -> f32 {
+        self.paper.margin()
+    }
+
+    fn width(&self) -> f32 {
+        self.paper.width() - 2.0 * self.paper.margin()
+    }
+
+    fn line_height(size: f32) -> f32 {
+        size * PT_TO_MM * LINE_SPACING
+    }
+
+    fn text_width(&self, text: &str, size: f32, bold: bool) -> f32 {
+        let face = if bold { &self.bold } else { &self.regular };
+        let units: u32 = text
+            .chars()
+            .filter_map(|c| face.glyph_index(c))
+            .filter_map(|g| face.glyph_hor_advance(g))
+            .map(u32::from)
+            .sum();
+        units as f32 / face.units_per_em() as f32 * size * PT_TO_MM
+    }
+
+    /// Greedy word wrap; words wider than a line are split between characters.
+    fn wrap(&self, text: &str, width: f32, size: f32, bold: bool) -> Vec<String> {
+        // Accents typed as separate combining marks would print beside their letter
+        let text: String = text.nfc().collect();
+        let mut lines = Vec::new();
+        let mut line = String::new();
+        for word in text.split_whitespace() {
+            let candidate = if line.is_empty() {
+                word.to_string()
+            } else {
+                format!("{} {}", line, word)
+            };
+            if self.text_width(&candidate, size, bold) <= width {
+                line = candidate;
+                continue;
+            }
+            if !line.is_empty() {
+                lines.push(std::mem::take(&mut line));
+            }
+            for c in word.chars() {
+                line.push(c);
+                if self.text_width(&line, size, bold) > width && line.chars().count() > 1 {
+                    line.pop();
+                    lines.push(std::mem::replace(&mut line, c.to_string()));
+                }
+            }
+        }
+        if !line.is_empty() || lines.is_empty() {
+            lines.push(line);
+        }
+        lines
+    }
+
+    /// Starts a new page when `height` does not fit on this one. Thermal
+    /// paper never breaks.
+    fn ensure(&mut self, height: f32) -> bool {
+        match self.paper.height() {
+            Some(page) if self.y + height > page - self.paper.margin() => {
+                self.pages.push(Vec::new());
+                self.y = self.paper.margin();
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn mark(&mut self, mark: Mark) {
+        if let Some(page) = self.pages.last_mut() {
+            page.push(mark);
+        }
+    }
+
+    fn text_at(&mut self, text: &str, x: f32, width: f32, size: f32, bold: bool, align: Align) {
+        let x = match align {
+            Align::Left => x,
+            Align::Center => x + (width - self.text_width(text, size, bold)) / 2.0,
+            Align::Right => x + width - self.text_width(text, size, bold),
+        };
+        let y = self.y + size * PT_TO_MM;
+        self.mark(Mark::Text {
+            x,
+            y,
+            size,
+            bold,
+            text: text.to_string(),
+        });
+    }
+
+    fn paragraph(&mut self, text: &str, size: f32, bold: bool, align: Align) {
+        let (left, width) = (self.left(), self.width());
+        for line in self.wrap(text, width, size, bold) {
+            self.ensure(Self::line_height(size));
+            self.text_at(&line, left, width, size, bold, align);
+            self.y += Self::line_height(size);
+        }
+    }
+
+    fn rule(&mut self) {
+        let (x1, x2) = (self.left(), self.left() + self.width());
+        self.ensure(Self::line_height(self.size) / 2.0);
+        let y = self.y + Self::line_height(self.size) / 4.0;
+        self.mark(Mark::Rule { x1, x2, y });
+        self.y += Self::line_height(self.size) / 2.0;
+    }
+
+    /// One table row; cells wrap within their column and the row is as tall as
+    /// its longest cell.
+    fn row(&mut self, columns: &[Column], cells: &[String], bold: bool) -> bool {
+        let total: f32 = columns.iter().map(|c| c.weight).sum();
+        let gap = 1.5;
+        let mut x = self.left();
+        let mut placed = Vec::new();
+        for (column, cell) in columns.iter().zip(cells) {
+            let width = self.width() * column.weight / total;
+            let lines = self.wrap(cell, width - gap, self.size, bold);
+            placed.push((x, width - gap, column.align, lines));
+            x += width;
+        }
+        let height = placed.iter().map(|p| p.3.len()).max().unwrap_or(1) as f32
+            * Self::line_height(self.size);
+        let broke = self.ensure(height);
+        let top = self.y;
+        for (x, width, align, lines) in placed {
+            self.y = top;
+            for line in lines {
+                self.text_at(&line, x, width, self.size, bold, align);
+                self.y += Self::line_height(self.size);
+            }
+        }
+        self.y = top + height;
+        broke
+    }
+
+    /// Glyph ids printed in the regular and in the bold font, with the
+    /// character each stands for.
+    fn glyphs(&self) -> (BTreeMap<u16, char>, BTreeMap<u16, char>) {
+        let (mut regular, mut bold) = (BTreeMap::new(), BTreeMap::new());
+        for mark in self.pages.iter().flatten() {
+            let Mark::Text {
+                text,
+                bold: is_bold,
+                ..
+            } = mark
+            else {
+                continue;
+            };
+            let (face, used) = if *is_bold {
+                (&self.bold, &mut bold)
+            } else {
+                (&self.regular, &mut regular)
+            };
+            used.extend(
+                text.chars()
+                    .filter_map(|c| face.glyph_index(c).map(|g| (g.0, c))),
+            );
+        }
+        (regular, bold)
+    }
+
+    fn block(&mut self, block: &Block) {
+        let size = self.size;
+        match block {
+            Block::Title(text) => {
+                self.y += Self::line_height(size) / 2.0;
+                self.paragraph(text, size * TITLE_SCALE, true, Align::Center);
+                self.y += Self::line_height(size) / 2.0;
+            }
+            Block::Text { text, bold, align } => self.paragraph(text, size, *bold, *align),
+            Block::Field(label, value) => {
+                self.paragraph(&format!("{}: {}", label, value), size, false, Align::Left)
+            }
+            Block::Amount { label, value, bold } => {
+                let (left, width) = (self.left(), self.width());
+                let value_width = self.text_width(value, size, *bold);
+                let label_lines = self.wrap(label, width - value_width - 2.0, size, *bold);
+                let height = label_lines.len() as f32 * Self::line_height(size);
+                self.ensure(height);
+                self.text_at(value, left, width, size, *bold, Align::Right);
+                for line in label_lines {
+                    self.text_at(&line, left, width, size, *bold, Align::Left);
+                    self.y += Self::line_height(size);
+                }
+            }
+            Block::Table { columns, rows } => {
+                let header: Vec<String> = columns.iter().map(|c| c.title.to_string()).collect();
+                self.row(columns, &header, true);
+                self.rule();
+                for cells in rows {
+                    if self.row(columns, cells, false) {
+                        // The row went to a new page: redraw it under the header
+                        if let Some(page) = self.pages.last_mut() {
+                            page.clear();
+                        }
+                        self.y = self.paper.margin();
+                        self.row(columns, &header, true);
+                        self.rule();
+                        self.row(columns, cells, false);
+                    }
+                }
+            }
+            Block::Rule => self.rule(),
+            Block::Space => self.y += Self::line_height(size) / 2.0,
+            Block::Signatures(roles) => {
+                let width = self.width() / roles.len().max(1) as f32;
+                let height = Self::line_height(size) + SIGNATURE_SPACE_MM;
+                self.ensure(height);
+                for (i, role) in roles.iter().enumerate() {
+                    let x = self.left() + width * i as f32;
+                    self.text_at(role, x, width, size, true, Align::Center);
+                }
+                self.y += height;
+            }
+        }
+    }
+}
+
+/// Lays the document out on `paper` and renders it with the embedded fonts.
+pub fn render(document: &Document, paper: Paper, fonts: &Fonts) -> Result<Vec<u8>, String> {
+    let regular = Face::parse(&fonts.regular, 0).map_err(|e| e.to_string())?;
+    let bold = Face::parse(&fonts.bold, 0).map_err(|e| e.to_string())?;
+    let mut layout = Layout {
+        regular,
+        bold,
+        paper,
+        size: paper.font_size(),
+        pages: vec![Vec::new()],
+        y: paper.margin(),
+    };
+    for block in &document.blocks {
+        layout.block(block);
+    }
+    let height = paper.height().unwrap_or(layout.y + paper.margin());
+    let (used_regular, used_bold) = layout.glyphs();
+
+    // Plain PDF: the default PDF/X conformance embeds a half-megabyte ICC profile
+    let pdf = PdfDocument::empty(&document.title)
+        .with_conformance(PdfConformance::Custom(CustomPdfConformance::default()));
+    let regular = pdf
+        .add_external_font(fonts.regular.as_slice())
+        .map_err(|e| e.to_string())?;
+    let bold = pdf
+        .add_external_font(fonts.bold.as_slice())
+        .map_err(|e| e.to_string())?;
+    for marks in &layout.pages {
+        let (page, layer) = pdf.add_page(Mm(paper.width()), Mm(height), "content");
+        let layer = pdf.get_page(page).get_layer(layer);
+        draw(&layer, marks, height, &regular, &bold);
+    }
+    let data = pdf.save_to_bytes().map_err(|e| e.to_string())?;
+    shrink_fonts(
+        &data,
+        &[(&fonts.regular, used_regular), (&fonts.bold, used_bold)],
+    )
+}
+
+// printpdf embeds every font whole and uncompressed, over a megabyte per
+// document, with a ToUnicode map that strict readers reject. Each font is cut
+// down to the outlines of the glyphs in use, which keeps glyph ids unchanged,
+// and gets a map of just those glyphs.
+fn shrink_fonts(data: &[u8], fonts: &[(&Vec<u8>, BTreeMap<u16, char>)]) -> Result<Vec<u8>, String> {
+    let mut pdf = lopdf::Document::load_mem(data).map_err(|e| e.to_string())?;
+    let mut embedded = Vec::new();
+    for (&id, object) in &pdf.objects {
+        let Ok(font) = object.as_dict() else {
+            continue;
+        };
+        if font.get(b"Subtype").and_then(Object::as_name).ok() != Some(b"Type0") {
+            continue;
+        }
+        let to_unicode = font.get(b"ToUnicode").and_then(Object::as_reference);
+        let file = font
+            .get(b"DescendantFonts")
+            .and_then(Object::as_array)
+            .ok()
+            .and_then(|fonts| fonts.first())
+            .and_then(|cid_font| cid_font.as_dict().ok())
+            .and_then(|cid_font| cid_font.get(b"FontDescriptor").ok())
+            .and_then(|descriptor| descriptor.as_reference().ok())
+            .and_then(|id| pdf.get_dictionary(id).ok())
+            .and_then(|descriptor| descriptor.get(b"FontFile2").ok())
+            .and_then(|file| file.as_reference().ok());
+        if let (Ok(to_unicode), Some(file)) = (to_unicode, file) {
+            embedded.push((id, file, to_unicode));
+        }
+    }
+
+    for (font, file, to_unicode) in embedded {
+        let stream = pdf
+            .get_object_mut(file)
+            .and_then(Object::as_stream_mut)
+            .map_err(|e| e.to_string())?;
+        // The same file may serve as both the regular and the bold font
+        let glyphs: BTreeMap<u16, char> = fonts
+            .iter()
+            .filter(|(font, _)| font.as_slice() == stream.content.as_slice())
+            .flat_map(|(_, glyphs)| glyphs.clone())
+            .collect();
+        let ids: Vec<u16> = glyphs.keys().copied().collect();
+        // Widths of the printed glyphs only, instead of the whole font
+        let face = Face::parse(&stream.content, 0).map_err(|e| e.to_string())?;
+        let scale = 1000.0 / face.units_per_em() as f32;
+        let widths: Vec<Object> = ids
+            .iter()
+            .flat_map(|&id| {
+                let advance = face.glyph_hor_advance(ttf_parser::GlyphId(id)).unwrap_or(0);
+                [
+                    Object::Integer(id as i64),
+                    Object::Array(vec![Object::Integer((advance as f32 * scale) as i64)]),
+                ]
+            })
+            .collect();
+        let subset =
+            subsetter::subset(&stream.content, 0, Profile::pdf(&ids)).map_err(|e| e.to_string())?;
+        stream.dict.set("Length1", subset.len() as i64);
+        stream.set_plain_content(subset);
+
+        let map = pdf
+            .get_object_mut(to_unicode)
+            .and_then(Object::as_stream_mut)
+            .map_err(|e| e.to_string())?;
+        map.set_plain_content(to_unicode_map(&glyphs).into_bytes());
+
+        let cid_font = pdf
+            .get_object_mut(font)
+            .and_then(Object::as_dict_mut)
+            .and_then(|font| font.get_mut(b"DescendantFonts"))
+            .and_then(Object::as_array_mut)
+            .map_err(|e| e.to_string())?
+            .first_mut()
+            .and_then(|cid_font| cid_font.as_dict_mut().ok());
+        if let Some(cid_font) = cid_font {
+            cid_font.set("W", widths);
+        }
+    }
+    pdf.compress();
+    let mut out = Vec::new();
+    pdf.save_to(&mut out).map_err(|e| e.to_string())?;
+    Ok(out)
+}
+
+/// CMap from glyph ids to the UTF-16 text they print, for copying and searching.
+fn to_unicode_map(glyphs: &BTreeMap<u16, char>) -> String {
+    let mut map = String::from(
+        "/CIDInit /ProcSet findresource begin\n12 dict begin\nbegincmap\n\
+         /CIDSystemInfo << /Registry (Adobe) /Ordering (UCS) /Supplement 0 >> def\n\
+         /CMapName /Adobe-Identity-UCS def\n/CMapType 2 def\n\
+         1 begincodespacerange\n<0000> <FFFF>\nendcodespacerange\n",
+    );
+    let entries: Vec<_> = glyphs.iter().collect();
+    // A bfchar section holds at most 100 entries
+    for chunk in entries.chunks(100) {
+        map.push_str(&format!("{} beginbfchar\n", chunk.len()));
+        for (glyph, c) in chunk {
+            let mut units = [0; 2];
+            let utf16: String = c
+                .encode_utf16(&mut units)
+                .iter()
+                .map(|u| format!("{:04X}", u))
+                .collect();
+            map.push_str(&format!("<{:04X}> <{}>\n", glyph, utf16));
+        }
+        map.push_str("endbfchar\n");
+    }
+    map.push_str("endcmap\nCMapName currentdict /CMap defineresource pop\nend\nend\n");
+    map
+}
+
+fn draw(
+    layer: &PdfLayerReference,
+    marks: &[Mark],
+    height: f32,
+    regular: &IndirectFontRef,
+    bold: &IndirectFontRef,
+) {
+    layer.set_outline_thickness(0.5);
+    for mark in marks {
+        match mark {
+            Mark::Text {
+                x,
+                y,
+                size,
+                bold: is_bold,
+                text,
+            } => {
+                let font = if *is_bold { bold } else { regular };
+                layer.use_text(text.clone(), *size, Mm(*x), Mm(height - y), font);
+            }
+            Mark::Rule { x1, x2, y } => layer.add_line(Line {
+                points: vec![
+                    (Point::new(Mm(*x1), Mm(height - y)), false),
+                    (Point::new(Mm(*x2), Mm(height - y)), false),
+                ],
+                is_closed: false,
+            }),
+        }
+    }
+}
@@ -0,0 +1,769 @@
+use chrono::{DateTime, Duration, Local};
+use serde::{Deserialize, Serialize};
+
+use crate::audit::{AuditAction, AuditEntity};
+use crate::config::Config;
+use crate::error::PharmacyError;
+use crate::models::{Pharmacy, WarehouseType};
+use crate::sequence::Sequence;
+use crate::tax::round_money;
+
+/// Who a sale is made to; each channel has its own price lists.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Channel {
+    #[default]
+    Retail,
+    Wholesale,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ListPrice {
+    pub medicine_id: u32,
+    pub price: f64,
+}
+
+/// Prices that replace the products' own prices on a channel, for everyone on
+/// it or for one customer group.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PriceList {
+    pub id: u32,
+    pub name: String,
+    pub channel: Channel,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub customer_group: Option<String>,
+    pub prices: Vec<ListPrice>,
+    pub created_at: DateTime<Local>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Offer {
+    /// `percent` off the line.
+    PercentOff { percent: f64 },
+    /// `amount` off each unit.
+    AmountOff { amount: f64 },
+    /// Of every `buy + get` units, `get` are free.
+    BuyXGetY { buy: u32, get: u32 },
+}
+
+impl Offer {
+    /// Line amount after the offer, for `quantity` units worth `full` together.
+    fn apply(&self, full: f64, quantity: u32) -> f64 {
+        match *self {
+            Offer::PercentOff { percent } => full * (1.0 - percent / 100.0),
+            Offer::AmountOff { amount } => (full - amount * quantity as f64).max(0.0),
+            Offer::BuyXGetY { buy, get } => {
+                let free = quantity / (buy + get) * get;
+                full - full / quantity as f64 * free as f64
+            }
+        }
+    }
+}
+
+/// A time-boxed offer on some or all products.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Promotion {
+    pub id: u32,
+    pub name: String,
+    pub offer: Offer,
+    /// Products on offer; empty for every product.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub medicine_ids: Vec<u32>,
+    /// Channel the offer is limited to, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub channel: Option<Channel>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub customer_group: Option<String>,
+    pub starts_at: DateTime<Local>,
+    /// Open-ended while missing.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ends_at: Option<DateTime<Local>>,
+    pub created_at: DateTime<Local>,
+}
+
+impl Promotion {
+    pub fn is_active(&self, at: DateTime<Local>) -> bool {
+        self.starts_at <= at && self.ends_at.is_none_or(|end| at < end)
+    }
+
+    fn applies(&self, medicine_id: u32, context: &PriceContext, at: DateTime<Local>) -> bool {
+        self.is_active(at)
+            && (self.medicine_ids.is_empty() || self.medicine_ids.contains(&medicine_id))
+            && self.channel.is_none_or(|c| c == context.channel)
+            && self
+                .customer_group
+                .as_ref()
+                .is_none_or(|group| context.customer_group.as_ref() == Some(group))
+    }
+}
+
+/// The customer side of a sale, as far as prices depend on it.
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct PriceContext {
+    #[serde(default)]
+    pub channel: Channel,
+    #[serde(default)]
+    pub customer_group: Option<String>,
+}
+
+/// A clearance markdown on one batch close to expiry.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Markdown {
+    pub batch_id: u32,
+    pub expiry_date: DateTime<Local>,
+    pub percent: u8,
+}
+
+/// What set the price of a sale line. Rules do not stack: the one giving the
+/// customer the lowest price wins.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum PriceRule {
+    /// No discount.
+    ListPrice,
+    Promotion {
+        promotion_id: u32,
+        name: String,
+    },
+    Clearance {
+        markdowns: Vec<Markdown>,
+    },
+}
+
+impl PriceRule {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            PriceRule::ListPrice => "list_price",
+            PriceRule::Promotion { .. } => "promotion",
+            PriceRule::Clearance { .. } => "clearance",
+        }
+    }
+}
+
+/// How a sale line was priced.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct LinePricing {
+    pub channel: Channel,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub customer_group: Option<String>,
+    /// Price list the unit price came from; without one the product's own
+    /// price, or each batch's selling price, was used.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub price_list_id: Option<u32>,
+    /// Unit price before the discount, averaged over the batches sold from.
+    pub list_price: f64,
+    /// Taken off the line as a whole.
+    pub discount: f64,
+    pub rule: PriceRule,
+}
+
+/// Units of a sale line from one batch, or from the catalog without one.
+pub struct Units {
+    pub batch_id: Option<u32>,
+    pub quantity: u32,
+    /// The product's or batch's own selling price.
+    pub price: f64,
+    pub expiry_date: Option<DateTime<Local>>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Quote {
+    pub medicine_id: u32,
+    pub quantity: u32,
+    /// Unit price charged.
+    pub price: f64,
+    /// Line amount charged, before VAT is added to VAT-exclusive prices.
+    pub amount: f64,
+    #[serde(flatten)]
+    pub pricing: LinePricing,
+}
+
+/// A store batch currently sold at a clearance markdown.
+#[derive(Debug, Serialize)]
+pub struct ClearanceItem {
+    pub batch_id: u32,
+    pub medicine_id: u32,
+    pub medicine_name: String,
+    pub warehouse_id: u32,
+    pub quantity: u32,
+    pub expiry_date: DateTime<Local>,
+    pub days_to_expiry: i64,
+    pub percent: u8,
+    /// Retail price before and after the markdown.
+    pub price: f64,
+    pub clearance_price: f64,
+}
+
+/// Markdown for a batch expiring at `expiry_date`, under the expiry alert horizons.
+fn clearance_percent(expiry_date: DateTime<Local>, at: DateTime<Local>, config: &Config) -> u8 {
+    let within = |days: i64| expiry_date <= at + Duration::days(days);
+    let pricing = &config.pricing;
+    let mut percent = 0;
+    if within(config.alerts.expiry_days) {
+        percent = pricing.clearance_percent;
+    }
+    if within(config.alerts.critical_expiry_days) {
+        percent = percent.max(pricing.critical_clearance_percent);
+    }
+    percent
+}
+
+fn group(customer_group: Option<String>) -> Option<String> {
+    customer_group
+        .map(|g| g.trim().to_string())
+        .filter(|g| !g.is_empty())
+}
+
+impl Pharmacy {
+    pub fn price_list(&self, id: u32) -> Result<&PriceList, PharmacyError> {
+        self.price_lists
+            .iter()
+            .find(|l| l.id == id)
+            .ok_or(PharmacyError::PriceListNotFound)
+    }
+
+    pub fn create_price_list(
+        &mut self,
+        user: &str,
+        name: String,
+        channel: Channel,
+        customer_group: Option<String>,
+        prices: Vec<ListPrice>,
+    ) -> Result<u32, PharmacyError> {
+        let customer_group = group(customer_group);
+        if let Some(list) = self
+            .price_lists
+            .iter()
+            .find(|l| l.channel == channel && l.customer_group == customer_group)
+        {
+            return Err(PharmacyError::PriceListExists {
+                price_list_id: list.id,
+            });
+        }
+        self.ensure_known_medicines(prices.iter().map(|p| p.medicine_id))?;
+
+        let id = self.next_id(Sequence::PriceList);
+        let mut list = PriceList {
+            id,
+            name: name.trim().to_string(),
+            channel,
+            customer_group,
+            prices: Vec::new(),
+            created_at: Local::now(),
+        };
+        for price in prices {
+            set_price(&mut list, price.medicine_id, Some(price.price));
+        }
+        self.record_audit(
+            user,
+            AuditAction::Create,
+            AuditEntity::PriceList,
+            id,
+            None,
+            Some(&list),
+        );
+        self.price_lists.push(list);
+        Ok(id)
+    }
+
+    /// Sets the given prices on a list; `None` takes the medicine off it.
+    pub fn set_list_prices(
+        &mut self,
+        user: &str,
+        id: u32,
+        prices: Vec<(u32, Option<f64>)>,
+    ) -> Result<PriceList, PharmacyError> {
+        self.ensure_known_medicines(prices.iter().map(|&(medicine_id, _)| medicine_id))?;
+        let list = self
+            .price_lists
+            .iter_mut()
+            .find(|l| l.id == id)
+            .ok_or(PharmacyError::PriceListNotFound)?;
+        let before = list.clone();
+        for (medicine_id, price) in prices {
+            set_price(list, medicine_id, price);
+        }
+        let after = list.clone();
+        self.record_audit(
+            user,
+            AuditAction::Update,
+            AuditEntity::PriceList,
+            id,
+            Some(&before),
+            Some(&after),
+        );
+        Ok(after)
+    }
+
+    pub fn delete_price_list(&mut self, user: &str, id: u32) -> Result<(), PharmacyError> {
+        let index = self
+            .price_lists
+            .iter()
+            .position(|l| l.id == id)
+            .ok_or(PharmacyError::PriceListNotFound)?;
+        let list = self.price_lists.remove(index);
+        self.record_audit(
+            user,
+            AuditAction::Delete,
+            AuditEntity::PriceList,
+            id,
+            Some(&list),
+            None,
+        );
+        Ok(())
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn create_promotion(
+        &mut self,
+        user: &str,
+        name: String,
+        offer: Offer,
+        medicine_ids: Vec<u32>,
+        channel: Option<Channel>,
+        customer_group: Option<String>,
+        starts_at: Option<DateTime<Local>>,
+        ends_at: Option<DateTime<Local>>,
+    ) -> Result<u32, PharmacyError> {
+        self.ensure_known_medicines(medicine_ids.iter().copied())?;
+        let now = Local::now();
+        let id = self.next_id(Sequence::Promotion);
+        let promotion = Promotion {
+            id,
+            name: name.trim().to_string(),
+            offer,
+            medicine_ids,
+            channel,
+            customer_group: group(customer_group),
+            starts_at: starts_at.unwrap_or(now),
+            ends_at,
+            created_at: now,
+        };
+        self.record_audit(
+            user,
+            AuditAction::Create,
+            AuditEntity::Promotion,
+            id,
+            None,
+            Some(&promotion),
+        );
+        self.promotions.push(promotion);
+        Ok(id)
+    }
+
+    /// Ends a promotion now. One that has already ended is left as it is.
+    pub fn end_promotion(&mut self, user: &str, id: u32) -> Result<Promotion, PharmacyError> {
+        let promotion = self
+            .promotions
+            .iter_mut()
+            .find(|p| p.id == id)
+            .ok_or(PharmacyError::PromotionNotFound)?;
+        let now = Local::now();
+        if promotion.ends_at.is_some_and(|end| end <= now) {
+            return Ok(promotion.clone());
+        }
+        let before = promotion.clone();
+        promotion.ends_at = Some(now);
+        let after = promotion.clone();
+        self.record_audit(
+            user,
+            AuditAction::Update,
+            AuditEntity::Promotion,
+            id,
+            Some(&before),
+            Some(&after),
+        );
+        Ok(after)
+    }
+
+    fn ensure_known_medicines(
+        &self,
+        mut medicine_ids: impl Iterator<Item = u32>,
+    ) -> Result<(), PharmacyError> {
+        if medicine_ids.any(|id| self.known_medicine_name(id).is_none()) {
+            return Err(PharmacyError::MedicineNotFound);
+        }
+        Ok(())
+    }
+
+    /// Price of the medicine on the best matching price list of the channel:
+    /// the customer group's list first, then the list for everyone.
+    fn listed_price(&self, medicine_id: u32, context: &PriceContext) -> Option<(u32, f64)> {
+        let price_on = |list: &PriceList| {
+            list.prices
+                .iter()
+                .find(|p| p.medicine_id == medicine_id)
+                .map(|p| (list.id, p.price))
+        };
+        let on_list_for = |customer_group: Option<&String>| {
+            self.price_lists
+                .iter()
+                .filter(|l| {
+                    l.channel == context.channel && l.customer_group.as_ref() == customer_group
+                })
+                .find_map(price_on)
+        };
+        context
+            .customer_group
+            .as_ref()
+            .and_then(|g| on_list_for(Some(g)))
+            .or_else(|| on_list_for(None))
+    }
+
+    /// Prices a sale line: the unit price from the price lists, then the best
+    /// of the promotions on offer and the clearance markdowns of the batches.
+    pub fn price_line(
+        &self,
+        medicine_id: u32,
+        units: &[Units],
+        context: &PriceContext,
+        at: DateTime<Local>,
+        config: &Config,
+    ) -> Quote {
+        let context = &PriceContext {
+            channel: context.channel,
+            customer_group: group(context.customer_group.clone()),
+        };
+        let currency = &config.currency;
+        let listed = self.listed_price(medicine_id, context);
+        let unit_price = |u: &Units| listed.map_or(u.price, |(_, price)| price);
+        let quantity: u32 = units.iter().map(|u| u.quantity).sum();
+        let full: f64 = units
+            .iter()
+            .map(|u| unit_price(u) * u.quantity as f64)
+            .sum();
+
+        let mut best = (round_money(full, currency), PriceRule::ListPrice);
+        let mut consider = |amount: f64, rule: PriceRule| {
+            let amount = round_money(amount, currency);
+            if amount < best.0 {
+                best = (amount, rule);
+            }
+        };
+
+        let markdowns: Vec<Markdown> = units
+            .iter()
+            .filter_map(|u| {
+                let (batch_id, expiry_date) = (u.batch_id?, u.expiry_date?);
+                let percent = clearance_percent(expiry_date, at, config);
+                (percent > 0).then_some(Markdown {
+                    batch_id,
+                    expiry_date,
+                    percent,
+                })
+            })
+            .collect();
+        if !markdowns.is_empty() {
+            let amount = units
+                .iter()
+                .map(|u| {
+                    let percent = markdowns
+                        .iter()
+                        .find(|m| Some(m.batch_id) == u.batch_id)
+                        .map_or(0, |m| m.percent);
+                    unit_price(u) * u.quantity as f64 * (1.0 - percent as f64 / 100.0)
+                })
+                .sum();
+            consider(amount, PriceRule::Clearance { markdowns });
+        }
+        for promotion in self
+            .promotions
+            .iter()
+            .filter(|p| p.applies(medicine_id, context, at))
+        {
+            consider(
+                promotion.offer.apply(full, quantity),
+                PriceRule::Promotion {
+                    promotion_id: promotion.id,
+                    name: promotion.name.clone(),
+                },
+            );
+        }
+
+        let (amount, rule) = best;
+        Quote {
+            medicine_id,
+            quantity,
+            price: amount / quantity as f64,
+            amount,
+            pricing: LinePricing {
+                channel: context.channel,
+                customer_group: context.customer_group.clone(),
+                price_list_id: listed.map(|(id, _)| id),
+                list_price: full / quantity as f64,
+                discount: round_money(full, currency) - amount,
+                rule,
+            },
+        }
+    }
+
+    /// Units a catalog sale of `quantity` would take.
+    pub fn catalog_units(
+        &self,
+        medicine_id: u32,
+        quantity: u32,
+    ) -> Result<Vec<Units>, PharmacyError> {
+        let medicine = self
+            .inventory
+            .iter()
+            .find(|m| m.id == medicine_id)
+            .ok_or(PharmacyError::MedicineNotFound)?;
+        if medicine.quantity < quantity {
+            return Err(PharmacyError::InsufficientStock {
+                available: medicine.quantity,
+            });
+        }
+        Ok(vec![Units {
+            batch_id: None,
+            quantity,
+            price: medicine.price,
+            expiry_date: None,
+        }])
+    }
+
+    /// Batches a store sale of `quantity` would take, earliest expiry first.
+    pub fn store_units(
+        &self,
+        medicine_id: u32,
+        quantity: u32,
+    ) -> Result<Vec<Units>, PharmacyError> {
+        let store = self
+            .warehouses
+            .iter()
+            .find(|w| matches!(w.warehouse_type, WarehouseType::Store) && w.archived_at.is_none())
+            .ok_or(PharmacyError::StoreWarehouseNotFound)?;
+        let mut batches: Vec<_> = self
+            .stock_batches
+            .iter()
+            .filter(|b| {
+                b.medicine_id == medicine_id && b.warehouse_id == store.id && b.quantity > 0
+            })
+            .collect();
+        batches.sort_by_key(|b| b.expiry_date);
+
+        let available: u32 = batches.iter().map(|b| b.quantity).sum();
+        if available < quantity {
+            return Err(PharmacyError::InsufficientStock { available });
+        }
+        let mut remaining = quantity;
+        let mut units = Vec::new();
+        for batch in batches {
+            if remaining == 0 {
+                break;
+            }
+            let taken = remaining.min(batch.quantity);
+            remaining -= taken;
+            units.push(Units {
+                batch_id: Some(batch.id),
+                quantity: taken,
+                price: batch.selling_price,
+                expiry_date: Some(batch.expiry_date),
+            });
+        }
+        Ok(units)
+    }
+
+    /// Store batches in stock that sell at a clearance markdown, soonest expiry
+    /// first, with their retail price before and after it.
+    pub fn clearance(&self, config: &Config) -> Vec<ClearanceItem> {
+        let now = Local::now();
+        let retail = PriceContext::default();
+        let mut items: Vec<ClearanceItem> = self
+            .stock_batches
+            .iter()
+            .filter(|b| {
+                b.quantity > 0
+                    && self.warehouses.iter().any(|w| {
+                        w.id == b.warehouse_id && matches!(w.warehouse_type, WarehouseType::Store)
+                    })
+            })
+            .filter_map(|b| {
+                let percent = clearance_percent(b.expiry_date, now, config);
+                if percent == 0 {
+                    return None;
+                }
+                let price = self
+                    .listed_price(b.medicine_id, &retail)
+                    .map_or(b.selling_price, |(_, price)| price);
+                Some(ClearanceItem {
+                    batch_id: b.id,
+                    medicine_id: b.medicine_id,
+                    medicine_name: b.medicine_name.clone(),
+                    warehouse_id: b.warehouse_id,
+                    quantity: b.quantity,
+                    expiry_date: b.expiry_date,
+                    days_to_expiry: (b.expiry_date - now).num_days(),
+                    percent,
+                    price,
+                    clearance_price: round_money(
+                        price * (1.0 - percent as f64 / 100.0),
+                        &config.currency,
+                    ),
+                })
+            })
+            .collect();
+        items.sort_by_key(|i| i.expiry_date);
+        items
+    }
+}
+
+fn set_price(list: &mut PriceList, medicine_id: u32, price: Option<f64>) {
+    let existing = list
+        .prices
+        .iter()
+        .position(|p| p.medicine_id == medicine_id);
+    match (existing, price) {
+        (Some(index), Some(price)) => list.prices[index].price = price,
+        (Some(index), None) => {
+            list.prices.remove(index);
+        }
+        (None, Some(price)) => list.prices.push(ListPrice { medicine_id, price }),
+        (None, None) => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> Config {
+        let mut config = Config::default();
+        config.pricing.clearance_percent = 20;
+        config.pricing.critical_clearance_percent = 40;
+        config
+    }
+
+    fn batch(batch_id: u32, quantity: u32, price: f64, expires_in_days: i64) -> Units {
+        Units {
+            batch_id: Some(batch_id),
+            quantity,
+            price,
+            expiry_date: Some(Local::now() + Duration::days(expires_in_days)),
+        }
+    }
+
+    fn promotion(id: u32, offer: Offer) -> Promotion {
+        let now = Local::now();
+        Promotion {
+            id,
+            name: format!("Promotion {}", id),
+            offer,
+            medicine_ids: vec![1],
+            channel: None,
+            customer_group: None,
+            starts_at: now - Duration::days(1),
+            ends_at: None,
+            created_at: now,
+        }
+    }
+
+    #[test]
+    fn buy_two_get_one_frees_one_unit_in_each_three() {
+        let offer = Offer::BuyXGetY { buy: 2, get: 1 };
+        // 7 units make two full sets of three, so 2 are free
+        assert_eq!(offer.apply(7000.0, 7), 5000.0);
+        assert_eq!(offer.apply(2000.0, 2), 2000.0);
+    }
+
+    #[test]
+    fn amount_off_never_goes_below_zero() {
+        assert_eq!(Offer::PercentOff { percent: 10.0 }.apply(7000.0, 7), 6300.0);
+        assert_eq!(Offer::AmountOff { amount: 300.0 }.apply(3000.0, 3), 2100.0);
+        assert_eq!(Offer::AmountOff { amount: 1500.0 }.apply(3000.0, 3), 0.0);
+    }
+
+    #[test]
+    fn critical_clearance_beats_a_smaller_promotion() {
+        let mut pharmacy = Pharmacy::new();
+        pharmacy
+            .promotions
+            .push(promotion(1, Offer::PercentOff { percent: 25.0 }));
+        let units = [batch(7, 2, 1000.0, 10)];
+
+        let quote =
+            pharmacy.price_line(1, &units, &PriceContext::default(), Local::now(), &config());
+        assert_eq!(quote.amount, 1200.0);
+        assert_eq!(quote.price, 600.0);
+        assert_eq!(quote.pricing.list_price, 1000.0);
+        assert_eq!(quote.pricing.discount, 800.0);
+        match quote.pricing.rule {
+            PriceRule::Clearance { markdowns } => {
+                assert_eq!(markdowns.len(), 1);
+                assert_eq!(markdowns[0].batch_id, 7);
+                assert_eq!(markdowns[0].percent, 40);
+            }
+            rule => panic!("expected clearance, got {:?}", rule),
+        }
+    }
+
+    #[test]
+    fn promotion_beats_a_smaller_clearance() {
+        let mut pharmacy = Pharmacy::new();
+        pharmacy
+            .promotions
+            .push(promotion(3, Offer::BuyXGetY { buy: 2, get: 1 }));
+        // 60 days out only the regular 20% markdown applies: 2400 against 2000
+        let units = [batch(7, 3, 1000.0, 60)];
+
+        let quote =
+            pharmacy.price_line(1, &units, &PriceContext::default(), Local::now(), &config());
+        assert_eq!(quote.amount, 2000.0);
+        assert_eq!(quote.pricing.discount, 1000.0);
+        assert_eq!(
+            quote.pricing.rule,
+            PriceRule::Promotion {
+                promotion_id: 3,
+                name: "Promotion 3".to_string()
+            }
+        );
+    }
+
+    #[test]
+    fn equal_discounts_keep_the_clearance_markdown() {
+        let mut pharmacy = Pharmacy::new();
+        pharmacy
+            .promotions
+            .push(promotion(1, Offer::PercentOff { percent: 20.0 }));
+        let units = [batch(7, 1, 1000.0, 60)];
+
+        let quote =
+            pharmacy.price_line(1, &units, &PriceContext::default(), Local::now(), &config());
+        assert_eq!(quote.amount, 800.0);
+        assert_eq!(quote.pricing.rule.kind(), "clearance");
+    }
+
+    #[test]
+    fn customer_group_list_wins_over_the_channel_list() {
+        let mut pharmacy = Pharmacy::new();
+        for (id, customer_group, price) in [(1, None, 900.0), (2, Some("clinic"), 800.0)] {
+            pharmacy.price_lists.push(PriceList {
+                id,
+                name: format!("List {}", id),
+                channel: Channel::Retail,
+                customer_group: customer_group.map(str::to_string),
+                prices: vec![ListPrice {
+                    medicine_id: 1,
+                    price,
+                }],
+                created_at: Local::now(),
+            });
+        }
+        let units = [batch(7, 2, 1000.0, 365)];
+        let quote = |customer_group: Option<&str>| {
+            let context = PriceContext {
+                channel: Channel::Retail,
+                customer_group: customer_group.map(str::to_string),
+            };
+            pharmacy.price_line(1, &units, &context, Local::now(), &config())
+        };
+
+        let clinic = quote(Some(" clinic "));
+        assert_eq!(clinic.pricing.price_list_id, Some(2));
+        assert_eq!(clinic.amount, 1600.0);
+        let walk_in = quote(None);
+        assert_eq!(walk_in.pricing.price_list_id, Some(1));
+        assert_eq!(walk_in.amount, 1800.0);
+        assert_eq!(walk_in.pricing.rule, PriceRule::ListPrice);
+    }
+}
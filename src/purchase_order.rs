@@ -0,0 +1,146 @@
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+
+use crate::audit::{AuditAction, AuditEntity};
+use crate::error::PharmacyError;
+use crate::models::Pharmacy;
+use crate::sequence::Sequence;
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum PurchaseOrderStatus {
+    Draft,
+    Ordered,
+    Received,
+    Cancelled,
+}
+
+impl PurchaseOrderStatus {
+    /// Drafts count as open too, so suggestions already turned into a draft are not repeated.
+    pub fn is_open(self) -> bool {
+        matches!(
+            self,
+            PurchaseOrderStatus::Draft | PurchaseOrderStatus::Ordered
+        )
+    }
+
+    fn can_become(self, next: PurchaseOrderStatus) -> bool {
+        use PurchaseOrderStatus::*;
+        matches!(
+            (self, next),
+            (Draft, Ordered) | (Draft, Cancelled) | (Ordered, Received) | (Ordered, Cancelled)
+        )
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PurchaseOrderLine {
+    pub medicine_id: u32,
+    pub medicine_name: String,
+    pub quantity: u32,
+    /// Expected cost per unit, taken from the last receipt.
+    pub unit_cost: f64,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PurchaseOrder {
+    pub id: u32,
+    pub supplier_id: u32,
+    /// Warehouse the goods are delivered to.
+    pub warehouse_id: u32,
+    pub status: PurchaseOrderStatus,
+    pub lines: Vec<PurchaseOrderLine>,
+    pub created_at: DateTime<Local>,
+    pub updated_at: DateTime<Local>,
+}
+
+impl PurchaseOrder {
+    pub fn total_cost(&self) -> f64 {
+        self.lines
+            .iter()
+            .fold(0.0, |sum, l| sum + l.unit_cost * l.quantity as f64)
+    }
+}
+
+impl Pharmacy {
+    pub fn create_purchase_order(
+        &mut self,
+        user: &str,
+        supplier_id: u32,
+        warehouse_id: u32,
+        lines: Vec<PurchaseOrderLine>,
+    ) -> Result<u32, PharmacyError> {
+        self.active_supplier(supplier_id)?;
+        self.active_warehouse(warehouse_id)?;
+        for line in &lines {
+            self.ensure_medicine_not_archived(line.medicine_id)?;
+        }
+
+        let now = Local::now();
+        let order = PurchaseOrder {
+            id: self.next_id(Sequence::PurchaseOrder),
+            supplier_id,
+            warehouse_id,
+            status: PurchaseOrderStatus::Draft,
+            lines,
+            created_at: now,
+            updated_at: now,
+        };
+        self.record_audit(
+            user,
+            AuditAction::Create,
+            AuditEntity::PurchaseOrder,
+            order.id,
+            None,
+            Some(&order),
+        );
+        let id = order.id;
+        self.purchase_orders.push(order);
+        Ok(id)
+    }
+
+    /// Moves an order along draft -> ordered -> received; open orders may be cancelled.
+    /// Receiving only closes the order; the goods are booked with `import_batch`.
+    pub fn set_purchase_order_status(
+        &mut self,
+        user: &str,
+        id: u32,
+        status: PurchaseOrderStatus,
+    ) -> Result<(), PharmacyError> {
+        let order = self
+            .purchase_orders
+            .iter_mut()
+            .find(|o| o.id == id)
+            .ok_or(PharmacyError::PurchaseOrderNotFound)?;
+        if !order.status.can_become(status) {
+            return Err(PharmacyError::InvalidStatusTransition {
+                from: format!("{:?}", order.status).to_lowercase(),
+                to: format!("{:?}", status).to_lowercase(),
+            });
+        }
+        let before = order.clone();
+        order.status = status;
+        order.updated_at = Local::now();
+        let after = order.clone();
+        self.record_audit(
+            user,
+            AuditAction::Update,
+            AuditEntity::PurchaseOrder,
+            id,
+            Some(&before),
+            Some(&after),
+        );
+        Ok(())
+    }
+
+    /// Units of a medicine on open purchase orders for a warehouse.
+    pub fn on_order(&self, medicine_id: u32, warehouse_id: u32) -> u32 {
+        self.purchase_orders
+            .iter()
+            .filter(|o| o.status.is_open() && o.warehouse_id == warehouse_id)
+            .flat_map(|o| &o.lines)
+            .filter(|l| l.medicine_id == medicine_id)
+            .map(|l| l.quantity)
+            .sum()
+    }
+}
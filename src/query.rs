@@ -0,0 +1,241 @@
+use axum::{
+    Json,
+    response::{IntoResponse, Response},
+};
+use chrono::{DateTime, Local, NaiveDate, TimeZone};
+use chrono_tz::Tz;
+use serde::{Deserialize, Serialize};
+
+use crate::error::PharmacyError;
+use crate::models::{ExportBatch, ImportBatch, InternalTransfer, StockBatch};
+
+/// Common accessors used to filter and sort the list endpoints.
+pub trait Listable {
+    fn medicine_id(&self) -> u32;
+    fn in_warehouse(&self, warehouse_id: u32) -> bool;
+    fn supplier_id(&self) -> Option<u32>;
+    fn quantity(&self) -> u32;
+    fn date(&self) -> DateTime<Local>;
+    fn expiry_date(&self) -> Option<DateTime<Local>> {
+        None
+    }
+    fn price(&self) -> Option<f64> {
+        None
+    }
+}
+
+impl Listable for StockBatch {
+    fn medicine_id(&self) -> u32 {
+        self.medicine_id
+    }
+    fn in_warehouse(&self, warehouse_id: u32) -> bool {
+        self.warehouse_id == warehouse_id
+    }
+    fn supplier_id(&self) -> Option<u32> {
+        self.supplier_id
+    }
+    fn quantity(&self) -> u32 {
+        self.quantity
+    }
+    fn date(&self) -> DateTime<Local> {
+        self.import_date
+    }
+    fn expiry_date(&self) -> Option<DateTime<Local>> {
+        Some(self.expiry_date)
+    }
+    fn price(&self) -> Option<f64> {
+        Some(self.selling_price)
+    }
+}
+
+impl Listable for ImportBatch {
+    fn medicine_id(&self) -> u32 {
+        self.medicine_id
+    }
+    fn in_warehouse(&self, warehouse_id: u32) -> bool {
+        self.warehouse_id == Some(warehouse_id)
+    }
+    fn supplier_id(&self) -> Option<u32> {
+        self.supplier_id
+    }
+    fn quantity(&self) -> u32 {
+        self.quantity
+    }
+    fn date(&self) -> DateTime<Local> {
+        self.timestamp
+    }
+    fn price(&self) -> Option<f64> {
+        Some(self.unit_cost)
+    }
+}
+
+impl Listable for ExportBatch {
+    fn medicine_id(&self) -> u32 {
+        self.medicine_id
+    }
+    fn in_warehouse(&self, warehouse_id: u32) -> bool {
+        self.warehouse_id == Some(warehouse_id)
+    }
+    fn supplier_id(&self) -> Option<u32> {
+        None
+    }
+    fn quantity(&self) -> u32 {
+        self.amount
+    }
+    fn date(&self) -> DateTime<Local> {
+        self.timestamp
+    }
+    fn price(&self) -> Option<f64> {
+        Some(self.price)
+    }
+}
+
+impl Listable for InternalTransfer {
+    fn medicine_id(&self) -> u32 {
+        self.medicine_id
+    }
+    fn in_warehouse(&self, warehouse_id: u32) -> bool {
+        self.from_warehouse_id == warehouse_id || self.to_warehouse_id == warehouse_id
+    }
+    fn supplier_id(&self) -> Option<u32> {
+        None
+    }
+    fn quantity(&self) -> u32 {
+        self.quantity
+    }
+    fn date(&self) -> DateTime<Local> {
+        self.timestamp
+    }
+}
+
+#[derive(Debug, Deserialize, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum SortKey {
+    Expiry,
+    ImportDate,
+    Price,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum SortOrder {
+    #[default]
+    Asc,
+    Desc,
+}
+
+#[derive(Debug, Deserialize, Default)]
+pub struct ListQuery {
+    pub medicine_id: Option<u32>,
+    pub warehouse_id: Option<u32>,
+    pub supplier_id: Option<u32>,
+    pub from: Option<String>, // ISO 8601 format
+    pub to: Option<String>,   // ISO 8601 format
+    pub in_stock: Option<bool>,
+    pub sort: Option<SortKey>,
+    #[serde(default)]
+    pub order: SortOrder,
+    pub offset: Option<usize>,
+    pub limit: Option<usize>,
+}
+
+pub struct Page<T> {
+    pub total: usize,
+    pub items: Vec<T>,
+}
+
+// The body stays a plain array; the unpaginated count travels in a header
+impl<T: Serialize> IntoResponse for Page<T> {
+    fn into_response(self) -> Response {
+        (
+            [("x-total-count", self.total.to_string())],
+            Json(self.items),
+        )
+            .into_response()
+    }
+}
+
+impl ListQuery {
+    /// Filters, sorts and paginates `items`; `total` counts matches before pagination.
+    pub fn apply<T: Listable + Clone>(&self, items: &[T]) -> Result<Page<T>, PharmacyError> {
+        let from = parse_optional_date(self.from.as_deref())?;
+        let to = parse_optional_date(self.to.as_deref())?;
+
+        let mut matched: Vec<T> = items
+            .iter()
+            .filter(|i| self.medicine_id.is_none_or(|id| i.medicine_id() == id))
+            .filter(|i| self.warehouse_id.is_none_or(|id| i.in_warehouse(id)))
+            .filter(|i| {
+                self.supplier_id
+                    .is_none_or(|id| i.supplier_id() == Some(id))
+            })
+            .filter(|i| from.is_none_or(|from| i.date() >= from))
+            .filter(|i| to.is_none_or(|to| i.date() <= to))
+            .filter(|i| !self.in_stock.unwrap_or(false) || i.quantity() > 0)
+            .cloned()
+            .collect();
+
+        if let Some(key) = self.sort {
+            matched.sort_by(|a, b| match key {
+                SortKey::Expiry => a.expiry_date().cmp(&b.expiry_date()),
+                SortKey::ImportDate => a.date().cmp(&b.date()),
+                SortKey::Price => a
+                    .price()
+                    .unwrap_or(0.0)
+                    .total_cmp(&b.price().unwrap_or(0.0)),
+            });
+            if matches!(self.order, SortOrder::Desc) {
+                matched.reverse();
+            }
+        }
+
+        let total = matched.len();
+        let items = matched
+            .into_iter()
+            .skip(self.offset.unwrap_or(0))
+            .take(self.limit.unwrap_or(usize::MAX))
+            .collect();
+        Ok(Page { total, items })
+    }
+}
+
+pub fn parse_optional_date(value: Option<&str>) -> Result<Option<DateTime<Local>>, PharmacyError> {
+    value
+        .map(|v| {
+            DateTime::parse_from_rfc3339(v)
+                .map(|dt| dt.with_timezone(&Local))
+                .map_err(|_| PharmacyError::InvalidDate)
+        })
+        .transpose()
+}
+
+/// Accepts a full ISO 8601 timestamp or a plain `YYYY-MM-DD` date, which is
+/// read in the pharmacy's time zone as the end of that day.
+pub fn parse_report_date(value: &str, tz: Tz) -> Result<DateTime<Local>, PharmacyError> {
+    parse_day_boundary(value, tz, 1).map(|dt| dt - chrono::Duration::nanoseconds(1))
+}
+
+/// Like `parse_report_date`, but a plain date means the start of that day.
+pub fn parse_report_start(value: &str, tz: Tz) -> Result<DateTime<Local>, PharmacyError> {
+    parse_day_boundary(value, tz, 0)
+}
+
+// Midnight `days_after` days after the given date; full timestamps pass through
+fn parse_day_boundary(
+    value: &str,
+    tz: Tz,
+    days_after: u64,
+) -> Result<DateTime<Local>, PharmacyError> {
+    if let Ok(dt) = DateTime::parse_from_rfc3339(value) {
+        return Ok(dt.with_timezone(&Local));
+    }
+    let midnight = NaiveDate::parse_from_str(value, "%Y-%m-%d")
+        .ok()
+        .and_then(|day| day.checked_add_days(chrono::Days::new(days_after)))
+        .and_then(|day| day.and_hms_opt(0, 0, 0))
+        .ok_or(PharmacyError::InvalidDate)?;
+    tz.from_local_datetime(&midnight)
+        .earliest()
+        .map(|dt| dt.with_timezone(&Local))
+        .ok_or(PharmacyError::InvalidDate)
+}
@@ -0,0 +1,337 @@
+use chrono::{Duration, Local};
+use chrono_tz::Tz;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+use crate::audit::{AuditAction, AuditEntity};
+use crate::config::Config;
+use crate::error::PharmacyError;
+use crate::models::Pharmacy;
+use crate::purchase_order::PurchaseOrderLine;
+use crate::sequence::Sequence;
+
+/// Min/max stock levels for one medicine in one warehouse.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ReorderSetting {
+    pub id: u32,
+    pub medicine_id: u32,
+    pub warehouse_id: u32,
+    /// Safety stock; suggestions below it are flagged as urgent.
+    pub min: u32,
+    /// Order when the expected stock at delivery falls to this level.
+    pub reorder_point: u32,
+    /// Order up to this level.
+    pub max: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub preferred_supplier_id: Option<u32>,
+}
+
+/// Fields of a reorder setting as sent by clients; the id is assigned on creation.
+#[derive(Debug, Deserialize, Clone)]
+pub struct ReorderSettingInput {
+    pub medicine_id: u32,
+    pub warehouse_id: u32,
+    pub min: u32,
+    pub reorder_point: u32,
+    pub max: u32,
+    #[serde(default)]
+    pub preferred_supplier_id: Option<u32>,
+}
+
+/// How far back to measure sales, how demand is forecast, how long deliveries
+/// take, and which batches are too close to expiry to count as sellable.
+#[derive(Debug, Clone, Copy)]
+pub struct ReplenishmentParams {
+    pub sales_window_days: i64,
+    pub moving_average_weeks: usize,
+    pub lead_time_days: i64,
+    pub expiry_cutoff_days: i64,
+    pub tz: Tz,
+}
+
+impl ReplenishmentParams {
+    /// Sellable stock excludes batches within the critical expiry window.
+    pub fn from_config(config: &Config) -> Self {
+        ReplenishmentParams {
+            sales_window_days: config.replenishment.sales_window_days,
+            moving_average_weeks: config.replenishment.moving_average_weeks,
+            lead_time_days: config.replenishment.lead_time_days,
+            expiry_cutoff_days: config.alerts.critical_expiry_days,
+            tz: config.tz(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct PurchaseSuggestion {
+    pub medicine_id: u32,
+    pub medicine_name: String,
+    pub warehouse_id: u32,
+    /// Units that can still be sold; batches expiring within the cutoff are left out.
+    pub on_hand: u32,
+    pub expiring: u32,
+    pub on_order: u32,
+    /// Average over the sales window, for reference.
+    pub daily_sales: f64,
+    /// Units forecast to sell before an order placed now arrives.
+    pub lead_time_demand: u32,
+    pub min: u32,
+    pub reorder_point: u32,
+    pub max: u32,
+    pub below_min: bool,
+    pub quantity: u32,
+    pub unit_cost: f64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SupplierSuggestions {
+    /// `None` when no preferred or previous supplier is known.
+    pub supplier_id: Option<u32>,
+    pub supplier_name: Option<String>,
+    pub total_cost: f64,
+    pub lines: Vec<PurchaseSuggestion>,
+}
+
+impl Pharmacy {
+    /// Creates the setting for a medicine and warehouse, or replaces the existing one.
+    pub fn set_reorder_setting(
+        &mut self,
+        user: &str,
+        input: ReorderSettingInput,
+    ) -> Result<u32, PharmacyError> {
+        self.active_warehouse(input.warehouse_id)?;
+        if let Some(id) = input.preferred_supplier_id {
+            self.active_supplier(id)?;
+        }
+        self.ensure_medicine_not_archived(input.medicine_id)?;
+        if self.known_medicine_name(input.medicine_id).is_none() {
+            return Err(PharmacyError::MedicineNotFound);
+        }
+
+        let existing = self.reorder_settings.iter().position(|s| {
+            s.medicine_id == input.medicine_id && s.warehouse_id == input.warehouse_id
+        });
+        let id = match existing {
+            Some(index) => self.reorder_settings[index].id,
+            None => self.next_id(Sequence::ReorderSetting),
+        };
+        let setting = ReorderSetting {
+            id,
+            medicine_id: input.medicine_id,
+            warehouse_id: input.warehouse_id,
+            min: input.min,
+            reorder_point: input.reorder_point,
+            max: input.max,
+            preferred_supplier_id: input.preferred_supplier_id,
+        };
+        match existing {
+            Some(index) => {
+                let before = std::mem::replace(&mut self.reorder_settings[index], setting.clone());
+                self.record_audit(
+                    user,
+                    AuditAction::Update,
+                    AuditEntity::ReorderSetting,
+                    id,
+                    Some(&before),
+                    Some(&setting),
+                );
+            }
+            None => {
+                self.record_audit(
+                    user,
+                    AuditAction::Create,
+                    AuditEntity::ReorderSetting,
+                    id,
+                    None,
+                    Some(&setting),
+                );
+                self.reorder_settings.push(setting);
+            }
+        }
+        Ok(id)
+    }
+
+    pub fn delete_reorder_setting(&mut self, user: &str, id: u32) -> Result<(), PharmacyError> {
+        let index = self
+            .reorder_settings
+            .iter()
+            .position(|s| s.id == id)
+            .ok_or(PharmacyError::ReorderSettingNotFound)?;
+        let setting = self.reorder_settings.remove(index);
+        self.record_audit(
+            user,
+            AuditAction::Delete,
+            AuditEntity::ReorderSetting,
+            id,
+            Some(&setting),
+            None,
+        );
+        Ok(())
+    }
+
+    /// Purchase suggestions for every reorder setting whose expected stock at
+    /// delivery (sellable on hand + on order - forecast lead time demand) is at
+    /// or below its reorder point, ordering enough to bring it back up to max.
+    /// Settings for archived warehouses or medicines are left out, since
+    /// nothing can be ordered for them.
+    pub fn purchase_suggestions(
+        &self,
+        params: ReplenishmentParams,
+        warehouse_id: Option<u32>,
+    ) -> Vec<SupplierSuggestions> {
+        let now = Local::now();
+        let cutoff = now + Duration::days(params.expiry_cutoff_days);
+        let sales_since = now - Duration::days(params.sales_window_days);
+
+        let mut groups: BTreeMap<Option<u32>, Vec<PurchaseSuggestion>> = BTreeMap::new();
+        for setting in self
+            .reorder_settings
+            .iter()
+            .filter(|s| warehouse_id.is_none_or(|id| id == s.warehouse_id))
+            .filter(|s| {
+                self.active_warehouse(s.warehouse_id).is_ok()
+                    && self.ensure_medicine_not_archived(s.medicine_id).is_ok()
+            })
+        {
+            let batches = self.stock_batches.iter().filter(|b| {
+                b.medicine_id == setting.medicine_id
+                    && b.warehouse_id == setting.warehouse_id
+                    && b.quantity > 0
+            });
+            let (mut on_hand, mut expiring) = (0, 0);
+            for batch in batches {
+                if batch.expiry_date > cutoff {
+                    on_hand += batch.quantity;
+                } else {
+                    expiring += batch.quantity;
+                }
+            }
+            let on_order = self.on_order(setting.medicine_id, setting.warehouse_id);
+            let sold: u32 = self
+                .export_log
+                .iter()
+                .filter(|e| {
+                    e.medicine_id == setting.medicine_id
+                        && e.warehouse_id == Some(setting.warehouse_id)
+                        && e.timestamp >= sales_since
+                })
+                .map(|e| e.amount)
+                .sum();
+            let daily_sales = sold as f64 / params.sales_window_days as f64;
+            let lead_time_demand = self
+                .forecast_demand(
+                    setting.medicine_id,
+                    Some(setting.warehouse_id),
+                    params.tz,
+                    params.moving_average_weeks,
+                    params.lead_time_days,
+                )
+                .ceil() as u32;
+
+            let expected = (on_hand + on_order).saturating_sub(lead_time_demand);
+            if expected > setting.reorder_point {
+                continue;
+            }
+            let quantity = setting.max.saturating_sub(expected);
+            if quantity == 0 {
+                continue;
+            }
+
+            let last_receipt = self
+                .import_log
+                .iter()
+                .rev()
+                .find(|e| e.medicine_id == setting.medicine_id && e.warehouse_id.is_some());
+            let supplier_id = setting
+                .preferred_supplier_id
+                .or_else(|| self.last_supplier(setting.medicine_id, setting.warehouse_id))
+                .filter(|id| self.active_supplier(*id).is_ok());
+            groups
+                .entry(supplier_id)
+                .or_default()
+                .push(PurchaseSuggestion {
+                    medicine_id: setting.medicine_id,
+                    medicine_name: self
+                        .known_medicine_name(setting.medicine_id)
+                        .unwrap_or_default()
+                        .to_string(),
+                    warehouse_id: setting.warehouse_id,
+                    on_hand,
+                    expiring,
+                    on_order,
+                    daily_sales,
+                    lead_time_demand,
+                    min: setting.min,
+                    reorder_point: setting.reorder_point,
+                    max: setting.max,
+                    below_min: on_hand + on_order < setting.min,
+                    quantity,
+                    unit_cost: last_receipt.map_or(0.0, |e| e.unit_cost),
+                });
+        }
+
+        groups
+            .into_iter()
+            .map(|(supplier_id, lines)| SupplierSuggestions {
+                supplier_id,
+                supplier_name: supplier_id.and_then(|id| {
+                    self.suppliers
+                        .iter()
+                        .find(|s| s.id == id)
+                        .map(|s| s.name.clone())
+                }),
+                total_cost: lines
+                    .iter()
+                    .fold(0.0, |sum, l| sum + l.unit_cost * l.quantity as f64),
+                lines,
+            })
+            .collect()
+    }
+
+    /// Turns the current suggestions into one draft purchase order per
+    /// supplier and warehouse. Suggestions without a supplier are skipped.
+    pub fn draft_purchase_orders(
+        &mut self,
+        user: &str,
+        params: ReplenishmentParams,
+        warehouse_id: Option<u32>,
+    ) -> Result<Vec<u32>, PharmacyError> {
+        let mut orders: BTreeMap<(u32, u32), Vec<PurchaseOrderLine>> = BTreeMap::new();
+        for group in self.purchase_suggestions(params, warehouse_id) {
+            let Some(supplier_id) = group.supplier_id else {
+                continue;
+            };
+            for line in group.lines {
+                orders
+                    .entry((supplier_id, line.warehouse_id))
+                    .or_default()
+                    .push(PurchaseOrderLine {
+                        medicine_id: line.medicine_id,
+                        medicine_name: line.medicine_name,
+                        quantity: line.quantity,
+                        unit_cost: line.unit_cost,
+                    });
+            }
+        }
+
+        let mut created = Vec::new();
+        for ((supplier_id, warehouse_id), lines) in orders {
+            created.push(self.create_purchase_order(user, supplier_id, warehouse_id, lines)?);
+        }
+        Ok(created)
+    }
+
+    // Supplier of the latest receipt of the medicine, preferring this warehouse
+    fn last_supplier(&self, medicine_id: u32, warehouse_id: u32) -> Option<u32> {
+        let receipts = || {
+            self.import_log
+                .iter()
+                .rev()
+                .filter(move |e| e.medicine_id == medicine_id && e.supplier_id.is_some())
+        };
+        receipts()
+            .find(|e| e.warehouse_id == Some(warehouse_id))
+            .or_else(|| receipts().next())
+            .and_then(|e| e.supplier_id)
+    }
+}
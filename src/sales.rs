@@ -0,0 +1,183 @@
+use chrono::{DateTime, Datelike, Duration, Local};
+use chrono_tz::Tz;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+use crate::models::{ExportBatch, Pharmacy};
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Period {
+    Day,
+    Week,
+    Month,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum GroupBy {
+    Product,
+    Category,
+    Warehouse,
+    Cashier,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SalesRow {
+    /// Start of the bucket in the pharmacy's time zone, e.g. `2026-10-12` for a week.
+    pub period: String,
+    pub key: String,
+    pub name: String,
+    pub units: u32,
+    pub revenue: f64,
+    pub cogs: f64,
+    pub gross_margin: f64,
+    /// Margin as a percentage of revenue; `None` without revenue.
+    pub margin_percent: Option<f64>,
+    /// Units sold without a recorded cost; they add revenue but no COGS.
+    pub uncosted_units: u32,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SalesReport {
+    pub from: Option<DateTime<Local>>,
+    pub to: Option<DateTime<Local>>,
+    pub period: Period,
+    pub group_by: GroupBy,
+    pub units: u32,
+    pub revenue: f64,
+    pub cogs: f64,
+    pub gross_margin: f64,
+    pub margin_percent: Option<f64>,
+    pub uncosted_units: u32,
+    pub rows: Vec<SalesRow>,
+}
+
+#[derive(Default)]
+struct Totals {
+    units: u32,
+    revenue: f64,
+    cogs: f64,
+    uncosted_units: u32,
+}
+
+impl Totals {
+    fn add(&mut self, sale: &ExportBatch) {
+        self.units += sale.amount;
+        self.revenue += sale.revenue();
+        match sale.cogs() {
+            Some(cogs) => self.cogs += cogs,
+            None => self.uncosted_units += sale.amount,
+        }
+    }
+
+    fn margin(&self) -> (f64, Option<f64>) {
+        let margin = self.revenue - self.cogs;
+        let percent = (self.revenue != 0.0).then(|| margin / self.revenue * 100.0);
+        (margin, percent)
+    }
+}
+
+impl Pharmacy {
+    /// Revenue, COGS and gross margin of sales in `[from, to]`, bucketed by
+    /// `period` in time zone `tz` and broken down by `group_by`.
+    pub fn sales_report(
+        &self,
+        from: Option<DateTime<Local>>,
+        to: Option<DateTime<Local>>,
+        period: Period,
+        group_by: GroupBy,
+        tz: Tz,
+    ) -> SalesReport {
+        let mut groups: BTreeMap<(String, String), (String, Totals)> = BTreeMap::new();
+        let mut total = Totals::default();
+        for sale in self.export_log.iter().filter(|e| {
+            from.is_none_or(|from| e.timestamp >= from) && to.is_none_or(|to| e.timestamp <= to)
+        }) {
+            let (key, name) = self.sales_group(sale, group_by);
+            let entry = groups
+                .entry((period_start(sale.timestamp, period, tz), key))
+                .or_insert_with(|| (name, Totals::default()));
+            entry.1.add(sale);
+            total.add(sale);
+        }
+
+        let rows = groups
+            .into_iter()
+            .map(|((period, key), (name, totals))| {
+                let (gross_margin, margin_percent) = totals.margin();
+                SalesRow {
+                    period,
+                    key,
+                    name,
+                    units: totals.units,
+                    revenue: totals.revenue,
+                    cogs: totals.cogs,
+                    gross_margin,
+                    margin_percent,
+                    uncosted_units: totals.uncosted_units,
+                }
+            })
+            .collect();
+        let (gross_margin, margin_percent) = total.margin();
+        SalesReport {
+            from,
+            to,
+            period,
+            group_by,
+            units: total.units,
+            revenue: total.revenue,
+            cogs: total.cogs,
+            gross_margin,
+            margin_percent,
+            uncosted_units: total.uncosted_units,
+            rows,
+        }
+    }
+
+    fn sales_group(&self, sale: &ExportBatch, group_by: GroupBy) -> (String, String) {
+        match group_by {
+            GroupBy::Product => (sale.medicine_id.to_string(), sale.medicine_name.clone()),
+            GroupBy::Category => {
+                let category = self
+                    .inventory
+                    .iter()
+                    .find(|m| m.id == sale.medicine_id)
+                    .and_then(|m| m.category.clone())
+                    .unwrap_or_else(|| "Uncategorized".to_string());
+                (category.clone(), category)
+            }
+            // Catalog sales have no warehouse
+            GroupBy::Warehouse => match sale.warehouse_id {
+                Some(id) => (
+                    id.to_string(),
+                    self.warehouses
+                        .iter()
+                        .find(|w| w.id == id)
+                        .map(|w| w.name.clone())
+                        .unwrap_or_default(),
+                ),
+                None => ("catalog".to_string(), "Catalog".to_string()),
+            },
+            GroupBy::Cashier => {
+                let cashier = sale
+                    .cashier
+                    .clone()
+                    .unwrap_or_else(|| "unknown".to_string());
+                (cashier.clone(), cashier)
+            }
+        }
+    }
+}
+
+// Weeks start on Monday, as in ISO 8601
+pub fn period_start(at: DateTime<Local>, period: Period, tz: Tz) -> String {
+    let day = at.with_timezone(&tz).date_naive();
+    match period {
+        Period::Day => day.format("%Y-%m-%d").to_string(),
+        Period::Week => (day - Duration::days(day.weekday().num_days_from_monday() as i64))
+            .format("%Y-%m-%d")
+            .to_string(),
+        Period::Month => day.format("%Y-%m").to_string(),
+    }
+}
@@ -0,0 +1,232 @@
+use serde::{Deserialize, Serialize};
+
+use crate::audit::AuditEntity;
+use crate::models::Pharmacy;
+
+/// Last id handed out per collection. Ids are never reused, even after the
+/// record that held one is deleted or its log entry pruned.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct Sequences {
+    pub medicine: u32,
+    pub warehouse: u32,
+    pub supplier: u32,
+    pub stock_batch: u32,
+    pub import_log: u32,
+    pub export_log: u32,
+    pub transfer_log: u32,
+    pub audit_log: u32,
+    pub reorder_setting: u32,
+    pub purchase_order: u32,
+    pub par_level: u32,
+    pub transfer_proposal: u32,
+    pub einvoice: u32,
+    pub price_list: u32,
+    pub promotion: u32,
+}
+
+#[derive(Debug, Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Sequence {
+    Medicine,
+    Warehouse,
+    Supplier,
+    StockBatch,
+    ImportLog,
+    ExportLog,
+    TransferLog,
+    AuditLog,
+    ReorderSetting,
+    PurchaseOrder,
+    ParLevel,
+    TransferProposal,
+    EInvoice,
+    PriceList,
+    Promotion,
+}
+
+impl Sequences {
+    pub fn next(&mut self, sequence: Sequence) -> u32 {
+        let last = self.last_mut(sequence);
+        *last += 1;
+        *last
+    }
+
+    pub fn last(&self, sequence: Sequence) -> u32 {
+        match sequence {
+            Sequence::Medicine => self.medicine,
+            Sequence::Warehouse => self.warehouse,
+            Sequence::Supplier => self.supplier,
+            Sequence::StockBatch => self.stock_batch,
+            Sequence::ImportLog => self.import_log,
+            Sequence::ExportLog => self.export_log,
+            Sequence::TransferLog => self.transfer_log,
+            Sequence::AuditLog => self.audit_log,
+            Sequence::ReorderSetting => self.reorder_setting,
+            Sequence::PurchaseOrder => self.purchase_order,
+            Sequence::ParLevel => self.par_level,
+            Sequence::TransferProposal => self.transfer_proposal,
+            Sequence::EInvoice => self.einvoice,
+            Sequence::PriceList => self.price_list,
+            Sequence::Promotion => self.promotion,
+        }
+    }
+
+    fn last_mut(&mut self, sequence: Sequence) -> &mut u32 {
+        match sequence {
+            Sequence::Medicine => &mut self.medicine,
+            Sequence::Warehouse => &mut self.warehouse,
+            Sequence::Supplier => &mut self.supplier,
+            Sequence::StockBatch => &mut self.stock_batch,
+            Sequence::ImportLog => &mut self.import_log,
+            Sequence::ExportLog => &mut self.export_log,
+            Sequence::TransferLog => &mut self.transfer_log,
+            Sequence::AuditLog => &mut self.audit_log,
+            Sequence::ReorderSetting => &mut self.reorder_setting,
+            Sequence::PurchaseOrder => &mut self.purchase_order,
+            Sequence::ParLevel => &mut self.par_level,
+            Sequence::TransferProposal => &mut self.transfer_proposal,
+            Sequence::EInvoice => &mut self.einvoice,
+            Sequence::PriceList => &mut self.price_list,
+            Sequence::Promotion => &mut self.promotion,
+        }
+    }
+}
+
+pub const ALL_SEQUENCES: [Sequence; 15] = [
+    Sequence::Medicine,
+    Sequence::Warehouse,
+    Sequence::Supplier,
+    Sequence::StockBatch,
+    Sequence::ImportLog,
+    Sequence::ExportLog,
+    Sequence::TransferLog,
+    Sequence::AuditLog,
+    Sequence::ReorderSetting,
+    Sequence::PurchaseOrder,
+    Sequence::ParLevel,
+    Sequence::TransferProposal,
+    Sequence::EInvoice,
+    Sequence::PriceList,
+    Sequence::Promotion,
+];
+
+impl Pharmacy {
+    pub fn next_id(&mut self, sequence: Sequence) -> u32 {
+        self.sequences.next(sequence)
+    }
+
+    /// Highest id in use for `sequence`. Deleted records still count when the
+    /// audit log or other logs remember their id.
+    pub fn max_id(&self, sequence: Sequence) -> u32 {
+        let audited = |entity: AuditEntity| {
+            self.audit_log
+                .iter()
+                .filter(move |e| e.entity == entity)
+                .map(|e| e.entity_id)
+        };
+        let max = match sequence {
+            Sequence::Medicine => self
+                .inventory
+                .iter()
+                .map(|m| m.id)
+                .chain(self.import_log.iter().map(|e| e.medicine_id))
+                .chain(self.export_log.iter().map(|e| e.medicine_id))
+                .chain(audited(AuditEntity::Medicine))
+                .max(),
+            Sequence::Warehouse => self
+                .warehouses
+                .iter()
+                .map(|w| w.id)
+                .chain(audited(AuditEntity::Warehouse))
+                .max(),
+            Sequence::Supplier => self
+                .suppliers
+                .iter()
+                .map(|s| s.id)
+                .chain(audited(AuditEntity::Supplier))
+                .max(),
+            Sequence::StockBatch => self
+                .stock_batches
+                .iter()
+                .map(|b| b.id)
+                .chain(audited(AuditEntity::StockBatch))
+                .max(),
+            Sequence::ImportLog => self
+                .import_log
+                .iter()
+                .map(|e| e.id)
+                .chain(audited(AuditEntity::ImportLog))
+                .max(),
+            Sequence::ExportLog => self
+                .export_log
+                .iter()
+                .map(|e| e.id)
+                .chain(audited(AuditEntity::ExportLog))
+                .max(),
+            Sequence::TransferLog => self
+                .transfer_log
+                .iter()
+                .map(|t| t.id)
+                .chain(audited(AuditEntity::TransferLog))
+                .max(),
+            Sequence::AuditLog => self.audit_log.iter().map(|e| e.id).max(),
+            Sequence::ReorderSetting => self
+                .reorder_settings
+                .iter()
+                .map(|s| s.id)
+                .chain(audited(AuditEntity::ReorderSetting))
+                .max(),
+            Sequence::PurchaseOrder => self
+                .purchase_orders
+                .iter()
+                .map(|o| o.id)
+                .chain(audited(AuditEntity::PurchaseOrder))
+                .max(),
+            Sequence::ParLevel => self
+                .par_levels
+                .iter()
+                .map(|p| p.id)
+                .chain(audited(AuditEntity::ParLevel))
+                .max(),
+            Sequence::TransferProposal => self
+                .transfer_proposals
+                .iter()
+                .map(|p| p.id)
+                .chain(audited(AuditEntity::TransferProposal))
+                .max(),
+            Sequence::EInvoice => self
+                .einvoices
+                .iter()
+                .map(|i| i.id)
+                .chain(audited(AuditEntity::EInvoice))
+                .max(),
+            Sequence::PriceList => self
+                .price_lists
+                .iter()
+                .map(|l| l.id)
+                .chain(audited(AuditEntity::PriceList))
+                .max(),
+            Sequence::Promotion => self
+                .promotions
+                .iter()
+                .map(|p| p.id)
+                .chain(audited(AuditEntity::Promotion))
+                .max(),
+        };
+        max.unwrap_or(0)
+    }
+
+    /// Moves every sequence past the ids already in use; never moves one backwards.
+    pub fn advance_sequences(&mut self) -> Vec<Sequence> {
+        let mut advanced = Vec::new();
+        for sequence in ALL_SEQUENCES {
+            let max = self.max_id(sequence);
+            if self.sequences.last(sequence) < max {
+                *self.sequences.last_mut(sequence) = max;
+                advanced.push(sequence);
+            }
+        }
+        advanced
+    }
+}
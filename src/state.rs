@@ -0,0 +1,144 @@
+use std::{
+    cell::Cell,
+    fs,
+    future::Future,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock},
+};
+use tokio::sync::Mutex;
+
+use crate::error::PharmacyError;
+use crate::migrate::SCHEMA_VERSION;
+use crate::models::Pharmacy;
+
+tokio::task_local! {
+    static COMMITTED: Cell<bool>;
+}
+
+/// Pharmacy data shared by all handlers.
+///
+/// Reads run concurrently against the last saved state. Writes are applied to a
+/// copy, one at a time, and the copy replaces the shared state only once it has
+/// been written to disk, so a failed save or a panicking handler leaves nothing
+/// behind in memory.
+pub struct SharedPharmacy {
+    pharmacy: RwLock<Arc<Pharmacy>>,
+    writer: Mutex<()>,
+    data_file: PathBuf,
+}
+
+impl SharedPharmacy {
+    /// Loads `data_file`, upgrading it from an older schema in memory. A missing
+    /// file starts an empty pharmacy; an unreadable, malformed or newer one is an
+    /// error, so it is never overwritten by the first save.
+    pub fn open(data_file: impl Into<PathBuf>) -> Result<Arc<Self>, String> {
+        let data_file = data_file.into();
+        Ok(Arc::new(SharedPharmacy {
+            pharmacy: RwLock::new(Arc::new(load_data(&data_file)?)),
+            writer: Mutex::new(()),
+            data_file,
+        }))
+    }
+
+    /// The last saved state. The lock is held only to copy the pointer, so a slow
+    /// report never holds up a write waiting to swap in the next state.
+    pub fn read(&self) -> Arc<Pharmacy> {
+        // The lock only guards swapping in a saved state, which cannot be left half-done
+        Arc::clone(&self.pharmacy.read().unwrap_or_else(|e| e.into_inner()))
+    }
+
+    /// Applies `change` to a copy of the data and makes it visible once it is on disk.
+    pub async fn update<T>(
+        &self,
+        change: impl FnOnce(&mut Pharmacy) -> Result<T, PharmacyError>,
+    ) -> Result<T, PharmacyError> {
+        let _writer = self.writer.lock().await;
+        let mut next = Pharmacy::clone(&self.read());
+        let value = change(&mut next)?;
+
+        let data_file = self.data_file.clone();
+        let next =
+            tokio::task::spawn_blocking(move || write_snapshot(&data_file, &next).map(|()| next))
+                .await
+                .unwrap_or_else(|e| Err(PharmacyError::Persistence(e.to_string())))
+                .inspect_err(|e| eprintln!("{}", e))?;
+
+        *self.pharmacy.write().unwrap_or_else(|e| e.into_inner()) = Arc::new(next);
+        let _ = COMMITTED.try_with(|committed| committed.set(true));
+        Ok(value)
+    }
+}
+
+/// Runs `future` and reports whether it committed a change through
+/// [`SharedPharmacy::update`], whatever response it ends up returning.
+pub async fn tracking_commits<F: Future>(future: F) -> (F::Output, bool) {
+    COMMITTED
+        .scope(Cell::new(false), async {
+            let output = future.await;
+            (output, COMMITTED.with(Cell::get))
+        })
+        .await
+}
+
+/// Reads a data file for offline tools. Unlike server startup, an unreadable
+/// or malformed file is an error instead of an empty pharmacy.
+pub fn read_snapshot(path: &Path) -> Result<Pharmacy, String> {
+    let data = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    serde_json::from_str(&data).map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+/// Atomically replaces `path` with `pharmacy`, in the same format the server writes.
+pub fn write_snapshot(path: &Path, pharmacy: &Pharmacy) -> Result<(), PharmacyError> {
+    let data = serde_json::to_string_pretty(pharmacy)
+        .map_err(|e| PharmacyError::Persistence(e.to_string()))?;
+    save_data(path, &data)
+        .map_err(|e| PharmacyError::Persistence(format!("{}: {}", path.display(), e)))
+}
+
+fn load_data(data_file: &Path) -> Result<Pharmacy, String> {
+    let data = match fs::read_to_string(data_file) {
+        Ok(data) => data,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Pharmacy::new()),
+        Err(e) => return Err(format!("{}: {}", data_file.display(), e)),
+    };
+    // Checked before parsing, since a newer format may not parse at all
+    let version = serde_json::from_str::<serde_json::Value>(&data)
+        .ok()
+        .and_then(|v| v.get("schema_version")?.as_u64());
+    if let Some(version) = version
+        && version > SCHEMA_VERSION as u64
+    {
+        return Err(format!(
+            "{} was written by a newer version (schema {})",
+            data_file.display(),
+            version
+        ));
+    }
+    let mut pharmacy: Pharmacy =
+        serde_json::from_str(&data).map_err(|e| format!("{}: {}", data_file.display(), e))?;
+
+    // Older files are upgraded in memory and written in the new format on the next save
+    if pharmacy.needs_migration() {
+        let report = pharmacy
+            .migrate()
+            .map_err(|e| format!("{}: {}", data_file.display(), e))?;
+        eprintln!(
+            "Upgraded {} from schema version {} to {}",
+            data_file.display(),
+            report.from,
+            report.to
+        );
+        for note in report.notes {
+            eprintln!("  - {}", note);
+        }
+    }
+    Ok(pharmacy)
+}
+
+// Write to a sibling file and rename so a crash never leaves a truncated data file
+fn save_data(data_file: &Path, data: &str) -> std::io::Result<()> {
+    let tmp_file = data_file.with_extension("json.tmp");
+    fs::write(&tmp_file, data)?;
+    fs::rename(&tmp_file, data_file)
+}
@@ -0,0 +1,224 @@
+use chrono::{DateTime, Local};
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+use crate::error::PharmacyError;
+use crate::models::Pharmacy;
+
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MovementKind {
+    Receipt,
+    Sale,
+    TransferIn,
+    TransferOut,
+    /// Difference between the logs and the stock on hand that no log explains.
+    Adjustment,
+}
+
+#[derive(Debug, Serialize)]
+pub struct StockMovement {
+    pub timestamp: DateTime<Local>,
+    pub kind: MovementKind,
+    /// Id of the import, export or transfer log entry; `None` for adjustments.
+    pub reference: Option<u32>,
+    /// `None` for the catalog quantity kept on the medicine itself.
+    pub warehouse_id: Option<u32>,
+    pub batch_id: Option<u32>,
+    pub quantity_in: u32,
+    pub quantity_out: u32,
+    pub balance: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct StockCard {
+    pub medicine_id: u32,
+    pub medicine_name: String,
+    pub warehouse_id: Option<u32>,
+    pub from: Option<DateTime<Local>>,
+    pub to: Option<DateTime<Local>>,
+    /// Balance before the first movement shown.
+    pub opening_balance: i64,
+    pub closing_balance: i64,
+    pub movements: Vec<StockMovement>,
+}
+
+impl Pharmacy {
+    /// Every movement of one medicine in date order with a running balance,
+    /// optionally limited to one warehouse and to `[from, to]`.
+    pub fn stock_card(
+        &self,
+        medicine_id: u32,
+        warehouse_id: Option<u32>,
+        from: Option<DateTime<Local>>,
+        to: Option<DateTime<Local>>,
+    ) -> Result<StockCard, PharmacyError> {
+        let mut movements = self.movements(medicine_id);
+        let medicine_name = self
+            .inventory
+            .iter()
+            .find(|m| m.id == medicine_id)
+            .map(|m| m.name.clone())
+            .or_else(|| {
+                self.stock_batches
+                    .iter()
+                    .find(|b| b.medicine_id == medicine_id)
+                    .map(|b| b.medicine_name.clone())
+            })
+            .ok_or(PharmacyError::MedicineNotFound)?;
+
+        movements.extend(self.adjustments(medicine_id, &movements));
+        movements.retain(|m| warehouse_id.is_none() || m.warehouse_id == warehouse_id);
+        // Stable sort: movements logged at the same instant keep their log order
+        movements.sort_by_key(|m| m.timestamp);
+
+        let mut balance = 0i64;
+        for movement in &mut movements {
+            balance += movement.quantity_in as i64 - movement.quantity_out as i64;
+            movement.balance = balance;
+        }
+        let opening_balance = movements
+            .iter()
+            .take_while(|m| from.is_some_and(|from| m.timestamp < from))
+            .last()
+            .map_or(0, |m| m.balance);
+        movements.retain(|m| {
+            from.is_none_or(|from| m.timestamp >= from) && to.is_none_or(|to| m.timestamp <= to)
+        });
+        let closing_balance = movements.last().map_or(opening_balance, |m| m.balance);
+
+        Ok(StockCard {
+            medicine_id,
+            medicine_name,
+            warehouse_id,
+            from,
+            to,
+            opening_balance,
+            closing_balance,
+            movements,
+        })
+    }
+
+    // Movements recorded in the import, export and transfer logs
+    fn movements(&self, medicine_id: u32) -> Vec<StockMovement> {
+        let movement =
+            |timestamp, kind, reference, warehouse_id, batch_id, quantity_in, quantity_out| {
+                StockMovement {
+                    timestamp,
+                    kind,
+                    reference: Some(reference),
+                    warehouse_id,
+                    batch_id,
+                    quantity_in,
+                    quantity_out,
+                    balance: 0,
+                }
+            };
+        let mut movements = Vec::new();
+        for entry in self
+            .import_log
+            .iter()
+            .filter(|e| e.medicine_id == medicine_id)
+        {
+            movements.push(movement(
+                entry.timestamp,
+                MovementKind::Receipt,
+                entry.id,
+                entry.warehouse_id,
+                entry.batch_id,
+                entry.quantity,
+                0,
+            ));
+        }
+        for sale in self
+            .export_log
+            .iter()
+            .filter(|e| e.medicine_id == medicine_id)
+        {
+            // Older sales did not record the batches they came from
+            if sale.batches.is_empty() {
+                movements.push(movement(
+                    sale.timestamp,
+                    MovementKind::Sale,
+                    sale.id,
+                    sale.warehouse_id,
+                    None,
+                    0,
+                    sale.amount,
+                ));
+            }
+            for batch in &sale.batches {
+                movements.push(movement(
+                    sale.timestamp,
+                    MovementKind::Sale,
+                    sale.id,
+                    sale.warehouse_id,
+                    Some(batch.batch_id),
+                    0,
+                    batch.quantity,
+                ));
+            }
+        }
+        for transfer in self
+            .transfer_log
+            .iter()
+            .filter(|t| t.medicine_id == medicine_id)
+        {
+            movements.push(movement(
+                transfer.timestamp,
+                MovementKind::TransferOut,
+                transfer.id,
+                Some(transfer.from_warehouse_id),
+                Some(transfer.batch_id),
+                0,
+                transfer.quantity,
+            ));
+            movements.push(movement(
+                transfer.timestamp,
+                MovementKind::TransferIn,
+                transfer.id,
+                Some(transfer.to_warehouse_id),
+                transfer.to_batch_id,
+                transfer.quantity,
+                0,
+            ));
+        }
+        movements
+    }
+
+    // One adjustment, dated now, per place whose logged movements do not add
+    // up to what is on hand there
+    fn adjustments(&self, medicine_id: u32, movements: &[StockMovement]) -> Vec<StockMovement> {
+        let mut difference: BTreeMap<Option<u32>, i64> = BTreeMap::new();
+        if let Some(medicine) = self.inventory.iter().find(|m| m.id == medicine_id) {
+            *difference.entry(None).or_default() += medicine.quantity as i64;
+        }
+        for batch in self
+            .stock_batches
+            .iter()
+            .filter(|b| b.medicine_id == medicine_id)
+        {
+            *difference.entry(Some(batch.warehouse_id)).or_default() += batch.quantity as i64;
+        }
+        for movement in movements {
+            *difference.entry(movement.warehouse_id).or_default() -=
+                movement.quantity_in as i64 - movement.quantity_out as i64;
+        }
+
+        let now = Local::now();
+        difference
+            .into_iter()
+            .filter(|(_, diff)| *diff != 0)
+            .map(|(warehouse_id, diff)| StockMovement {
+                timestamp: now,
+                kind: MovementKind::Adjustment,
+                reference: None,
+                warehouse_id,
+                batch_id: None,
+                quantity_in: diff.max(0) as u32,
+                quantity_out: (-diff).max(0) as u32,
+                balance: 0,
+            })
+            .collect()
+    }
+}
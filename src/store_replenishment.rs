@@ -0,0 +1,333 @@
+use chrono::{DateTime, Duration, Local};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+use crate::audit::{AuditAction, AuditEntity};
+use crate::error::PharmacyError;
+use crate::models::{Pharmacy, WarehouseType};
+use crate::sequence::Sequence;
+
+/// Stock a Store warehouse should hold of one medicine.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ParLevel {
+    pub id: u32,
+    pub warehouse_id: u32,
+    pub medicine_id: u32,
+    pub par: u32,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ProposalStatus {
+    Pending,
+    Approved,
+    Rejected,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ProposalLine {
+    pub medicine_id: u32,
+    pub medicine_name: String,
+    /// Source batch in the Main warehouse.
+    pub batch_id: u32,
+    pub quantity: u32,
+    pub expiry_date: DateTime<Local>,
+}
+
+/// Transfers from one Main warehouse to one Store, run together once approved.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct TransferProposal {
+    pub id: u32,
+    pub from_warehouse_id: u32,
+    pub to_warehouse_id: u32,
+    pub status: ProposalStatus,
+    pub lines: Vec<ProposalLine>,
+    pub created_at: DateTime<Local>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub decided_at: Option<DateTime<Local>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub decided_by: Option<String>,
+}
+
+impl Pharmacy {
+    /// Creates or replaces the par level of a medicine in a Store warehouse.
+    pub fn set_par_level(
+        &mut self,
+        user: &str,
+        warehouse_id: u32,
+        medicine_id: u32,
+        par: u32,
+    ) -> Result<u32, PharmacyError> {
+        let warehouse = self.active_warehouse(warehouse_id)?;
+        if !matches!(warehouse.warehouse_type, WarehouseType::Store) {
+            return Err(PharmacyError::NotAStore);
+        }
+        self.ensure_medicine_not_archived(medicine_id)?;
+
+        let existing = self
+            .par_levels
+            .iter()
+            .position(|p| p.warehouse_id == warehouse_id && p.medicine_id == medicine_id);
+        let id = match existing {
+            Some(index) => self.par_levels[index].id,
+            None => self.next_id(Sequence::ParLevel),
+        };
+        let level = ParLevel {
+            id,
+            warehouse_id,
+            medicine_id,
+            par,
+        };
+        match existing {
+            Some(index) => {
+                let before = std::mem::replace(&mut self.par_levels[index], level.clone());
+                self.record_audit(
+                    user,
+                    AuditAction::Update,
+                    AuditEntity::ParLevel,
+                    id,
+                    Some(&before),
+                    Some(&level),
+                );
+            }
+            None => {
+                self.record_audit(
+                    user,
+                    AuditAction::Create,
+                    AuditEntity::ParLevel,
+                    id,
+                    None,
+                    Some(&level),
+                );
+                self.par_levels.push(level);
+            }
+        }
+        Ok(id)
+    }
+
+    pub fn delete_par_level(&mut self, user: &str, id: u32) -> Result<(), PharmacyError> {
+        let index = self
+            .par_levels
+            .iter()
+            .position(|p| p.id == id)
+            .ok_or(PharmacyError::ParLevelNotFound)?;
+        let level = self.par_levels.remove(index);
+        self.record_audit(
+            user,
+            AuditAction::Delete,
+            AuditEntity::ParLevel,
+            id,
+            Some(&level),
+            None,
+        );
+        Ok(())
+    }
+
+    /// Proposes transfers for every store medicine whose sellable stock, plus
+    /// what pending proposals already bring, is below par. Source batches are
+    /// taken from Main warehouses earliest expiry first, skipping batches that
+    /// expire within `expiry_cutoff_days`. Returns the ids of new proposals.
+    pub fn propose_store_transfers(
+        &mut self,
+        user: &str,
+        store_id: Option<u32>,
+        expiry_cutoff_days: i64,
+    ) -> Vec<u32> {
+        let cutoff = Local::now() + Duration::days(expiry_cutoff_days);
+        let is_active_main = |id: u32| {
+            self.warehouses.iter().any(|w| {
+                w.id == id
+                    && w.archived_at.is_none()
+                    && matches!(w.warehouse_type, WarehouseType::Main)
+            })
+        };
+        let is_active_store = |id: u32| {
+            self.warehouses.iter().any(|w| {
+                w.id == id
+                    && w.archived_at.is_none()
+                    && matches!(w.warehouse_type, WarehouseType::Store)
+            })
+        };
+
+        // Units of each batch already promised to pending proposals
+        let mut reserved: BTreeMap<u32, u32> = BTreeMap::new();
+        for line in self
+            .transfer_proposals
+            .iter()
+            .filter(|p| p.status == ProposalStatus::Pending)
+            .flat_map(|p| &p.lines)
+        {
+            *reserved.entry(line.batch_id).or_default() += line.quantity;
+        }
+
+        let mut proposals: BTreeMap<(u32, u32), Vec<ProposalLine>> = BTreeMap::new();
+        for level in self.par_levels.iter().filter(|p| {
+            store_id.is_none_or(|id| id == p.warehouse_id) && is_active_store(p.warehouse_id)
+        }) {
+            let on_hand: u32 = self
+                .stock_batches
+                .iter()
+                .filter(|b| {
+                    b.warehouse_id == level.warehouse_id
+                        && b.medicine_id == level.medicine_id
+                        && b.expiry_date > cutoff
+                })
+                .map(|b| b.quantity)
+                .sum();
+            let incoming: u32 = self
+                .transfer_proposals
+                .iter()
+                .filter(|p| {
+                    p.status == ProposalStatus::Pending && p.to_warehouse_id == level.warehouse_id
+                })
+                .flat_map(|p| &p.lines)
+                .filter(|l| l.medicine_id == level.medicine_id)
+                .map(|l| l.quantity)
+                .sum();
+            let mut needed = level.par.saturating_sub(on_hand + incoming);
+            if needed == 0 {
+                continue;
+            }
+
+            let mut sources: Vec<_> = self
+                .stock_batches
+                .iter()
+                .filter(|b| {
+                    b.medicine_id == level.medicine_id
+                        && b.expiry_date > cutoff
+                        && is_active_main(b.warehouse_id)
+                })
+                .collect();
+            sources.sort_by_key(|b| (b.expiry_date, b.id));
+            for batch in sources {
+                if needed == 0 {
+                    break;
+                }
+                let reserved = reserved.entry(batch.id).or_default();
+                let free = batch.quantity.saturating_sub(*reserved);
+                let quantity = needed.min(free);
+                if quantity == 0 {
+                    continue;
+                }
+                *reserved += quantity;
+                needed -= quantity;
+                proposals
+                    .entry((batch.warehouse_id, level.warehouse_id))
+                    .or_default()
+                    .push(ProposalLine {
+                        medicine_id: batch.medicine_id,
+                        medicine_name: batch.medicine_name.clone(),
+                        batch_id: batch.id,
+                        quantity,
+                        expiry_date: batch.expiry_date,
+                    });
+            }
+        }
+
+        let now = Local::now();
+        let mut created = Vec::new();
+        for ((from_warehouse_id, to_warehouse_id), lines) in proposals {
+            let proposal = TransferProposal {
+                id: self.next_id(Sequence::TransferProposal),
+                from_warehouse_id,
+                to_warehouse_id,
+                status: ProposalStatus::Pending,
+                lines,
+                created_at: now,
+                decided_at: None,
+                decided_by: None,
+            };
+            self.record_audit(
+                user,
+                AuditAction::Create,
+                AuditEntity::TransferProposal,
+                proposal.id,
+                None,
+                Some(&proposal),
+            );
+            created.push(proposal.id);
+            self.transfer_proposals.push(proposal);
+        }
+        created
+    }
+
+    /// Runs every transfer of a pending proposal. Nothing moves unless all of
+    /// its source batches still hold enough stock.
+    pub fn approve_transfer_proposal(&mut self, user: &str, id: u32) -> Result<(), PharmacyError> {
+        let proposal = self.pending_proposal(id, ProposalStatus::Approved)?.clone();
+        self.active_warehouse(proposal.to_warehouse_id)?;
+
+        let mut wanted: BTreeMap<u32, u32> = BTreeMap::new();
+        for line in &proposal.lines {
+            *wanted.entry(line.batch_id).or_default() += line.quantity;
+        }
+        for (batch_id, quantity) in wanted {
+            let batch = self
+                .stock_batches
+                .iter()
+                .find(|b| b.id == batch_id && b.warehouse_id == proposal.from_warehouse_id)
+                .ok_or(PharmacyError::BatchNotFound)?;
+            if batch.quantity < quantity {
+                return Err(PharmacyError::InsufficientStock {
+                    available: batch.quantity,
+                });
+            }
+        }
+
+        for line in &proposal.lines {
+            self.transfer_batch(user, line.batch_id, proposal.to_warehouse_id, line.quantity)?;
+        }
+        self.decide_proposal(user, id, ProposalStatus::Approved)
+    }
+
+    pub fn reject_transfer_proposal(&mut self, user: &str, id: u32) -> Result<(), PharmacyError> {
+        self.pending_proposal(id, ProposalStatus::Rejected)?;
+        self.decide_proposal(user, id, ProposalStatus::Rejected)
+    }
+
+    fn pending_proposal(
+        &self,
+        id: u32,
+        next: ProposalStatus,
+    ) -> Result<&TransferProposal, PharmacyError> {
+        let proposal = self
+            .transfer_proposals
+            .iter()
+            .find(|p| p.id == id)
+            .ok_or(PharmacyError::TransferProposalNotFound)?;
+        if proposal.status != ProposalStatus::Pending {
+            return Err(PharmacyError::InvalidStatusTransition {
+                from: format!("{:?}", proposal.status).to_lowercase(),
+                to: format!("{:?}", next).to_lowercase(),
+            });
+        }
+        Ok(proposal)
+    }
+
+    fn decide_proposal(
+        &mut self,
+        user: &str,
+        id: u32,
+        status: ProposalStatus,
+    ) -> Result<(), PharmacyError> {
+        let proposal = self
+            .transfer_proposals
+            .iter_mut()
+            .find(|p| p.id == id)
+            .ok_or(PharmacyError::TransferProposalNotFound)?;
+        let before = proposal.clone();
+        proposal.status = status;
+        proposal.decided_at = Some(Local::now());
+        proposal.decided_by = Some(user.to_string());
+        let after = proposal.clone();
+        self.record_audit(
+            user,
+            AuditAction::Update,
+            AuditEntity::TransferProposal,
+            id,
+            Some(&before),
+            Some(&after),
+        );
+        Ok(())
+    }
+}
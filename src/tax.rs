@@ -0,0 +1,418 @@
+use chrono::{DateTime, Local};
+use chrono_tz::Tz;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+
+use crate::audit::{AuditAction, AuditEntity};
+use crate::config::Config;
+use crate::error::PharmacyError;
+use crate::models::Pharmacy;
+use crate::sales::{Period, period_start};
+
+/// Rounds to the smallest unit of `currency`; the đồng has no subunit.
+pub fn round_money(value: f64, currency: &str) -> f64 {
+    if currency == "VND" {
+        value.round()
+    } else {
+        (value * 100.0).round() / 100.0
+    }
+}
+
+/// VAT rate categories (thuế suất GTGT), written as the percentage in JSON.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(try_from = "u8", into = "u8")]
+pub enum VatRate {
+    Zero,
+    Five,
+    Eight,
+    Ten,
+}
+
+impl VatRate {
+    pub fn percent(self) -> u8 {
+        match self {
+            VatRate::Zero => 0,
+            VatRate::Five => 5,
+            VatRate::Eight => 8,
+            VatRate::Ten => 10,
+        }
+    }
+
+    /// Parses a command-line value such as `5` or `5%`.
+    pub fn parse(value: &str) -> Result<VatRate, String> {
+        let percent = value
+            .trim()
+            .trim_end_matches('%')
+            .parse::<u8>()
+            .map_err(|_| format!("{:?} is not a VAT rate", value))?;
+        VatRate::try_from(percent)
+    }
+}
+
+impl TryFrom<u8> for VatRate {
+    type Error = String;
+
+    fn try_from(percent: u8) -> Result<Self, Self::Error> {
+        match percent {
+            0 => Ok(VatRate::Zero),
+            5 => Ok(VatRate::Five),
+            8 => Ok(VatRate::Eight),
+            10 => Ok(VatRate::Ten),
+            _ => Err(format!("VAT rate must be 0, 5, 8 or 10, not {}", percent)),
+        }
+    }
+}
+
+impl From<VatRate> for u8 {
+    fn from(rate: VatRate) -> u8 {
+        rate.percent()
+    }
+}
+
+impl fmt::Display for VatRate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}%", self.percent())
+    }
+}
+
+/// VAT on a whole sale line, worked out when the sale was made.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy)]
+pub struct LineTax {
+    pub vat_rate: VatRate,
+    /// Amount before VAT.
+    pub net: f64,
+    pub vat: f64,
+}
+
+impl LineTax {
+    /// Splits a line `amount` charged at `vat_rate`. With `includes_vat` the
+    /// VAT is part of the amount; otherwise it comes on top.
+    pub fn new(amount: f64, vat_rate: VatRate, includes_vat: bool, currency: &str) -> LineTax {
+        let rate = vat_rate.percent() as f64 / 100.0;
+        let amount = round_money(amount, currency);
+        let (net, vat) = if includes_vat {
+            let net = round_money(amount / (1.0 + rate), currency);
+            (net, amount - net)
+        } else {
+            (amount, round_money(amount * rate, currency))
+        };
+        LineTax { vat_rate, net, vat }
+    }
+
+    /// What the customer pays for the line.
+    pub fn gross(&self) -> f64 {
+        self.net + self.vat
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct VatRow {
+    /// Start of the bucket in the pharmacy's time zone, as in sales reports.
+    pub period: String,
+    pub vat_rate: VatRate,
+    /// Sale lines at this rate.
+    pub sales: u32,
+    pub net: f64,
+    pub vat: f64,
+    pub gross: f64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct VatRateTotal {
+    pub vat_rate: VatRate,
+    pub sales: u32,
+    pub net: f64,
+    pub vat: f64,
+    pub gross: f64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct VatReport {
+    pub from: Option<DateTime<Local>>,
+    pub to: Option<DateTime<Local>>,
+    pub period: Period,
+    pub net: f64,
+    pub vat: f64,
+    pub gross: f64,
+    pub by_rate: Vec<VatRateTotal>,
+    /// Sales recorded before VAT was tracked; they are left out of the totals.
+    pub untaxed_sales: u32,
+    pub untaxed_revenue: f64,
+    pub rows: Vec<VatRow>,
+}
+
+#[derive(Default)]
+struct Totals {
+    sales: u32,
+    net: f64,
+    vat: f64,
+}
+
+impl Totals {
+    fn add(&mut self, tax: &LineTax) {
+        self.sales += 1;
+        self.net += tax.net;
+        self.vat += tax.vat;
+    }
+}
+
+impl Pharmacy {
+    /// VAT rate of a catalog product, or `default` when it has none of its own.
+    pub fn vat_rate(&self, medicine_id: u32, default: VatRate) -> VatRate {
+        self.inventory
+            .iter()
+            .find(|m| m.id == medicine_id)
+            .and_then(|m| m.vat_rate)
+            .unwrap_or(default)
+    }
+
+    /// VAT on a sale line of `amount` at the medicine's rate, under the
+    /// configured pricing.
+    pub fn line_tax(&self, medicine_id: u32, amount: f64, config: &Config) -> LineTax {
+        LineTax::new(
+            amount,
+            self.vat_rate(medicine_id, config.tax.default_vat_rate),
+            config.tax.prices_include_vat,
+            &config.currency,
+        )
+    }
+
+    /// Sets or clears (`None`: the configured default applies) a medicine's VAT rate.
+    pub fn set_medicine_vat_rate(
+        &mut self,
+        user: &str,
+        id: u32,
+        vat_rate: Option<VatRate>,
+    ) -> Result<(), PharmacyError> {
+        self.ensure_medicine_not_archived(id)?;
+        let medicine = self
+            .inventory
+            .iter_mut()
+            .find(|m| m.id == id)
+            .ok_or(PharmacyError::MedicineNotFound)?;
+        let before = medicine.clone();
+        medicine.vat_rate = vat_rate;
+        let after = medicine.clone();
+        self.record_audit(
+            user,
+            AuditAction::Update,
+            AuditEntity::Medicine,
+            id,
+            Some(&before),
+            Some(&after),
+        );
+        Ok(())
+    }
+
+    /// Output VAT of sales in `[from, to]` per `period` and rate, for the VAT return.
+    pub fn vat_report(
+        &self,
+        from: Option<DateTime<Local>>,
+        to: Option<DateTime<Local>>,
+        period: Period,
+        tz: Tz,
+    ) -> VatReport {
+        let mut buckets: BTreeMap<(String, VatRate), Totals> = BTreeMap::new();
+        let mut by_rate: BTreeMap<VatRate, Totals> = BTreeMap::new();
+        let (mut untaxed_sales, mut untaxed_revenue) = (0, 0.0);
+        for sale in self.export_log.iter().filter(|e| {
+            from.is_none_or(|from| e.timestamp >= from) && to.is_none_or(|to| e.timestamp <= to)
+        }) {
+            let Some(tax) = &sale.tax else {
+                untaxed_sales += 1;
+                untaxed_revenue += sale.revenue();
+                continue;
+            };
+            buckets
+                .entry((period_start(sale.timestamp, period, tz), tax.vat_rate))
+                .or_default()
+                .add(tax);
+            by_rate.entry(tax.vat_rate).or_default().add(tax);
+        }
+
+        let rows = buckets
+            .into_iter()
+            .map(|((period, vat_rate), totals)| VatRow {
+                period,
+                vat_rate,
+                sales: totals.sales,
+                net: totals.net,
+                vat: totals.vat,
+                gross: totals.net + totals.vat,
+            })
+            .collect();
+        let by_rate: Vec<VatRateTotal> = by_rate
+            .into_iter()
+            .map(|(vat_rate, totals)| VatRateTotal {
+                vat_rate,
+                sales: totals.sales,
+                net: totals.net,
+                vat: totals.vat,
+                gross: totals.net + totals.vat,
+            })
+            .collect();
+        let net = by_rate.iter().map(|t| t.net).sum();
+        let vat = by_rate.iter().map(|t| t.vat).sum();
+        VatReport {
+            from,
+            to,
+            period,
+            net,
+            vat,
+            gross: net + vat,
+            by_rate,
+            untaxed_sales,
+            untaxed_revenue,
+            rows,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{TimeZone, Utc};
+
+    use crate::models::ExportBatch;
+
+    const RATES: [VatRate; 4] = [VatRate::Zero, VatRate::Five, VatRate::Eight, VatRate::Ten];
+
+    fn split(tax: LineTax) -> (f64, f64) {
+        (tax.net, tax.vat)
+    }
+
+    #[test]
+    fn vat_inclusive_prices_are_split_into_net_and_vat() {
+        let expected = [
+            (110000.0, 0.0),
+            (104762.0, 5238.0),
+            (101852.0, 8148.0),
+            (100000.0, 10000.0),
+        ];
+        for (rate, expected) in RATES.into_iter().zip(expected) {
+            let tax = LineTax::new(110000.0, rate, true, "VND");
+            assert_eq!(split(tax), expected, "at {}", rate);
+            assert_eq!(tax.gross(), 110000.0, "at {}", rate);
+        }
+    }
+
+    #[test]
+    fn vat_exclusive_prices_get_vat_on_top() {
+        let expected = [0.0, 5500.0, 8800.0, 11000.0];
+        for (rate, vat) in RATES.into_iter().zip(expected) {
+            let tax = LineTax::new(110000.0, rate, false, "VND");
+            assert_eq!(split(tax), (110000.0, vat), "at {}", rate);
+            assert_eq!(tax.gross(), 110000.0 + vat, "at {}", rate);
+        }
+    }
+
+    #[test]
+    fn amounts_are_rounded_to_the_currency() {
+        assert_eq!(
+            split(LineTax::new(999.6, VatRate::Eight, false, "VND")),
+            (1000.0, 80.0)
+        );
+        assert_eq!(
+            split(LineTax::new(10.5, VatRate::Five, true, "USD")),
+            (10.0, 0.5)
+        );
+    }
+
+    fn sale(id: u32, day: (i32, u32, u32), tax: Option<LineTax>) -> ExportBatch {
+        ExportBatch {
+            id,
+            medicine_id: 1,
+            medicine_name: "Paracetamol".to_string(),
+            warehouse_id: Some(1),
+            amount: 2,
+            price: 5000.0,
+            unit_cost: None,
+            cashier: None,
+            batches: Vec::new(),
+            pricing: None,
+            tax,
+            timestamp: Utc
+                .with_ymd_and_hms(day.0, day.1, day.2, 3, 0, 0)
+                .unwrap()
+                .with_timezone(&Local),
+        }
+    }
+
+    fn pharmacy() -> Pharmacy {
+        let mut pharmacy = Pharmacy::new();
+        pharmacy.export_log = vec![
+            sale(
+                1,
+                (2026, 3, 10),
+                Some(LineTax::new(110000.0, VatRate::Five, true, "VND")),
+            ),
+            sale(
+                2,
+                (2026, 3, 20),
+                Some(LineTax::new(100000.0, VatRate::Ten, false, "VND")),
+            ),
+            sale(
+                3,
+                (2026, 4, 2),
+                Some(LineTax::new(21000.0, VatRate::Five, true, "VND")),
+            ),
+            sale(4, (2026, 3, 5), None),
+        ];
+        pharmacy
+    }
+
+    #[test]
+    fn vat_report_totals_per_period_and_rate() {
+        let report = pharmacy().vat_report(None, None, Period::Month, chrono_tz::Asia::Ho_Chi_Minh);
+
+        let rows: Vec<_> = report
+            .rows
+            .iter()
+            .map(|r| (r.period.as_str(), r.vat_rate, r.sales, r.net, r.vat))
+            .collect();
+        assert_eq!(
+            rows,
+            [
+                ("2026-03", VatRate::Five, 1, 104762.0, 5238.0),
+                ("2026-03", VatRate::Ten, 1, 100000.0, 10000.0),
+                ("2026-04", VatRate::Five, 1, 20000.0, 1000.0),
+            ]
+        );
+        let by_rate: Vec<_> = report
+            .by_rate
+            .iter()
+            .map(|t| (t.vat_rate, t.sales, t.net, t.vat, t.gross))
+            .collect();
+        assert_eq!(
+            by_rate,
+            [
+                (VatRate::Five, 2, 124762.0, 6238.0, 131000.0),
+                (VatRate::Ten, 1, 100000.0, 10000.0, 110000.0),
+            ]
+        );
+        assert_eq!(
+            (report.net, report.vat, report.gross),
+            (224762.0, 16238.0, 241000.0)
+        );
+        assert_eq!((report.untaxed_sales, report.untaxed_revenue), (1, 10000.0));
+    }
+
+    #[test]
+    fn vat_report_only_counts_sales_in_range() {
+        let from = Utc
+            .with_ymd_and_hms(2026, 4, 1, 0, 0, 0)
+            .unwrap()
+            .with_timezone(&Local);
+        let report = pharmacy().vat_report(
+            Some(from),
+            None,
+            Period::Month,
+            chrono_tz::Asia::Ho_Chi_Minh,
+        );
+
+        assert_eq!(report.rows.len(), 1);
+        assert_eq!((report.net, report.vat), (20000.0, 1000.0));
+        assert_eq!(report.untaxed_sales, 0);
+    }
+}
@@ -0,0 +1,132 @@
+use chrono::{DateTime, Local};
+use serde::Serialize;
+
+use crate::config::is_tax_code;
+use crate::error::PharmacyError;
+use crate::models::Pharmacy;
+
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub struct FieldError {
+    pub field: String,
+    pub code: &'static str,
+    pub message: String,
+}
+
+/// Implemented by every request payload; all rule violations are collected
+/// so the client gets the complete list in a single response.
+pub trait Validate {
+    fn validate(&self, pharmacy: &Pharmacy) -> Result<(), PharmacyError>;
+}
+
+#[derive(Debug, Default)]
+pub struct Validator {
+    errors: Vec<FieldError>,
+}
+
+impl Validator {
+    pub fn new() -> Self {
+        Validator::default()
+    }
+
+    pub fn check(&mut self, field: &str, ok: bool, code: &'static str, message: &str) -> &mut Self {
+        if !ok {
+            self.errors.push(FieldError {
+                field: field.to_string(),
+                code,
+                message: message.to_string(),
+            });
+        }
+        self
+    }
+
+    pub fn not_blank(&mut self, field: &str, value: &str) -> &mut Self {
+        self.check(
+            field,
+            !value.trim().is_empty(),
+            "REQUIRED",
+            "must not be empty",
+        )
+    }
+
+    pub fn positive(&mut self, field: &str, value: u32) -> &mut Self {
+        self.check(
+            field,
+            value > 0,
+            "NOT_POSITIVE",
+            "must be greater than zero",
+        )
+    }
+
+    pub fn price(&mut self, field: &str, value: f64) -> &mut Self {
+        self.check(
+            field,
+            value.is_finite() && value >= 0.0,
+            "INVALID_PRICE",
+            "must be a non-negative number",
+        )
+    }
+
+    /// Accepts 9-15 digits with an optional leading `+` and common separators.
+    pub fn phone(&mut self, field: &str, value: &str) -> &mut Self {
+        let value = value.trim();
+        let digits = value.chars().filter(|c| c.is_ascii_digit()).count();
+        let well_formed = value
+            .strip_prefix('+')
+            .unwrap_or(value)
+            .chars()
+            .all(|c| c.is_ascii_digit() || matches!(c, ' ' | '.' | '-'));
+        self.check(
+            field,
+            well_formed && (9..=15).contains(&digits),
+            "INVALID_PHONE",
+            "must be a phone number of 9 to 15 digits",
+        )
+    }
+
+    /// Accepts a company tax code, `0123456789`, or a branch's, `0123456789-001`.
+    pub fn tax_code(&mut self, field: &str, value: &str) -> &mut Self {
+        self.check(
+            field,
+            is_tax_code(value.trim()),
+            "INVALID_TAX_CODE",
+            "must be a tax code of 10 digits, or 13 with a branch suffix",
+        )
+    }
+
+    /// Checks an ISO 8601 date that must lie in the future.
+    pub fn future_date(&mut self, field: &str, value: &str) -> &mut Self {
+        match DateTime::parse_from_rfc3339(value) {
+            Ok(dt) => self.check(
+                field,
+                dt.with_timezone(&Local) > Local::now(),
+                "DATE_IN_PAST",
+                "must be in the future",
+            ),
+            Err(_) => self.check(field, false, "INVALID_DATE", "must be an ISO 8601 date"),
+        }
+    }
+
+    pub fn finish(&mut self) -> Result<(), PharmacyError> {
+        if self.errors.is_empty() {
+            Ok(())
+        } else {
+            Err(PharmacyError::Validation(std::mem::take(&mut self.errors)))
+        }
+    }
+}
+
+impl Pharmacy {
+    /// Name already on record for a medicine id, from the catalog or an earlier batch.
+    pub fn known_medicine_name(&self, medicine_id: u32) -> Option<&str> {
+        self.inventory
+            .iter()
+            .find(|m| m.id == medicine_id)
+            .map(|m| m.name.as_str())
+            .or_else(|| {
+                self.stock_batches
+                    .iter()
+                    .find(|b| b.medicine_id == medicine_id)
+                    .map(|b| b.medicine_name.as_str())
+            })
+    }
+}
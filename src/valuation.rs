@@ -0,0 +1,361 @@
+use chrono::{DateTime, Local};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+
+use crate::audit::AuditEntity;
+use crate::models::{Pharmacy, StockBatch};
+
+#[derive(Debug, Serialize)]
+pub struct ValuationLine {
+    pub medicine_id: u32,
+    pub medicine_name: String,
+    pub quantity: u32,
+    pub fifo_value: f64,
+    pub average_unit_cost: f64,
+    pub average_value: f64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct WarehouseValuation {
+    pub warehouse_id: u32,
+    pub warehouse_name: String,
+    pub quantity: u32,
+    pub fifo_value: f64,
+    pub average_value: f64,
+    pub lines: Vec<ValuationLine>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ValuationReport {
+    pub as_of: DateTime<Local>,
+    pub quantity: u32,
+    pub fifo_value: f64,
+    pub average_value: f64,
+    pub warehouses: Vec<WarehouseValuation>,
+}
+
+// A receipt of stock at a known unit cost
+struct Layer {
+    at: DateTime<Local>,
+    quantity: u32,
+    unit_cost: f64,
+}
+
+impl Pharmacy {
+    /// Stock batches as they stood at `as_of`, replayed from the audit log.
+    /// Batches created before auditing began count from their import date.
+    pub fn stock_batches_at(&self, as_of: DateTime<Local>) -> Vec<StockBatch> {
+        let mut states: HashMap<u32, Option<StockBatch>> = HashMap::new();
+        let mut first_seen_later: HashMap<u32, Option<StockBatch>> = HashMap::new();
+        for entry in self
+            .audit_log
+            .iter()
+            .filter(|e| e.entity == AuditEntity::StockBatch)
+        {
+            let snapshot = |value: &Option<serde_json::Value>| {
+                value
+                    .clone()
+                    .and_then(|v| serde_json::from_value::<StockBatch>(v).ok())
+                    .map(|mut b| {
+                        b.split_legacy_price();
+                        b
+                    })
+            };
+            if entry.timestamp <= as_of {
+                states.insert(entry.entity_id, snapshot(&entry.after));
+            } else if !states.contains_key(&entry.entity_id) {
+                // The state just before the first later change is the state at `as_of`
+                first_seen_later
+                    .entry(entry.entity_id)
+                    .or_insert_with(|| snapshot(&entry.before));
+            }
+        }
+        for (id, before) in first_seen_later {
+            states.entry(id).or_insert(before);
+        }
+        for batch in &self.stock_batches {
+            if !states.contains_key(&batch.id) && batch.import_date <= as_of {
+                states.insert(batch.id, Some(batch.clone()));
+            }
+        }
+
+        let mut batches: Vec<StockBatch> = states.into_values().flatten().collect();
+        batches.sort_by_key(|b| b.id);
+        batches
+    }
+
+    /// Values stock on hand at `as_of` per warehouse under FIFO and moving
+    /// weighted-average cost. Only warehouse stock is valued; catalog
+    /// quantities carry no purchase cost.
+    pub fn valuation(&self, as_of: DateTime<Local>, warehouse_id: Option<u32>) -> ValuationReport {
+        let batches = self.stock_batches_at(as_of);
+
+        let mut on_hand: BTreeMap<(u32, u32), (String, u32, f64)> = BTreeMap::new();
+        for batch in batches.iter().filter(|b| b.quantity > 0) {
+            let entry = on_hand
+                .entry((batch.warehouse_id, batch.medicine_id))
+                .or_insert_with(|| (batch.medicine_name.clone(), 0, 0.0));
+            entry.1 += batch.quantity;
+            entry.2 += batch.unit_cost * batch.quantity as f64;
+        }
+
+        let mut warehouses: BTreeMap<u32, WarehouseValuation> = BTreeMap::new();
+        for ((wh_id, medicine_id), (medicine_name, quantity, batch_cost)) in on_hand {
+            if warehouse_id.is_some_and(|id| id != wh_id) {
+                continue;
+            }
+            let batch_unit_cost = batch_cost / quantity as f64;
+            let fifo_value = self.fifo_value(as_of, wh_id, medicine_id, quantity, batch_unit_cost);
+            let average_unit_cost = self
+                .moving_average_cost(as_of, medicine_id)
+                .unwrap_or(batch_unit_cost);
+            let line = ValuationLine {
+                medicine_id,
+                medicine_name,
+                quantity,
+                fifo_value,
+                average_unit_cost,
+                average_value: average_unit_cost * quantity as f64,
+            };
+
+            let warehouse = warehouses
+                .entry(wh_id)
+                .or_insert_with(|| WarehouseValuation {
+                    warehouse_id: wh_id,
+                    warehouse_name: self
+                        .warehouses
+                        .iter()
+                        .find(|w| w.id == wh_id)
+                        .map(|w| w.name.clone())
+                        .unwrap_or_default(),
+                    quantity: 0,
+                    fifo_value: 0.0,
+                    average_value: 0.0,
+                    lines: Vec::new(),
+                });
+            warehouse.quantity += line.quantity;
+            warehouse.fifo_value += line.fifo_value;
+            warehouse.average_value += line.average_value;
+            warehouse.lines.push(line);
+        }
+
+        let warehouses: Vec<_> = warehouses.into_values().collect();
+        ValuationReport {
+            as_of,
+            quantity: warehouses.iter().map(|w| w.quantity).sum(),
+            // Folded from 0.0: summing no floats yields -0.0
+            fifo_value: warehouses.iter().fold(0.0, |sum, w| sum + w.fifo_value),
+            average_value: warehouses.iter().fold(0.0, |sum, w| sum + w.average_value),
+            warehouses,
+        }
+    }
+
+    // Under FIFO the oldest units leave first, so what remains is the newest
+    // receipts into the warehouse. Units no receipt accounts for (data from
+    // before receipts were logged) are valued at their batches' cost.
+    fn fifo_value(
+        &self,
+        as_of: DateTime<Local>,
+        warehouse_id: u32,
+        medicine_id: u32,
+        quantity: u32,
+        fallback_unit_cost: f64,
+    ) -> f64 {
+        let mut layers = self.receipts_into(as_of, warehouse_id, medicine_id);
+        layers.sort_by_key(|l| std::cmp::Reverse(l.at));
+
+        let mut remaining = quantity;
+        let mut value = 0.0;
+        for layer in layers {
+            if remaining == 0 {
+                break;
+            }
+            let taken = remaining.min(layer.quantity);
+            value += taken as f64 * layer.unit_cost;
+            remaining -= taken;
+        }
+        value + remaining as f64 * fallback_unit_cost
+    }
+
+    fn receipts_into(
+        &self,
+        as_of: DateTime<Local>,
+        warehouse_id: u32,
+        medicine_id: u32,
+    ) -> Vec<Layer> {
+        let imports = self
+            .import_log
+            .iter()
+            .filter(|e| {
+                e.warehouse_id == Some(warehouse_id)
+                    && e.medicine_id == medicine_id
+                    && e.timestamp <= as_of
+            })
+            .map(|e| Layer {
+                at: e.timestamp,
+                quantity: e.quantity,
+                unit_cost: e.unit_cost,
+            });
+        // Transfers keep the cost of the batch they came from
+        let transfers = self
+            .transfer_log
+            .iter()
+            .filter(|t| {
+                t.to_warehouse_id == warehouse_id
+                    && t.medicine_id == medicine_id
+                    && t.timestamp <= as_of
+            })
+            .filter_map(|t| {
+                let source = self.stock_batches.iter().find(|b| b.id == t.batch_id)?;
+                Some(Layer {
+                    at: t.timestamp,
+                    quantity: t.quantity,
+                    unit_cost: source.unit_cost,
+                })
+            });
+        imports.chain(transfers).collect()
+    }
+
+    /// Moving weighted-average unit cost of a medicine across all warehouses
+    /// at `as_of`: each receipt re-averages with the units still on hand,
+    /// sales take units out at the current average. `None` without receipts.
+    pub fn moving_average_cost(&self, as_of: DateTime<Local>, medicine_id: u32) -> Option<f64> {
+        enum Movement {
+            In(u32, f64),
+            Out(u32),
+        }
+        let mut movements: Vec<(DateTime<Local>, Movement)> = self
+            .import_log
+            .iter()
+            .filter(|e| {
+                e.warehouse_id.is_some() && e.medicine_id == medicine_id && e.timestamp <= as_of
+            })
+            .map(|e| (e.timestamp, Movement::In(e.quantity, e.unit_cost)))
+            .chain(
+                self.export_log
+                    .iter()
+                    .filter(|e| {
+                        e.warehouse_id.is_some()
+                            && e.medicine_id == medicine_id
+                            && e.timestamp <= as_of
+                    })
+                    .map(|e| (e.timestamp, Movement::Out(e.amount))),
+            )
+            .collect();
+        movements.sort_by_key(|(at, _)| *at);
+
+        let mut on_hand = 0u32;
+        let mut average = None;
+        for (_, movement) in movements {
+            match movement {
+                Movement::In(quantity, unit_cost) => {
+                    let current = average.unwrap_or(0.0);
+                    let total = on_hand + quantity;
+                    if total > 0 {
+                        average = Some(
+                            (current * on_hand as f64 + unit_cost * quantity as f64) / total as f64,
+                        );
+                    }
+                    on_hand = total;
+                }
+                Movement::Out(quantity) => on_hand = on_hand.saturating_sub(quantity),
+            }
+        }
+        average
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+
+    use crate::config::Config;
+    use crate::models::WarehouseType;
+    use crate::pricing::PriceContext;
+
+    // Receives `quantity` units of medicine 1 into the store `days_ago`
+    fn receive(pharmacy: &mut Pharmacy, store: u32, quantity: u32, unit_cost: f64, days_ago: i64) {
+        let expiry_date = Local::now() + Duration::days(400 - days_ago);
+        pharmacy
+            .import_batch(
+                "test",
+                1,
+                "Paracetamol".to_string(),
+                store,
+                None,
+                quantity,
+                unit_cost,
+                2000.0,
+                expiry_date,
+            )
+            .unwrap();
+        pharmacy.import_log.last_mut().unwrap().timestamp = Local::now() - Duration::days(days_ago);
+    }
+
+    fn store() -> (Pharmacy, u32) {
+        let mut pharmacy = Pharmacy::new();
+        let store = pharmacy.add_warehouse("test", "Store".to_string(), WarehouseType::Store);
+        (pharmacy, store)
+    }
+
+    fn sell(pharmacy: &mut Pharmacy, quantity: u32) {
+        pharmacy
+            .sell_with_fefo(
+                "test",
+                1,
+                quantity,
+                &PriceContext::default(),
+                &Config::default(),
+            )
+            .unwrap();
+    }
+
+    #[test]
+    fn fifo_values_the_newest_receipts_after_a_partial_sale() {
+        let (mut pharmacy, store) = store();
+        receive(&mut pharmacy, store, 10, 100.0, 10);
+        receive(&mut pharmacy, store, 10, 130.0, 5);
+        sell(&mut pharmacy, 4);
+
+        let report = pharmacy.valuation(Local::now(), None);
+        let line = &report.warehouses[0].lines[0];
+        assert_eq!(line.quantity, 16);
+        // The 4 units sold are the oldest ones: 10 x 130 + 6 x 100
+        assert_eq!(line.fifo_value, 1900.0);
+        // Average of both receipts, unchanged by the sale: 16 x 115
+        assert_eq!(line.average_unit_cost, 115.0);
+        assert_eq!(line.average_value, 1840.0);
+        assert_eq!(report.fifo_value, 1900.0);
+    }
+
+    #[test]
+    fn moving_average_reaverages_with_the_units_left_on_hand() {
+        let (mut pharmacy, store) = store();
+        receive(&mut pharmacy, store, 10, 100.0, 10);
+        sell(&mut pharmacy, 5);
+        pharmacy.export_log.last_mut().unwrap().timestamp = Local::now() - Duration::days(7);
+        receive(&mut pharmacy, store, 10, 130.0, 5);
+
+        // 5 left at 100 with 10 more at 130: (500 + 1300) / 15
+        assert_eq!(pharmacy.moving_average_cost(Local::now(), 1), Some(120.0));
+        let line = &pharmacy.valuation(Local::now(), None).warehouses[0].lines[0];
+        assert_eq!(line.quantity, 15);
+        assert_eq!(line.average_value, 1800.0);
+        assert_eq!(line.fifo_value, 1800.0);
+    }
+
+    #[test]
+    fn other_warehouses_are_left_out() {
+        let (mut pharmacy, store) = store();
+        receive(&mut pharmacy, store, 10, 100.0, 10);
+
+        assert_eq!(
+            pharmacy.valuation(Local::now(), Some(store)).fifo_value,
+            1000.0
+        );
+        let other = pharmacy.valuation(Local::now(), Some(store + 1));
+        assert!(other.warehouses.is_empty());
+        assert_eq!(other.fifo_value, 0.0);
+    }
+}